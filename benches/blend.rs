@@ -0,0 +1,53 @@
+//! Overlaying a layer onto a large canvas, the way every token is rendered,
+//! against the `imageops::overlay` it replaces.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/blend.rs"]
+mod blend;
+
+/// A canvas-sized layer that is transparent around a shape with soft edges,
+/// like most trait layers.
+fn layer(size: u32) -> DynamicImage {
+    let center = size as f32 / 2.0;
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(size, size, |x, y| {
+        let distance = ((x as f32 - center).powi(2) + (y as f32 - center).powi(2)).sqrt();
+        let alpha = ((center * 0.6 - distance) * 4.0).clamp(0.0, 255.0) as u8;
+        Rgba([(x % 256) as u8, (y % 256) as u8, 128, alpha])
+    }))
+}
+
+fn overlay(c: &mut Criterion) {
+    for size in [1024, 4096] {
+        let layer = layer(size);
+        let canvas = RgbaImage::from_pixel(size, size, Rgba([40, 80, 120, 255]));
+        let mut group = c.benchmark_group(format!("overlay {0}x{0}", size));
+        group.sample_size(10);
+        group.bench_function("imageops", |b| {
+            b.iter(|| {
+                let mut canvas = canvas.clone();
+                image::imageops::overlay(&mut canvas, black_box(&layer), 0, 0);
+                canvas
+            })
+        });
+        group.bench_function("draw_layer", |b| {
+            b.iter(|| {
+                let mut canvas = canvas.clone();
+                blend::draw_layer(
+                    &mut canvas,
+                    black_box(&layer),
+                    0,
+                    0,
+                    blend::BlendMode::Normal,
+                );
+                canvas
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, overlay);
+criterion_main!(benches);
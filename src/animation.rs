@@ -0,0 +1,128 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::file_names;
+use crate::{CustomError, ALLOWED_EXTENSION};
+
+/// A file animating a trait value, such as a GIF, a video or a 3D model, for
+/// the `animation_url` of the tokens that show it.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TraitAnimation {
+    pub layer: String,
+    pub value: String,
+    pub file: String,
+}
+
+/// The `animations` of a run, by layer and value.
+#[derive(Debug, Default, Clone)]
+pub struct Animations {
+    files: HashMap<(String, String), PathBuf>,
+    url: String,
+}
+
+impl Animations {
+    /// Checks that every entry is for one of the layers of `names` and has a
+    /// file, which its token's copy can be named after.
+    pub fn compile(
+        entries: &[TraitAnimation],
+        names: &[String],
+        image_url: &str,
+    ) -> Result<Animations, CustomError> {
+        let mut files = HashMap::new();
+        for entry in entries {
+            let invalid = |reason: String| {
+                CustomError::Config(format!(
+                    "animation of {}/{} {}",
+                    entry.layer, entry.value, reason
+                ))
+            };
+            if !names.contains(&entry.layer) {
+                return Err(invalid("references an unknown layer".to_string()));
+            }
+            let file = PathBuf::from(&entry.file);
+            if !file.is_file() {
+                return Err(invalid(format!("{} is not a file", entry.file)));
+            }
+            match file.extension().and_then(|extension| extension.to_str()) {
+                None => return Err(invalid(format!("{} has no extension", entry.file))),
+                // The copy would take the name of the image.
+                Some(extension) if ALLOWED_EXTENSION.eq_ignore_ascii_case(extension) => {
+                    return Err(invalid(format!("{} can't be a PNG", entry.file)))
+                }
+                Some(_) => {}
+            }
+            files.insert((entry.layer.clone(), entry.value.clone()), file);
+        }
+        Ok(Animations {
+            files,
+            url: image_url.to_string(),
+        })
+    }
+
+    /// The animation of `layer`'s `value`, if it has one.
+    pub fn get(&self, layer: &str, value: &str) -> Option<&Path> {
+        self.files
+            .get(&(layer.to_string(), value.to_string()))
+            .map(PathBuf::as_path)
+    }
+
+    /// The `animation_url` of a copied animation, named `file_name`.
+    pub fn animation_url(&self, file_name: &str) -> Option<String> {
+        file_names::file_url(&self.url, file_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_animations() {
+        let dir = tempdir().unwrap();
+        let file = |name: &str| dir.path().join(name).display().to_string();
+        for name in ["laser.gif", "crown", "crown.png"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let entries = vec![TraitAnimation {
+            layer: "Eyes".to_string(),
+            value: "Laser".to_string(),
+            file: file("laser.gif"),
+        }];
+        let names = vec!["Eyes".to_string(), "Hat".to_string()];
+        let animations = Animations::compile(&entries, &names, "ipfs://bafy/").unwrap();
+        assert_eq!(
+            animations.get("Eyes", "Laser"),
+            Some(dir.path().join("laser.gif").as_path())
+        );
+        assert_eq!(animations.get("Eyes", "Normal"), None);
+        assert_eq!(
+            animations.animation_url("1.gif"),
+            Some("ipfs://bafy/1.gif".to_string())
+        );
+        assert_eq!(
+            Animations::compile(&entries, &names, "")
+                .unwrap()
+                .animation_url("1.gif"),
+            None
+        );
+
+        for (layer, name) in [
+            ("Body", "laser.gif"),
+            ("Hat", "missing.gif"),
+            ("Hat", "crown"),
+            ("Hat", "crown.png"),
+        ] {
+            let entries = vec![TraitAnimation {
+                layer: layer.to_string(),
+                value: "Crown".to_string(),
+                file: file(name),
+            }];
+            assert!(Animations::compile(&entries, &names, "").is_err());
+        }
+    }
+}
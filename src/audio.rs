@@ -0,0 +1,214 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use walkdir::WalkDir;
+
+use crate::file_names;
+use crate::video;
+use crate::CustomError;
+
+/// Music tokens: layers of audio stems, chosen like image layers, mixed per
+/// token into one file linked as its `animation_url`, while the image layers
+/// make its cover art.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Audio {
+    /// The layers of `layerFolders` holding stems rather than images.
+    pub layers: Vec<String>,
+    #[serde(default)]
+    pub format: AudioFormat,
+    /// The ffmpeg executable, looked up in `PATH` unless it is a path.
+    #[serde(default = "video::default_ffmpeg")]
+    pub ffmpeg: String,
+    /// The base URI of the mixes; `imageUrl` by default.
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum AudioFormat {
+    #[default]
+    Mp3,
+    Wav,
+}
+
+impl AudioFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Wav => "wav",
+        }
+    }
+
+    fn codec_args(self) -> &'static [&'static str] {
+        match self {
+            AudioFormat::Mp3 => &["-c:a", "libmp3lame", "-q:a", "2"],
+            AudioFormat::Wav => &["-c:a", "pcm_s16le"],
+        }
+    }
+}
+
+/// The extensions of stem files.
+const STEM_EXTENSIONS: [&str; 2] = ["wav", "mp3"];
+
+/// Whether an asset is an audio stem rather than an image.
+pub fn is_stem(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            STEM_EXTENSIONS
+                .iter()
+                .any(|stem| stem.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// The stems of an audio layer, sublayer folders included, in name order.
+pub fn stem_paths(dir: &Path) -> Vec<String> {
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.path().to_string_lossy().into_owned())
+        .filter(|path| is_stem(path))
+        .collect()
+}
+
+impl Audio {
+    /// Checks that every audio layer is one of the layers of `names`.
+    pub fn check_layers(&self, names: &[String]) -> Result<(), CustomError> {
+        match self.layers.iter().find(|layer| !names.contains(layer)) {
+            Some(layer) => Err(CustomError::Config(format!(
+                "audio references unknown layer {}",
+                layer
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The `audio` of a run, checked.
+#[derive(Debug, Clone)]
+pub struct AudioSettings {
+    audio: Audio,
+    url: String,
+}
+
+impl AudioSettings {
+    /// Checks that ffmpeg runs.
+    pub fn compile(audio: &Audio, image_url: &str) -> Result<AudioSettings, CustomError> {
+        video::check_ffmpeg("audio", &audio.ffmpeg)?;
+        Ok(AudioSettings {
+            audio: audio.clone(),
+            url: audio.url.as_deref().unwrap_or(image_url).to_string(),
+        })
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.audio.format.extension()
+    }
+
+    /// The `animation_url` of the mix `file_name`.
+    pub fn animation_url(&self, file_name: &str) -> Option<String> {
+        file_names::file_url(&self.url, file_name)
+    }
+
+    fn mix_args(&self, stems: &[&Path], output: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = ["-v", "error", "-nostdin", "-y"]
+            .map(OsString::from)
+            .to_vec();
+        for stem in stems {
+            args.push("-i".into());
+            args.push(stem.into());
+        }
+        // Stems are played together at their own levels, for as long as the
+        // longest.
+        args.push("-filter_complex".into());
+        args.push(format!("amix=inputs={}:duration=longest:normalize=0", stems.len()).into());
+        args.extend(self.audio.format.codec_args().iter().map(OsString::from));
+        args.push(output.into());
+        args
+    }
+
+    /// Mixes `stems` into `output`.
+    pub fn mix(&self, stems: &[&Path], output: &Path) -> Result<(), CustomError> {
+        let result = Command::new(&self.audio.ffmpeg)
+            .args(self.mix_args(stems, output))
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| CustomError::Audio(format!("{}: {}", output.display(), e)))?;
+        if !result.status.success() {
+            return Err(CustomError::Audio(format!(
+                "{}: ffmpeg {}: {}",
+                output.display(),
+                result.status,
+                String::from_utf8_lossy(&result.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_audio() {
+        let dir = tempdir().unwrap();
+        let drums = dir.path().join("Drums");
+        fs::create_dir_all(drums.join("Live#5")).unwrap();
+        for name in [
+            "Funky#20.wav",
+            "Rock#10.MP3",
+            "notes.txt",
+            "Live#5/Jazz#1.mp3",
+        ] {
+            fs::write(drums.join(name), b"").unwrap();
+        }
+        assert_eq!(
+            stem_paths(&drums),
+            vec![
+                drums.join("Funky#20.wav").display().to_string(),
+                drums.join("Live#5/Jazz#1.mp3").display().to_string(),
+                drums.join("Rock#10.MP3").display().to_string(),
+            ]
+        );
+        assert!(!is_stem("./images/Eyes/Laser#10.png"));
+        assert!(!is_stem("./images/Drums/None#3"));
+
+        let audio: Audio = serde_json::from_str(r#"{"layers": ["Drums", "Bass"]}"#).unwrap();
+        assert_eq!(audio.ffmpeg, "ffmpeg");
+        let names = vec!["Drums".to_string(), "Bass".to_string()];
+        assert!(audio.check_layers(&names).is_ok());
+        assert!(audio.check_layers(&names[..1]).is_err());
+
+        let settings = AudioSettings {
+            audio,
+            url: "ipfs://bafy".to_string(),
+        };
+        assert_eq!(
+            settings.animation_url("1.mp3"),
+            Some("ipfs://bafy/1.mp3".to_string())
+        );
+        let args = settings.mix_args(
+            &[
+                Path::new("Drums/Funky#20.wav"),
+                Path::new("Bass/Deep#5.mp3"),
+            ],
+            Path::new("out/1.mp3"),
+        );
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["-i", "Bass/Deep#5.mp3"]));
+        assert!(args
+            .iter()
+            .any(|arg| arg == "amix=inputs=2:duration=longest:normalize=0"));
+        assert_eq!(args.last().unwrap(), "out/1.mp3");
+    }
+}
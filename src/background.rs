@@ -0,0 +1,223 @@
+use image::{Rgba, RgbaImage};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::palette::parse_color;
+use crate::paths::LayerLayout;
+use crate::{is_file_asset, path_trait, CustomError};
+
+/// A background layer generated at render time from weighted fills, drawn
+/// under every other layer.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Background {
+    /// Trait type of the background in the metadata.
+    #[serde(default = "default_layer")]
+    pub layer: String,
+    pub fills: Vec<BackgroundFill>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BackgroundFill {
+    /// Trait value of the fill in the metadata.
+    pub name: String,
+    #[serde(default = "default_weight")]
+    pub weight: u64,
+    #[serde(flatten)]
+    pub kind: FillKind,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum FillKind {
+    /// A `#rrggbb` color.
+    Color(String),
+    Gradient(Gradient),
+}
+
+/// A linear gradient between two `#rrggbb` colors.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Gradient {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub direction: GradientDirection,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum GradientDirection {
+    /// From the top to the bottom.
+    #[default]
+    Vertical,
+    /// From the left to the right.
+    Horizontal,
+    /// From the top left to the bottom right corner.
+    Diagonal,
+}
+
+fn default_layer() -> String {
+    "Background".to_string()
+}
+
+fn default_weight() -> u64 {
+    1
+}
+
+impl Background {
+    /// The assets of the background layer. Like `None` assets they have no
+    /// file, only a path carrying the layer, the fill name and its weight.
+    pub fn assets(
+        &self,
+        base_path: &str,
+        layer_names: &[String],
+    ) -> Result<Vec<String>, CustomError> {
+        if layer_names.contains(&self.layer) {
+            return Err(CustomError::InvalidTrait(format!(
+                "background layer {} is also in layerFolders",
+                self.layer
+            )));
+        }
+        if self.fills.is_empty() {
+            return Err(CustomError::InvalidTrait(
+                "background has no fills".to_string(),
+            ));
+        }
+
+        let mut assets = Vec::new();
+        for (index, fill) in self.fills.iter().enumerate() {
+            if fill.name.is_empty() || fill.name.contains(['.', '/', '#']) {
+                return Err(CustomError::InvalidTrait(format!(
+                    "background fill name {:?} can't be empty or contain '.', '/' or '#'",
+                    fill.name
+                )));
+            }
+            if self.fills[..index]
+                .iter()
+                .any(|other| other.name == fill.name)
+            {
+                return Err(CustomError::InvalidTrait(format!(
+                    "background fill {} is listed more than once",
+                    fill.name
+                )));
+            }
+            fill.colors().map_err(|_| {
+                CustomError::InvalidTrait(format!(
+                    "background fill {} has a color that is not #rrggbb",
+                    fill.name
+                ))
+            })?;
+            assets.push(
+                Path::new(base_path)
+                    .join(&self.layer)
+                    .join(format!("{}#{}", fill.name, fill.weight))
+                    .display()
+                    .to_string(),
+            );
+        }
+        Ok(assets)
+    }
+
+    /// Renders the fill a background asset stands for, or `None` when `path`
+    /// isn't one.
+    pub fn render(
+        &self,
+        layout: &LayerLayout,
+        path: &str,
+        width: u32,
+        height: u32,
+    ) -> Option<RgbaImage> {
+        if is_file_asset(path) {
+            return None;
+        }
+        let (layer, value) = path_trait(layout, path)?;
+        if layer != self.layer {
+            return None;
+        }
+        let fill = self.fills.iter().find(|fill| fill.name == value)?;
+        let (from, to) = fill.colors().ok()?;
+
+        let direction = match &fill.kind {
+            FillKind::Color(_) => return Some(RgbaImage::from_pixel(width, height, from)),
+            FillKind::Gradient(gradient) => gradient.direction,
+        };
+        let span = |length: u32| (length.max(2) - 1) as f32;
+        Some(RgbaImage::from_fn(width, height, |x, y| {
+            let t = match direction {
+                GradientDirection::Vertical => y as f32 / span(height),
+                GradientDirection::Horizontal => x as f32 / span(width),
+                GradientDirection::Diagonal => (x + y) as f32 / (span(width) + span(height)),
+            };
+            let channel = |index: usize| {
+                (from.0[index] as f32 + (to.0[index] as f32 - from.0[index] as f32) * t).round()
+                    as u8
+            };
+            Rgba([channel(0), channel(1), channel(2), 255])
+        }))
+    }
+}
+
+impl BackgroundFill {
+    /// The start and end colors; both are the same for a solid color.
+    fn colors(&self) -> Result<(Rgba<u8>, Rgba<u8>), CustomError> {
+        let rgba = |[r, g, b]: [u8; 3]| Rgba([r, g, b, 255]);
+        match &self.kind {
+            FillKind::Color(color) => {
+                let color = rgba(parse_color(color)?);
+                Ok((color, color))
+            }
+            FillKind::Gradient(gradient) => Ok((
+                rgba(parse_color(&gradient.from)?),
+                rgba(parse_color(&gradient.to)?),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_background() {
+        let background: Background = serde_json::from_str(
+            r##"{
+                "fills": [
+                    {"name": "Blue", "weight": 10, "color": "#0000ff"},
+                    {"name": "Sunset", "gradient": {"from": "#000000", "to": "#ff0000", "direction": "horizontal"}}
+                ]
+            }"##,
+        )
+        .unwrap();
+        let assets = background.assets("./images/", &[]).unwrap();
+        assert_eq!(
+            assets,
+            vec![
+                "./images/Background/Blue#10",
+                "./images/Background/Sunset#1"
+            ]
+        );
+        assert!(background
+            .assets("./images/", &["Background".to_string()])
+            .is_err());
+
+        let layout = LayerLayout::new("./images/");
+        let blue = background.render(&layout, &assets[0], 4, 2).unwrap();
+        assert!(blue.pixels().all(|pixel| pixel.0 == [0, 0, 255, 255]));
+        let sunset = background.render(&layout, &assets[1], 3, 1).unwrap();
+        assert_eq!(sunset.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(sunset.get_pixel(1, 0).0, [128, 0, 0, 255]);
+        assert_eq!(sunset.get_pixel(2, 0).0, [255, 0, 0, 255]);
+        assert_eq!(
+            background.render(&layout, "./images/Background/Blue#10.png", 4, 2),
+            None
+        );
+
+        let invalid: Background =
+            serde_json::from_str(r#"{"fills": [{"name": "Red", "color": "red"}]}"#).unwrap();
+        assert!(invalid.assets("./images/", &[]).is_err());
+    }
+}
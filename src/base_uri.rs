@@ -0,0 +1,137 @@
+use clap::Args;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+use walkdir::WalkDir;
+
+use crate::file_names::FileNames;
+use crate::localization::LOCALIZED_DIR_NAME;
+use crate::{write_json, Config, CustomError};
+
+/// Metadata fields holding the URI of a token's media.
+const URI_FIELDS: [&str; 2] = ["image", "animation_url"];
+
+#[derive(Debug, Args)]
+pub struct SetBaseUriArgs {
+    /// New base URI, e.g. `ipfs://<CID>` or a gateway URL.
+    pub uri: String,
+}
+
+/// `uri` with everything before its file name replaced by `base_uri`.
+pub fn rebase_uri(uri: &str, base_uri: &str) -> String {
+    let file_name = uri.rsplit('/').next().unwrap_or(uri);
+    format!("{}/{}", base_uri.trim_end_matches('/'), file_name)
+}
+
+/// Points the media URIs of a metadata document at `base_uri` and returns
+/// whether any changed.
+pub fn set_base_uri(metadata: &mut Value, base_uri: &str) -> bool {
+    let mut changed = false;
+    for field in URI_FIELDS {
+        let Some(uri) = metadata.get(field).and_then(Value::as_str) else {
+            continue;
+        };
+        let rebased = rebase_uri(uri, base_uri);
+        if rebased != uri {
+            metadata[field] = Value::from(rebased);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Rewrites the metadata files at the top of `metadata_dir` and the localized
+/// copies of `output_path`, and returns how many changed.
+pub fn set_base_uri_in_dir(
+    output_path: &Path,
+    metadata_dir: &Path,
+    base_uri: &str,
+) -> Result<usize, CustomError> {
+    // The localized copies are in `metadata/`, which may be `metadata_dir`.
+    let paths: BTreeSet<_> = WalkDir::new(metadata_dir)
+        .max_depth(1)
+        .into_iter()
+        .chain(WalkDir::new(output_path.join(LOCALIZED_DIR_NAME)))
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    let mut rewritten = 0;
+    for path in &paths {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| CustomError::WriteOutput(format!("{}: {}", path.display(), e)))?;
+        let Ok(mut metadata) = serde_json::from_str::<Value>(&contents) else {
+            continue;
+        };
+        if !set_base_uri(&mut metadata, base_uri) {
+            continue;
+        }
+
+        write_json(path, &metadata)?;
+        rewritten += 1;
+    }
+    Ok(rewritten)
+}
+
+pub fn run(args: &SetBaseUriArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let output_path = Path::new(&config.output_path);
+    let metadata_dir = FileNames::compile(config)?.metadata_dir(output_path);
+    let rewritten = set_base_uri_in_dir(output_path, &metadata_dir, &args.uri)?;
+    info!(
+        "Base URI set to {} in {} metadata files.",
+        args.uri.trim_end_matches('/'),
+        rewritten
+    );
+    if config.image_url.trim_end_matches('/') != args.uri.trim_end_matches('/') {
+        info!("Set imageUrl in the config too, so the next run uses it.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_set_base_uri() {
+        assert_eq!(
+            rebase_uri("https://example.com/images/7.png", "ipfs://bafy/"),
+            "ipfs://bafy/7.png"
+        );
+
+        let mut metadata = json!({
+            "name": "Test",
+            "image": "https://example.com/images/7.png",
+            "animation_url": "https://example.com/images/7.mp4"
+        });
+        assert!(set_base_uri(&mut metadata, "ipfs://bafy"));
+        assert_eq!(metadata["image"], "ipfs://bafy/7.png");
+        assert_eq!(metadata["animation_url"], "ipfs://bafy/7.mp4");
+        assert!(!set_base_uri(&mut metadata, "ipfs://bafy"));
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("7.json"), metadata.to_string()).unwrap();
+        fs::write(dir.path().join("stats.json"), r#"{"totalSupply": 1}"#).unwrap();
+        let locale_dir = dir.path().join(LOCALIZED_DIR_NAME).join("ja");
+        fs::create_dir_all(&locale_dir).unwrap();
+        fs::write(locale_dir.join("7.json"), metadata.to_string()).unwrap();
+        assert_eq!(
+            set_base_uri_in_dir(dir.path(), dir.path(), "ipfs://other").unwrap(),
+            2
+        );
+        let metadata_dir = dir.path().join(LOCALIZED_DIR_NAME);
+        fs::write(metadata_dir.join("8.json"), metadata.to_string()).unwrap();
+        assert_eq!(
+            set_base_uri_in_dir(dir.path(), &metadata_dir, "ipfs://third").unwrap(),
+            2
+        );
+        let written: Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("7.json")).unwrap()).unwrap();
+        assert_eq!(written["image"], "ipfs://other/7.png");
+    }
+}
@@ -0,0 +1,189 @@
+use clap::Args;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{info, info_span, warn};
+
+use crate::cli::{Command, GenerateArgs};
+use crate::overrides::Override;
+use crate::{generate, load_config, namespace, Config, CustomError};
+
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    /// Config files, or folders whose `.json` files are all configs.
+    #[arg(required = true)]
+    pub configs: Vec<String>,
+    /// Collections generated at the same time, sharing one pool of workers.
+    #[arg(long, default_value_t = 1)]
+    pub parallel: usize,
+    #[command(flatten)]
+    pub generate: GenerateArgs,
+}
+
+/// How one collection of a batch went.
+#[derive(Debug)]
+pub struct CollectionRun {
+    pub config: String,
+    pub output_path: String,
+    pub supply: u32,
+    pub elapsed: Duration,
+    pub result: Result<(), CustomError>,
+}
+
+/// The config files named by `inputs`, the ones in a folder in file name
+/// order, each once.
+pub fn config_paths(inputs: &[String]) -> Result<Vec<String>, CustomError> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            let mut entries: Vec<String> = fs::read_dir(path)
+                .map_err(|e| CustomError::Config(format!("{}: {}", input, e)))?
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"))
+                .map(|path| path.display().to_string())
+                .collect();
+            entries.sort();
+            paths.extend(entries);
+        } else {
+            paths.push(input.clone());
+        }
+    }
+    let mut seen = Vec::new();
+    paths.retain(|path| {
+        let new = !seen.contains(path);
+        seen.push(path.clone());
+        new
+    });
+    if paths.is_empty() {
+        return Err(CustomError::Config(format!(
+            "no config file in {}",
+            inputs.join(", ")
+        )));
+    }
+    Ok(paths)
+}
+
+/// Refuses collections writing to the same output folder, which would
+/// overwrite each other.
+pub fn check_outputs(collections: &[(String, Config)]) -> Result<(), CustomError> {
+    let mut outputs: HashMap<&str, &str> = HashMap::new();
+    for (path, config) in collections {
+        let output_path = config.output_path.trim_end_matches('/');
+        if let Some(other) = outputs.insert(output_path, path) {
+            return Err(CustomError::Config(format!(
+                "{} and {} both write to {}",
+                other, path, config.output_path
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Generates one collection like `generate` does, in a run folder of its own
+/// when it has a namespace.
+fn run_collection(path: &str, mut config: Config, args: &GenerateArgs) -> CollectionRun {
+    let _span = info_span!("collection", config = path).entered();
+    let start = Instant::now();
+    let output_root = config.output_path.clone();
+    let supply = config.total_supply;
+    let command = Command::Generate(args.clone());
+    let result = namespace::select_output(&mut config, &command, None).and_then(|new_run| {
+        let output_path = config.output_path.clone();
+        // Every error of a run is a CustomError; anything else stands for an
+        // output that couldn't be written.
+        generate(config, args).map_err(|error| match error.downcast::<CustomError>() {
+            Ok(error) => *error,
+            Err(error) => CustomError::WriteOutput(error.to_string()),
+        })?;
+        if let Some(run) = new_run {
+            namespace::write_latest_run(&output_root, &run)?;
+        }
+        Ok(output_path)
+    });
+    CollectionRun {
+        config: path.to_string(),
+        output_path: result.as_ref().map_or(output_root.clone(), Clone::clone),
+        supply,
+        elapsed: start.elapsed(),
+        result: result.map(|_| ()),
+    }
+}
+
+/// Generates every collection, each with `overrides` set over its config.
+pub fn run(args: &BatchArgs, overrides: &[Override]) -> Result<(), Box<dyn Error>> {
+    let mut collections = Vec::new();
+    for path in config_paths(&args.configs)? {
+        let config = load_config(&path, overrides)?;
+        collections.push((path, config));
+    }
+    check_outputs(&collections)?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.parallel.max(1))
+        .build()?;
+    let runs: Vec<CollectionRun> = pool.install(|| {
+        collections
+            .into_par_iter()
+            .map(|(path, config)| run_collection(&path, config, &args.generate))
+            .collect()
+    });
+
+    let total = runs.len();
+    let mut failures = Vec::new();
+    for run in runs {
+        match run.result {
+            Ok(()) => info!(
+                "{}: {} tokens in {} ({:.1?}).",
+                run.config, run.supply, run.output_path, run.elapsed
+            ),
+            Err(error) => {
+                warn!("{}: failed: {}", run.config, error);
+                failures.push((run.config, error));
+            }
+        }
+    }
+    info!(
+        "{} of {} collections generated.",
+        total - failures.len(),
+        total
+    );
+
+    let failed = failures.len();
+    match failures.into_iter().next() {
+        Some((config, source)) => Err(CustomError::Batch {
+            failed,
+            total,
+            config,
+            source: Box::new(source),
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_config_paths() {
+        let dir = tempdir().unwrap();
+        let drops = dir.path().join("drops");
+        fs::create_dir_all(&drops).unwrap();
+        for name in ["b.json", "a.json", "notes.txt"] {
+            fs::write(drops.join(name), "{}").unwrap();
+        }
+        let a = drops.join("a.json").display().to_string();
+        let b = drops.join("b.json").display().to_string();
+
+        let paths = config_paths(&[drops.display().to_string(), a.clone()]).unwrap();
+        assert_eq!(paths, vec![a, b]);
+        assert!(config_paths(&[dir.path().display().to_string()]).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::paths::LayerLayout;
+use crate::{is_file_asset, path_share, path_trait, path_weight, weight_annotation, CustomError};
+
+/// What the `#N` in a file name means.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum WeightMode {
+    /// A relative weight: the value is picked with probability N / total.
+    #[default]
+    Weight,
+    /// An exact count: N tokens get the value.
+    Quota,
+}
+
+/// "At most `max` tokens have `value` in `layer`."
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+pub struct TraitCap {
+    pub layer: String,
+    pub value: String,
+    pub max: usize,
+}
+
+/// The caps of a run and how many tokens already use each capped trait. One
+/// instance is shared by every pool, so a cap covers the whole collection.
+#[derive(Debug, Default, Clone)]
+pub struct Caps {
+    limits: HashMap<(String, String), usize>,
+    counts: HashMap<(String, String), usize>,
+}
+
+impl Caps {
+    pub fn compile(caps: &[TraitCap], layer_names: &[String]) -> Result<Caps, CustomError> {
+        let mut limits = HashMap::new();
+        for cap in caps {
+            if !layer_names.contains(&cap.layer) {
+                return Err(CustomError::InvalidTrait(format!(
+                    "trait cap references unknown layer {}",
+                    cap.layer
+                )));
+            }
+            let limit = limits
+                .entry((cap.layer.clone(), cap.value.clone()))
+                .or_insert(cap.max);
+            *limit = (*limit).min(cap.max);
+        }
+
+        Ok(Caps {
+            limits,
+            counts: HashMap::new(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    /// How many more tokens may have the trait, or `None` when it isn't capped.
+    pub fn remaining(&self, trait_pair: &(String, String)) -> Option<usize> {
+        let limit = self.limits.get(trait_pair)?;
+        let count = self.counts.get(trait_pair).copied().unwrap_or(0);
+        Some(limit.saturating_sub(count))
+    }
+
+    pub fn is_full(&self, trait_pair: &(String, String)) -> bool {
+        self.remaining(trait_pair) == Some(0)
+    }
+
+    /// Counts the traits of an accepted token.
+    pub fn record(&mut self, traits: &[(String, String)]) {
+        for trait_pair in traits {
+            if self.limits.contains_key(trait_pair) {
+                *self.counts.entry(trait_pair.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    /// Every token takes one value from each non-empty layer, so a layer whose
+    /// values are all capped can't be part of more tokens than its caps allow.
+    pub fn check_reachable(
+        &self,
+        layout: &LayerLayout,
+        layers: &[Vec<String>],
+        supply: usize,
+    ) -> Result<(), CustomError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        for layer in layers {
+            let traits: Option<BTreeSet<(String, String)>> =
+                layer.iter().map(|path| path_trait(layout, path)).collect();
+            let Some(traits) = traits.filter(|traits| !traits.is_empty()) else {
+                continue;
+            };
+
+            let remaining: Option<usize> = traits
+                .iter()
+                .map(|trait_pair| self.remaining(trait_pair))
+                .sum();
+            if let Some(remaining) = remaining.filter(|&remaining| remaining < supply) {
+                let (layer_name, _) = traits.first().unwrap();
+                return Err(CustomError::TraitCap(format!(
+                    "the caps of layer {} allow {} more tokens, {} are needed",
+                    layer_name, remaining, supply
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The count the `#N` of a file name stands for in quota mode. A percentage or
+/// ratio is that share of the supply, rounded.
+pub fn file_name_weight(layout: &LayerLayout, path: &str, supply: usize) -> usize {
+    if !is_file_asset(path) {
+        return path_weight(layout, path) as usize;
+    }
+    if let Some(share) = path_share(layout, path) {
+        return (share * supply as f64 / 100.0).round() as usize;
+    }
+    weight_annotation(layout, path)
+        .and_then(|weight| weight.parse().ok())
+        .unwrap_or(0)
+}
+
+/// In quota mode every value gets a cap of its `#N`. A layer's quotas have to
+/// add up to the supply, so every cap is reached and every count is exact.
+pub fn quota_caps(
+    layout: &LayerLayout,
+    layers: &[Vec<String>],
+    supply: usize,
+) -> Result<Vec<TraitCap>, CustomError> {
+    let mut caps = Vec::new();
+    for layer in layers.iter().filter(|layer| !layer.is_empty()) {
+        let mut quotas: BTreeMap<(String, String), usize> = BTreeMap::new();
+        for path in layer {
+            if let Some(trait_pair) = path_trait(layout, path) {
+                *quotas.entry(trait_pair).or_default() += file_name_weight(layout, path, supply);
+            }
+        }
+
+        let total: usize = quotas.values().sum();
+        if let Some(((layer_name, _), _)) = quotas.first_key_value() {
+            if total != supply {
+                return Err(CustomError::TraitCap(format!(
+                    "the quotas of layer {} add up to {}, the total supply is {}",
+                    layer_name, total, supply
+                )));
+            }
+        }
+
+        caps.extend(
+            quotas
+                .into_iter()
+                .map(|((layer, value), max)| TraitCap { layer, value, max }),
+        );
+    }
+    Ok(caps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(layer: &str, value: &str) -> (String, String) {
+        (layer.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_caps() {
+        let caps: Vec<TraitCap> = serde_json::from_str(
+            r#"[{"layer": "Background", "value": "Gold", "max": 1},
+                {"layer": "Background", "value": "Blue", "max": 2}]"#,
+        )
+        .unwrap();
+        let names = vec!["Background".to_string()];
+        let layers = vec![vec![
+            "./images/Background/Gold#1.png".to_string(),
+            "./images/Background/Blue#10.png".to_string(),
+        ]];
+
+        let mut compiled = Caps::compile(&caps, &names).unwrap();
+        assert!(compiled
+            .check_reachable(&LayerLayout::default(), &layers, 3)
+            .is_ok());
+        assert!(compiled
+            .check_reachable(&LayerLayout::default(), &layers, 4)
+            .is_err());
+
+        compiled.record(&[pair("Background", "Gold"), pair("Eyes", "Laser")]);
+        assert!(compiled.is_full(&pair("Background", "Gold")));
+        assert!(!compiled.is_full(&pair("Background", "Blue")));
+        assert!(!compiled.is_full(&pair("Eyes", "Laser")));
+        assert!(compiled
+            .check_reachable(&LayerLayout::default(), &layers, 3)
+            .is_err());
+
+        let uncapped = vec![vec![
+            "./images/Background/Gold#1.png".to_string(),
+            "./images/Background/Red.png".to_string(),
+        ]];
+        assert!(compiled
+            .check_reachable(&LayerLayout::default(), &uncapped, 100)
+            .is_ok());
+        assert!(Caps::compile(&caps, &[]).is_err());
+    }
+
+    #[test]
+    fn test_quota_caps() {
+        let layers = vec![
+            vec![
+                "./images/Background/Gold#1.png".to_string(),
+                "./images/Background/Blue#3.png".to_string(),
+            ],
+            vec![
+                "./images/Hair/Black#700/Long#2.png".to_string(),
+                "./images/Hair/Red#300/Long#1.png".to_string(),
+                "./images/Hair/Red#300/Short#1.png".to_string(),
+            ],
+        ];
+
+        let caps = quota_caps(&LayerLayout::default(), &layers, 4).unwrap();
+        assert_eq!(caps.len(), 4);
+        assert!(caps.contains(&TraitCap {
+            layer: "Hair".to_string(),
+            value: "Long".to_string(),
+            max: 3,
+        }));
+        assert!(quota_caps(&LayerLayout::default(), &layers, 5).is_err());
+        assert_eq!(
+            file_name_weight(
+                &LayerLayout::default(),
+                "./images/Background/Gold#10%.png",
+                40
+            ),
+            4
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use clap::{ArgAction, Args, Parser, Subcommand};
+
+use crate::base_uri::SetBaseUriArgs;
+use crate::batch::BatchArgs;
+use crate::contrast::CheckContrastArgs;
+use crate::estimate::EstimateArgs;
+use crate::explore::ExploreArgs;
+use crate::export::ExportFormat;
+use crate::golden::CompareGoldenArgs;
+use crate::init::InitArgs;
+use crate::layer_pack::{ImportLayersArgs, PackLayersArgs};
+use crate::lint::LintMetadataArgs;
+use crate::overrides::OverrideArgs;
+use crate::palette::CheckPaletteArgs;
+use crate::preview::PreviewArgs;
+use crate::rename::RenameTraitsArgs;
+use crate::replay::ReplayArgs;
+use crate::rerender::RerenderChangedArgs;
+use crate::reroll::RerollArgs;
+use crate::upload::UploadTarget;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Generates NFT art and metadata from image layers")]
+pub struct Cli {
+    /// Path to the collection config file.
+    #[arg(short, long, global = true, default_value = "config.json")]
+    pub config: String,
+
+    /// Run folder under the output path to work on, for collections generated
+    /// with an output namespace. Defaults to the latest run.
+    #[arg(long, global = true)]
+    pub run: Option<String>,
+
+    /// Print warnings only, no progress.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log each stage and token with timings; `-vv` logs every draw too.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    #[command(flatten)]
+    pub overrides: OverrideArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Write a starter config, with example layers and a dependency rule, to the config path.
+    Init(InitArgs),
+    /// Generate the collection described by the config file (default).
+    Generate(GenerateArgs),
+    /// Generate several collections, each from its own config file, and print a summary.
+    Batch(BatchArgs),
+    /// Check the config and layers and print how many permutations are possible, without generating.
+    Validate,
+    /// Print the JSON Schema of the config file, for editors to validate and complete it.
+    Schema,
+    /// Render a few sample tokens and estimate the disk usage and render time of the full run.
+    Estimate(EstimateArgs),
+    /// Sample the collection under several seeds and compare their rarity and sample renders.
+    Explore(ExploreArgs),
+    /// Render a handful of random tokens into `preview/`, ignoring the supply and duplicates, to check the layers quickly.
+    Preview(PreviewArgs),
+    /// Browse the layers, tweak weights and supply, and run and follow a generation in the terminal.
+    Tui,
+    /// Upload the generated output to a storage backend.
+    Upload {
+        #[command(subcommand)]
+        target: UploadTarget,
+    },
+    /// Render with a fixed seed and compare the result against a stored golden set.
+    CompareGolden(CompareGoldenArgs),
+    /// Rewrite trait values in the generated metadata using a mapping file.
+    RenameTraits(RenameTraitsArgs),
+    /// Re-render only the tokens drawn with layer files changed since the last run.
+    RerenderChanged(RerenderChangedArgs),
+    /// Flag generated images, or layer files, with pixels outside the configured palette.
+    CheckPalette(CheckPaletteArgs),
+    /// Flag foreground assets that are hard to see on some background.
+    CheckContrast(CheckContrastArgs),
+    /// Export the attributes of the generated tokens for review outside JSON.
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Check the generated metadata files against the OpenSea and EIP-721 expectations.
+    LintMetadata(LintMetadataArgs),
+    /// Pack the layer folders, with their manifests and license, into a single `.nftlayers` file.
+    PackLayers(PackLayersArgs),
+    /// Check a `.nftlayers` file and extract its layer folders into the base path.
+    ImportLayers(ImportLayersArgs),
+    /// Sample the collection again with the seed of its replay log and check every draw and token DNA.
+    Replay(ReplayArgs),
+    /// Draw new unique combinations for some tokens of the last run and render just those.
+    Reroll(RerollArgs),
+    /// Point the `image` and `animation_url` of every generated metadata file at a new base URI.
+    SetBaseUri(SetBaseUriArgs),
+}
+
+#[derive(Debug, Args, Default, Clone)]
+pub struct GenerateArgs {
+    /// Pick the tokens and write `stats.json` without rendering anything.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Write this run to its own folder under the output path, overriding `outputNamespace`.
+    #[arg(long)]
+    pub namespace: Option<String>,
+
+    /// Render even when the estimated output doesn't fit in the free disk space.
+    #[arg(long)]
+    pub ignore_space: bool,
+
+    /// Tokens composited at the same time, one per CPU by default.
+    #[arg(long)]
+    pub jobs: Option<usize>,
+
+    /// Threads writing images and metadata to disk while compositing goes on, 2 by default.
+    #[arg(long)]
+    pub io_jobs: Option<usize>,
+
+    /// Draw the `watermark` of the config over every image, for previews shared before the release.
+    #[arg(long)]
+    pub watermark: bool,
+}
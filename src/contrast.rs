@@ -0,0 +1,233 @@
+use clap::Args;
+use image::RgbaImage;
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::error::Error;
+
+use crate::paths::LayerLayout;
+use crate::{
+    is_image_asset, layer_layout, layer_names, load_layers, path_trait, Config, CustomError,
+};
+
+#[derive(Debug, Args)]
+pub struct CheckContrastArgs {
+    /// Contrast ratio below which a pair is flagged, overriding the config.
+    #[arg(long)]
+    pub min_contrast: Option<f64>,
+}
+
+/// Layers whose assets are checked against every asset of `background`.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ContrastCheck {
+    pub background: String,
+    pub foreground: Vec<String>,
+    /// Minimum contrast ratio, from 1 (identical colors) to 21 (black on white).
+    pub min_contrast: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LowContrast {
+    pub background: String,
+    pub foreground: String,
+    pub contrast: f64,
+}
+
+/// Relative luminance of an sRGB color, as defined by WCAG.
+fn luminance(pixel: &[u8]) -> f64 {
+    let channel = |value: u8| {
+        let value = value as f64 / 255.0;
+        if value <= 0.03928 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * channel(pixel[0]) + 0.7152 * channel(pixel[1]) + 0.0722 * channel(pixel[2])
+}
+
+/// Contrast ratio between the visible pixels of `foreground` and the pixels of
+/// `background` under them, comparing their average luminance. An invisible
+/// foreground has no contrast problem and gets the maximum ratio.
+pub fn contrast_ratio(background: &RgbaImage, foreground: &RgbaImage) -> f64 {
+    let mut visible = 0u64;
+    let mut foreground_luminance = 0.0;
+    let mut background_luminance = 0.0;
+    for (x, y, pixel) in foreground.enumerate_pixels() {
+        if pixel.0[3] == 0 || x >= background.width() || y >= background.height() {
+            continue;
+        }
+        visible += 1;
+        foreground_luminance += luminance(&pixel.0);
+        background_luminance += luminance(&background.get_pixel(x, y).0);
+    }
+    if visible == 0 {
+        return 21.0;
+    }
+
+    let foreground_luminance = foreground_luminance / visible as f64;
+    let background_luminance = background_luminance / visible as f64;
+    let (lighter, darker) = if foreground_luminance > background_luminance {
+        (foreground_luminance, background_luminance)
+    } else {
+        (background_luminance, foreground_luminance)
+    };
+    (lighter + 0.05) / (darker + 0.05)
+}
+
+fn open_layer(layout: &LayerLayout, path: &String) -> Result<(String, RgbaImage), CustomError> {
+    let image = image::open(path)
+        .map_err(|e| CustomError::Contrast(format!("{}: {}", path, e)))?
+        .to_rgba8();
+    let name = path_trait(layout, path)
+        .map(|(trait_type, value)| format!("{}/{}", trait_type, value))
+        .unwrap_or_else(|| path.clone());
+    Ok((name, image))
+}
+
+/// Every background and foreground asset pair whose contrast is below
+/// `min_contrast`, lowest contrast first.
+pub fn find_low_contrast(
+    layout: &LayerLayout,
+    backgrounds: &[String],
+    foregrounds: &[String],
+    min_contrast: f64,
+) -> Result<Vec<LowContrast>, CustomError> {
+    let backgrounds = backgrounds
+        .par_iter()
+        .map(|path| open_layer(layout, path))
+        .collect::<Result<Vec<_>, CustomError>>()?;
+    let foregrounds = foregrounds
+        .par_iter()
+        .map(|path| open_layer(layout, path))
+        .collect::<Result<Vec<_>, CustomError>>()?;
+
+    let mut low_contrast: Vec<LowContrast> = backgrounds
+        .par_iter()
+        .flat_map(|background| {
+            foregrounds
+                .par_iter()
+                .map(move |foreground| (background, foreground))
+        })
+        .filter_map(
+            |((background, background_image), (foreground, foreground_image))| {
+                let contrast = contrast_ratio(background_image, foreground_image);
+                (contrast < min_contrast).then(|| LowContrast {
+                    background: background.clone(),
+                    foreground: foreground.clone(),
+                    contrast,
+                })
+            },
+        )
+        .collect();
+    low_contrast.sort_by(|a, b| a.contrast.total_cmp(&b.contrast));
+    Ok(low_contrast)
+}
+
+pub fn run(args: &CheckContrastArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let check = config
+        .contrast_check
+        .as_ref()
+        .ok_or_else(|| CustomError::Contrast("the config has no contrastCheck".to_string()))?;
+    let min_contrast = args.min_contrast.unwrap_or(check.min_contrast);
+
+    let names = layer_names(config);
+    let layers = load_layers(config)?;
+    let layer = |name: &String| {
+        names
+            .iter()
+            .position(|layer_name| layer_name == name)
+            .map(|index| {
+                layers[index]
+                    .iter()
+                    .filter(|path| is_image_asset(path))
+                    .cloned()
+                    .collect::<Vec<String>>()
+            })
+            .ok_or_else(|| {
+                CustomError::InvalidTrait(format!(
+                    "contrastCheck references unknown layer {}",
+                    name
+                ))
+            })
+    };
+
+    let backgrounds = layer(&check.background)?;
+    let mut foregrounds = Vec::new();
+    for name in &check.foreground {
+        foregrounds.extend(layer(name)?);
+    }
+
+    let low_contrast = find_low_contrast(
+        &layer_layout(config),
+        &backgrounds,
+        &foregrounds,
+        min_contrast,
+    )?;
+    for pair in &low_contrast {
+        println!(
+            "{} on {}: contrast {:.2}.",
+            pair.foreground, pair.background, pair.contrast
+        );
+    }
+
+    let pairs = backgrounds.len() * foregrounds.len();
+    if low_contrast.is_empty() {
+        println!(
+            "All {} pairs have a contrast of at least {}.",
+            pairs, min_contrast
+        );
+        Ok(())
+    } else {
+        Err(CustomError::Contrast(format!(
+            "{} of {} pairs are below a contrast of {}",
+            low_contrast.len(),
+            pairs,
+            min_contrast
+        ))
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_contrast_ratio() {
+        let black = RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 255]));
+        let white = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255]));
+        let mut silhouette = RgbaImage::from_pixel(2, 2, Rgba([10, 10, 10, 0]));
+        silhouette.put_pixel(0, 0, Rgba([10, 10, 10, 255]));
+
+        assert!((contrast_ratio(&black, &white) - 21.0).abs() < 1e-9);
+        assert!(contrast_ratio(&black, &silhouette) < 1.1);
+        assert!(contrast_ratio(&white, &silhouette) > 15.0);
+
+        let dir = tempdir().unwrap();
+        let layer_path = |layer: &str, value: &str| {
+            let layer_dir = dir.path().join("images").join(layer);
+            std::fs::create_dir_all(&layer_dir).unwrap();
+            layer_dir
+                .join(format!("{}.png", value))
+                .display()
+                .to_string()
+        };
+        let black_path = layer_path("Background", "Black");
+        let white_path = layer_path("Background", "White");
+        let silhouette_path = layer_path("Body", "Shadow");
+        black.save(&black_path).unwrap();
+        white.save(&white_path).unwrap();
+        silhouette.save(&silhouette_path).unwrap();
+
+        let layout = LayerLayout::new(&dir.path().join("images").display().to_string());
+        let low_contrast =
+            find_low_contrast(&layout, &[black_path, white_path], &[silhouette_path], 1.5).unwrap();
+        assert_eq!(low_contrast.len(), 1);
+        assert!(low_contrast[0].background.ends_with("/Black"));
+        assert!(low_contrast[0].foreground.ends_with("/Shadow"));
+    }
+}
@@ -0,0 +1,128 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashSet;
+use tracing::warn;
+
+use crate::paths::LayerLayout;
+use crate::{is_image_asset, path_trait, CustomError};
+
+/// What a layer file whose size differs from the canvas does to a run.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum DimensionPolicy {
+    #[default]
+    Warn,
+    Fail,
+    Ignore,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DimensionMismatch {
+    pub file: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The layer files whose size isn't `canvas`, read from their headers.
+/// `exempt_layers` are scaled or moved on purpose and left out.
+pub fn find_mismatches(
+    layout: &LayerLayout,
+    all_layers: &[Vec<String>],
+    canvas: (u32, u32),
+    exempt_layers: &HashSet<String>,
+) -> Result<Vec<DimensionMismatch>, CustomError> {
+    let mut mismatches = Vec::new();
+    for path in all_layers.iter().flatten() {
+        if !is_image_asset(path)
+            || path_trait(layout, path).is_some_and(|(layer, _)| exempt_layers.contains(&layer))
+        {
+            continue;
+        }
+        let (width, height) = image::image_dimensions(path)
+            .map_err(|e| CustomError::Dimensions(format!("{}: {}", path, e)))?;
+        if (width, height) != canvas {
+            mismatches.push(DimensionMismatch {
+                file: path.clone(),
+                width,
+                height,
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Reports the mismatched layer files as `policy` asks: printed as warnings,
+/// or an error listing them.
+pub fn report_mismatches(
+    mismatches: &[DimensionMismatch],
+    canvas: (u32, u32),
+    policy: DimensionPolicy,
+) -> Result<(), CustomError> {
+    if mismatches.is_empty() || policy == DimensionPolicy::Ignore {
+        return Ok(());
+    }
+    let describe = |mismatch: &DimensionMismatch| {
+        format!(
+            "{} is {}x{}",
+            mismatch.file, mismatch.width, mismatch.height
+        )
+    };
+    if policy == DimensionPolicy::Fail {
+        return Err(CustomError::Dimensions(format!(
+            "the canvas is {}x{} but {}",
+            canvas.0,
+            canvas.1,
+            mismatches
+                .iter()
+                .map(describe)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    for mismatch in mismatches {
+        warn!(
+            "{}, the canvas is {}x{}.",
+            describe(mismatch),
+            canvas.0,
+            canvas.1
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find_mismatches() {
+        let dir = tempdir().unwrap();
+        let layer_dir = dir.path().join("Eyes");
+        std::fs::create_dir_all(&layer_dir).unwrap();
+        let laser = layer_dir.join("Laser.png").display().to_string();
+        let round = layer_dir.join("Round.png").display().to_string();
+        RgbaImage::new(8, 8).save(&laser).unwrap();
+        RgbaImage::new(8, 4).save(&round).unwrap();
+        let layers = vec![vec![laser, round.clone()]];
+
+        let layout = LayerLayout::new(&dir.path().display().to_string());
+        let mismatches = find_mismatches(&layout, &layers, (8, 8), &HashSet::new()).unwrap();
+        let exempt = HashSet::from(["Eyes".to_string()]);
+        assert!(find_mismatches(&layout, &layers, (8, 8), &exempt)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            mismatches,
+            vec![DimensionMismatch {
+                file: round,
+                width: 8,
+                height: 4
+            }]
+        );
+        assert!(report_mismatches(&mismatches, (8, 8), DimensionPolicy::Warn).is_ok());
+        assert!(report_mismatches(&mismatches, (8, 8), DimensionPolicy::Fail).is_err());
+        assert!(report_mismatches(&[], (8, 8), DimensionPolicy::Fail).is_ok());
+    }
+}
@@ -0,0 +1,136 @@
+#[cfg(test)]
+use std::cell::Cell;
+use std::io;
+use std::path::Path;
+use tracing::warn;
+
+use crate::estimate::format_bytes;
+use crate::CustomError;
+
+/// Below this share of headroom over the estimate, a run that fits is still
+/// warned about, since the estimate is extrapolated from a few samples.
+const SOFT_MARGIN: f64 = 0.1;
+
+#[cfg(test)]
+thread_local! {
+    /// The free space every disk has for the tests of the current thread, when
+    /// set.
+    pub static FREE_SPACE: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// The free space of the disk of `folder`.
+fn available_space(folder: &Path) -> io::Result<u64> {
+    #[cfg(test)]
+    if let Some(free_space) = FREE_SPACE.with(Cell::get) {
+        return Ok(free_space);
+    }
+    fs2::available_space(folder)
+}
+
+/// The closest folder of `path` that exists, where its free space is read.
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(Path::new("."))
+}
+
+/// Whether `needed` bytes fit in `available`: an error when they don't, or a
+/// warning when they barely fit or `ignore_space` is set.
+pub fn check_space(
+    needed: u64,
+    available: u64,
+    ignore_space: bool,
+) -> Result<Option<String>, CustomError> {
+    let message = format!(
+        "about {} needed, {} free",
+        format_bytes(needed),
+        format_bytes(available)
+    );
+    if needed > available {
+        if ignore_space {
+            return Ok(Some(message));
+        }
+        return Err(CustomError::DiskSpace(format!(
+            "{}; pass --ignore-space to try anyway",
+            message
+        )));
+    }
+    if needed as f64 * (1.0 + SOFT_MARGIN) > available as f64 {
+        return Ok(Some(message));
+    }
+    Ok(None)
+}
+
+/// Checks that `needed` bytes can be written to `path`, counting `reclaimed`
+/// bytes the command removes first, and prints a warning when it's close.
+pub fn ensure_space(
+    path: &Path,
+    needed: u64,
+    reclaimed: u64,
+    ignore_space: bool,
+) -> Result<(), CustomError> {
+    let folder = existing_ancestor(path);
+    let available = available_space(folder)
+        .map_err(|e| CustomError::DiskSpace(format!("{}: {}", folder.display(), e)))?;
+    if let Some(warning) = check_space(needed, available.saturating_add(reclaimed), ignore_space)? {
+        warn!("low disk space in {}: {}.", folder.display(), warning);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::GenerateArgs;
+    use crate::init::{scaffold, Answers};
+    use crate::{generate, load_config, CustomError};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_space() {
+        assert_eq!(check_space(100, 1000, false), Ok(None));
+        assert!(check_space(950, 1000, false).unwrap().is_some());
+        assert!(check_space(2000, 1000, false).is_err());
+        assert!(check_space(2000, 1000, true).unwrap().is_some());
+        assert_eq!(
+            existing_ancestor(Path::new("/tmp/does/not/exist")),
+            Path::new("/tmp")
+        );
+    }
+
+    #[test]
+    fn test_generate_out_of_space() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        let base_path = dir.path().join("images").display().to_string();
+        let answers = Answers {
+            name: "Space".to_string(),
+            supply: 4,
+            width: 8,
+            height: 8,
+        };
+        scaffold(&config_path, &base_path, &answers, false).unwrap();
+        let mut config = load_config(&config_path.display().to_string(), &[]).unwrap();
+        config.output_path = dir.path().join("output").display().to_string();
+
+        FREE_SPACE.with(|free_space| free_space.set(Some(1)));
+        let error = generate(config.clone(), &GenerateArgs::default()).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<CustomError>(),
+            Some(CustomError::DiskSpace(_))
+        ));
+        assert!(fs::read_dir(&config.output_path).unwrap().all(|entry| entry
+            .unwrap()
+            .path()
+            .extension()
+            .is_none_or(|ext| ext != "png")));
+
+        let args = GenerateArgs {
+            ignore_space: true,
+            ..GenerateArgs::default()
+        };
+        generate(config, &args).unwrap();
+        FREE_SPACE.with(|free_space| free_space.set(None));
+    }
+}
@@ -0,0 +1,210 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::paths::LayerLayout;
+use crate::{trait_path_parts, write_json, CustomError};
+
+pub const DNA_FILE_NAME: &str = "dna.json";
+
+/// Hash used for DNAs. Both are stable across Rust releases and platforms, so
+/// DNAs recorded by one build can be compared with the ones of another.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum DnaAlgorithm {
+    #[default]
+    Sha256,
+    Xxh64,
+}
+
+impl DnaAlgorithm {
+    fn hash(&self, input: &[u8]) -> String {
+        match self {
+            DnaAlgorithm::Sha256 => format!("{:x}", Sha256::digest(input)),
+            DnaAlgorithm::Xxh64 => format!("{:016x}", xxh64(input, 0)),
+        }
+    }
+}
+
+impl fmt::Display for DnaAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnaAlgorithm::Sha256 => write!(f, "sha256"),
+            DnaAlgorithm::Xxh64 => write!(f, "xxh64"),
+        }
+    }
+}
+
+/// Whether the DNAs of tokens burned or removed since a previous run may be
+/// generated again.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum DnaReissue {
+    /// Every DNA ever issued stays excluded.
+    #[default]
+    Never,
+    /// Only the DNAs of `previousDnaFiles`, the live tokens, are excluded.
+    AllowRemoved,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DnaEntry {
+    pub token_id: usize,
+    pub dna: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DnaIndex {
+    pub algorithm: DnaAlgorithm,
+    pub tokens: Vec<DnaEntry>,
+}
+
+pub fn trait_pairs(layout: &LayerLayout, image_paths: &[String]) -> Vec<(String, String)> {
+    image_paths
+        .iter()
+        .filter_map(|path| {
+            let parts = trait_path_parts(layout, path);
+            let (layer, value) = parts.split_first()?;
+            Some((layer.clone(), value.join("/")))
+        })
+        .collect()
+}
+
+/// Hashes the `layer=value` lines of a token, one per layer in drawing order.
+pub fn compute_dna(
+    algorithm: DnaAlgorithm,
+    layout: &LayerLayout,
+    image_paths: &[String],
+) -> String {
+    let canonical: Vec<String> = trait_pairs(layout, image_paths)
+        .into_iter()
+        .map(|(layer, value)| format!("{}={}", layer, value))
+        .collect();
+    algorithm.hash(canonical.join("\n").as_bytes())
+}
+
+/// The DNA standing for a 1/1 token, which has no layers: the hash of its name.
+pub fn one_of_one_dna(algorithm: DnaAlgorithm, name: &str) -> String {
+    algorithm.hash(name.as_bytes())
+}
+
+pub fn write_dna_index(output_path: &Path, index: &DnaIndex) -> Result<(), CustomError> {
+    let file_path = output_path.join(DNA_FILE_NAME);
+    write_json(&file_path, index)
+}
+
+/// Loads the DNAs of previous runs. They must have been computed with
+/// `algorithm`, otherwise none of them could ever match.
+pub fn load_dna_files(
+    paths: &[String],
+    algorithm: DnaAlgorithm,
+) -> Result<HashSet<String>, CustomError> {
+    let mut dnas = HashSet::new();
+    for path in paths {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| CustomError::InvalidDna(format!("{}: {}", path, e)))?;
+        let index: DnaIndex = match serde_json::from_str(&contents) {
+            Ok(index) => index,
+            Err(_) if serde_json::from_str::<Vec<DnaEntry>>(&contents).is_ok() => {
+                return Err(CustomError::InvalidDna(format!(
+                    "{}: written by a version without a stable DNA hash, regenerate it",
+                    path
+                )));
+            }
+            Err(e) => return Err(CustomError::InvalidDna(format!("{}: {}", path, e))),
+        };
+        if index.algorithm != algorithm {
+            return Err(CustomError::InvalidDna(format!(
+                "{}: uses {} DNAs but the config uses {}",
+                path, index.algorithm, algorithm
+            )));
+        }
+        dnas.extend(index.tokens.into_iter().map(|entry| entry.dna));
+    }
+    Ok(dnas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compute_dna() {
+        let token = vec![
+            "./images/Background/Blue#50.png".to_string(),
+            "./images/Hair/Black#700/Style2#25.png".to_string(),
+        ];
+        let reweighted = vec![
+            "./images/Background/Blue#10.png".to_string(),
+            "./images/Hair/Black#700/Style2#5.png".to_string(),
+        ];
+        let other_folder = vec![
+            "./images/Background/Blue#50.png".to_string(),
+            "./images/Hair/Red#700/Style2#25.png".to_string(),
+        ];
+
+        let layout = LayerLayout::new("./images");
+        assert_eq!(
+            trait_pairs(&layout, &token),
+            vec![
+                ("Background".to_string(), "Blue".to_string()),
+                ("Hair".to_string(), "Black/Style2".to_string()),
+            ]
+        );
+        let sha256 = DnaAlgorithm::Sha256;
+        assert_eq!(
+            compute_dna(sha256, &layout, &token),
+            compute_dna(sha256, &layout, &reweighted)
+        );
+        assert_ne!(
+            compute_dna(sha256, &layout, &token),
+            compute_dna(sha256, &layout, &other_folder)
+        );
+        assert_eq!(
+            compute_dna(sha256, &layout, &token),
+            format!("{:x}", Sha256::digest("Background=Blue\nHair=Black/Style2"))
+        );
+        assert_eq!(compute_dna(DnaAlgorithm::Xxh64, &layout, &token).len(), 16);
+    }
+
+    #[test]
+    fn test_write_and_load_dna_index() {
+        let dir = tempdir().unwrap();
+        let index = DnaIndex {
+            algorithm: DnaAlgorithm::Xxh64,
+            tokens: vec![
+                DnaEntry {
+                    token_id: 0,
+                    dna: "00000000000000aa".to_string(),
+                },
+                DnaEntry {
+                    token_id: 1,
+                    dna: "00000000000000bb".to_string(),
+                },
+            ],
+        };
+
+        write_dna_index(dir.path(), &index).unwrap();
+        let index_paths = vec![dir.path().join(DNA_FILE_NAME).display().to_string()];
+        let loaded = load_dna_files(&index_paths, DnaAlgorithm::Xxh64).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.contains("00000000000000aa"));
+        assert!(load_dna_files(&index_paths, DnaAlgorithm::Sha256).is_err());
+        assert!(load_dna_files(&["missing.json".to_string()], DnaAlgorithm::Sha256).is_err());
+
+        let legacy_path = dir.path().join("legacy.json");
+        fs::write(&legacy_path, r#"[{"tokenId": 0, "dna": "aa"}]"#).unwrap();
+        assert!(
+            load_dna_files(&[legacy_path.display().to_string()], DnaAlgorithm::Sha256).is_err()
+        );
+    }
+}
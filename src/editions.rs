@@ -0,0 +1,192 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use tracing::{info, info_span};
+
+use crate::cli::GenerateArgs;
+use crate::{dna, generate, paths, validate, Config, CustomError};
+
+/// A part of the collection with its own supply, drawn after the editions
+/// listed before it and numbered after them.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Edition {
+    pub name: String,
+    pub supply: u32,
+    /// The `layerFolders` the edition draws from, all of them by default.
+    pub layers: Option<Vec<String>>,
+    /// Entries added to, or replacing, the config `metadata`.
+    #[serde(default)]
+    pub metadata: HashMap<String, Value>,
+    /// Folder under `outputPath` the edition is written to, its name by
+    /// default.
+    pub output_folder: Option<String>,
+    /// Replaces the config `imageUrl`, e.g. when editions are uploaded apart.
+    pub image_url: Option<String>,
+}
+
+impl Edition {
+    pub fn output_folder(&self) -> &str {
+        self.output_folder.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// The config of every edition: its supply, token ids following the previous
+/// editions, layers, metadata and output folder.
+pub fn edition_configs(config: &Config) -> Result<Vec<(String, Config)>, CustomError> {
+    let supply: u32 = config.editions.iter().map(|edition| edition.supply).sum();
+    if supply != config.total_supply {
+        return Err(CustomError::Config(format!(
+            "editions add up to {} tokens but totalSupply is {}",
+            supply, config.total_supply
+        )));
+    }
+    if config.reserved_supply.is_some()
+        || config.one_of_ones.is_some()
+        || !config.fixed_tokens.is_empty()
+        || !config.unique_combinations.is_empty()
+    {
+        return Err(CustomError::Config(
+            "editions can't be combined with reservedSupply, oneOfOnes, fixedTokens or uniqueCombinations"
+                .to_string(),
+        ));
+    }
+
+    let layer_names: Vec<&str> = config
+        .layer_folders
+        .iter()
+        .map(|layer_folder| layer_folder.name())
+        .collect();
+    let base_path = regex::escape(&paths::portable(&config.base_path));
+    let mut start_index = config.start_index;
+    let mut configs = Vec::new();
+    for edition in &config.editions {
+        let mut edition_config = config.clone();
+        edition_config.editions = Vec::new();
+        edition_config.total_supply = edition.supply;
+        edition_config.start_index = start_index;
+        edition_config.output_path = Path::new(&config.output_path)
+            .join(edition.output_folder())
+            .display()
+            .to_string();
+        edition_config.metadata.extend(edition.metadata.clone());
+        if let Some(image_url) = &edition.image_url {
+            edition_config.image_url = image_url.clone();
+        }
+
+        // Layers left out are skipped, so they are drawn empty.
+        if let Some(layers) = &edition.layers {
+            if let Some(unknown) = layers
+                .iter()
+                .find(|layer| !layer_names.contains(&layer.as_str()))
+            {
+                return Err(CustomError::Config(format!(
+                    "edition {}: {} is not a layer in layerFolders",
+                    edition.name, unknown
+                )));
+            }
+            let skipped_traits = edition_config.skipped_traits.get_or_insert_with(Vec::new);
+            skipped_traits.extend(
+                config
+                    .layer_folders
+                    .iter()
+                    .filter(|layer_folder| !layers.iter().any(|layer| layer == layer_folder.name()))
+                    .map(|layer_folder| match layer_folder.path() {
+                        Some(path) => format!("^{}/", regex::escape(&paths::portable(path))),
+                        None => format!(
+                            "^{}/+{}(#[^/]*)?/",
+                            base_path,
+                            regex::escape(layer_folder.name())
+                        ),
+                    }),
+            );
+        }
+
+        start_index += edition.supply as usize;
+        configs.push((edition.name.clone(), edition_config));
+    }
+    Ok(configs)
+}
+
+/// Generates the editions in order, each excluding the DNAs of the ones
+/// generated before it.
+pub fn generate_editions(config: &Config, args: &GenerateArgs) -> Result<(), Box<dyn Error>> {
+    let mut generated_dna_files = Vec::new();
+    for (name, mut edition_config) in edition_configs(config)? {
+        let _span = info_span!("edition", name = name.as_str()).entered();
+        info!(
+            "Edition {}: tokens {} to {} in {}.",
+            name,
+            edition_config.start_index,
+            edition_config.start_index + edition_config.total_supply as usize - 1,
+            edition_config.output_path
+        );
+        edition_config
+            .previous_dna_files
+            .extend(generated_dna_files.iter().cloned());
+        let dna_file = Path::new(&edition_config.output_path)
+            .join(dna::DNA_FILE_NAME)
+            .display()
+            .to_string();
+        generate(edition_config, args)?;
+        if !args.dry_run {
+            generated_dna_files.push(dna_file);
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_editions(config: &Config) -> Result<(), Box<dyn Error>> {
+    for (name, edition_config) in edition_configs(config)? {
+        println!("Edition {}:", name);
+        validate(edition_config)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edition_configs() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "metadata": {"name": "Drop", "description": "A drop"},
+                "image": {"width": 8, "height": 8},
+                "totalSupply": 30,
+                "startIndex": 1,
+                "basePath": "./images/",
+                "outputPath": "./output/",
+                "imageUrl": "",
+                "layerFolders": ["Background", "Hat"],
+                "forcedCombinations": [],
+                "editions": [
+                    {"name": "Genesis", "supply": 10, "metadata": {"description": "First"}},
+                    {"name": "Standard", "supply": 20, "layers": ["Background"], "outputFolder": "std"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let configs = edition_configs(&config).unwrap();
+        let (name, genesis) = &configs[0];
+        assert_eq!(name, "Genesis");
+        assert_eq!((genesis.start_index, genesis.total_supply), (1, 10));
+        assert_eq!(genesis.metadata["description"], "First");
+        assert_eq!(genesis.metadata["name"], "Drop");
+
+        let (_, standard) = &configs[1];
+        assert_eq!((standard.start_index, standard.total_supply), (11, 20));
+        assert_eq!(Path::new(&standard.output_path), Path::new("./output/std"));
+        let skipped = regex::Regex::new(&standard.skipped_traits.as_ref().unwrap()[0]).unwrap();
+        assert!(skipped.is_match("./images/Hat#2/Red.png"));
+        assert!(!skipped.is_match("./images/Background/Hat.png"));
+
+        config.total_supply = 25;
+        assert!(edition_configs(&config).is_err());
+    }
+}
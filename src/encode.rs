@@ -0,0 +1,472 @@
+use color_quant::NeuQuant;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ColorType, ImageEncoder, RgbaImage};
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::CustomError;
+
+/// Number of low bits dropped from each color channel on the last re-encode
+/// attempts. Fewer distinct colors compress much better in PNG.
+const MAX_DROPPED_BITS: u8 = 4;
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum OversizePolicy {
+    #[default]
+    Reencode,
+    Fail,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FileSizeLimit {
+    pub max_kb: u64,
+    pub policy: OversizePolicy,
+}
+
+/// Largest palette of an indexed PNG.
+const MAX_PALETTE_SIZE: usize = 256;
+
+/// Samples NeuQuant learns from, 1 being every pixel and 30 the fewest.
+const QUANTIZER_SAMPLE_FACTOR: i32 = 10;
+
+/// The colors of `image` and the index of every pixel among them, unless
+/// there are more than a palette holds.
+fn exact_palette(image: &RgbaImage) -> Option<(Vec<[u8; 4]>, Vec<u8>)> {
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut indexes: HashMap<[u8; 4], u8> = HashMap::new();
+    let mut pixels = Vec::with_capacity(image.pixels().len());
+    for pixel in image.pixels() {
+        let index = match indexes.entry(pixel.0) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                if palette.len() == MAX_PALETTE_SIZE {
+                    return None;
+                }
+                palette.push(pixel.0);
+                *entry.insert((palette.len() - 1) as u8)
+            }
+        };
+        pixels.push(index);
+    }
+    Some((palette, pixels))
+}
+
+/// The palette of `image` and the index of every pixel in it. Images with
+/// more colors than a palette holds are quantized to 256 colors.
+fn palette(image: &RgbaImage) -> (Vec<[u8; 4]>, Vec<u8>) {
+    if let Some(exact) = exact_palette(image) {
+        return exact;
+    }
+    let quantizer = NeuQuant::new(QUANTIZER_SAMPLE_FACTOR, MAX_PALETTE_SIZE, image.as_raw());
+    let palette = quantizer
+        .color_map_rgba()
+        .chunks_exact(4)
+        .map(|color| [color[0], color[1], color[2], color[3]])
+        .collect();
+    let pixels = image
+        .pixels()
+        .map(|pixel| quantizer.index_of(&pixel.0) as u8)
+        .collect();
+    (palette, pixels)
+}
+
+/// How hard images are worked on to make them smaller, never changing a
+/// pixel.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum PngOptimization {
+    /// The color type with the least data, filtered row by row.
+    Fast,
+    /// Every color type that holds the image with every filter, keeping the
+    /// smallest file.
+    Thorough,
+}
+
+/// How images are written.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct PngOutput {
+    pub indexed: bool,
+    pub optimization: Option<PngOptimization>,
+}
+
+/// Image data in one PNG color type, with the palette of an indexed one.
+struct RawPng {
+    color: png::ColorType,
+    depth: png::BitDepth,
+    palette: Option<Vec<[u8; 4]>>,
+    data: Vec<u8>,
+}
+
+/// The filter of every row, or the best one for each row.
+#[derive(Debug, Clone, Copy)]
+enum Filter {
+    Fixed(png::FilterType),
+    Adaptive,
+}
+
+const FILTERS: [Filter; 6] = [
+    Filter::Fixed(png::FilterType::NoFilter),
+    Filter::Fixed(png::FilterType::Sub),
+    Filter::Fixed(png::FilterType::Up),
+    Filter::Fixed(png::FilterType::Avg),
+    Filter::Fixed(png::FilterType::Paeth),
+    Filter::Adaptive,
+];
+
+/// Packs palette indexes into rows of `bit_depth` bits each.
+fn pack(pixels: &[u8], width: usize, bit_depth: u8) -> Vec<u8> {
+    let per_byte = 8 / bit_depth as usize;
+    pixels
+        .chunks(width)
+        .flat_map(|row| {
+            row.chunks(per_byte).map(|indexes| {
+                indexes
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (position, &index)| {
+                        byte | index << (8 - bit_depth as usize * (position + 1))
+                    })
+            })
+        })
+        .collect()
+}
+
+/// Indexed image data, with as few bits per pixel as the palette allows.
+fn indexed_raw(palette: Vec<[u8; 4]>, pixels: &[u8], width: u32) -> RawPng {
+    let (bit_depth, depth) = match palette.len() {
+        0..=2 => (1, png::BitDepth::One),
+        3..=4 => (2, png::BitDepth::Two),
+        5..=16 => (4, png::BitDepth::Four),
+        _ => (8, png::BitDepth::Eight),
+    };
+    RawPng {
+        color: png::ColorType::Indexed,
+        depth,
+        palette: Some(palette),
+        data: pack(pixels, width as usize, bit_depth),
+    }
+}
+
+/// `image` in every color type that holds it without changing a pixel.
+fn lossless_raws(image: &RgbaImage) -> Vec<RawPng> {
+    let opaque = image.pixels().all(|pixel| pixel[3] == u8::MAX);
+    let gray = image
+        .pixels()
+        .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]);
+    let raw = |color, channels: &[usize]| RawPng {
+        color,
+        depth: png::BitDepth::Eight,
+        palette: None,
+        data: image
+            .pixels()
+            .flat_map(|pixel| channels.iter().map(|&channel| pixel[channel]))
+            .collect(),
+    };
+
+    let mut raws = vec![raw(png::ColorType::Rgba, &[0, 1, 2, 3])];
+    if opaque {
+        raws.push(raw(png::ColorType::Rgb, &[0, 1, 2]));
+    }
+    if gray {
+        raws.push(raw(png::ColorType::GrayscaleAlpha, &[0, 3]));
+    }
+    if opaque && gray {
+        raws.push(raw(png::ColorType::Grayscale, &[0]));
+    }
+    if let Some((palette, pixels)) = exact_palette(image) {
+        raws.push(indexed_raw(palette, &pixels, image.width()));
+    }
+    raws
+}
+
+fn write_png(
+    raw: &RawPng,
+    width: u32,
+    height: u32,
+    compression: png::Compression,
+    filter: Filter,
+) -> Result<Vec<u8>, CustomError> {
+    let mut bytes = Vec::new();
+    let mut encoder = png::Encoder::new(&mut bytes, width, height);
+    encoder.set_color(raw.color);
+    encoder.set_depth(raw.depth);
+    if let Some(palette) = &raw.palette {
+        encoder.set_palette(
+            palette
+                .iter()
+                .flat_map(|color| &color[..3])
+                .copied()
+                .collect::<Vec<u8>>(),
+        );
+        if palette.iter().any(|color| color[3] < u8::MAX) {
+            encoder.set_trns(palette.iter().map(|color| color[3]).collect::<Vec<u8>>());
+        }
+    }
+    encoder.set_compression(compression);
+    match filter {
+        Filter::Fixed(filter) => encoder.set_filter(filter),
+        Filter::Adaptive => encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive),
+    }
+    let write_error = |e: png::EncodingError| CustomError::WriteOutput(e.to_string());
+    let mut writer = encoder.write_header().map_err(write_error)?;
+    writer.write_image_data(&raw.data).map_err(write_error)?;
+    writer.finish().map_err(write_error)?;
+    Ok(bytes)
+}
+
+/// Encodes `image` every way `optimization` tries, in parallel, and keeps the
+/// smallest file. An `indexed` image keeps its palette.
+fn optimize(
+    image: &RgbaImage,
+    indexed: bool,
+    optimization: PngOptimization,
+) -> Result<Vec<u8>, CustomError> {
+    let (width, height) = image.dimensions();
+    let raws = if indexed {
+        let (palette, pixels) = palette(image);
+        vec![indexed_raw(palette, &pixels, width)]
+    } else {
+        lossless_raws(image)
+    };
+    let candidates: Vec<(&RawPng, Filter)> = match optimization {
+        PngOptimization::Fast => raws
+            .iter()
+            .min_by_key(|raw| raw.data.len())
+            .map(|raw| (raw, Filter::Adaptive))
+            .into_iter()
+            .collect(),
+        PngOptimization::Thorough => raws
+            .iter()
+            .flat_map(|raw| FILTERS.iter().map(move |&filter| (raw, filter)))
+            .collect(),
+    };
+    let encoded = candidates
+        .into_par_iter()
+        .map(|(raw, filter)| write_png(raw, width, height, png::Compression::Best, filter))
+        .collect::<Result<Vec<Vec<u8>>, CustomError>>()?;
+    Ok(encoded.into_iter().min_by_key(Vec::len).unwrap_or_default())
+}
+
+fn encode(
+    image: &RgbaImage,
+    compression: Option<CompressionType>,
+    output: PngOutput,
+) -> Result<Vec<u8>, CustomError> {
+    if let Some(optimization) = output.optimization {
+        return optimize(image, output.indexed, optimization);
+    }
+    if output.indexed {
+        let (palette, pixels) = palette(image);
+        let (compression, filter) = match compression {
+            Some(CompressionType::Best) => (png::Compression::Best, Filter::Adaptive),
+            _ => (
+                png::Compression::Default,
+                Filter::Fixed(png::FilterType::default()),
+            ),
+        };
+        return write_png(
+            &indexed_raw(palette, &pixels, image.width()),
+            image.width(),
+            image.height(),
+            compression,
+            filter,
+        );
+    }
+    let mut bytes = Vec::new();
+    let encoder = match compression {
+        Some(compression) => {
+            PngEncoder::new_with_quality(&mut bytes, compression, FilterType::Adaptive)
+        }
+        None => PngEncoder::new(&mut bytes),
+    };
+    encoder
+        .write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ColorType::Rgba8,
+        )
+        .map_err(|e| CustomError::WriteOutput(e.to_string()))?;
+    Ok(bytes)
+}
+
+fn posterize(image: &RgbaImage, dropped_bits: u8) -> RgbaImage {
+    let mask = u8::MAX << dropped_bits;
+    let mut posterized = image.clone();
+    for pixel in posterized.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel &= mask;
+        }
+    }
+    posterized
+}
+
+/// Encodes `image` as PNG the way `output` says. When the result is larger
+/// than the limit, it is re-encoded with the best compression and then with
+/// fewer colors until it fits, unless the policy says to fail right away.
+pub fn encode_png(
+    image: &RgbaImage,
+    output: PngOutput,
+    limit: Option<FileSizeLimit>,
+    file_name: &str,
+) -> Result<Vec<u8>, CustomError> {
+    let bytes = encode(image, None, output)?;
+    let Some(limit) = limit else {
+        return Ok(bytes);
+    };
+
+    let max_bytes = limit.max_kb * 1024;
+    let too_large = |bytes: &Vec<u8>| bytes.len() as u64 > max_bytes;
+    if !too_large(&bytes) {
+        return Ok(bytes);
+    }
+
+    if limit.policy == OversizePolicy::Reencode {
+        let best = encode(image, Some(CompressionType::Best), output)?;
+        if !too_large(&best) {
+            return Ok(best);
+        }
+        for dropped_bits in 1..=MAX_DROPPED_BITS {
+            let reduced = encode(
+                &posterize(image, dropped_bits),
+                Some(CompressionType::Best),
+                output,
+            )?;
+            if !too_large(&reduced) {
+                return Ok(reduced);
+            }
+        }
+    }
+
+    Err(CustomError::FileSizeExceeded(
+        file_name.to_string(),
+        (bytes.len() as u64).div_ceil(1024),
+        limit.max_kb,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn noisy_image() -> RgbaImage {
+        RgbaImage::from_fn(64, 64, |x, y| {
+            let value = (x * 31 + y * 17) ^ (x * y);
+            Rgba([value as u8, (value >> 2) as u8, (value >> 4) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn test_encode_png() {
+        let image = noisy_image();
+        let unlimited = encode_png(&image, PngOutput::default(), None, "0.png").unwrap();
+        assert_eq!(
+            unlimited,
+            encode(&image, None, PngOutput::default()).unwrap()
+        );
+
+        let smallest = encode(
+            &posterize(&image, MAX_DROPPED_BITS),
+            Some(CompressionType::Best),
+            PngOutput::default(),
+        )
+        .unwrap()
+        .len() as u64;
+        let max_kb = smallest.div_ceil(1024);
+        assert!(max_kb * 1024 < unlimited.len() as u64);
+
+        let reencoded = encode_png(
+            &image,
+            PngOutput::default(),
+            Some(FileSizeLimit {
+                max_kb,
+                policy: OversizePolicy::Reencode,
+            }),
+            "0.png",
+        )
+        .unwrap();
+        assert!(reencoded.len() as u64 <= max_kb * 1024);
+        assert_eq!(image::load_from_memory(&reencoded).unwrap().width(), 64);
+
+        assert_eq!(
+            encode_png(
+                &image,
+                PngOutput::default(),
+                Some(FileSizeLimit {
+                    max_kb,
+                    policy: OversizePolicy::Fail,
+                }),
+                "0.png",
+            ),
+            Err(CustomError::FileSizeExceeded(
+                "0.png".to_string(),
+                (unlimited.len() as u64).div_ceil(1024),
+                max_kb
+            ))
+        );
+    }
+
+    #[test]
+    fn test_encode_indexed() {
+        // A few colors, one of them half transparent, round trip exactly.
+        let colors = [
+            Rgba([0, 0, 0, 0]),
+            Rgba([255, 0, 0, 255]),
+            Rgba([0, 128, 255, 128]),
+        ];
+        let pixel_art = RgbaImage::from_fn(13, 7, |x, y| colors[((x + y) % 3) as usize]);
+        let output = PngOutput {
+            indexed: true,
+            optimization: None,
+        };
+        let indexed = encode_png(&pixel_art, output, None, "0.png").unwrap();
+        assert!(
+            indexed.len()
+                < encode(&pixel_art, None, PngOutput::default())
+                    .unwrap()
+                    .len()
+        );
+        let decoded = image::load_from_memory(&indexed).unwrap().to_rgba8();
+        assert_eq!(decoded, pixel_art);
+
+        // More colors than a palette holds are quantized.
+        let image = noisy_image();
+        let decoded = image::load_from_memory(&encode_png(&image, output, None, "0.png").unwrap())
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(decoded.dimensions(), image.dimensions());
+        let mut colors: Vec<[u8; 4]> = decoded.pixels().map(|pixel| pixel.0).collect();
+        colors.sort();
+        colors.dedup();
+        assert!(colors.len() <= MAX_PALETTE_SIZE);
+    }
+
+    #[test]
+    fn test_optimize() {
+        let gray = RgbaImage::from_fn(48, 48, |x, y| {
+            let value = ((x / 4 + y / 4) * 9) as u8;
+            Rgba([value, value, value, 255])
+        });
+        for image in [gray, noisy_image()] {
+            let plain = encode(&image, None, PngOutput::default()).unwrap();
+            let mut sizes = Vec::new();
+            for optimization in [PngOptimization::Fast, PngOptimization::Thorough] {
+                let output = PngOutput {
+                    indexed: false,
+                    optimization: Some(optimization),
+                };
+                let optimized = encode_png(&image, output, None, "0.png").unwrap();
+                let decoded = image::load_from_memory(&optimized).unwrap().to_rgba8();
+                assert_eq!(decoded, image);
+                sizes.push(optimized.len());
+            }
+            assert!(sizes[0] < plain.len());
+            assert!(sizes[1] <= sizes[0]);
+        }
+    }
+}
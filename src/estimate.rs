@@ -0,0 +1,211 @@
+use clap::Args;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use walkdir::WalkDir;
+
+use crate::file_names;
+use crate::prefetch::DecodedLayers;
+use crate::{
+    generate_image_and_metadata, load_layers, plan_collection, render_settings, Config, CustomError,
+};
+
+#[derive(Debug, Args)]
+pub struct EstimateArgs {
+    /// Number of tokens rendered to measure from.
+    #[arg(long, default_value_t = DEFAULT_SAMPLES)]
+    pub samples: usize,
+}
+
+/// Number of tokens rendered to measure from, unless told otherwise.
+pub const DEFAULT_SAMPLES: usize = 10;
+
+/// What rendering the sample tokens took, in total.
+#[derive(Debug, PartialEq)]
+pub struct SampleRender {
+    pub tokens: usize,
+    pub bytes: u64,
+    /// Loading the layer files, done one token after the other.
+    pub load_time: Duration,
+    /// Compositing and encoding, done in parallel.
+    pub render_time: Duration,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Estimate {
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+impl SampleRender {
+    pub fn extrapolate(&self, supply: usize, threads: usize) -> Estimate {
+        let scale = supply as f64 / self.tokens.max(1) as f64;
+        Estimate {
+            bytes: (self.bytes as f64 * scale).round() as u64,
+            duration: self.load_time.mul_f64(scale)
+                + self.render_time.mul_f64(scale / threads.max(1) as f64),
+        }
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// The first `samples` tokens of the collection, drawn with its rules and caps.
+fn sample_tokens(config: &Config, samples: usize) -> Result<Vec<Vec<String>>, CustomError> {
+    let mut rng = match config.seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_entropy(),
+    };
+    let all_layers = load_layers(config)?;
+    Ok(plan_collection(config, &all_layers, &mut rng, None)?
+        .permutations
+        .into_iter()
+        .take(samples)
+        .map(|(_, image_paths)| image_paths)
+        .collect())
+}
+
+fn render_samples(
+    config: &Config,
+    tokens: &[Vec<String>],
+    render_dir: &Path,
+) -> Result<SampleRender, CustomError> {
+    let mut sample_config = config.clone();
+    sample_config.output_path = render_dir.display().to_string();
+    file_names::use_plain_names(&mut sample_config);
+    let settings = render_settings(&sample_config)?;
+
+    let mut load_time = Duration::ZERO;
+    let mut render_time = Duration::ZERO;
+    for (index, image_paths) in tokens.iter().enumerate() {
+        let start = Instant::now();
+        let mut render = generate_image_and_metadata(
+            config.metadata.clone(),
+            image_paths.clone(),
+            index,
+            &settings,
+            &DecodedLayers::new(),
+        )
+        .map_err(|e| e.for_token(index, image_paths))?;
+        load_time += start.elapsed();
+
+        let start = Instant::now();
+        render().map_err(|e| e.for_token(index, image_paths))?;
+        render_time += start.elapsed();
+    }
+
+    Ok(SampleRender {
+        tokens: fs::read_dir(render_dir).map_or(0, |entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "png"))
+                .count()
+        }),
+        bytes: dir_size(render_dir),
+        load_time,
+        render_time,
+    })
+}
+
+/// Sample renders started by this process, numbering their folders.
+static SAMPLE_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Renders the layers of `tokens` into a temporary folder, removed afterwards.
+pub fn render_temporary_samples(
+    config: &Config,
+    tokens: &[Vec<String>],
+) -> Result<SampleRender, CustomError> {
+    // Collections of a batch render their samples at the same time.
+    let render_dir = std::env::temp_dir().join(format!(
+        "rust-nft-generator-estimate-{}-{}",
+        std::process::id(),
+        SAMPLE_RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&render_dir)
+        .map_err(|e| CustomError::WriteOutput(format!("{}: {}", render_dir.display(), e)))?;
+    let sample = render_samples(config, tokens, &render_dir);
+    _ = fs::remove_dir_all(&render_dir);
+    sample
+}
+
+pub fn run(args: &EstimateArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let tokens = sample_tokens(config, args.samples)?;
+    let sample = render_temporary_samples(config, &tokens)?;
+
+    if sample.tokens == 0 {
+        warn!("No token could be rendered, nothing to estimate.");
+        return Ok(());
+    }
+
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let supply = config.total_supply as usize;
+    let estimate = sample.extrapolate(supply, threads);
+    println!(
+        "Rendered {} sample tokens: {} per token, loading layers in {:.2?} and compositing in {:.2?} on average.",
+        sample.tokens,
+        format_bytes(sample.bytes / sample.tokens as u64),
+        sample.load_time / sample.tokens as u32,
+        sample.render_time / sample.tokens as u32,
+    );
+    println!(
+        "Estimated for {} tokens: {} of output, about {:.0?} on {} threads.",
+        supply,
+        format_bytes(estimate.bytes),
+        estimate.duration,
+        threads
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extrapolate() {
+        let sample = SampleRender {
+            tokens: 10,
+            bytes: 5 * 1024 * 1024,
+            load_time: Duration::from_secs(2),
+            render_time: Duration::from_secs(8),
+        };
+        assert_eq!(
+            sample.extrapolate(10_000, 4),
+            Estimate {
+                bytes: 5 * 1024 * 1024 * 1000,
+                duration: Duration::from_secs(2000 + 2000),
+            }
+        );
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1000), "4.9 GB");
+    }
+}
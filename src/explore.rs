@@ -0,0 +1,235 @@
+use clap::Args;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::file_names;
+use crate::paths::LayerLayout;
+use crate::permutations::{self, Constraints};
+use crate::prefetch::DecodedLayers;
+use crate::rules::Rules;
+use crate::{
+    compile_caps, dna, fixed_tokens, generate_image_and_metadata, layer_layout, layer_names,
+    load_layers, path_trait, previous_dna, public_pools, rarity, render_settings, sample_pools,
+    skipped_trait_regexes, stats, write_json, Config, CustomError,
+};
+
+pub const EXPLORE_DIR_NAME: &str = "explore";
+pub const EXPLORE_REPORT_FILE_NAME: &str = "explore.json";
+
+#[derive(Debug, Args)]
+pub struct ExploreArgs {
+    /// Number of seeds to try.
+    #[arg(long, default_value_t = 5)]
+    pub seeds: u64,
+    /// First seed tried; the others follow it.
+    #[arg(long, default_value_t = 0)]
+    pub first_seed: u64,
+    /// Number of tokens rendered for every seed.
+    #[arg(long, default_value_t = 8)]
+    pub samples: usize,
+}
+
+/// How the collection sampled with one seed turned out.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SeedReport {
+    pub seed: u64,
+    /// Largest gap, in percentage points, between how often a value was picked
+    /// and the share its weight gives it.
+    pub max_drift: f64,
+    /// The `layer/value` with that gap.
+    pub max_drift_trait: String,
+    pub top_rarity_score: f64,
+    pub mean_rarity_score: f64,
+    /// The traits of the rarest token, as `layer: value`.
+    pub rarest_token: Vec<String>,
+}
+
+pub fn seed_report(
+    seed: u64,
+    layout: &LayerLayout,
+    layers: &[Vec<String>],
+    tokens: &[Vec<String>],
+) -> SeedReport {
+    let stats = stats::compute_stats(layout, layers, tokens);
+    let (max_drift, max_drift_trait) = stats
+        .layers
+        .iter()
+        .flat_map(|(layer, values)| {
+            values.iter().map(move |(value, value_stats)| {
+                (
+                    (value_stats.percentage - value_stats.expected_percentage).abs(),
+                    format!("{}/{}", layer, value),
+                )
+            })
+        })
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .unwrap_or_default();
+
+    let rarity = rarity::compute_rarity(layout, tokens);
+    let rarest = rarity.tokens.iter().find(|token| token.rank == 1);
+    let mean_rarity_score = if rarity.tokens.is_empty() {
+        0.0
+    } else {
+        rarity.tokens.iter().map(|token| token.score).sum::<f64>() / rarity.tokens.len() as f64
+    };
+
+    SeedReport {
+        seed,
+        max_drift,
+        max_drift_trait,
+        top_rarity_score: rarest.map_or(0.0, |token| token.score),
+        mean_rarity_score,
+        rarest_token: rarest
+            .map(|token| {
+                tokens[token.token_id]
+                    .iter()
+                    .filter_map(|path| path_trait(layout, path))
+                    .map(|(layer, value)| format!("{}: {}", layer, value))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// Samples the public supply with `seed`, the way `generate` would, and
+/// returns the tokens in token id order.
+fn sample_collection(config: &Config, seed: u64) -> Result<Vec<Vec<String>>, CustomError> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let (mut excluded_dna, _) = previous_dna(config)?;
+    let all_layers = load_layers(config)?;
+    let layout = layer_layout(config);
+    excluded_dna.extend(
+        fixed_tokens(config, &all_layers)?
+            .iter()
+            .map(|(_, image_paths)| dna::compute_dna(config.dna_algorithm, &layout, image_paths)),
+    );
+    let pools = public_pools(config, &all_layers)?;
+    let skipped_traits = skipped_trait_regexes(config)?;
+    let names = layer_names(config);
+    let mut constraints = Constraints {
+        excluded_dna,
+        dna_algorithm: config.dna_algorithm,
+        rules: Rules::compile(&config.dependency_rules, &config.trait_tags, &names)?,
+        layout: layout.clone(),
+    };
+    let mut caps = compile_caps(config, &all_layers, &skipped_traits, &names)?;
+    let tokens = sample_pools(
+        &pools,
+        &names,
+        &skipped_traits,
+        &mut constraints,
+        &mut caps,
+        &mut rng,
+        None,
+    )?
+    .permutations;
+
+    let mut tokens = tokens;
+    config
+        .token_order
+        .sort(&mut tokens, config.dna_algorithm, &layout);
+    Ok(tokens.into_iter().map(|(_, paths)| paths).collect())
+}
+
+fn render_samples(
+    config: &Config,
+    tokens: &[Vec<String>],
+    samples: usize,
+    render_dir: &Path,
+) -> Result<(), CustomError> {
+    fs::create_dir_all(render_dir)
+        .map_err(|e| CustomError::WriteOutput(format!("{}: {}", render_dir.display(), e)))?;
+    let mut sample_config = config.clone();
+    sample_config.output_path = render_dir.display().to_string();
+    file_names::use_plain_names(&mut sample_config);
+    let settings = render_settings(&sample_config)?;
+
+    for (index, image_paths) in tokens.iter().take(samples).enumerate() {
+        let mut render = generate_image_and_metadata(
+            config.metadata.clone(),
+            image_paths.clone(),
+            index,
+            &settings,
+            &DecodedLayers::new(),
+        )
+        .map_err(|e| e.for_token(index, image_paths))?;
+        render().map_err(|e| e.for_token(index, image_paths))?;
+    }
+    Ok(())
+}
+
+pub fn run(args: &ExploreArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let explore_dir = Path::new(&config.output_path).join(EXPLORE_DIR_NAME);
+    let layout = layer_layout(config);
+    let layers =
+        permutations::without_skipped(&load_layers(config)?, &skipped_trait_regexes(config)?);
+
+    let mut reports = Vec::new();
+    for seed in args.first_seed..args.first_seed.saturating_add(args.seeds) {
+        let tokens = sample_collection(config, seed)?;
+        render_samples(
+            config,
+            &tokens,
+            args.samples,
+            &explore_dir.join(format!("seed-{}", seed)),
+        )?;
+        let report = seed_report(seed, &layout, &layers, &tokens);
+        println!(
+            "Seed {}: max drift {:.1} points ({}), top rarity score {:.2}, mean {:.2}.",
+            report.seed,
+            report.max_drift,
+            report.max_drift_trait,
+            report.top_rarity_score,
+            report.mean_rarity_score
+        );
+        reports.push(report);
+    }
+
+    let file_path = explore_dir.join(EXPLORE_REPORT_FILE_NAME);
+    write_json(&file_path, &reports)?;
+
+    if let Some(closest) = reports
+        .iter()
+        .min_by(|a, b| a.max_drift.total_cmp(&b.max_drift))
+    {
+        println!(
+            "Seed {} is the closest to the configured weights. Samples and {} are in {}.",
+            closest.seed,
+            EXPLORE_REPORT_FILE_NAME,
+            explore_dir.display()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed_report() {
+        let layers = vec![vec![
+            "./images/Background/Blue#50.png".to_string(),
+            "./images/Background/Red#50.png".to_string(),
+        ]];
+        let tokens = vec![
+            vec![layers[0][0].clone()],
+            vec![layers[0][0].clone()],
+            vec![layers[0][0].clone()],
+            vec![layers[0][1].clone()],
+        ];
+
+        let report = seed_report(7, &LayerLayout::new("./images"), &layers, &tokens);
+        assert_eq!(report.seed, 7);
+        assert_eq!(report.max_drift, 25.0);
+        assert_eq!(report.top_rarity_score, 4.0);
+        assert_eq!(report.mean_rarity_score, (3.0 * 4.0 / 3.0 + 4.0) / 4.0);
+        assert_eq!(report.rarest_token, vec!["Background: Red"]);
+    }
+}
@@ -0,0 +1,164 @@
+use clap::{Args, Subcommand};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::file_names::FileNames;
+use crate::metadata::MetadataProfile;
+use crate::rarity::RARITY_FILE_NAME;
+use crate::{layer_names, Config, CustomError};
+
+#[derive(Debug, Subcommand)]
+pub enum ExportFormat {
+    /// Write one row per token with a column for every layer, the DNA and the rarity score.
+    Csv(CsvArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct CsvArgs {
+    /// Where to write the CSV file. Defaults to `tokens.csv` in the output folder.
+    #[arg(long)]
+    pub output: Option<String>,
+}
+
+pub fn run(format: &ExportFormat, config: &Config) -> Result<(), Box<dyn Error>> {
+    match format {
+        ExportFormat::Csv(args) => {
+            let output_path = Path::new(&config.output_path);
+            let layers = layer_names(config);
+            let files = FileNames::compile(config)?.token_files(output_path)?;
+            let csv = tokens_csv(output_path, &files, &layers, &config.metadata_profile)?;
+            let file_path = args
+                .output
+                .as_ref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| output_path.join("tokens.csv"));
+            fs::write(&file_path, csv)
+                .map_err(|e| CustomError::Export(format!("{}: {}", file_path.display(), e)))?;
+            info!("Token attributes exported to {}.", file_path.display());
+        }
+    }
+    Ok(())
+}
+
+fn read_json(path: &Path) -> Result<Value, CustomError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| CustomError::Export(format!("{}: {}", path.display(), e)))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| CustomError::Export(format!("{}: {}", path.display(), e)))
+}
+
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The CSV of the metadata `files` of `output_path`, with their id.
+pub fn tokens_csv(
+    output_path: &Path,
+    files: &[(usize, PathBuf)],
+    layers: &[String],
+    profile: &MetadataProfile,
+) -> Result<String, CustomError> {
+    let rarity_path = output_path.join(RARITY_FILE_NAME);
+    let rarity: HashMap<u64, (String, String)> = if rarity_path.exists() {
+        read_json(&rarity_path)?["tokens"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|token| {
+                Some((
+                    token["tokenId"].as_u64()?,
+                    (token["score"].to_string(), token["rank"].to_string()),
+                ))
+            })
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let mut header = vec!["token_id".to_string()];
+    header.extend(layers.iter().cloned());
+    header.extend(["dna", "rarity_score", "rarity_rank"].map(String::from));
+    let mut rows = vec![header];
+
+    for (token_id, path) in files {
+        let token_id = *token_id;
+        let metadata = read_json(path)?;
+        let attributes: HashMap<&str, String> = metadata["attributes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|attribute| {
+                let value = match profile.value(attribute)? {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                Some((profile.trait_type(attribute)?, value))
+            })
+            .collect();
+
+        let mut row = vec![token_id.to_string()];
+        row.extend(
+            layers
+                .iter()
+                .map(|layer| attributes.get(layer.as_str()).cloned().unwrap_or_default()),
+        );
+        row.push(metadata["dna"].as_str().unwrap_or_default().to_string());
+        let (score, rank) = rarity.get(&(token_id as u64)).cloned().unwrap_or_default();
+        row.push(score);
+        row.push(rank);
+        rows.push(row);
+    }
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            format!("{}\n", fields.join(","))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_tokens_csv() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("0.json"),
+            r#"{"dna": "aa", "attributes": [{"trait_type": "Eyes", "value": "Laser, Red"}]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("10.json"),
+            r#"{"dna": "bb", "attributes": [{"trait_type": "Background", "value": "Blue"}]}"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("dna.json"), "[]").unwrap();
+        fs::write(
+            dir.path().join(RARITY_FILE_NAME),
+            r#"{"tokens": [{"tokenId": 0, "rank": 2, "score": 1.5}, {"tokenId": 10, "rank": 1, "score": 4.0}]}"#,
+        )
+        .unwrap();
+
+        let layers = vec!["Background".to_string(), "Eyes".to_string()];
+        let files = FileNames::default().token_files(dir.path()).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            tokens_csv(dir.path(), &files, &layers, &MetadataProfile::default()).unwrap(),
+            "token_id,Background,Eyes,dna,rarity_score,rarity_rank\n\
+             0,,\"Laser, Red\",aa,1.5,2\n\
+             10,Blue,,bb,4.0,1\n"
+        );
+    }
+}
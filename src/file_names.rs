@@ -0,0 +1,312 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use crate::manifest;
+use crate::name_template::{self, Piece};
+use crate::{token_file_name, Config, CustomError};
+
+/// Characters a `{name}` can't bring into a file name.
+const RESERVED_CHARACTERS: [char; 9] = ['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Id,
+    Dna,
+    Name,
+}
+
+/// An `imageFileName` or `metadataFileName`, such as `{id}.png`.
+#[derive(Debug, Clone, PartialEq)]
+struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    fn compile(field: &str, template: &str, extension: &str) -> Result<Template, CustomError> {
+        let invalid =
+            |reason: String| CustomError::Config(format!("{} {:?} {}", field, template, reason));
+        let parts = name_template::pieces(template)
+            .map_err(|reason| invalid(reason.to_string()))?
+            .into_iter()
+            .map(|piece| match piece {
+                Piece::Text(text) if text.contains(['/', '\\']) => {
+                    Err(invalid("can't name a folder".to_string()))
+                }
+                Piece::Text(text) => Ok(Part::Text(text.to_string())),
+                Piece::Placeholder("id") => Ok(Part::Id),
+                Piece::Placeholder("dna") => Ok(Part::Dna),
+                Piece::Placeholder("name") => Ok(Part::Name),
+                Piece::Placeholder(unknown) => Err(invalid(format!(
+                    "references {{{}}}, which is neither id, dna nor name",
+                    unknown
+                ))),
+            })
+            .collect::<Result<Vec<Part>, CustomError>>()?;
+        // The id and the DNA are the only parts no two tokens share.
+        if !parts
+            .iter()
+            .any(|part| matches!(part, Part::Id | Part::Dna))
+        {
+            return Err(invalid(
+                "needs {id} or {dna} to tell the tokens apart".to_string(),
+            ));
+        }
+        if !template.ends_with(extension) {
+            return Err(invalid(format!("must end with {}", extension)));
+        }
+        Ok(Template { parts })
+    }
+}
+
+/// An `imageFolder` or `metadataFolder`, which must stay inside `outputPath`.
+fn folder(field: &str, folder: &Option<String>) -> Result<PathBuf, CustomError> {
+    let Some(folder) = folder else {
+        return Ok(PathBuf::new());
+    };
+    let path = PathBuf::from(folder);
+    if path
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(CustomError::Config(format!(
+            "{} {:?} must be a folder inside outputPath",
+            field, folder
+        )));
+    }
+    Ok(path)
+}
+
+/// Where the images and metadata of a collection are written: their folder
+/// under `outputPath` and their file names. `{id}` is the token id, padded to
+/// `tokenIdPadding` digits, `{dna}` its DNA and `{name}` the `name` of the
+/// config metadata.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileNames {
+    image: Option<Template>,
+    metadata: Option<Template>,
+    image_folder: PathBuf,
+    metadata_folder: PathBuf,
+    padding: usize,
+    name: String,
+}
+
+impl FileNames {
+    pub fn compile(config: &Config) -> Result<FileNames, CustomError> {
+        let compile = |field, template: &Option<String>, extension| {
+            template
+                .as_deref()
+                .map(|template| Template::compile(field, template, extension))
+                .transpose()
+        };
+        let name = config
+            .metadata
+            .get("name")
+            .and_then(|name| name.as_str())
+            .unwrap_or_default();
+        Ok(FileNames {
+            image: compile("imageFileName", &config.image_file_name, ".png")?,
+            metadata: compile("metadataFileName", &config.metadata_file_name, ".json")?,
+            image_folder: folder("imageFolder", &config.image_folder)?,
+            metadata_folder: folder("metadataFolder", &config.metadata_folder)?,
+            padding: config.token_id_padding,
+            name: name.replace(RESERVED_CHARACTERS, "-"),
+        })
+    }
+
+    fn render(
+        &self,
+        template: &Option<Template>,
+        extension: &str,
+        token_id: usize,
+        dna: &str,
+    ) -> String {
+        let Some(template) = template else {
+            return token_file_name(token_id, self.padding, extension);
+        };
+        template
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Id => format!("{:0width$}", token_id, width = self.padding),
+                Part::Dna => dna.to_string(),
+                Part::Name => self.name.clone(),
+            })
+            .collect()
+    }
+
+    /// The file name of an image, which its URL ends with.
+    pub fn image_name(&self, token_id: usize, dna: &str) -> String {
+        self.render(&self.image, "png", token_id, dna)
+    }
+
+    /// The path of an image under `outputPath`.
+    pub fn image(&self, token_id: usize, dna: &str) -> String {
+        self.image_folder
+            .join(self.image_name(token_id, dna))
+            .display()
+            .to_string()
+    }
+
+    /// The file name of an animation of a token, such as its video: the one of
+    /// its image with `extension`.
+    pub fn animation_name(&self, token_id: usize, dna: &str, extension: &str) -> String {
+        Path::new(&self.image_name(token_id, dna))
+            .with_extension(extension)
+            .display()
+            .to_string()
+    }
+
+    /// The path of an animation under `outputPath`, next to the image.
+    pub fn animation(&self, token_id: usize, dna: &str, extension: &str) -> String {
+        self.image_folder
+            .join(self.animation_name(token_id, dna, extension))
+            .display()
+            .to_string()
+    }
+
+    /// The path of the spritesheet of a token under `outputPath`, such as
+    /// `1.sheet.png` next to `1.png`.
+    pub fn sheet(&self, token_id: usize, dna: &str) -> String {
+        self.animation(token_id, dna, "sheet.png")
+    }
+
+    /// The path of a metadata file under `outputPath`.
+    pub fn metadata(&self, token_id: usize, dna: &str) -> String {
+        self.metadata_folder
+            .join(self.render(&self.metadata, "json", token_id, dna))
+            .display()
+            .to_string()
+    }
+
+    pub fn image_dir(&self, output_path: &Path) -> PathBuf {
+        output_path.join(&self.image_folder)
+    }
+
+    pub fn metadata_dir(&self, output_path: &Path) -> PathBuf {
+        output_path.join(&self.metadata_folder)
+    }
+
+    /// Returns the token metadata files of `output_path`, ordered by id: the
+    /// ones of the tokens in its manifest, or the `<id>.json` files of
+    /// `metadataFolder` for an output without one.
+    pub fn token_files(&self, output_path: &Path) -> Result<Vec<(usize, PathBuf)>, CustomError> {
+        if let Ok(manifest) = manifest::read_manifest(output_path) {
+            return Ok(manifest
+                .token_dnas()
+                .into_iter()
+                .map(|(token_id, dna)| (token_id, output_path.join(self.metadata(token_id, &dna))))
+                .collect());
+        }
+        let metadata_dir = self.metadata_dir(output_path);
+        let entries = fs::read_dir(&metadata_dir)
+            .map_err(|_| CustomError::GetEntriesByPath(metadata_dir.display().to_string()))?;
+        let mut files: Vec<(usize, PathBuf)> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| {
+                let token_id = path.file_stem()?.to_str()?.parse().ok()?;
+                Some((token_id, path))
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    /// The image of every token of `token_ids`, with its id, given the DNA of
+    /// every token.
+    pub fn images(
+        &self,
+        token_ids: Range<usize>,
+        dnas: &BTreeMap<usize, String>,
+    ) -> Result<Vec<(usize, String)>, CustomError> {
+        token_ids
+            .map(|token_id| {
+                let dna = dnas.get(&token_id).ok_or_else(|| {
+                    CustomError::WriteOutput(format!("token {} is not in the manifest", token_id))
+                })?;
+                Ok((token_id, self.image(token_id, dna)))
+            })
+            .collect()
+    }
+}
+
+/// The URL of `file_name` under the base URI `base`, unless there is no base
+/// URI to put in front of it.
+pub fn file_url(base: &str, file_name: &str) -> Option<String> {
+    (!base.is_empty()).then(|| format!("{}/{}", base.trim_end_matches('/'), file_name))
+}
+
+/// Makes `config` write `{id}.png` and `{id}.json` side by side, for sample
+/// renders, which have no DNA and are numbered in a folder of their own.
+pub fn use_plain_names(config: &mut Config) {
+    config.image_file_name = None;
+    config.metadata_file_name = None;
+    config.image_folder = None;
+    config.metadata_folder = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_names() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "metadata": {"name": "Cool: Cats", "description": "A drop"},
+                "image": {"width": 8, "height": 8},
+                "totalSupply": 10,
+                "tokenIdPadding": 3,
+                "basePath": "./images/",
+                "outputPath": "./output/",
+                "imageUrl": "",
+                "layerFolders": ["Background"],
+                "forcedCombinations": []
+            }"#,
+        )
+        .unwrap();
+        let file_names = FileNames::compile(&config).unwrap();
+        assert_eq!(file_names.image(7, "ab12"), "007.png");
+        assert_eq!(file_names.metadata(7, "ab12"), "007.json");
+
+        config.image_file_name = Some("{name}-{id}.png".to_string());
+        config.metadata_file_name = Some("{dna}.json".to_string());
+        let file_names = FileNames::compile(&config).unwrap();
+        assert_eq!(file_names.image(7, "ab12"), "Cool- Cats-007.png");
+        assert_eq!(file_names.metadata(7, "ab12"), "ab12.json");
+        let dnas = BTreeMap::from([(1, "cd34".to_string())]);
+        assert_eq!(
+            file_names.images(1..2, &dnas).unwrap(),
+            vec![(1, "Cool- Cats-001.png".to_string())]
+        );
+        assert!(file_names.images(0..2, &dnas).is_err());
+
+        config.image_folder = Some("images".to_string());
+        config.metadata_folder = Some("metadata".to_string());
+        let file_names = FileNames::compile(&config).unwrap();
+        assert_eq!(file_names.image_name(7, "ab12"), "Cool- Cats-007.png");
+        assert_eq!(
+            Path::new(&file_names.image(7, "ab12")),
+            Path::new("images/Cool- Cats-007.png")
+        );
+        assert_eq!(
+            Path::new(&file_names.metadata(7, "ab12")),
+            Path::new("metadata/ab12.json")
+        );
+
+        for invalid in ["{name}.png", "{id}.webp", "art/{id}.png", "{id}-{size}.png"] {
+            config.image_file_name = Some(invalid.to_string());
+            assert!(FileNames::compile(&config).is_err(), "{}", invalid);
+        }
+        config.image_file_name = None;
+        for invalid in ["../images", "/srv/images"] {
+            config.image_folder = Some(invalid.to_string());
+            assert!(FileNames::compile(&config).is_err(), "{}", invalid);
+        }
+    }
+}
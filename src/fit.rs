@@ -0,0 +1,68 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::borrow::Cow;
+
+/// How an asset whose size differs from the canvas is scaled to it.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum Fit {
+    /// Drawn at its own size from the top left corner.
+    #[default]
+    None,
+    /// Scaled to the canvas size, ignoring its aspect ratio.
+    Stretch,
+    /// Scaled to fit inside the canvas, centered, keeping its aspect ratio.
+    Contain,
+    /// Scaled to cover the whole canvas keeping its aspect ratio, and cropped
+    /// around the center.
+    Cover,
+}
+
+/// Scales `layer` to a `width` x `height` canvas and returns it with the
+/// position of its top left corner on the canvas.
+pub fn fit_layer(
+    layer: &DynamicImage,
+    width: u32,
+    height: u32,
+    fit: Fit,
+) -> (Cow<'_, DynamicImage>, i64, i64) {
+    if fit == Fit::None || (layer.width() == width && layer.height() == height) {
+        return (Cow::Borrowed(layer), 0, 0);
+    }
+
+    let fitted = match fit {
+        Fit::None => unreachable!(),
+        Fit::Stretch => layer.resize_exact(width, height, FilterType::Lanczos3),
+        Fit::Contain => layer.resize(width, height, FilterType::Lanczos3),
+        Fit::Cover => layer.resize_to_fill(width, height, FilterType::Lanczos3),
+    };
+    let x = (width as i64 - fitted.width() as i64) / 2;
+    let y = (height as i64 - fitted.height() as i64) / 2;
+    (Cow::Owned(fitted), x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_fit_layer() {
+        let layer = DynamicImage::ImageRgba8(RgbaImage::new(50, 25));
+        let fitted = |fit| {
+            let (image, x, y) = fit_layer(&layer, 100, 100, fit);
+            (image.width(), image.height(), x, y)
+        };
+
+        assert_eq!(fitted(Fit::None), (50, 25, 0, 0));
+        assert_eq!(fitted(Fit::Stretch), (100, 100, 0, 0));
+        assert_eq!(fitted(Fit::Contain), (100, 50, 0, 25));
+        assert_eq!(fitted(Fit::Cover), (100, 100, 0, 0));
+        assert!(matches!(
+            fit_layer(&layer, 50, 25, Fit::Cover).0,
+            Cow::Borrowed(_)
+        ));
+    }
+}
@@ -0,0 +1,308 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::paths::LayerLayout;
+use crate::rules::TraitRef;
+use crate::{is_none_asset, path_trait, CustomError};
+
+/// A token id set aside for a given combination, e.g. the team honoraries.
+/// Layers it doesn't list must be optional and get `None`.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FixedToken {
+    pub token_id: usize,
+    pub traits: Vec<TraitRef>,
+}
+
+/// A combination the collection must have exactly once, at any token id.
+/// Layers it doesn't list must be optional and get `None`.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UniqueCombination {
+    pub traits: Vec<TraitRef>,
+}
+
+/// Reads the unique combinations of a `.json` file, listed like
+/// `uniqueCombinations`, or of a `.csv` file with a layer name per column
+/// and a combination per row, where an empty cell leaves the layer out.
+pub fn load_unique_combinations(path: &str) -> Result<Vec<UniqueCombination>, CustomError> {
+    let invalid = |reason: String| CustomError::Config(format!("{}: {}", path, reason));
+    let contents = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "json" => serde_json::from_str(&contents).map_err(|e| invalid(e.to_string())),
+        "csv" => {
+            let rows = csv_rows(&contents).map_err(invalid)?;
+            let Some((header, rows)) = rows.split_first() else {
+                return Ok(Vec::new());
+            };
+            rows.iter()
+                .enumerate()
+                .map(|(index, row)| {
+                    if row.len() != header.len() {
+                        return Err(invalid(format!(
+                            "row {} has {} cells for {} layers",
+                            index + 2,
+                            row.len(),
+                            header.len()
+                        )));
+                    }
+                    let traits = header
+                        .iter()
+                        .zip(row)
+                        .filter(|(_, value)| !value.is_empty())
+                        .map(|(layer, value)| TraitRef {
+                            layer: layer.clone(),
+                            value: value.clone(),
+                        })
+                        .collect();
+                    Ok(UniqueCombination { traits })
+                })
+                .collect()
+        }
+        _ => Err(invalid("must be a .json or .csv file".to_string())),
+    }
+}
+
+/// The rows of a CSV file, fields quoted the way `export csv` writes them,
+/// without blank lines.
+fn csv_rows(contents: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            _ if quoted => field.push(c),
+            ',' => row.push(std::mem::take(&mut field).trim().to_string()),
+            '\r' => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field).trim().to_string());
+                let row = std::mem::take(&mut row);
+                if row.iter().any(|field| !field.is_empty()) {
+                    rows.push(row);
+                }
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("a quoted field isn't closed".to_string());
+    }
+    row.push(field.trim().to_string());
+    if row.iter().any(|field| !field.is_empty()) {
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// The asset paths of `traits`, one per layer in `names`; `label` names the
+/// token or combination they belong to in errors.
+fn trait_paths(
+    layout: &LayerLayout,
+    traits: &[TraitRef],
+    names: &[String],
+    layers: &[Vec<String>],
+    label: &str,
+) -> Result<Vec<String>, CustomError> {
+    if let Some(unknown) = traits
+        .iter()
+        .find(|trait_ref| !names.contains(&trait_ref.layer))
+    {
+        return Err(CustomError::InvalidTrait(format!(
+            "{} references unknown layer {}",
+            label, unknown.layer
+        )));
+    }
+
+    names
+        .iter()
+        .zip(layers)
+        .map(|(name, layer)| {
+            let asset = match traits.iter().find(|t| &t.layer == name) {
+                Some(trait_ref) => layer.iter().find(|path| {
+                    path_trait(layout, path).is_some_and(|(_, value)| value == trait_ref.value)
+                }),
+                None => layer.iter().find(|path| is_none_asset(path)),
+            };
+            asset.cloned().ok_or_else(|| {
+                CustomError::InvalidTrait(format!("{} has no value of layer {}", label, name))
+            })
+        })
+        .collect()
+}
+
+/// Resolves every fixed token to its asset paths, one per layer in `names`.
+pub fn resolve(
+    layout: &LayerLayout,
+    fixed_tokens: &[FixedToken],
+    names: &[String],
+    layers: &[Vec<String>],
+    token_ids: &Range<usize>,
+) -> Result<Vec<(usize, Vec<String>)>, CustomError> {
+    let mut taken = HashSet::new();
+    fixed_tokens
+        .iter()
+        .map(|fixed_token| {
+            let token_id = fixed_token.token_id;
+            if !token_ids.contains(&token_id) || !taken.insert(token_id) {
+                return Err(CustomError::InvalidTrait(format!(
+                    "fixed token id {} is taken or not in {:?}",
+                    token_id, token_ids
+                )));
+            }
+            let label = format!("fixed token {}", token_id);
+            let paths = trait_paths(layout, &fixed_token.traits, names, layers, &label)?;
+            Ok((token_id, paths))
+        })
+        .collect()
+}
+
+/// Resolves every unique combination to its asset paths, one per layer in
+/// `names`. A combination listed twice, or also a fixed token, is an error,
+/// since it would be in the collection more than once.
+pub fn resolve_unique(
+    layout: &LayerLayout,
+    unique_combinations: &[UniqueCombination],
+    fixed: &[(usize, Vec<String>)],
+    names: &[String],
+    layers: &[Vec<String>],
+) -> Result<Vec<Vec<String>>, CustomError> {
+    let mut seen: HashSet<Vec<String>> = fixed.iter().map(|(_, paths)| paths.clone()).collect();
+    unique_combinations
+        .iter()
+        .enumerate()
+        .map(|(index, unique_combination)| {
+            let label = format!("unique combination {}", index + 1);
+            let paths = trait_paths(layout, &unique_combination.traits, names, layers, &label)?;
+            if !seen.insert(paths.clone()) {
+                return Err(CustomError::InvalidTrait(format!(
+                    "{} is listed twice or is a fixed token",
+                    label
+                )));
+            }
+            Ok(paths)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::none_asset;
+
+    #[test]
+    fn test_resolve() {
+        let layout = LayerLayout::new("./images");
+        let names = vec!["Background".to_string(), "Hat".to_string()];
+        let layers = vec![
+            vec![
+                "./images/Background/Blue#10.png".to_string(),
+                "./images/Background/Gold#1.png".to_string(),
+            ],
+            vec![
+                "./images/Hat/Cap#10.png".to_string(),
+                none_asset("./images/Hat", 5),
+            ],
+        ];
+        let fixed_tokens: Vec<FixedToken> = serde_json::from_str(
+            r#"[
+                {"tokenId": 0, "traits": [{"layer": "Background", "value": "Gold"}, {"layer": "Hat", "value": "Cap"}]},
+                {"tokenId": 1, "traits": [{"layer": "Background", "value": "Gold"}]}
+            ]"#,
+        )
+        .unwrap();
+
+        let resolved = resolve(&layout, &fixed_tokens, &names, &layers, &(0..10)).unwrap();
+        assert_eq!(
+            resolved[0],
+            (0, vec![layers[0][1].clone(), layers[1][0].clone()])
+        );
+        assert_eq!(
+            resolved[1],
+            (1, vec![layers[0][1].clone(), layers[1][1].clone()])
+        );
+
+        assert!(resolve(&layout, &fixed_tokens, &names, &layers, &(0..1)).is_err());
+        assert!(resolve(&layout, &fixed_tokens, &names, &layers, &(1..11)).is_err());
+        for invalid in [
+            r#"[{"tokenId": 0, "traits": [{"layer": "Hat", "value": "Cap"}]}]"#,
+            r#"[{"tokenId": 0, "traits": [{"layer": "Background", "value": "Red"}]}]"#,
+            r#"[{"tokenId": 0, "traits": [{"layer": "Eyes", "value": "Laser"}]}]"#,
+        ] {
+            let invalid: Vec<FixedToken> = serde_json::from_str(invalid).unwrap();
+            assert!(resolve(&layout, &invalid, &names, &layers, &(0..10)).is_err());
+        }
+
+        let unique: Vec<UniqueCombination> = serde_json::from_str(
+            r#"[
+                {"traits": [{"layer": "Background", "value": "Blue"}]},
+                {"traits": [{"layer": "Background", "value": "Gold"}]}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            resolve_unique(&layout, &unique, &[], &names, &layers).unwrap(),
+            vec![
+                vec![layers[0][0].clone(), layers[1][1].clone()],
+                vec![layers[0][1].clone(), layers[1][1].clone()]
+            ]
+        );
+        // The second is fixed token 1.
+        assert!(resolve_unique(&layout, &unique, &resolved, &names, &layers).is_err());
+    }
+
+    #[test]
+    fn test_load_unique_combinations() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, contents: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path.display().to_string()
+        };
+        let combinations = load_unique_combinations(&file(
+            "airdrops.csv",
+            "Background,Hat\r\nGold,Cap\n\n\"Blue, Light\",\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            combinations,
+            serde_json::from_str::<Vec<UniqueCombination>>(
+                r#"[
+                    {"traits": [{"layer": "Background", "value": "Gold"}, {"layer": "Hat", "value": "Cap"}]},
+                    {"traits": [{"layer": "Background", "value": "Blue, Light"}]}
+                ]"#
+            )
+            .unwrap()
+        );
+        let json = r#"[{"traits": [{"layer": "Hat", "value": "Cap"}]}]"#;
+        assert_eq!(
+            load_unique_combinations(&file("airdrops.json", json)).unwrap(),
+            serde_json::from_str::<Vec<UniqueCombination>>(json).unwrap()
+        );
+
+        for (name, contents) in [
+            ("short.csv", "Background,Hat\nGold\n"),
+            ("open.csv", "Background\n\"Gold\n"),
+            ("airdrops.txt", "Background\nGold\n"),
+        ] {
+            assert!(load_unique_combinations(&file(name, contents)).is_err());
+        }
+        assert!(load_unique_combinations("missing.csv").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use serde::Serialize;
+use std::path::Path;
+
+use crate::{write_json, CustomError, ForcedCombinations, Layer};
+
+pub const FORCED_COMBINATIONS_FILE_NAME: &str = "forced-combinations.json";
+
+/// How many tokens a forced combination was planned and ended up with.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BucketCount {
+    /// The combo, as `layer=value` pairs.
+    pub combination: String,
+    /// Whether the combination belongs to the reserved supply.
+    pub reserved: bool,
+    pub percentage: u8,
+    /// The supply the percentage is taken of.
+    pub supply: usize,
+    /// Tokens the percentage stands for, rounded down.
+    pub planned: usize,
+    pub received: usize,
+}
+
+impl BucketCount {
+    /// How far the tokens received are from the planned ones, in percentage
+    /// points of the supply.
+    pub fn deviation(&self) -> f64 {
+        if self.supply == 0 {
+            return 0.0;
+        }
+        self.planned.abs_diff(self.received) as f64 * 100.0 / self.supply as f64
+    }
+}
+
+/// The combo of a forced combination, as `layer=value` pairs.
+fn describe(forced_combination: &ForcedCombinations) -> String {
+    forced_combination
+        .combo
+        .iter()
+        .map(|combo| match &combo.layer {
+            Layer::Simple(layer) => format!("{}={}", layer, combo.value),
+            Layer::Complex {
+                mainLayer,
+                subLayer,
+            } => format!("{}/{}={}", mainLayer, subLayer, combo.value),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The counts of `forced_combinations`, given the tokens `received` by each of
+/// their pools and the planned supply of each.
+pub fn bucket_counts(
+    forced_combinations: &[ForcedCombinations],
+    planned: &[usize],
+    received: &[usize],
+    supply: usize,
+    reserved: bool,
+) -> Vec<BucketCount> {
+    forced_combinations
+        .iter()
+        .zip(planned.iter().zip(received))
+        .map(|(forced_combination, (&planned, &received))| BucketCount {
+            combination: describe(forced_combination),
+            reserved,
+            percentage: forced_combination.percentage,
+            supply,
+            planned,
+            received,
+        })
+        .collect()
+}
+
+/// Fails when a bucket is further than `tolerance` percentage points from its
+/// planned count.
+pub fn check_buckets(buckets: &[BucketCount], tolerance: f64) -> Result<(), CustomError> {
+    let off: Vec<String> = buckets
+        .iter()
+        .filter(|bucket| bucket.deviation() > tolerance)
+        .map(|bucket| {
+            format!(
+                "{} got {} of {} tokens",
+                bucket.combination, bucket.received, bucket.planned
+            )
+        })
+        .collect();
+    if off.is_empty() {
+        Ok(())
+    } else {
+        Err(CustomError::ForcedCombinations(off.join(", ")))
+    }
+}
+
+pub fn write_report(output_path: &Path, buckets: &[BucketCount]) -> Result<(), CustomError> {
+    let file_path = output_path.join(FORCED_COMBINATIONS_FILE_NAME);
+    write_json(&file_path, buckets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_buckets() {
+        let forced_combinations: Vec<ForcedCombinations> = serde_json::from_str(
+            r#"[
+                {"combo": [{"layer": "Eyes", "value": "Laser"}], "percentage": 20},
+                {"combo": [{"layer": {"mainLayer": "Hair", "subLayer": "White"}, "value": "*"}], "percentage": 30}
+            ]"#,
+        )
+        .unwrap();
+        let buckets = bucket_counts(&forced_combinations, &[20, 30], &[20, 28], 100, false);
+        assert_eq!(buckets[0].combination, "Eyes=Laser");
+        assert_eq!(buckets[1].combination, "Hair/White=*");
+        assert_eq!(buckets[1].deviation(), 2.0);
+
+        assert!(check_buckets(&buckets, 2.0).is_ok());
+        assert_eq!(
+            check_buckets(&buckets, 1.0),
+            Err(CustomError::ForcedCombinations(
+                "Hair/White=* got 28 of 30 tokens".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use clap::Args;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+use walkdir::WalkDir;
+
+use crate::cli::GenerateArgs;
+use crate::manifest::MANIFEST_FILE_NAME;
+use crate::{generate, Config, CustomError};
+
+#[derive(Debug, Args)]
+pub struct CompareGoldenArgs {
+    /// Folder holding the golden images and metadata.
+    pub golden_dir: String,
+    /// Seed used to render the collection, overriding the one in the config.
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Maximum per-channel difference allowed between pixels. Without it, images
+    /// are compared by the hash of their pixel data.
+    #[arg(long)]
+    pub pixel_tolerance: Option<u8>,
+    /// Render the golden set into `golden_dir` instead of comparing against it.
+    #[arg(long)]
+    pub update: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GoldenDifference {
+    Missing(String),
+    Unexpected(String),
+    Image { file: String, differing_pixels: u64 },
+    ImageSize(String),
+    Metadata(String),
+    Contents(String),
+}
+
+impl fmt::Display for GoldenDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GoldenDifference::Missing(file) => write!(f, "{}: missing from the render", file),
+            GoldenDifference::Unexpected(file) => write!(f, "{}: not in the golden set", file),
+            GoldenDifference::Image {
+                file,
+                differing_pixels,
+            } => write!(f, "{}: {} pixels differ", file, differing_pixels),
+            GoldenDifference::ImageSize(file) => write!(f, "{}: image size differs", file),
+            GoldenDifference::Metadata(file) => write!(f, "{}: metadata differs", file),
+            GoldenDifference::Contents(file) => write!(f, "{}: contents differ", file),
+        }
+    }
+}
+
+pub fn run(args: &CompareGoldenArgs, mut config: Config) -> Result<(), Box<dyn Error>> {
+    config.seed = Some(args.seed);
+
+    if args.update {
+        config.output_path = args.golden_dir.clone();
+        generate(config, &GenerateArgs::default())?;
+        info!("Golden set written to {}.", args.golden_dir);
+        return Ok(());
+    }
+
+    let render_dir =
+        std::env::temp_dir().join(format!("rust-nft-generator-golden-{}", std::process::id()));
+    config.output_path = render_dir.display().to_string();
+    generate(config, &GenerateArgs::default())?;
+
+    let differences = compare_dirs(
+        Path::new(&args.golden_dir),
+        &render_dir,
+        args.pixel_tolerance,
+    );
+    _ = fs::remove_dir_all(&render_dir);
+    let differences = differences?;
+
+    if differences.is_empty() {
+        println!("Output matches the golden set in {}.", args.golden_dir);
+        return Ok(());
+    }
+
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    Err(CustomError::GoldenMismatch(differences.len()).into())
+}
+
+fn relative_files(dir: &Path) -> BTreeSet<PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_file())
+        .filter_map(|entry| entry.path().strip_prefix(dir).ok().map(Path::to_path_buf))
+        .collect()
+}
+
+pub fn compare_dirs(
+    golden_dir: &Path,
+    render_dir: &Path,
+    pixel_tolerance: Option<u8>,
+) -> Result<Vec<GoldenDifference>, CustomError> {
+    let golden_files = relative_files(golden_dir);
+    let rendered_files = relative_files(render_dir);
+    let mut differences = Vec::new();
+
+    for file in golden_files.union(&rendered_files) {
+        let name = file.display().to_string();
+        if !rendered_files.contains(file) {
+            differences.push(GoldenDifference::Missing(name));
+            continue;
+        }
+        if !golden_files.contains(file) {
+            differences.push(GoldenDifference::Unexpected(name));
+            continue;
+        }
+
+        let golden = golden_dir.join(file);
+        let rendered = render_dir.join(file);
+        let difference = match file.extension().and_then(|ext| ext.to_str()) {
+            Some("png") => compare_images(&golden, &rendered, pixel_tolerance, name)?,
+            Some("json") => compare_metadata(
+                &golden,
+                &rendered,
+                file == Path::new(MANIFEST_FILE_NAME),
+                name,
+            )?,
+            _ => {
+                let read = |path: &Path| {
+                    fs::read(path).map_err(|e| CustomError::Golden(format!("{}: {}", name, e)))
+                };
+                (read(&golden)? != read(&rendered)?).then_some(GoldenDifference::Contents(name))
+            }
+        };
+        differences.extend(difference);
+    }
+
+    Ok(differences)
+}
+
+fn compare_images(
+    golden: &Path,
+    rendered: &Path,
+    pixel_tolerance: Option<u8>,
+    name: String,
+) -> Result<Option<GoldenDifference>, CustomError> {
+    let open = |path: &Path| {
+        image::open(path)
+            .map(|img| img.to_rgba8())
+            .map_err(|e| CustomError::Golden(format!("{}: {}", path.display(), e)))
+    };
+    let golden = open(golden)?;
+    let rendered = open(rendered)?;
+
+    if golden.dimensions() != rendered.dimensions() {
+        return Ok(Some(GoldenDifference::ImageSize(name)));
+    }
+
+    let differing_pixels = match pixel_tolerance {
+        None => {
+            if Sha256::digest(golden.as_raw()) == Sha256::digest(rendered.as_raw()) {
+                0
+            } else {
+                golden
+                    .pixels()
+                    .zip(rendered.pixels())
+                    .filter(|(a, b)| a != b)
+                    .count() as u64
+            }
+        }
+        Some(tolerance) => golden
+            .pixels()
+            .zip(rendered.pixels())
+            .filter(|(a, b)| {
+                a.0.iter()
+                    .zip(b.0.iter())
+                    .any(|(x, y)| x.abs_diff(*y) > tolerance)
+            })
+            .count() as u64,
+    };
+
+    Ok((differing_pixels > 0).then_some(GoldenDifference::Image {
+        file: name,
+        differing_pixels,
+    }))
+}
+
+/// Leaves out the timestamps of `manifest.json`, which date the run rather
+/// than describe the collection, so two renders of it compare equal.
+fn strip_timestamps(manifest: &mut Value) {
+    let Some(manifest) = manifest.as_object_mut() else {
+        return;
+    };
+    manifest.remove("generatedAt");
+    manifest.remove("updatedAt");
+    for token in manifest
+        .get_mut("tokens")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+    {
+        if let Some(token) = token.as_object_mut() {
+            token.remove("renderedAt");
+        }
+    }
+}
+
+fn compare_metadata(
+    golden: &Path,
+    rendered: &Path,
+    is_manifest: bool,
+    name: String,
+) -> Result<Option<GoldenDifference>, CustomError> {
+    let read = |path: &Path| -> Result<Value, CustomError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| CustomError::Golden(format!("{}: {}", path.display(), e)))?;
+        let mut value = serde_json::from_str(&contents)
+            .map_err(|e| CustomError::Golden(format!("{}: {}", path.display(), e)))?;
+        if is_manifest {
+            strip_timestamps(&mut value);
+        }
+        Ok(value)
+    };
+
+    Ok((read(golden)? != read(rendered)?).then_some(GoldenDifference::Metadata(name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::{scaffold, Answers};
+    use crate::load_config;
+    use image::{Rgba, RgbaImage};
+    use tempfile::tempdir;
+
+    fn write_image(dir: &Path, name: &str, pixel: [u8; 4]) {
+        RgbaImage::from_pixel(4, 4, Rgba(pixel))
+            .save(dir.join(name))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_compare_dirs() {
+        let golden = tempdir().unwrap();
+        let rendered = tempdir().unwrap();
+
+        write_image(golden.path(), "0.png", [10, 10, 10, 255]);
+        write_image(rendered.path(), "0.png", [12, 10, 10, 255]);
+        fs::write(golden.path().join("0.json"), r#"{"name": "a", "id": 0}"#).unwrap();
+        fs::write(rendered.path().join("0.json"), r#"{"id": 0, "name": "a"}"#).unwrap();
+
+        assert_eq!(
+            compare_dirs(golden.path(), rendered.path(), Some(2)).unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            compare_dirs(golden.path(), rendered.path(), None).unwrap(),
+            vec![GoldenDifference::Image {
+                file: "0.png".to_string(),
+                differing_pixels: 16
+            }]
+        );
+
+        fs::write(rendered.path().join("0.json"), r#"{"name": "b", "id": 0}"#).unwrap();
+        write_image(golden.path(), "1.png", [0, 0, 0, 255]);
+        fs::write(rendered.path().join("1.json"), "{}").unwrap();
+
+        assert_eq!(
+            compare_dirs(golden.path(), rendered.path(), Some(2)).unwrap(),
+            vec![
+                GoldenDifference::Metadata("0.json".to_string()),
+                GoldenDifference::Unexpected("1.json".to_string()),
+                GoldenDifference::Missing("1.png".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compare_golden_twice() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        let base_path = dir.path().join("images").display().to_string();
+        let answers = Answers {
+            name: "Golden".to_string(),
+            supply: 6,
+            width: 8,
+            height: 8,
+        };
+        scaffold(&config_path, &base_path, &answers, false).unwrap();
+        let config = load_config(&config_path.display().to_string(), &[]).unwrap();
+
+        let golden_dir = dir.path().join("golden").display().to_string();
+        let mut args = CompareGoldenArgs {
+            golden_dir,
+            seed: 0,
+            pixel_tolerance: None,
+            update: true,
+        };
+        run(&args, config.clone()).unwrap();
+        args.update = false;
+        run(&args, config.clone()).unwrap();
+        // The manifest of a render a second later has other timestamps.
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        run(&args, config).unwrap();
+    }
+}
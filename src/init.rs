@@ -0,0 +1,265 @@
+use clap::Args;
+use image::{Rgba, RgbaImage};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use tracing::warn;
+
+use crate::{write_json, CustomError};
+
+/// Folder the example layers are written to, like `basePath` relative to
+/// the working directory.
+const EXAMPLE_BASE_PATH: &str = "./images/";
+
+/// Combinations of the example layers under the example rule: 3 backgrounds
+/// with a human (2 eyes) or a robot (laser eyes only).
+const EXAMPLE_COMBINATIONS: u32 = 9;
+
+#[derive(Debug, Args)]
+pub struct InitArgs {
+    /// Collection name; asked for when not given.
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Number of tokens; asked for when not given.
+    #[arg(long)]
+    pub supply: Option<u32>,
+    /// Canvas width in pixels; asked for when not given.
+    #[arg(long)]
+    pub width: Option<u32>,
+    /// Canvas height in pixels; asked for when not given.
+    #[arg(long)]
+    pub height: Option<u32>,
+    /// Take the defaults for everything not given instead of asking.
+    #[arg(long, short = 'y')]
+    pub yes: bool,
+    /// Overwrite an existing config file and example layers.
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// What the starter collection is made of.
+#[derive(Debug, PartialEq)]
+pub struct Answers {
+    pub name: String,
+    pub supply: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Asks `question` until the answer parses, `default` standing for an empty
+/// answer.
+fn ask<T: std::str::FromStr + std::fmt::Display>(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: T,
+) -> io::Result<T> {
+    loop {
+        write!(output, "{} [{}]: ", question, default)?;
+        output.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(default);
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(default);
+        }
+        match answer.parse() {
+            Ok(value) => return Ok(value),
+            Err(_) => writeln!(output, "{:?} is not a valid answer.", answer)?,
+        }
+    }
+}
+
+/// The answers given as flags, asking for the others unless `args.yes`.
+pub fn answers(
+    args: &InitArgs,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<Answers> {
+    let name = match &args.name {
+        Some(name) => name.clone(),
+        None if args.yes => "My Collection".to_string(),
+        None => ask(
+            input,
+            output,
+            "Collection name",
+            "My Collection".to_string(),
+        )?,
+    };
+    let mut number = |given: Option<u32>, question: &str, default: u32| match given {
+        Some(value) => Ok(value),
+        None if args.yes => Ok(default),
+        None => ask(input, output, question, default),
+    };
+    Ok(Answers {
+        name,
+        supply: number(args.supply, "Total supply", 9)?.max(1),
+        width: number(args.width, "Canvas width", 1000)?.max(1),
+        height: number(args.height, "Canvas height", 1000)?.max(1),
+    })
+}
+
+/// The example layers: every value with its color, as `(layer, file, color)`.
+fn example_assets() -> Vec<(&'static str, &'static str, [u8; 4])> {
+    vec![
+        ("Background", "Blue#50.png", [70, 130, 180, 255]),
+        ("Background", "Gold#20.png", [218, 165, 32, 255]),
+        ("Background", "Red#30.png", [178, 34, 34, 255]),
+        ("Body", "Human#60.png", [241, 194, 125, 255]),
+        ("Body", "Robot#40.png", [160, 160, 170, 255]),
+        ("Eyes", "Laser#10.png", [255, 0, 0, 255]),
+        ("Eyes", "Normal#90.png", [20, 20, 20, 255]),
+    ]
+}
+
+/// A placeholder for an asset of `layer`: the background fills the canvas,
+/// the other layers are a shape over it.
+fn example_image(layer: &str, color: [u8; 4], width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        let (fx, fy) = (x as f64 / width as f64, y as f64 / height as f64);
+        let inside = match layer {
+            "Background" => true,
+            "Body" => (fx - 0.5).powi(2) + (fy - 0.6).powi(2) < 0.09,
+            _ => (0.35..0.45).contains(&fx) || (0.55..0.65).contains(&fx),
+        };
+        let inside = inside && (layer != "Eyes" || (0.45..0.52).contains(&fy));
+        if inside {
+            Rgba(color)
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    })
+}
+
+/// The config of the starter collection, reading its layers from `base_path`.
+pub fn starter_config(answers: &Answers, base_path: &str) -> Value {
+    json!({
+        "metadata": {
+            "name": answers.name,
+            "description": format!("{} collection", answers.name)
+        },
+        "image": {
+            "width": answers.width,
+            "height": answers.height
+        },
+        "totalSupply": answers.supply,
+        "basePath": base_path,
+        "outputPath": "./output/",
+        "imageUrl": "https://example.com/images",
+        "layerFolders": ["Background", "Body", "Eyes"],
+        "forcedCombinations": [],
+        "dependencyRules": [
+            {
+                "if": { "layer": "Body", "value": "Robot" },
+                "then": { "layer": "Eyes", "values": ["Laser"] }
+            }
+        ]
+    })
+}
+
+/// Writes the starter config to `config_path` and its example layers to
+/// `base_path`, refusing to replace either unless `force`.
+pub fn scaffold(
+    config_path: &Path,
+    base_path: &str,
+    answers: &Answers,
+    force: bool,
+) -> Result<(), CustomError> {
+    for path in [config_path, Path::new(base_path)] {
+        if path.exists() && !force {
+            return Err(CustomError::Config(format!(
+                "{} already exists; pass --force to overwrite it",
+                path.display()
+            )));
+        }
+    }
+
+    let write_error = |path: &Path, e: &dyn std::fmt::Display| {
+        CustomError::WriteOutput(format!("{}: {}", path.display(), e))
+    };
+    for (layer, file_name, color) in example_assets() {
+        let layer_dir = Path::new(base_path).join(layer);
+        fs::create_dir_all(&layer_dir).map_err(|e| write_error(&layer_dir, &e))?;
+        let path = layer_dir.join(file_name);
+        example_image(layer, color, answers.width, answers.height)
+            .save(&path)
+            .map_err(|e| write_error(&path, &e))?;
+    }
+    write_json(config_path, &starter_config(answers, base_path))
+}
+
+pub fn run(args: &InitArgs, config_path: &str) -> Result<(), Box<dyn Error>> {
+    let mut answers = answers(args, &mut io::stdin().lock(), &mut io::stdout())?;
+    if answers.supply > EXAMPLE_COMBINATIONS {
+        warn!(
+            "The example layers have {} combinations; the starter config uses a supply of {} until you add your own.",
+            EXAMPLE_COMBINATIONS, EXAMPLE_COMBINATIONS
+        );
+        answers.supply = EXAMPLE_COMBINATIONS;
+    }
+    scaffold(
+        Path::new(config_path),
+        EXAMPLE_BASE_PATH,
+        &answers,
+        args.force,
+    )?;
+    println!(
+        "Wrote {} and example layers in {}. Replace them with your own, then run `validate` and `generate`.",
+        config_path, EXAMPLE_BASE_PATH
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_init() {
+        let args = InitArgs {
+            name: None,
+            supply: Some(5),
+            width: None,
+            height: None,
+            yes: false,
+            force: false,
+        };
+        let mut input = Cursor::new("Cool Cats\nwide\n64\n\n");
+        let mut output = Vec::new();
+        let answers = answers(&args, &mut input, &mut output).unwrap();
+        assert_eq!(
+            answers,
+            Answers {
+                name: "Cool Cats".to_string(),
+                supply: 5,
+                width: 64,
+                height: 1000,
+            }
+        );
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("\"wide\" is not a valid answer."));
+
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        let base_path = dir.path().join("images").display().to_string();
+        let answers = Answers {
+            height: 32,
+            ..answers
+        };
+        scaffold(&config_path, &base_path, &answers, false).unwrap();
+        let config: Value =
+            serde_json::from_str(&fs::read_to_string(&config_path).unwrap()).unwrap();
+        assert_eq!(config["metadata"]["name"], "Cool Cats");
+        let robot = image::open(dir.path().join("images/Body/Robot#40.png")).unwrap();
+        assert_eq!((robot.width(), robot.height()), (64, 32));
+        assert!(scaffold(&config_path, &base_path, &answers, false).is_err());
+        assert!(scaffold(&config_path, &base_path, &answers, true).is_ok());
+    }
+}
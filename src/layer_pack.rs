@@ -0,0 +1,310 @@
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path};
+use tracing::info;
+use walkdir::WalkDir;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{layer_folder_dirs, load_layers, Config, CustomError};
+
+pub const PACK_EXTENSION: &str = "nftlayers";
+pub const PACK_MANIFEST_FILE_NAME: &str = "pack.json";
+pub const LAYER_MANIFEST_FILE_NAME: &str = "layer.json";
+const PACK_FORMAT: u32 = 1;
+
+#[derive(Debug, Args)]
+pub struct PackLayersArgs {
+    /// File to write; `.nftlayers` is added when it has no extension.
+    pub output: String,
+    /// License the layers are handed over under, e.g. `CC-BY-4.0` or the
+    /// terms agreed with the studio.
+    #[arg(long)]
+    pub license: String,
+    #[arg(long)]
+    pub author: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportLayersArgs {
+    /// The `.nftlayers` file to import.
+    pub pack: String,
+    /// Folder the layers are extracted into, instead of `basePath`.
+    #[arg(long)]
+    pub into: Option<String>,
+}
+
+/// `pack.json`, at the root of a pack.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PackManifest {
+    pub format: u32,
+    pub license: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Layer folders, in drawing order.
+    pub layers: Vec<String>,
+}
+
+/// `layer.json`, in every layer folder of a pack.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerManifest {
+    pub layer: String,
+    pub files: Vec<PackedFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PackedFile {
+    /// Path in the layer folder, with `/` separators.
+    pub path: String,
+    pub sha256: String,
+}
+
+fn pack_error(e: impl std::fmt::Display) -> CustomError {
+    CustomError::LayerPack(e.to_string())
+}
+
+/// Writes the layer folders in `layer_dirs`, in drawing order, to a pack at
+/// `pack_path`.
+pub fn pack(
+    pack_path: &Path,
+    layer_dirs: &[(String, &Path)],
+    license: &str,
+    author: Option<&str>,
+) -> Result<PackManifest, CustomError> {
+    let file = File::create(pack_path)
+        .map_err(|e| pack_error(format!("{}: {}", pack_path.display(), e)))?;
+    let mut writer = ZipWriter::new(file);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut folders = Vec::new();
+    for (layer, layer_dir) in layer_dirs {
+        let folder = layer_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut files = Vec::new();
+        for entry in WalkDir::new(layer_dir).sort_by_file_name() {
+            let entry = entry.map_err(pack_error)?;
+            if !entry.file_type().is_file() || entry.file_name() == ".DS_Store" {
+                continue;
+            }
+            let relative = entry.path().strip_prefix(layer_dir).map_err(pack_error)?;
+            let path = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let contents = fs::read(entry.path())
+                .map_err(|e| pack_error(format!("{}: {}", entry.path().display(), e)))?;
+            writer
+                .start_file(format!("{}/{}", folder, path), options)
+                .map_err(pack_error)?;
+            writer.write_all(&contents).map_err(pack_error)?;
+            files.push(PackedFile {
+                path,
+                sha256: format!("{:x}", Sha256::digest(&contents)),
+            });
+        }
+
+        let layer_manifest = LayerManifest {
+            layer: layer.clone(),
+            files,
+        };
+        writer
+            .start_file(format!("{}/{}", folder, LAYER_MANIFEST_FILE_NAME), options)
+            .map_err(pack_error)?;
+        serde_json::to_writer_pretty(&mut writer, &layer_manifest).map_err(pack_error)?;
+        folders.push(folder);
+    }
+
+    let manifest = PackManifest {
+        format: PACK_FORMAT,
+        license: license.to_string(),
+        author: author.map(str::to_string),
+        layers: folders,
+    };
+    writer
+        .start_file(PACK_MANIFEST_FILE_NAME, options)
+        .map_err(pack_error)?;
+    serde_json::to_writer_pretty(&mut writer, &manifest).map_err(pack_error)?;
+    writer.finish().map_err(pack_error)?;
+    Ok(manifest)
+}
+
+fn read_entry<R: Read + std::io::Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Vec<u8>, CustomError> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|e| pack_error(format!("{}: {}", name, e)))?;
+    let mut contents = Vec::new();
+    entry.read_to_end(&mut contents).map_err(pack_error)?;
+    Ok(contents)
+}
+
+/// A relative path with no `..`, so extracting it stays in the target folder.
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Checks every file of the pack at `pack_path` against its layer manifest,
+/// then extracts the layer folders into `target`, which must not have them
+/// yet. Nothing is written when the pack is invalid.
+pub fn import(pack_path: &Path, target: &Path) -> Result<PackManifest, CustomError> {
+    let file =
+        File::open(pack_path).map_err(|e| pack_error(format!("{}: {}", pack_path.display(), e)))?;
+    let mut archive = ZipArchive::new(file).map_err(pack_error)?;
+    let manifest: PackManifest =
+        serde_json::from_slice(&read_entry(&mut archive, PACK_MANIFEST_FILE_NAME)?)
+            .map_err(|e| pack_error(format!("{}: {}", PACK_MANIFEST_FILE_NAME, e)))?;
+    if manifest.format != PACK_FORMAT {
+        return Err(pack_error(format!(
+            "format {} is not supported, expected {}",
+            manifest.format, PACK_FORMAT
+        )));
+    }
+
+    let mut expected = HashSet::from([PACK_MANIFEST_FILE_NAME.to_string()]);
+    let mut files = Vec::new();
+    for folder in &manifest.layers {
+        if !is_safe_path(folder) || folder.contains('/') {
+            return Err(pack_error(format!("invalid layer folder {:?}", folder)));
+        }
+        if target.join(folder).exists() {
+            return Err(pack_error(format!(
+                "{} already exists",
+                target.join(folder).display()
+            )));
+        }
+        let layer_manifest_path = format!("{}/{}", folder, LAYER_MANIFEST_FILE_NAME);
+        let layer_manifest: LayerManifest =
+            serde_json::from_slice(&read_entry(&mut archive, &layer_manifest_path)?)
+                .map_err(|e| pack_error(format!("{}: {}", layer_manifest_path, e)))?;
+        expected.insert(layer_manifest_path);
+
+        for packed in layer_manifest.files {
+            if !is_safe_path(&packed.path) {
+                return Err(pack_error(format!("invalid file path {:?}", packed.path)));
+            }
+            let name = format!("{}/{}", folder, packed.path);
+            let contents = read_entry(&mut archive, &name)?;
+            if format!("{:x}", Sha256::digest(&contents)) != packed.sha256 {
+                return Err(pack_error(format!("{} doesn't match its sha256", name)));
+            }
+            expected.insert(name.clone());
+            files.push((name, contents));
+        }
+    }
+    if let Some(unlisted) = archive
+        .file_names()
+        .find(|name| !name.ends_with('/') && !expected.contains(*name))
+    {
+        return Err(pack_error(format!(
+            "{} is not listed in any layer manifest",
+            unlisted
+        )));
+    }
+
+    for (name, contents) in files {
+        let path = target.join(&name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| pack_error(format!("{}: {}", parent.display(), e)))?;
+        }
+        fs::write(&path, contents).map_err(|e| pack_error(format!("{}: {}", path.display(), e)))?;
+    }
+    Ok(manifest)
+}
+
+pub fn pack_layers(args: &PackLayersArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    // Only layers the generator accepts are packed.
+    load_layers(config)?;
+    let dirs = layer_folder_dirs(config)?;
+    let layers: Vec<(String, &Path)> = dirs
+        .iter()
+        .map(|(name, dir)| (name.clone(), Path::new(dir)))
+        .collect();
+
+    let mut pack_path = Path::new(&args.output).to_path_buf();
+    if pack_path.extension().is_none() {
+        pack_path.set_extension(PACK_EXTENSION);
+    }
+    let manifest = pack(&pack_path, &layers, &args.license, args.author.as_deref())?;
+    info!(
+        "{} layers packed into {}.",
+        manifest.layers.len(),
+        pack_path.display()
+    );
+    Ok(())
+}
+
+pub fn import_layers(args: &ImportLayersArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let target = args.into.as_deref().unwrap_or(&config.base_path);
+    let manifest = import(Path::new(&args.pack), Path::new(target))?;
+    info!(
+        "Layers imported into {}: {}.",
+        target,
+        manifest.layers.join(", ")
+    );
+    match &manifest.author {
+        Some(author) => info!("License: {} ({}).", manifest.license, author),
+        None => info!("License: {}.", manifest.license),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_pack_and_import() {
+        let layers = tempdir().unwrap();
+        let background = layers.path().join("Background");
+        let hair = layers.path().join("Hair#2");
+        fs::create_dir_all(&background).unwrap();
+        fs::create_dir_all(hair.join("Black#700")).unwrap();
+        fs::write(background.join("Blue#50.png"), b"blue").unwrap();
+        fs::write(hair.join("Black#700").join("Long#2.png"), b"long").unwrap();
+
+        let output = tempdir().unwrap();
+        let pack_path = output.path().join("layers.nftlayers");
+        let manifest = pack(
+            &pack_path,
+            &[
+                ("Background".to_string(), background.as_path()),
+                ("Hair".to_string(), hair.as_path()),
+            ],
+            "CC-BY-4.0",
+            Some("Artist"),
+        )
+        .unwrap();
+        assert_eq!(manifest.layers, vec!["Background", "Hair#2"]);
+
+        let target = tempdir().unwrap();
+        assert_eq!(import(&pack_path, target.path()).unwrap(), manifest);
+        assert_eq!(
+            fs::read(target.path().join("Hair#2/Black#700/Long#2.png")).unwrap(),
+            b"long"
+        );
+        // The layer folders are there now, so a second import is refused.
+        assert!(import(&pack_path, target.path()).is_err());
+
+        assert!(is_safe_path("Black#700/Long#2.png"));
+        assert!(!is_safe_path("../Long#2.png"));
+        assert!(!is_safe_path("/etc/passwd"));
+    }
+}
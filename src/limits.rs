@@ -0,0 +1,140 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+use crate::metadata::MetadataProfile;
+use crate::CustomError;
+
+/// Limits a marketplace puts on token metadata. `generate` warns about every
+/// token going over them; unset limits aren't checked.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataLimits {
+    pub max_attributes: Option<usize>,
+    /// In characters, for trait types and values alike.
+    pub max_value_length: Option<usize>,
+    /// Size of the metadata file in bytes.
+    pub max_json_bytes: Option<usize>,
+}
+
+impl MetadataLimits {
+    /// What goes over the limits in the metadata of one token, written as
+    /// `json`.
+    pub fn check(&self, json: &str, profile: &MetadataProfile) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(max_json_bytes) = self.max_json_bytes {
+            if json.len() > max_json_bytes {
+                problems.push(format!(
+                    "{} bytes, over the limit of {}",
+                    json.len(),
+                    max_json_bytes
+                ));
+            }
+        }
+
+        let metadata: Value = match serde_json::from_str(json) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                problems.push(format!("not valid JSON: {}", e));
+                return problems;
+            }
+        };
+        let attributes = metadata
+            .get("attributes")
+            .and_then(Value::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        if let Some(max_attributes) = self.max_attributes {
+            if attributes.len() > max_attributes {
+                problems.push(format!(
+                    "{} attributes, over the limit of {}",
+                    attributes.len(),
+                    max_attributes
+                ));
+            }
+        }
+        if let Some(max_value_length) = self.max_value_length {
+            for attribute in attributes {
+                let trait_type = profile.trait_type(attribute).unwrap_or_default();
+                let value = profile.value(attribute).and_then(Value::as_str);
+                let mut texts = vec![(trait_type, format!("trait type {}", trait_type))];
+                if let Some(value) = value {
+                    texts.push((value, format!("value {} of {}", value, trait_type)));
+                }
+                for (text, label) in texts {
+                    let length = text.chars().count();
+                    if length > max_value_length {
+                        problems.push(format!(
+                            "{} is {} characters, over the limit of {}",
+                            label, length, max_value_length
+                        ));
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    /// Checks the metadata files in `output_path` and returns the problems
+    /// found, by file name.
+    pub fn check_files(
+        &self,
+        output_path: &Path,
+        file_names: &[String],
+        profile: &MetadataProfile,
+    ) -> Result<Vec<(String, String)>, CustomError> {
+        let mut problems = Vec::new();
+        for file_name in file_names {
+            let file_path = output_path.join(file_name);
+            let json = fs::read_to_string(&file_path)
+                .map_err(|e| CustomError::WriteOutput(format!("{}: {}", file_path.display(), e)))?;
+            problems.extend(
+                self.check(&json, profile)
+                    .into_iter()
+                    .map(|problem| (file_name.clone(), problem)),
+            );
+        }
+        Ok(problems)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_limits() {
+        let limits: MetadataLimits = serde_json::from_str(
+            r#"{"maxAttributes": 2, "maxValueLength": 8, "maxJsonBytes": 200}"#,
+        )
+        .unwrap();
+        let profile = MetadataProfile::default();
+
+        let within = r#"{"attributes": [{"trait_type": "Eyes", "value": "Laser"}]}"#;
+        assert!(limits.check(within, &profile).is_empty());
+
+        let over = r#"{"attributes": [
+            {"trait_type": "Eyes", "value": "Laser"},
+            {"trait_type": "Background", "value": "Blue"},
+            {"trait_type": "Hat", "value": "Extraordinary"}
+        ]}"#;
+        assert_eq!(
+            limits.check(over, &profile),
+            vec![
+                "3 attributes, over the limit of 2",
+                "trait type Background is 10 characters, over the limit of 8",
+                "value Extraordinary of Hat is 13 characters, over the limit of 8",
+            ]
+        );
+
+        let small = MetadataLimits {
+            max_json_bytes: Some(10),
+            ..MetadataLimits::default()
+        };
+        assert_eq!(
+            small.check(within, &profile),
+            vec![format!("{} bytes, over the limit of 10", within.len())]
+        );
+    }
+}
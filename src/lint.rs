@@ -0,0 +1,256 @@
+use clap::Args;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::file_names::FileNames;
+use crate::{Config, CustomError};
+
+#[derive(Debug, Args)]
+pub struct LintMetadataArgs {
+    /// Output folder to check, such as the one of an earlier run; the output
+    /// path of the config by default.
+    pub path: Option<String>,
+}
+
+/// Fields EIP-721 metadata is expected to have.
+const REQUIRED_FIELDS: [&str; 3] = ["name", "description", "image"];
+
+/// Fields holding a URL.
+const URL_FIELDS: [&str; 3] = ["image", "animation_url", "external_url"];
+
+/// The URL schemes marketplaces fetch media from.
+const URL_SCHEMES: [&str; 5] = ["ipfs://", "ar://", "https://", "http://", "data:"];
+
+/// The keys of an OpenSea attribute; marketplaces ignore, or choke on, others.
+const ATTRIBUTE_KEYS: [&str; 4] = ["trait_type", "value", "display_type", "max_value"];
+
+/// The `display_type`s OpenSea shows.
+const DISPLAY_TYPES: [&str; 4] = ["number", "boost_number", "boost_percentage", "date"];
+
+/// What is wrong with `url`, if anything.
+fn url_violation(url: &str) -> Option<String> {
+    if url.contains(char::is_whitespace) {
+        return Some("contains whitespace".to_string());
+    }
+    let Some(scheme) = URL_SCHEMES.iter().find(|scheme| url.starts_with(*scheme)) else {
+        return Some("is not an ipfs://, ar://, https://, http:// or data: URL".to_string());
+    };
+    let rest = &url[scheme.len()..];
+    if rest.is_empty() {
+        return Some("has nothing after its scheme".to_string());
+    }
+    if *scheme == "ipfs://" && rest.starts_with("ipfs/") {
+        return Some("repeats ipfs/ after ipfs://".to_string());
+    }
+    None
+}
+
+/// Every way `metadata` breaks the OpenSea and EIP-721 expectations.
+pub fn lint_metadata(metadata: &Value) -> Vec<String> {
+    let Some(metadata) = metadata.as_object() else {
+        return vec!["is not a JSON object".to_string()];
+    };
+    let mut violations = Vec::new();
+
+    for field in REQUIRED_FIELDS {
+        match metadata.get(field) {
+            None => violations.push(format!("{} is missing", field)),
+            Some(Value::String(text)) if text.trim().is_empty() => {
+                violations.push(format!("{} is empty", field))
+            }
+            Some(Value::String(_)) => {}
+            Some(_) => violations.push(format!("{} is not a string", field)),
+        }
+    }
+    // The required fields were checked for being empty or not a string.
+    for field in URL_FIELDS {
+        match metadata.get(field) {
+            Some(Value::String(url)) if !url.trim().is_empty() => {
+                if let Some(violation) = url_violation(url) {
+                    violations.push(format!("{} {:?} {}", field, url, violation));
+                }
+            }
+            _ if REQUIRED_FIELDS.contains(&field) => {}
+            Some(Value::String(_)) => violations.push(format!("{} is empty", field)),
+            Some(_) => violations.push(format!("{} is not a string", field)),
+            None => {}
+        }
+    }
+    if let Some(color) = metadata.get("background_color") {
+        let valid = color
+            .as_str()
+            .is_some_and(|color| color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit()));
+        if !valid {
+            violations.push(format!(
+                "background_color {} is not six hex digits without #",
+                color
+            ));
+        }
+    }
+
+    let attributes = match metadata.get("attributes") {
+        None => return violations,
+        Some(Value::Array(attributes)) => attributes,
+        Some(_) => {
+            violations.push("attributes is not an array".to_string());
+            return violations;
+        }
+    };
+    let mut trait_types = HashSet::new();
+    for (index, attribute) in attributes.iter().enumerate() {
+        let label = format!("attributes[{}]", index);
+        let Some(attribute) = attribute.as_object() else {
+            violations.push(format!("{} is not an object", label));
+            continue;
+        };
+        for (key, value) in attribute {
+            if value.is_null() {
+                violations.push(format!("{}.{} is null, as NaN is written", label, key));
+            }
+            if !ATTRIBUTE_KEYS.contains(&key.as_str()) {
+                violations.push(format!("{} has the nonstandard key {}", label, key));
+            }
+        }
+
+        match attribute.get("trait_type") {
+            None => violations.push(format!("{} has no trait_type", label)),
+            Some(Value::String(trait_type)) if trait_type.is_empty() => {
+                violations.push(format!("{}.trait_type is empty", label))
+            }
+            Some(Value::String(trait_type)) => {
+                if !trait_types.insert(trait_type.as_str()) {
+                    violations.push(format!("{} repeats the trait_type {}", label, trait_type));
+                }
+            }
+            Some(_) => violations.push(format!("{}.trait_type is not a string", label)),
+        }
+
+        let value = attribute.get("value");
+        match value {
+            None => violations.push(format!("{} has no value", label)),
+            Some(Value::String(_) | Value::Number(_) | Value::Null) => {}
+            Some(_) => violations.push(format!("{}.value is neither a string nor a number", label)),
+        }
+        if let Some(display_type) = attribute.get("display_type") {
+            match display_type.as_str() {
+                Some(display_type) if DISPLAY_TYPES.contains(&display_type) => {
+                    if value.is_some_and(|value| !value.is_number() && !value.is_null()) {
+                        violations.push(format!(
+                            "{} has the display_type {} but a value that is not a number",
+                            label, display_type
+                        ));
+                    }
+                }
+                _ => violations.push(format!(
+                    "{}.display_type {} is not one of {}",
+                    label,
+                    display_type,
+                    DISPLAY_TYPES.join(", ")
+                )),
+            }
+        }
+        if let Some(max_value) = attribute.get("max_value").filter(|max| !max.is_null()) {
+            match (max_value.as_f64(), value.and_then(Value::as_f64)) {
+                (None, _) => violations.push(format!("{}.max_value is not a number", label)),
+                (Some(max_value), Some(value)) if value > max_value => violations.push(format!(
+                    "{}.value {} is over its max_value {}",
+                    label, value, max_value
+                )),
+                _ => {}
+            }
+        }
+    }
+    violations
+}
+
+pub fn run(args: &LintMetadataArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let output_path = Path::new(args.path.as_deref().unwrap_or(&config.output_path));
+    let files = FileNames::compile(config)?.token_files(output_path)?;
+
+    let mut failed = 0;
+    for (_, path) in &files {
+        let violations = match fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str::<Value>(&contents) {
+                Ok(metadata) => lint_metadata(&metadata),
+                Err(e) => vec![format!("is not valid JSON: {}", e)],
+            },
+            Err(e) => vec![format!("can't be read: {}", e)],
+        };
+        for violation in &violations {
+            println!("{}: {}", path.display(), violation);
+        }
+        if !violations.is_empty() {
+            failed += 1;
+        }
+    }
+
+    if failed == 0 {
+        println!("All {} metadata files follow the standard.", files.len());
+        Ok(())
+    } else {
+        Err(CustomError::Lint(format!(
+            "{} of {} metadata files break the standard",
+            failed,
+            files.len()
+        ))
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_lint_metadata() {
+        let valid = json!({
+            "name": "Test #1",
+            "description": "A drop",
+            "image": "ipfs://bafy/1.png",
+            "external_url": "https://example.com/1",
+            "background_color": "00ff00",
+            "attributes": [
+                {"trait_type": "Eyes", "value": "Laser"},
+                {"display_type": "boost_number", "trait_type": "Level", "value": 3, "max_value": 10}
+            ]
+        });
+        assert_eq!(lint_metadata(&valid), Vec::<String>::new());
+
+        let invalid = json!({
+            "name": "",
+            "image": "ipfs://ipfs/bafy/1.png",
+            "animation_url": "/videos/1.mp4",
+            "background_color": "#00ff00",
+            "attributes": [
+                {"trait_type": "Eyes", "value": "Laser", "weight": null},
+                {"trait_type": "Eyes", "value": "Normal"},
+                {"display_type": "number", "trait_type": "Level", "value": "high"},
+                {"display_type": "rank", "trait_type": "Power", "value": 12, "max_value": 10},
+                {"value": ["Red"]}
+            ]
+        });
+        assert_eq!(
+            lint_metadata(&invalid),
+            vec![
+                "name is empty",
+                "description is missing",
+                "image \"ipfs://ipfs/bafy/1.png\" repeats ipfs/ after ipfs://",
+                "animation_url \"/videos/1.mp4\" is not an ipfs://, ar://, https://, http:// or data: URL",
+                "background_color \"#00ff00\" is not six hex digits without #",
+                "attributes[0].weight is null, as NaN is written",
+                "attributes[0] has the nonstandard key weight",
+                "attributes[1] repeats the trait_type Eyes",
+                "attributes[2] has the display_type number but a value that is not a number",
+                "attributes[3].display_type \"rank\" is not one of number, boost_number, boost_percentage, date",
+                "attributes[3].value 12 is over its max_value 10",
+                "attributes[4] has no trait_type",
+                "attributes[4].value is neither a string nor a number",
+            ]
+        );
+        assert_eq!(lint_metadata(&json!([])), vec!["is not a JSON object"]);
+    }
+}
@@ -0,0 +1,210 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::metadata::MetadataProfile;
+use crate::rename::{RenameRule, TraitRenames};
+use crate::{write_json, CustomError};
+
+/// Folder of the output holding a folder of metadata files per locale.
+pub const LOCALIZED_DIR_NAME: &str = "metadata";
+
+/// Translations of the metadata, written the ERC-1155 way: every token's
+/// metadata gets a `localization` field pointing at its translated copies.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Localization {
+    /// Locale of the metadata as generated, e.g. `en`.
+    pub default: String,
+    /// Where the translated files are served, with `{locale}` and `{id}`
+    /// placeholders, e.g. `ipfs://<CID>/metadata/{locale}/{id}.json`.
+    pub uri: String,
+    pub locales: BTreeMap<String, Locale>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Locale {
+    /// Fields replacing the ones of the metadata, like `description`.
+    pub metadata: HashMap<String, Value>,
+    pub trait_types: HashMap<String, String>,
+    /// Translations of the values, as they are written in the metadata, in
+    /// the mapping format of `rename-traits`.
+    pub values: HashMap<String, RenameRule>,
+}
+
+fn is_locale_name(locale: &str) -> bool {
+    !locale.is_empty()
+        && locale
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Localization {
+    /// Every locale, the default one first.
+    pub fn locales(&self) -> Vec<&str> {
+        let mut locales = vec![self.default.as_str()];
+        locales.extend(
+            self.locales
+                .keys()
+                .map(String::as_str)
+                .filter(|locale| *locale != self.default),
+        );
+        locales
+    }
+
+    pub fn check(&self) -> Result<(), CustomError> {
+        match self
+            .locales()
+            .into_iter()
+            .find(|locale| !is_locale_name(locale))
+        {
+            Some(locale) => Err(CustomError::Config(format!(
+                "localization locale {:?} is not a folder name",
+                locale
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Locale {
+    /// A translated copy of the metadata of a token.
+    pub fn translate(&self, metadata: &Value, profile: &MetadataProfile) -> Value {
+        let mut translated = metadata.clone();
+        for (field, value) in &self.metadata {
+            translated[field] = value.clone();
+        }
+        let values = TraitRenames::from_rules(self.values.clone());
+        let Some(attributes) = translated
+            .get_mut("attributes")
+            .and_then(Value::as_array_mut)
+        else {
+            return translated;
+        };
+        for attribute in attributes {
+            let Some(trait_type) = profile.trait_type(attribute).map(str::to_string) else {
+                continue;
+            };
+            if let Some(value) = profile.value(attribute).and_then(Value::as_str) {
+                if let Some(value) = values.rename(&trait_type, value) {
+                    attribute[&profile.value_key] = Value::from(value.clone());
+                }
+            }
+            if let Some(translated_type) = self.trait_types.get(&trait_type) {
+                attribute[&profile.trait_type_key] = Value::from(translated_type.clone());
+            }
+        }
+        translated
+    }
+}
+
+/// Adds the `localization` field to the metadata files `file_names` of
+/// `output_path` and writes their copy in every locale to
+/// `metadata/<locale>/`, under their file name.
+pub fn localize_files(
+    output_path: &Path,
+    file_names: &[String],
+    localization: &Localization,
+    profile: &MetadataProfile,
+) -> Result<(), CustomError> {
+    let write_error = |path: &Path, e: &dyn std::fmt::Display| {
+        CustomError::WriteOutput(format!("{}: {}", path.display(), e))
+    };
+    let default_locale = Locale::default();
+    for locale in localization.locales() {
+        let locale_dir = output_path.join(LOCALIZED_DIR_NAME).join(locale);
+        fs::create_dir_all(&locale_dir).map_err(|e| write_error(&locale_dir, &e))?;
+    }
+
+    for file_name in file_names {
+        let file_path = output_path.join(file_name);
+        let contents = fs::read_to_string(&file_path).map_err(|e| write_error(&file_path, &e))?;
+        let mut metadata: Value =
+            serde_json::from_str(&contents).map_err(|e| write_error(&file_path, &e))?;
+        metadata["localization"] = json!({
+            "uri": localization.uri,
+            "default": localization.default,
+            "locales": localization.locales(),
+        });
+
+        for locale in localization.locales() {
+            let translated = localization
+                .locales
+                .get(locale)
+                .unwrap_or(&default_locale)
+                .translate(&metadata, profile);
+            let locale_path = output_path
+                .join(LOCALIZED_DIR_NAME)
+                .join(locale)
+                .join(file_path.file_name().unwrap_or_default());
+            write_json(&locale_path, &translated)?;
+        }
+
+        write_json(&file_path, &metadata)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_localize_files() {
+        let localization: Localization = serde_json::from_str(
+            r#"{
+                "default": "en",
+                "uri": "ipfs://bafy/metadata/{locale}/{id}.json",
+                "locales": {
+                    "ja": {
+                        "metadata": {"description": "テスト"},
+                        "traitTypes": {"Eyes": "目"},
+                        "values": {"Eyes": {"Laser": "レーザー"}}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        localization.check().unwrap();
+        assert_eq!(localization.locales(), vec!["en", "ja"]);
+
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("3.json"),
+            r#"{"description": "Test", "attributes": [{"trait_type": "Eyes", "value": "Laser"}]}"#,
+        )
+        .unwrap();
+        localize_files(
+            dir.path(),
+            &["3.json".to_string()],
+            &localization,
+            &MetadataProfile::default(),
+        )
+        .unwrap();
+
+        let read = |path: &Path| -> Value {
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+        };
+        let metadata = read(&dir.path().join("3.json"));
+        assert_eq!(metadata["localization"]["locales"], json!(["en", "ja"]));
+        let japanese = read(&dir.path().join("metadata/ja/3.json"));
+        assert_eq!(japanese["description"], "テスト");
+        assert_eq!(
+            japanese["attributes"][0],
+            json!({"trait_type": "目", "value": "レーザー"})
+        );
+        let english = read(&dir.path().join("metadata/en/3.json"));
+        assert_eq!(english, metadata);
+
+        let invalid = Localization {
+            default: "../en".to_string(),
+            ..localization
+        };
+        assert!(invalid.check().is_err());
+    }
+}
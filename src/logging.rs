@@ -0,0 +1,68 @@
+use std::fmt;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Most detailed level logged for the `-q` and `-v` flags: warnings only,
+/// progress (default), stages and tokens with `-v`, everything with `-vv`.
+pub fn max_level(quiet: bool, verbose: u8) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::WARN,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    }
+}
+
+/// Prints events as the plain lines the generator always printed, warnings
+/// prefixed, without timestamps or spans.
+struct PlainFormat;
+
+impl<S, N> FormatEvent<S, N> for PlainFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::ERROR => write!(writer, "Error: ")?,
+            Level::WARN => write!(writer, "Warning: ")?,
+            _ => {}
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+/// Sends the log to stdout: plain lines by default, and with `-v` timed
+/// lines with their level and the stage and token they belong to.
+pub fn init(quiet: bool, verbose: u8) {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(max_level(quiet, verbose))
+        .with_writer(std::io::stdout);
+    if verbose == 0 {
+        builder.event_format(PlainFormat).init();
+    } else {
+        builder.with_target(false).init();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_level() {
+        assert_eq!(max_level(false, 0), LevelFilter::INFO);
+        assert_eq!(max_level(false, 1), LevelFilter::DEBUG);
+        assert_eq!(max_level(false, 3), LevelFilter::TRACE);
+        assert_eq!(max_level(true, 2), LevelFilter::WARN);
+    }
+}
@@ -1,55 +1,322 @@
-use image::{DynamicImage, ImageBuffer};
+mod animation;
+mod audio;
+mod background;
+mod base_uri;
+mod batch;
+mod blend;
+mod caps;
+mod cli;
+mod contrast;
+mod dimensions;
+mod disk_space;
+mod dna;
+mod editions;
+mod encode;
+mod estimate;
+mod explore;
+mod export;
+mod file_names;
+mod fit;
+mod fixed;
+mod forced;
+mod golden;
+mod init;
+mod layer_pack;
+mod limits;
+mod lint;
+mod localization;
+mod logging;
+mod manifest;
+mod metadata;
+mod name_template;
+mod namespace;
+mod one_of_ones;
+mod order;
+mod overrides;
+mod palette;
+mod paths;
+mod perceptual;
+mod permutations;
+mod postprocess;
+mod prefetch;
+mod preprocess;
+mod preview;
+mod provenance;
+mod rarity;
+mod recolor;
+mod rename;
+mod replay;
+mod rerender;
+mod reroll;
+mod rules;
+mod schema;
+mod shuffle;
+mod sidecar;
+mod spritesheet;
+mod stacking;
+mod stats;
+mod trait_fields;
+mod transform;
+mod tui;
+mod unused;
+mod upload;
+mod variants;
+mod video;
+mod watermark;
+mod weights;
+mod writer;
+
+use animation::{Animations, TraitAnimation};
+use audio::{Audio, AudioSettings};
+use background::Background;
+use blend::BlendMode;
+use caps::{Caps, TraitCap, WeightMode};
+use clap::Parser;
+use cli::{Cli, Command, GenerateArgs};
+use contrast::ContrastCheck;
+use dimensions::DimensionPolicy;
+use dna::{DnaAlgorithm, DnaReissue};
+use editions::Edition;
+use encode::{FileSizeLimit, OversizePolicy, PngOptimization, PngOutput};
+use file_names::FileNames;
+use fit::Fit;
+use fixed::{FixedToken, UniqueCombination};
+use image::{imageops, DynamicImage, ImageBuffer, RgbaImage};
+use limits::MetadataLimits;
+use localization::Localization;
+use metadata::{DisplayType, MetadataProfile};
+use name_template::NameTemplate;
+use one_of_ones::OneOfOne;
+use one_of_ones::OneOfOnes;
+use order::TokenOrder;
+use overrides::Override;
+use palette::Palette;
+use paths::LayerLayout;
+use perceptual::NearDuplicates;
+use permutations::Constraints;
+use postprocess::{PostEffect, PostProcessing};
+use prefetch::{DecodedLayers, Prefetcher};
+use preprocess::{PreprocessStep, Preprocessing, ResizeFilter};
+use recolor::{Recolor, Recolors};
+use rename::{DisplayNames, TraitRenames};
+use replay::ReplayLog;
+use rules::{DependencyRule, Rules, TraitRef};
+use spritesheet::Spritesheet;
+use stacking::{Stacking, TraitZIndex};
+use trait_fields::{TraitFieldTable, TraitFields};
+use transform::{TraitTransform, Transform, Transforms};
+use variants::Variants;
+use video::{Video, VideoSettings};
+use watermark::{Stamp, Watermark};
+use weights::Weights;
+use writer::{FileSink, OutputWriter};
 
 use lazy_static::lazy_static;
 use rand::distributions::Uniform;
 use rand::prelude::SliceRandom;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use rayon::prelude::*;
 use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::to_string_pretty;
 use serde_json::Value;
-use std::collections::hash_map::DefaultHasher;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::error::Error;
 use std::fs::{read_dir, File};
-use std::hash::{Hash, Hasher};
-use std::io::Write;
-use std::path::Path;
+use std::io::BufReader;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
 use std::time::Instant;
 use std::{fmt, fs};
-use strsim::levenshtein;
+use tracing::{debug, debug_span, info, info_span, trace, warn};
 use walkdir::WalkDir;
 
 lazy_static! {
-    static ref RE_WEIGHT: Regex = Regex::new(r"#\d*").unwrap();
-    static ref RE_FILENAME: Regex = Regex::new(r"^(.*?)(?:#(\d+))?\..*$").unwrap();
-    static ref RE_PATH: Regex = Regex::new(r"#\d+|\.\w+$").unwrap();
+    static ref RE_WEIGHT: Regex = Regex::new(r"#(\d+(?:\.\d+)?%|\d+-\d+|\d+)").unwrap();
+    static ref RE_FILENAME: Regex =
+        Regex::new(r"^(.*?)(?:#(\d+(?:\.\d+)?%|\d+-\d+|\d+))?\..*$").unwrap();
+    static ref RE_PATH: Regex = Regex::new(r"#(?:\d+(?:\.\d+)?%|\d+-\d+|\d+)|\.\w+$").unwrap();
     static ref ALLOWED_EXTENSION: &'static str = "png";
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct Image {
     width: u32,
     height: u32,
+    #[serde(default)]
+    padding: Padding,
+    output: Option<OutputSize>,
+    pixel_art: Option<PixelArt>,
 }
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+
+/// Size the composed image is scaled to before it is written, so the layers
+/// can be drawn at the size of the masters and the files stay light.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, JsonSchema)]
 #[serde(rename_all = "camelCase")]
-struct Combination {
-    layer: String,
-    value: String,
+struct OutputSize {
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    filter: ResizeFilter,
+}
+
+/// Pixel art is composed at its native resolution and every pixel is written
+/// as a `scale` by `scale` block, so the assets are drawn at the native size.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct PixelArt {
+    scale: u32,
+    grid: Option<GridLines>,
+}
+
+/// Lines drawn between the blocks of upscaled pixel art.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct GridLines {
+    /// A `#rrggbb` color.
+    color: String,
+    #[serde(default = "default_grid_width")]
+    width: u32,
+}
+
+fn default_grid_width() -> u32 {
+    1
+}
+
+/// Room around the final image on each side. Layers are composed on a canvas
+/// grown by the padding, which is then cropped off, so assets exported with
+/// bleed line up with the final image.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase", default)]
+struct Padding {
+    top: u32,
+    right: u32,
+    bottom: u32,
+    left: u32,
+}
+
+impl Image {
+    /// Size of the canvas the layers are drawn on, padding included.
+    fn canvas_size(&self) -> (u32, u32) {
+        (
+            self.width + self.padding.left + self.padding.right,
+            self.height + self.padding.top + self.padding.bottom,
+        )
+    }
+
+    /// Crops the padding off a composed canvas.
+    fn crop_padding(&self, canvas: RgbaImage) -> RgbaImage {
+        if self.padding == Padding::default() {
+            return canvas;
+        }
+        imageops::crop_imm(
+            &canvas,
+            self.padding.left,
+            self.padding.top,
+            self.width,
+            self.height,
+        )
+        .to_image()
+    }
+
+    /// Checks what serde can't: `output` and `pixelArt` exclude one another,
+    /// and grid lines must leave room for the pixels.
+    fn check(&self) -> Result<(), CustomError> {
+        let Some(pixel_art) = &self.pixel_art else {
+            return Ok(());
+        };
+        if self.output.is_some() {
+            return Err(CustomError::Config(
+                "image.output and image.pixelArt can't both be set".to_string(),
+            ));
+        }
+        if pixel_art.scale == 0 {
+            return Err(CustomError::Config(
+                "image.pixelArt.scale must be at least 1".to_string(),
+            ));
+        }
+        if let Some(grid) = &pixel_art.grid {
+            palette::parse_color(&grid.color).map_err(|e| CustomError::Config(e.to_string()))?;
+            if grid.width == 0 || grid.width >= pixel_art.scale {
+                return Err(CustomError::Config(format!(
+                    "image.pixelArt.grid.width must be between 1 and {}",
+                    pixel_art.scale.saturating_sub(1)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Size of a composed image once scaled.
+    fn output_size(&self) -> (u32, u32) {
+        match (&self.pixel_art, self.output) {
+            (Some(pixel_art), _) => (self.width * pixel_art.scale, self.height * pixel_art.scale),
+            (None, Some(output)) => (output.width, output.height),
+            (None, None) => (self.width, self.height),
+        }
+    }
+
+    /// Scales a composed image, padding already cropped, to the `output` size,
+    /// or upscales pixel art.
+    fn scale_to_output(&self, image: RgbaImage) -> RgbaImage {
+        if let Some(pixel_art) = &self.pixel_art {
+            return upscale_pixel_art(&image, pixel_art);
+        }
+        match self.output {
+            Some(output) if image.dimensions() != (output.width, output.height) => {
+                imageops::resize(
+                    &image,
+                    output.width,
+                    output.height,
+                    output.filter.filter_type(),
+                )
+            }
+            _ => image,
+        }
+    }
+}
+
+/// Draws every pixel of `image` as a block of `scale` pixels a side, with the
+/// grid lines over the first columns and rows of every block but the first.
+fn upscale_pixel_art(image: &RgbaImage, pixel_art: &PixelArt) -> RgbaImage {
+    let scale = pixel_art.scale;
+    let mut upscaled = imageops::resize(
+        image,
+        image.width() * scale,
+        image.height() * scale,
+        imageops::FilterType::Nearest,
+    );
+    if let Some(grid) = &pixel_art.grid {
+        let [r, g, b] = palette::parse_color(&grid.color).unwrap_or_default();
+        let on_line = |position: u32| position >= scale && position % scale < grid.width;
+        for (x, y, pixel) in upscaled.enumerate_pixels_mut() {
+            if on_line(x) || on_line(y) {
+                *pixel = image::Rgba([r, g, b, 255]);
+            }
+        }
+    }
+    upscaled
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct ForcedCombo {
     layer: Layer,
     value: String,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
 #[serde(untagged)]
 #[allow(non_snake_case)]
 enum Layer {
@@ -57,172 +324,694 @@ enum Layer {
     Complex { mainLayer: String, subLayer: String },
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+enum Visibility {
+    #[default]
+    Public,
+    RarityOnly,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct LayerOptions {
+    name: String,
+    /// The folder of the layer, when it isn't the one named after it in
+    /// `basePath`.
+    path: Option<String>,
+    #[serde(default)]
+    visibility: Visibility,
+    #[serde(default)]
+    skip_probability: f64,
+    #[serde(default)]
+    emit_none: bool,
+    #[serde(default)]
+    blend_mode: BlendMode,
+    #[serde(default)]
+    offset_x: i64,
+    #[serde(default)]
+    offset_y: i64,
+    #[serde(default)]
+    fit: Fit,
+    display_type: Option<DisplayType>,
+    #[serde(flatten)]
+    transform: Transform,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(untagged)]
+enum LayerFolder {
+    Simple(String),
+    Detailed(LayerOptions),
+}
+
+impl LayerFolder {
+    fn name(&self) -> &str {
+        match self {
+            LayerFolder::Simple(name) => name,
+            LayerFolder::Detailed(options) => &options.name,
+        }
+    }
+
+    fn path(&self) -> Option<&str> {
+        match self {
+            LayerFolder::Simple(_) => None,
+            LayerFolder::Detailed(options) => options.path.as_deref(),
+        }
+    }
+
+    fn visibility(&self) -> Visibility {
+        match self {
+            LayerFolder::Simple(_) => Visibility::Public,
+            LayerFolder::Detailed(options) => options.visibility,
+        }
+    }
+
+    fn skip_probability(&self) -> f64 {
+        match self {
+            LayerFolder::Simple(_) => 0.0,
+            LayerFolder::Detailed(options) => options.skip_probability,
+        }
+    }
+
+    fn emit_none(&self) -> bool {
+        match self {
+            LayerFolder::Simple(_) => false,
+            LayerFolder::Detailed(options) => options.emit_none,
+        }
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        match self {
+            LayerFolder::Simple(_) => BlendMode::Normal,
+            LayerFolder::Detailed(options) => options.blend_mode,
+        }
+    }
+
+    /// Where the top left corner of the layer's assets goes on the canvas.
+    fn offset(&self) -> (i64, i64) {
+        match self {
+            LayerFolder::Simple(_) => (0, 0),
+            LayerFolder::Detailed(options) => (options.offset_x, options.offset_y),
+        }
+    }
+
+    fn fit(&self) -> Fit {
+        match self {
+            LayerFolder::Simple(_) => Fit::None,
+            LayerFolder::Detailed(options) => options.fit,
+        }
+    }
+
+    fn display_type(&self) -> Option<DisplayType> {
+        match self {
+            LayerFolder::Simple(_) => None,
+            LayerFolder::Detailed(options) => options.display_type,
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        match self {
+            LayerFolder::Simple(_) => Transform::default(),
+            LayerFolder::Detailed(options) => options.transform,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct ForcedCombinations {
     combo: Vec<ForcedCombo>,
     percentage: u8,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+/// Tokens kept for the team or treasury, generated after the public supply.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct ReservedSupply {
+    supply: u32,
+    #[serde(default)]
+    forced_combinations: Vec<ForcedCombinations>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct Config {
     metadata: HashMap<String, Value>,
     image: Image,
     total_supply: u32,
+    #[serde(default)]
+    start_index: usize,
+    #[serde(default)]
+    token_id_padding: usize,
+    image_file_name: Option<String>,
+    metadata_file_name: Option<String>,
+    image_folder: Option<String>,
+    metadata_folder: Option<String>,
     base_path: String,
     output_path: String,
     image_url: String,
-    layer_folders: Vec<String>,
+    #[serde(alias = "layers")]
+    layer_folders: Vec<LayerFolder>,
     skipped_traits: Option<Vec<String>>,
     forced_combinations: Vec<ForcedCombinations>,
+    seed: Option<u64>,
+    #[serde(default)]
+    previous_dna_files: Vec<String>,
+    #[serde(default)]
+    previous_manifest_files: Vec<String>,
+    #[serde(default)]
+    dna_reissue: DnaReissue,
+    #[serde(default)]
+    embed_rarity: bool,
+    max_file_size_kb: Option<u64>,
+    #[serde(default)]
+    oversize_policy: OversizePolicy,
+    #[serde(default)]
+    indexed_color: bool,
+    png_optimization: Option<PngOptimization>,
+    cache_dir: Option<String>,
+    #[serde(default)]
+    dna_algorithm: DnaAlgorithm,
+    #[serde(default)]
+    dependency_rules: Vec<DependencyRule>,
+    #[serde(default)]
+    trait_tags: HashMap<String, Vec<TraitRef>>,
+    #[serde(default)]
+    trait_caps: Vec<TraitCap>,
+    palette: Option<Palette>,
+    recolor: Option<Recolor>,
+    contrast_check: Option<ContrastCheck>,
+    near_duplicates: Option<NearDuplicates>,
+    #[serde(default)]
+    weight_mode: WeightMode,
+    #[serde(default)]
+    metadata_profile: MetadataProfile,
+    output_namespace: Option<String>,
+    reserved_supply: Option<ReservedSupply>,
+    #[serde(default)]
+    trait_transforms: Vec<TraitTransform>,
+    #[serde(default)]
+    trait_z_indexes: Vec<TraitZIndex>,
+    #[serde(default)]
+    trait_fields: Vec<TraitFields>,
+    #[serde(default)]
+    animations: Vec<TraitAnimation>,
+    prefetch_batch_size: Option<usize>,
+    background: Option<Background>,
+    #[serde(default)]
+    post_processing: Vec<PostEffect>,
+    watermark: Option<Watermark>,
+    video: Option<Video>,
+    spritesheet: Option<Spritesheet>,
+    audio: Option<Audio>,
+    one_of_ones: Option<OneOfOnes>,
+    #[serde(default)]
+    fixed_tokens: Vec<FixedToken>,
+    #[serde(default)]
+    unique_combinations: Vec<UniqueCombination>,
+    unique_combinations_file: Option<String>,
+    metadata_limits: Option<MetadataLimits>,
+    #[serde(default)]
+    shuffle_token_ids: bool,
+    #[serde(default)]
+    token_order: TokenOrder,
+    name_template: Option<String>,
+    display_names: Option<DisplayNames>,
+    localization: Option<Localization>,
+    weights: Option<Weights>,
+    #[serde(default)]
+    dimension_mismatch: DimensionPolicy,
+    auto_resize: Option<ResizeFilter>,
+    #[serde(default)]
+    forced_combination_tolerance: f64,
+    #[serde(default)]
+    editions: Vec<Edition>,
+}
+/// Settings shared by every token of a run.
+#[derive(Debug, Clone)]
+struct RenderSettings {
+    output_path: String,
+    /// How the paths of the assets rendered are read.
+    layout: LayerLayout,
+    image: Image,
+    rarity_only_traits: HashSet<String>,
+    emit_none_traits: HashSet<String>,
+    blend_modes: HashMap<String, BlendMode>,
+    offsets: HashMap<String, (i64, i64)>,
+    fits: HashMap<String, Fit>,
+    display_types: HashMap<String, DisplayType>,
+    display_names: TraitRenames,
+    transforms: Transforms,
+    stacking: Stacking,
+    background: Option<Background>,
+    recolors: Recolors,
+    post_processing: PostProcessing,
+    /// Drawn over every image of a `--watermark` run.
+    watermark: Option<Arc<Stamp>>,
+    video: Option<VideoSettings>,
+    spritesheet: Option<Spritesheet>,
+    audio: Option<AudioSettings>,
+    metadata_profile: MetadataProfile,
+    trait_fields: TraitFieldTable,
+    animations: Animations,
+    file_size_limit: Option<FileSizeLimit>,
+    png_output: PngOutput,
+    preprocessing: Preprocessing,
+    file_names: FileNames,
+    name_template: Option<NameTemplate>,
+    variants: Variants,
+    /// Where the images and metadata are written.
+    sink: FileSink,
 }
+
 #[derive(Serialize, Clone)]
 struct Attribute {
     trait_type: String,
     value: String,
-    weight: f64,
+    weight: Option<f64>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, thiserror::Error)]
 pub enum CustomError {
+    #[error("Failed to retrieve entries by path folder: {0}")]
     GetEntriesByPath(String),
+    #[error("Invalid trait config: {0}")]
     InvalidTrait(String),
+    #[error("Invalid total supply. Expected: {0}. Actual: {1}.")]
     InvalidTotalSupply(u64, u64),
+    #[error("{0}")]
     TotalPercentageExceeded(String),
+    #[error("{0}")]
     InvalidImageExtension(String),
+    #[error("Upload failed: {0}")]
+    Upload(String),
+    #[error("Golden comparison failed: {0}")]
+    Golden(String),
+    #[error("Output differs from the golden set in {0} files.")]
+    GoldenMismatch(usize),
+    #[error("Failed to write output: {0}")]
+    WriteOutput(String),
+    #[error("Invalid DNA file: {0}")]
+    InvalidDna(String),
+    #[error("{0} is {1} KB, over the maximum file size of {2} KB.")]
+    FileSizeExceeded(String, u64, u64),
+    #[error("Failed to preprocess layer: {0}")]
+    Preprocess(String),
+    #[error("Export failed: {0}")]
+    Export(String),
+    #[error("Trait caps cannot be met: {0}")]
+    TraitCap(String),
+    #[error("Re-render failed: {0}")]
+    Rerender(String),
+    #[error("Palette check failed: {0}")]
+    Palette(String),
+    #[error("Contrast check failed: {0}")]
+    Contrast(String),
+    #[error("Metadata lint failed: {0}")]
+    Lint(String),
+    #[error("Invalid 1/1 tokens: {0}")]
+    OneOfOnes(String),
+    #[error("Invalid config file: {0}")]
+    Config(String),
+    #[error("Invalid layer pack: {0}")]
+    LayerPack(String),
+    #[error("Replay verification failed: {0}")]
+    Replay(String),
+    #[error("Cannot re-roll tokens: {0}")]
+    Reroll(String),
+    #[error("Not enough disk space: {0}")]
+    DiskSpace(String),
+    #[error("Layer size mismatch: {0}")]
+    Dimensions(String),
+    #[error("Forced combination quotas not met: {0}")]
+    ForcedCombinations(String),
+    #[error("Video composition failed: {0}")]
+    Video(String),
+    #[error("Audio mixing failed: {0}")]
+    Audio(String),
+    /// An error rendering one token, with the assets it was drawn with.
+    #[error("Token {token_id} ({}) failed: {source}", assets.join(", "))]
+    Token {
+        token_id: usize,
+        assets: Vec<String>,
+        source: Box<CustomError>,
+    },
+    /// The first failure of a batch, counting the others.
+    #[error("{failed} of {total} collections failed; {config}: {source}")]
+    Batch {
+        failed: usize,
+        total: usize,
+        config: String,
+        source: Box<CustomError>,
+    },
 }
 
-impl fmt::Display for CustomError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            CustomError::GetEntriesByPath(ref path) => {
-                write!(f, "Failed to retrieve entries by path folder: {}", path)
-            }
-            CustomError::InvalidTrait(ref msg) => write!(f, "Invalid trait config: {}", msg),
-            CustomError::InvalidTotalSupply(expected, actual) => write!(
-                f,
-                "Invalid total supply. Expected: {}. Actual: {}.",
-                expected, actual
-            ),
-            CustomError::TotalPercentageExceeded(ref msg) => {
-                write!(f, "{}", msg)
-            }
-            CustomError::InvalidImageExtension(ref msg) => {
-                write!(f, "{}", msg)
-            }
+/// What kind of failure ended a command. Its value is the exit code, so
+/// wrapping scripts and CI can branch on it; 1 is any other error and 2 a
+/// command line usage error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FailureClass {
+    /// The config file or the layer folders it points at are invalid.
+    Config = 3,
+    /// A check of the assets or the output failed.
+    Validation = 4,
+    /// The layers can't produce the requested supply.
+    InfeasibleSupply = 5,
+    /// Rendering or writing the output failed.
+    Render = 6,
+    /// Uploading the output failed.
+    Upload = 7,
+}
+
+impl CustomError {
+    /// This error as the failure of rendering `token_id` from `assets`.
+    fn for_token(self, token_id: usize, assets: &[String]) -> CustomError {
+        CustomError::Token {
+            token_id,
+            assets: assets.to_vec(),
+            source: Box::new(self),
         }
     }
-}
 
-impl PartialEq for CustomError {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (CustomError::GetEntriesByPath(msg1), CustomError::GetEntriesByPath(msg2)) => {
-                msg1 == msg2
+    fn failure_class(&self) -> FailureClass {
+        match self {
+            CustomError::GetEntriesByPath(_)
+            | CustomError::InvalidTrait(_)
+            | CustomError::TotalPercentageExceeded(_)
+            | CustomError::InvalidImageExtension(_)
+            | CustomError::InvalidDna(_)
+            | CustomError::OneOfOnes(_)
+            | CustomError::Config(_)
+            | CustomError::LayerPack(_)
+            | CustomError::Reroll(_) => FailureClass::Config,
+            CustomError::GoldenMismatch(_)
+            | CustomError::Palette(_)
+            | CustomError::Contrast(_)
+            | CustomError::Lint(_)
+            | CustomError::Replay(_)
+            | CustomError::Dimensions(_) => FailureClass::Validation,
+            CustomError::InvalidTotalSupply(_, _)
+            | CustomError::TraitCap(_)
+            | CustomError::ForcedCombinations(_) => FailureClass::InfeasibleSupply,
+            CustomError::WriteOutput(_)
+            | CustomError::FileSizeExceeded(_, _, _)
+            | CustomError::Preprocess(_)
+            | CustomError::Export(_)
+            | CustomError::Rerender(_)
+            | CustomError::Golden(_)
+            | CustomError::DiskSpace(_)
+            | CustomError::Video(_)
+            | CustomError::Audio(_) => FailureClass::Render,
+            CustomError::Upload(_) => FailureClass::Upload,
+            CustomError::Token { source, .. } | CustomError::Batch { source, .. } => {
+                source.failure_class()
             }
-            (CustomError::InvalidTrait(msg1), CustomError::InvalidTrait(msg2)) => msg1 == msg2,
-            (
-                CustomError::InvalidTotalSupply(expected1, actual1),
-                CustomError::InvalidTotalSupply(expected2, actual2),
-            ) => expected1 == expected2 && actual1 == actual2,
-            _ => false,
         }
     }
 }
 
-impl Error for CustomError {}
+/// The exit code of a failed command.
+fn exit_code(error: &(dyn Error + 'static)) -> u8 {
+    error
+        .downcast_ref::<CustomError>()
+        .map_or(1, |error| error.failure_class() as u8)
+}
 
 fn get_entries_by_path_dir(path: String) -> Result<Vec<String>, CustomError> {
     let mut entries: Vec<String> = Vec::new();
     let base_path_dir = read_dir(path.clone());
     match base_path_dir {
         Ok(dir_entries) => {
-            for entry in dir_entries {
-                if let Ok(entry) = entry {
-                    entries.push(entry.path().display().to_string());
-                }
+            for entry in dir_entries.flatten() {
+                entries.push(entry.path().display().to_string());
             }
+            entries.sort();
             Ok(entries)
         }
         Err(_) => Err(CustomError::GetEntriesByPath(path)),
     }
 }
 
-fn compare_and_verify_traits<T: PartialEq + Ord + Clone>(
-    mut traits_by_path: Vec<String>,
-    mut traits_by_config: Vec<String>,
+/// The folder of every layer, in the order of `layerFolders`, which is the
+/// order the layers are drawn in. A layer with a `path` uses that folder;
+/// otherwise a layer named `Eyes` is the folder `Eyes` or `Eyes#N` in
+/// `basePath`. A missing folder, a layer matching no folder or several of
+/// them, and a folder in `basePath` no layer uses are errors.
+fn layer_dirs(
+    entries: &[String],
+    layer_folders: &[LayerFolder],
     base_path: &str,
 ) -> Result<Vec<String>, CustomError> {
-    if traits_by_path.len() != traits_by_config.len() {
+    let folder_name = |entry: &str| {
+        let name = Path::new(entry)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        name.split('#').next().unwrap_or("").to_string()
+    };
+    let layer_names: Vec<&str> = layer_folders
+        .iter()
+        .map(|layer_folder| layer_folder.name())
+        .collect();
+
+    let mut dirs: Vec<String> = Vec::new();
+    for (index, (layer_name, layer_folder)) in layer_names.iter().zip(layer_folders).enumerate() {
+        if layer_names[..index].contains(layer_name) {
+            return Err(CustomError::InvalidTrait(format!(
+                "layer {} is listed more than once",
+                layer_name
+            )));
+        }
+        if let Some(path) = layer_folder.path() {
+            if !Path::new(path).is_dir() {
+                return Err(CustomError::InvalidTrait(format!(
+                    "the folder {} of layer {} does not exist",
+                    path, layer_name
+                )));
+            }
+            if let Some(dir) = dirs.iter().find(|dir| paths::same_folder(dir, path)) {
+                return Err(CustomError::InvalidTrait(format!(
+                    "layer {} uses the folder {} of another layer",
+                    layer_name, dir
+                )));
+            }
+            dirs.push(path.to_string());
+            continue;
+        }
+        let matches: Vec<&String> = entries
+            .iter()
+            .filter(|entry| folder_name(entry) == *layer_name)
+            .collect();
+        match matches.as_slice() {
+            [dir] => dirs.push(dir.to_string()),
+            [] => {
+                return Err(CustomError::InvalidTrait(format!(
+                    "no folder for layer {} in {}",
+                    layer_name, base_path
+                )))
+            }
+            _ => {
+                return Err(CustomError::InvalidTrait(format!(
+                    "layer {} matches several folders: {}",
+                    layer_name,
+                    matches
+                        .iter()
+                        .map(|dir| dir.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )))
+            }
+        }
+    }
+
+    if let Some(unused) = entries
+        .iter()
+        .find(|entry| !dirs.iter().any(|dir| paths::same_folder(dir, entry)))
+    {
         return Err(CustomError::InvalidTrait(format!(
-            "[traits_by_path: {} traits_by_config: {}]",
-            traits_by_path.len(),
-            traits_by_config.len()
+            "{} is not a layer in layerFolders",
+            unused
         )));
     }
 
-    let mut treated = traits_by_path
-        .iter()
-        .map(|s| s.split('#').next().unwrap_or("").to_string())
-        .collect::<Vec<String>>();
+    Ok(dirs)
+}
 
-    treated.sort();
-    let unordered_traits_by_config = traits_by_config.clone();
-    traits_by_config.sort();
+/// Sum of the plain `#N` weights of a path, sublayer folders included, or the
+/// weight the config gives it. Percentages and ratios are not weights, see
+/// `path_share`.
+fn path_weight(layout: &LayerLayout, image_filename: &str) -> u64 {
+    if let Some(annotation) = layout.configured_weight(image_filename) {
+        return annotation.parse().unwrap_or(0);
+    }
+    RE_WEIGHT
+        .captures_iter(image_filename)
+        .filter_map(|captures| captures[1].parse::<u64>().ok())
+        .sum()
+}
 
-    if treated != traits_by_config {
-        return Err(CustomError::InvalidTrait(
-            "[traits_by_path and traits_by_config are different]".to_string(),
-        ));
+/// Percentage a `#N%` or `#A-B` annotation stands for. `#A-B` reads "A in B",
+/// so `#1-4` is 25%.
+fn annotation_share(annotation: &str) -> Option<f64> {
+    if let Some(percent) = annotation.strip_suffix('%') {
+        return percent.parse().ok();
     }
+    let (numerator, denominator) = annotation.split_once('-')?;
+    let numerator: f64 = numerator.parse().ok()?;
+    let denominator: f64 = denominator.parse().ok()?;
+    Some(numerator * 100.0 / denominator)
+}
 
-    traits_by_path.sort_by_cached_key(|item| {
-        let mut best_score = usize::MAX;
-        let mut best_index = 0;
+/// The weight annotation of an asset: the one the config gives it, or else
+/// the `#N`, `#N%` or `#A-B` at the end of its file name.
+fn weight_annotation(layout: &LayerLayout, path: &str) -> Option<String> {
+    if let Some(annotation) = layout.configured_weight(path) {
+        return Some(annotation);
+    }
+    let file_name = Path::new(path).file_name()?.to_str()?;
+    Some(
+        RE_FILENAME
+            .captures(file_name)?
+            .get(2)?
+            .as_str()
+            .to_string(),
+    )
+}
 
-        for (index, first_item) in unordered_traits_by_config.iter().enumerate() {
-            let score = levenshtein(first_item, &item.trim_start_matches(base_path));
-            if score < best_score {
-                best_score = score;
-                best_index = index;
-            }
+/// The `#N` weight an asset is drawn with, or `None` when it has none or asks
+/// for a share of its layer instead. Assets without a file, like `None#3`,
+/// have their weight too.
+fn asset_weight(layout: &LayerLayout, path: &str) -> Option<f64> {
+    let annotation = match layout.configured_weight(path) {
+        Some(annotation) => annotation,
+        None => RE_WEIGHT.captures(Path::new(path).file_name()?.to_str()?)?[1].to_string(),
+    };
+    annotation.parse().ok()
+}
+
+/// Share of the picks of its layer, in percent, an asset asks for with a
+/// `#N%` or `#A-B`.
+fn path_share(layout: &LayerLayout, path: &str) -> Option<f64> {
+    annotation_share(&weight_annotation(layout, path)?)
+}
+
+/// Units of weight a percent of a layer is turned into when its assets mix
+/// percentages and plain weights.
+const SHARE_SCALE: f64 = 10_000.0;
+
+/// Whether every asset of `layer` that has neither a percentage nor is the
+/// `None` one is unweighted, next to some that do. They are then equally
+/// likely, so each one counts as a weight of 1.
+fn has_unweighted_plain_assets(layout: &LayerLayout, layer: &[String]) -> bool {
+    let is_plain = |path: &&String| !is_none_asset(path) && path_share(layout, path).is_none();
+    layer.iter().any(|path| !is_plain(&path))
+        && layer
+            .iter()
+            .filter(is_plain)
+            .all(|path| path_weight(layout, path) == 0)
+}
+
+/// Weight of every asset of a layer. Assets with a percentage get that share of
+/// the layer and the plain weighted ones split what is left by their weights;
+/// with no plain asset, the percentages are scaled up to fill the layer. The
+/// `None` asset keeps its own weight, which is computed against the others.
+fn resolve_weights(layout: &LayerLayout, layer: &[String]) -> Vec<u64> {
+    let unweighted_plain = has_unweighted_plain_assets(layout, layer);
+    let plain_weight = |path: &String| {
+        if unweighted_plain && !is_none_asset(path) {
+            1
+        } else {
+            path_weight(layout, path)
         }
+    };
 
-        best_index
-    });
+    let shares: Vec<Option<f64>> = layer
+        .iter()
+        .map(|path| {
+            (!is_none_asset(path))
+                .then(|| path_share(layout, path))
+                .flatten()
+        })
+        .collect();
+    if shares.iter().all(Option::is_none) {
+        return layer.iter().map(plain_weight).collect();
+    }
+
+    let share_total: f64 = shares.iter().flatten().sum();
+    let plain_total: u64 = layer
+        .iter()
+        .zip(&shares)
+        .filter(|(path, share)| share.is_none() && !is_none_asset(path))
+        .map(|(path, _)| plain_weight(path))
+        .sum();
+    let left = (100.0 - share_total).max(0.0);
+    let share_factor = if plain_total == 0 {
+        100.0 / share_total
+    } else {
+        1.0
+    };
 
-    Ok(traits_by_path)
+    layer
+        .iter()
+        .zip(&shares)
+        .map(|(path, share)| match share {
+            Some(share) => (share * share_factor * SHARE_SCALE).round() as u64,
+            None if is_none_asset(path) => path_weight(layout, path),
+            None => {
+                (left * SHARE_SCALE * plain_weight(path) as f64 / plain_total as f64).round() as u64
+            }
+        })
+        .collect()
 }
 
-fn calculate_weights_and_total(layer: &[String]) -> (Vec<u64>, u64) {
+fn calculate_weights_and_total(layout: &LayerLayout, layer: &[String]) -> (Vec<u64>, u64) {
     let mut total_weight = 0;
     let mut weights = Vec::with_capacity(layer.len());
 
-    for image_filename in layer {
-        let weight_occurrences: Vec<&str> = RE_WEIGHT
-            .captures_iter(image_filename)
-            .map(|captures| captures.get(0).unwrap().as_str().trim_start_matches('#'))
-            .collect();
-
-        let accumulated_weight: u64 = weight_occurrences
-            .iter()
-            .map(|&weight_value| weight_value.parse::<u64>().unwrap())
-            .sum();
-
-        total_weight += accumulated_weight;
+    for weight in resolve_weights(layout, layer) {
+        total_weight += weight;
         weights.push(total_weight);
     }
 
     (weights, total_weight)
 }
 
-fn choose_image_with_precomputed_weights<'a>(
+/// Percentages have to be positive and leave room for each other.
+fn check_shares(layout: &LayerLayout, layer: &[String]) -> Result<(), CustomError> {
+    let mut share_total = 0.0;
+    for path in layer {
+        let Some(share) = path_share(layout, path) else {
+            continue;
+        };
+        if !(share > 0.0 && share <= 100.0) {
+            return Err(CustomError::InvalidTrait(format!(
+                "{} asks for {}% of its layer",
+                path, share
+            )));
+        }
+        share_total += share;
+    }
+    if share_total > 100.0 + 1e-9 {
+        let layer_name = layer
+            .first()
+            .and_then(|path| path_trait(layout, path))
+            .unwrap_or_default()
+            .0;
+        return Err(CustomError::InvalidTrait(format!(
+            "the percentages of layer {} add up to {}%",
+            layer_name, share_total
+        )));
+    }
+    Ok(())
+}
+
+fn choose_image_with_precomputed_weights<'a, R: Rng>(
     layer: &'a [String],
     weights: &[u64],
     total_weight: u64,
+    rng: &mut R,
 ) -> &'a String {
-    let mut rng = rand::thread_rng();
     let dist = Uniform::from(0..total_weight);
     let random_value = rng.sample(dist);
     let chosen_index = match weights.binary_search_by(|&probe| probe.cmp(&random_value)) {
@@ -232,181 +1021,1237 @@ fn choose_image_with_precomputed_weights<'a>(
 
     &layer[chosen_index]
 }
-fn generate_permutations(
-    layers: &Vec<Vec<String>>,
-    total_supply: usize,
-) -> HashMap<u64, Vec<String>> {
-    let layer_weights: Vec<_> = layers
-        .iter()
-        .map(|layer| calculate_weights_and_total(layer))
-        .collect();
-
-    let mut rng = rand::thread_rng();
-    let mut permutations: HashMap<u64, Vec<String>> = HashMap::new();
-    let mut seen_permutations: HashSet<Vec<String>> = HashSet::new();
-
-    while permutations.len() < total_supply {
-        let current_permutation: Vec<String> = layers
-            .iter()
-            .enumerate()
-            .zip(&layer_weights)
-            .filter_map(|((_index, layer), &(ref weights, total_weight))| {
-                if layer.is_empty() {
-                    None
-                } else if total_weight == 0 {
-                    Some(layer.choose(&mut rng).unwrap().to_owned())
-                } else {
-                    let chosen =
-                        choose_image_with_precomputed_weights(layer, weights, total_weight);
-                    Some(chosen.to_owned())
-                }
-            })
-            .collect();
+/// Key the permutations are stored and ordered by. It only depends on the asset
+/// paths, so the order is the same on every platform and Rust release.
+fn permutation_key(permutation: &[String]) -> u64 {
+    let digest = Sha256::digest(permutation.join("\n").as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
 
-        if seen_permutations.insert(current_permutation.clone()) {
-            let mut hasher = DefaultHasher::new();
-            current_permutation.hash(&mut hasher);
-            let hash = hasher.finish();
-            permutations.insert(hash, current_permutation);
-        }
+fn choose_from_layer<R: Rng>(
+    layer: &[String],
+    weights: &[u64],
+    total_weight: u64,
+    rng: &mut R,
+) -> String {
+    if total_weight == 0 {
+        layer.choose(rng).unwrap().to_owned()
+    } else {
+        choose_image_with_precomputed_weights(layer, weights, total_weight, rng).to_owned()
     }
-
-    permutations
 }
 
-fn get_image_paths_recursive(dir: &Path) -> Vec<String> {
-    WalkDir::new(dir)
-        .into_iter()
-        .filter_map(Result::ok) // Ignore errors (like permissions denied)
-        .filter(|entry| {
-            let entry_path = entry.path();
-            if entry_path.is_file() {
-                if let Some(extension) = entry_path.extension() {
-                    if let Some(ext_str) = extension.to_str() {
-                        return ALLOWED_EXTENSION.eq_ignore_ascii_case(ext_str);
-                    }
-                }
-            }
-            return false;
-        })
-        .map(|e| e.path().to_string_lossy().into_owned())
-        .collect::<Vec<String>>()
-}
+/// How many samples in a row may hit a layer with no value left to pick, or be
+/// rejected after the caps narrowed their values, before the dependency rules
+/// and trait caps are deemed impossible to satisfy together.
+const MAX_DEAD_ENDS: usize = 100_000;
+
+/// Once the caps leave few values to pick from, the last samples are often
+/// duplicates. This swaps one asset of such a sample with an accepted
+/// permutation so both are unique and allowed, which leaves the number of
+/// tokens using every value unchanged, and returns the sample to accept.
+fn swap_with_accepted(
+    permutations: &mut HashMap<u64, Vec<String>>,
+    draws: &mut [u64],
+    seen_permutations: &mut HashSet<Vec<String>>,
+    candidate: &[String],
+    constraints: &Constraints,
+) -> Option<Vec<String>> {
+    let mut keys: Vec<u64> = permutations.keys().copied().collect();
+    keys.sort_unstable();
+
+    for key in keys {
+        let accepted = &permutations[&key];
+        if accepted.len() != candidate.len() {
+            continue;
+        }
 
-fn get_layers_by_traits(traits: Vec<String>) -> Vec<Vec<String>> {
-    let mut layers = Vec::<Vec<String>>::new();
+        for position in 0..candidate.len() {
+            if accepted[position] == candidate[position] {
+                continue;
+            }
+            let mut swapped_candidate = candidate.to_vec();
+            swapped_candidate[position] = accepted[position].clone();
+            let mut swapped_accepted = accepted.clone();
+            swapped_accepted[position] = candidate[position].clone();
+
+            if swapped_candidate == swapped_accepted
+                || seen_permutations.contains(&swapped_candidate)
+                || seen_permutations.contains(&swapped_accepted)
+                || !constraints.allows(&swapped_candidate)
+                || !constraints.allows(&swapped_accepted)
+            {
+                continue;
+            }
 
-    for trait_path in traits.iter() {
-        let layers_by_trait = get_image_paths_recursive(Path::new(&trait_path));
-        layers.push(layers_by_trait);
+            let previous = permutations.remove(&key)?;
+            seen_permutations.remove(&previous);
+            seen_permutations.insert(swapped_accepted.clone());
+            // The swapped permutation keeps the place of the one it replaces.
+            let swapped_key = permutation_key(&swapped_accepted);
+            if let Some(draw) = draws.iter_mut().find(|draw| **draw == key) {
+                *draw = swapped_key;
+            }
+            permutations.insert(swapped_key, swapped_accepted);
+            return Some(swapped_candidate);
+        }
     }
 
-    return layers;
+    None
 }
 
-fn generate_image_and_metadata(
-    metadata: HashMap<String, Value>,
-    image_paths: Vec<String>,
-    output_path: String,
-    config_image: Image,
-    image_name: usize,
-) -> impl FnMut() {
-    let images: Vec<(DynamicImage, Attribute)> = image_paths
-        .par_iter()
-        .map(|path| {
-            let img = image::open(&Path::new(&path)).unwrap();
-            let filename = Path::new(&path)
-                .file_name()
-                .and_then(|name| name.to_str())
-                .unwrap_or("");
-
-            let captures = RE_FILENAME.captures(filename);
+/// How many times the capped values are assigned to the tokens anew before
+/// the dependency rules and trait caps are deemed impossible to satisfy
+/// together.
+const MAX_ASSIGNMENTS: usize = 100;
 
-            let weight_value: f64 = captures
-                .as_ref()
-                .and_then(|caps| caps.get(2).map(|m| m.as_str().parse().ok()))
-                .flatten()
-                .unwrap_or(1.0);
-
-            let mut path_parts: Vec<String> = path.split("/").map(|s| s.to_string()).collect();
+/// A value of a layer and how many more tokens it may go to.
+type CappedValue = ((String, String), usize);
 
-            path_parts.drain(0..2).for_each(drop);
+/// The distinct values of a layer with their capacity, when every value of
+/// the layer is capped.
+fn capped_values(traits: &[Option<(String, String)>], caps: &Caps) -> Option<Vec<CappedValue>> {
+    if traits.is_empty() || caps.is_empty() {
+        return None;
+    }
+    let mut values: Vec<CappedValue> = Vec::new();
+    for trait_pair in traits {
+        let trait_pair = trait_pair.as_ref()?;
+        let remaining = caps.remaining(trait_pair)?;
+        if values.iter().all(|(value, _)| value != trait_pair) {
+            values.push((trait_pair.clone(), remaining));
+        }
+    }
+    Some(values)
+}
 
-            for string in path_parts.iter_mut() {
-                *string = RE_PATH.replace_all(&string, "").to_string();
+/// Gives `token` a value among its `allowed` ones, moving the tokens already
+/// given one along to another of theirs when the value is used up.
+fn assign_token(
+    token: usize,
+    allowed: &[Vec<usize>],
+    capacities: &[usize],
+    assigned: &mut [Vec<usize>],
+    visited: &mut [bool],
+) -> bool {
+    for &value in &allowed[token] {
+        if visited[value] {
+            continue;
+        }
+        visited[value] = true;
+        if assigned[value].len() < capacities[value] {
+            assigned[value].push(token);
+            return true;
+        }
+        for index in 0..assigned[value].len() {
+            let other = assigned[value][index];
+            if assign_token(other, allowed, capacities, assigned, visited) {
+                assigned[value][index] = token;
+                return true;
             }
+        }
+    }
+    false
+}
 
-            let attribute = Attribute {
-                trait_type: path_parts.first().unwrap().to_string(),
-                value: path_parts.last().unwrap().to_string(),
-                weight: weight_value,
-            };
-
-            (img, attribute)
+/// Gives every token one of `values` the rules allow with the traits it
+/// already has, none more often than its capacity, or `None` when there's no
+/// such assignment. Returns the index of the value of every token.
+fn match_capped_values<R: Rng>(
+    token_traits: &[Vec<(String, String)>],
+    values: &[CappedValue],
+    rules: &Rules,
+    rng: &mut R,
+) -> Option<Vec<usize>> {
+    let allowed: Vec<Vec<usize>> = token_traits
+        .iter()
+        .map(|traits| {
+            let mut allowed: Vec<usize> = (0..values.len())
+                .filter(|&value| {
+                    let mut traits = traits.clone();
+                    traits.push(values[value].0.clone());
+                    rules.is_consistent(&traits)
+                })
+                .collect();
+            allowed.shuffle(rng);
+            allowed
         })
         .collect();
-    let width = config_image.width;
-    let height = config_image.height;
-
-    let mut combined_image = ImageBuffer::new(width, height);
+    let capacities: Vec<usize> = values.iter().map(|(_, capacity)| *capacity).collect();
+    let mut assigned: Vec<Vec<usize>> = vec![Vec::new(); values.len()];
+    let mut order: Vec<usize> = (0..token_traits.len()).collect();
+    order.shuffle(rng);
+    for token in order {
+        let mut visited = vec![false; values.len()];
+        if !assign_token(token, &allowed, &capacities, &mut assigned, &mut visited) {
+            return None;
+        }
+    }
 
-    let closure = move || {
-        let mut attributes: Vec<Value> = Vec::new();
+    let mut token_values = vec![0; token_traits.len()];
+    for (value, tokens) in assigned.iter().enumerate() {
+        for &token in tokens {
+            token_values[token] = value;
+        }
+    }
+    Some(token_values)
+}
 
-        for (image, attribute) in &images {
-            let mut attribute_map = serde_json::Map::new();
-            let attr = attribute.clone();
-            attribute_map.insert("trait_type".to_string(), Value::from(attr.trait_type));
-            attribute_map.insert("value".to_string(), Value::from(attr.value));
-            attributes.push(Value::Object(attribute_map));
+/// Makes every token unique and allowed by swapping one of its assets with
+/// another token's, which leaves the number of tokens using every value
+/// unchanged. Returns whether it could.
+fn swap_until_unique(tokens: &mut [Vec<String>], constraints: &Constraints) -> bool {
+    let mut seen: HashSet<Vec<String>> = HashSet::new();
+    let mut invalid = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        if !constraints.allows(token) || !seen.insert(token.clone()) {
+            invalid.push(index);
+        }
+    }
 
-            image::imageops::overlay(&mut combined_image, image, 0, 0);
+    'tokens: for index in invalid {
+        for other in 0..tokens.len() {
+            if other == index || !seen.contains(&tokens[other]) {
+                continue;
+            }
+            for position in 0..tokens[index].len() {
+                if tokens[index][position] == tokens[other][position] {
+                    continue;
+                }
+                let mut swapped = tokens[index].clone();
+                swapped[position] = tokens[other][position].clone();
+                let mut swapped_other = tokens[other].clone();
+                swapped_other[position] = tokens[index][position].clone();
+                if swapped == swapped_other
+                    || seen.contains(&swapped)
+                    || seen.contains(&swapped_other)
+                    || !constraints.allows(&swapped)
+                    || !constraints.allows(&swapped_other)
+                {
+                    continue;
+                }
+                seen.remove(&tokens[other]);
+                seen.insert(swapped.clone());
+                seen.insert(swapped_other.clone());
+                tokens[index] = swapped;
+                tokens[other] = swapped_other;
+                continue 'tokens;
+            }
         }
+        return false;
+    }
+    true
+}
 
-        combined_image
-            .save(format!("{}/{}.png", output_path, image_name))
-            .unwrap();
-
-        let mut combined_metadata = metadata.clone();
-        combined_metadata.insert("attributes".to_string(), Value::Array(attributes));
-
-        let serialized = to_string_pretty(&combined_metadata).unwrap();
+/// One try at sampling `total_supply` tokens by assigning the values of the
+/// fully capped layers first, honoring the dependency rules between them,
+/// then drawing the values of the other layers.
+fn assign_tokens<R: Rng>(
+    layers: &[Vec<String>],
+    layer_traits: &[Vec<Option<(String, String)>>],
+    total_supply: usize,
+    constraints: &Constraints,
+    caps: &mut Caps,
+    rng: &mut R,
+) -> Option<Vec<Vec<String>>> {
+    let mut tokens: Vec<Vec<Option<String>>> = vec![vec![None; layers.len()]; total_supply];
+    let mut token_traits: Vec<Vec<(String, String)>> = vec![Vec::new(); total_supply];
+
+    let capped: Vec<Option<Vec<CappedValue>>> = layer_traits
+        .iter()
+        .map(|traits| capped_values(traits, caps))
+        .collect();
+    for (position, values) in capped.iter().enumerate() {
+        let Some(values) = values else {
+            continue;
+        };
+        let token_values = match_capped_values(&token_traits, values, &constraints.rules, rng)?;
+        for (token, value) in token_values.into_iter().enumerate() {
+            let trait_pair = &values[value].0;
+            let paths: Vec<String> = layers[position]
+                .iter()
+                .zip(&layer_traits[position])
+                .filter(|(_, path_trait)| path_trait.as_ref() == Some(trait_pair))
+                .map(|(path, _)| path.clone())
+                .collect();
+            let (weights, total_weight) = calculate_weights_and_total(&constraints.layout, &paths);
+            tokens[token][position] = Some(choose_from_layer(&paths, &weights, total_weight, rng));
+            token_traits[token].push(trait_pair.clone());
+            caps.record(std::slice::from_ref(trait_pair));
+        }
+    }
 
-        let mut file = File::create(format!("{}/{}.json", output_path, image_name)).unwrap();
-        file.write_all(serialized.as_bytes()).unwrap();
-    };
+    for (position, (layer, traits)) in layers.iter().zip(layer_traits).enumerate() {
+        if layer.is_empty() || capped[position].is_some() {
+            continue;
+        }
+        for (token, chosen_traits) in tokens.iter_mut().zip(&mut token_traits) {
+            let candidates: Vec<String> = layer
+                .iter()
+                .zip(traits)
+                .filter(|(_, path_trait)| match path_trait {
+                    Some(trait_pair) => {
+                        let mut with_value = chosen_traits.clone();
+                        with_value.push(trait_pair.clone());
+                        constraints.rules.is_consistent(&with_value) && !caps.is_full(trait_pair)
+                    }
+                    None => true,
+                })
+                .map(|(path, _)| path.clone())
+                .collect();
+            if candidates.is_empty() {
+                return None;
+            }
+            let (weights, total_weight) =
+                calculate_weights_and_total(&constraints.layout, &candidates);
+            let chosen = choose_from_layer(&candidates, &weights, total_weight, rng);
+            if let Some(trait_pair) = path_trait(&constraints.layout, &chosen) {
+                caps.record(std::slice::from_ref(&trait_pair));
+                chosen_traits.push(trait_pair);
+            }
+            token[position] = Some(chosen);
+        }
+    }
 
-    closure
+    let mut tokens: Vec<Vec<String>> = tokens
+        .into_iter()
+        .map(|token| token.into_iter().flatten().collect())
+        .collect();
+    swap_until_unique(&mut tokens, constraints).then_some(tokens)
 }
 
-fn get_permutations(layers: &Vec<Vec<String>>, skipped_traits: Option<Vec<String>>) -> usize {
-    let regexes = skipped_traits.map(|traits| {
-        traits
+/// Samples `total_supply` tokens when some layer has every value capped, like
+/// every layer in quota mode. Drawing token by token could use up the values
+/// a dependency rule requires before the tokens needing them, so those values
+/// are handed out to the tokens up front.
+fn assign_permutations<R: Rng>(
+    layers: &[Vec<String>],
+    layer_traits: &[Vec<Option<(String, String)>>],
+    total_supply: usize,
+    constraints: &Constraints,
+    caps: &mut Caps,
+    rng: &mut R,
+    replay: Option<&mut ReplayLog>,
+) -> Result<Vec<(u64, Vec<String>)>, CustomError> {
+    for _ in 0..MAX_ASSIGNMENTS {
+        let mut assigned_caps = caps.clone();
+        let Some(tokens) = assign_tokens(
+            layers,
+            layer_traits,
+            total_supply,
+            constraints,
+            &mut assigned_caps,
+            rng,
+        ) else {
+            trace!("Assignment of the capped values rejected.");
+            continue;
+        };
+
+        *caps = assigned_caps;
+        if let Some(replay) = replay {
+            let layers: Vec<&Vec<String>> =
+                layers.iter().filter(|layer| !layer.is_empty()).collect();
+            for token in &tokens {
+                for (layer, chosen) in layers.iter().zip(token) {
+                    replay.record(&constraints.layout, layer, chosen);
+                }
+            }
+        }
+        return Ok(tokens
             .into_iter()
-            .map(|pattern| Regex::new(&pattern).unwrap())
-            .collect::<Vec<Regex>>()
-    });
+            .map(|token| (permutation_key(&token), token))
+            .collect());
+    }
+
+    Err(CustomError::TraitCap(format!(
+        "no assignment of the capped values to {} tokens satisfies the dependency rules",
+        total_supply
+    )))
+}
 
-    layers.iter().fold(1, |acc, layer| {
-        let value = if let Some(regexes) = &regexes {
+fn generate_permutations<R: Rng>(
+    layers: &[Vec<String>],
+    total_supply: usize,
+    constraints: &Constraints,
+    caps: &mut Caps,
+    rng: &mut R,
+    mut replay: Option<&mut ReplayLog>,
+) -> Result<Vec<(u64, Vec<String>)>, CustomError> {
+    let layer_weights: Vec<_> = layers
+        .iter()
+        .map(|layer| calculate_weights_and_total(&constraints.layout, layer))
+        .collect();
+    let layer_traits: Vec<Vec<Option<(String, String)>>> = layers
+        .iter()
+        .map(|layer| {
             layer
                 .iter()
-                .filter(|item| !regexes.iter().any(|regex| regex.is_match(item)))
-                .count()
-                .max(1)
+                .map(|path| path_trait(&constraints.layout, path))
+                .collect()
+        })
+        .collect();
+    if layer_traits
+        .iter()
+        .any(|traits| capped_values(traits, caps).is_some())
+    {
+        return assign_permutations(
+            layers,
+            &layer_traits,
+            total_supply,
+            constraints,
+            caps,
+            rng,
+            replay,
+        );
+    }
+
+    let mut permutations: HashMap<u64, Vec<String>> = HashMap::new();
+    // Keys of the permutations in the order they were drawn.
+    let mut draws: Vec<u64> = Vec::new();
+    let mut seen_permutations: HashSet<Vec<String>> = HashSet::new();
+    let mut dead_ends = 0;
+
+    'sampling: while permutations.len() < total_supply {
+        if dead_ends >= MAX_DEAD_ENDS {
+            return Err(CustomError::TraitCap(format!(
+                "no combination satisfying the dependency rules and caps is left after {} tokens",
+                permutations.len()
+            )));
+        }
+
+        let mut current_permutation: Vec<String> = Vec::with_capacity(layers.len());
+        let mut chosen_traits: Vec<(String, String)> = Vec::new();
+        let mut restricted_by_caps = false;
+
+        for ((layer, (weights, total_weight)), traits) in
+            layers.iter().zip(&layer_weights).zip(&layer_traits)
+        {
+            if layer.is_empty() {
+                continue;
+            }
+
+            let allowed = match &traits[0] {
+                Some((layer_name, _)) if !constraints.rules.is_empty() => {
+                    constraints.rules.allowed_values(&chosen_traits, layer_name)
+                }
+                _ => None,
+            };
+            let capped = !caps.is_empty()
+                && traits
+                    .iter()
+                    .flatten()
+                    .any(|trait_pair| caps.is_full(trait_pair));
+            restricted_by_caps |= capped;
+            let chosen = if allowed.is_none() && !capped {
+                let chosen = choose_from_layer(layer, weights, *total_weight, rng);
+                if let Some(replay) = replay.as_deref_mut() {
+                    replay.record(&constraints.layout, layer, &chosen);
+                }
+                chosen
+            } else {
+                let candidates: Vec<String> = layer
+                    .iter()
+                    .zip(traits)
+                    .filter(|(_, path_trait)| match path_trait {
+                        Some(trait_pair) => {
+                            allowed
+                                .as_ref()
+                                .is_none_or(|allowed| allowed.contains(&trait_pair.1))
+                                && !caps.is_full(trait_pair)
+                        }
+                        None => allowed.is_none(),
+                    })
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                if candidates.is_empty() {
+                    dead_ends += 1;
+                    continue 'sampling;
+                }
+                let (weights, total_weight) =
+                    calculate_weights_and_total(&constraints.layout, &candidates);
+                let chosen = choose_from_layer(&candidates, &weights, total_weight, rng);
+                if let Some(replay) = replay.as_deref_mut() {
+                    replay.record(&constraints.layout, &candidates, &chosen);
+                }
+                chosen
+            };
+
+            chosen_traits.extend(path_trait(&constraints.layout, &chosen));
+            current_permutation.push(chosen);
+        }
+
+        let accepted = if seen_permutations.insert(current_permutation.clone())
+            && constraints.allows(&current_permutation)
+        {
+            Some(current_permutation)
+        } else if restricted_by_caps {
+            swap_with_accepted(
+                &mut permutations,
+                &mut draws,
+                &mut seen_permutations,
+                &current_permutation,
+                constraints,
+            )
         } else {
-            layer.len().max(1)
+            None
         };
-        acc * value
+
+        match accepted {
+            Some(permutation) => {
+                trace!("Draw accepted: {:?}.", permutation);
+                dead_ends = 0;
+                caps.record(&chosen_traits);
+                seen_permutations.insert(permutation.clone());
+                let key = permutation_key(&permutation);
+                draws.push(key);
+                permutations.insert(key, permutation);
+            }
+            None if restricted_by_caps => {
+                trace!("Draw rejected by the caps.");
+                dead_ends += 1
+            }
+            None => trace!("Draw rejected as a duplicate or by the dependency rules."),
+        }
+    }
+
+    Ok(draws
+        .into_iter()
+        .filter_map(|key| Some((key, permutations.remove(&key)?)))
+        .collect())
+}
+
+fn get_image_paths_recursive(dir: &Path) -> Vec<String> {
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || !(variants::is_variant_dir(entry.file_name())
+                    || spritesheet::is_frame_dir(entry.file_name()))
+        })
+        .filter_map(Result::ok) // Ignore errors (like permissions denied)
+        .filter(|entry| {
+            let entry_path = entry.path();
+            if entry_path.is_file() {
+                if let Some(extension) = entry_path.extension() {
+                    if let Some(ext_str) = extension.to_str() {
+                        return ALLOWED_EXTENSION.eq_ignore_ascii_case(ext_str);
+                    }
+                }
+            }
+            false
+        })
+        .map(|e| e.path().to_string_lossy().into_owned())
+        .collect::<Vec<String>>()
+}
+
+fn get_layers_by_traits(traits: Vec<String>) -> Vec<Vec<String>> {
+    let mut layers = Vec::<Vec<String>>::new();
+
+    for trait_path in traits.iter() {
+        let layers_by_trait = get_image_paths_recursive(Path::new(&trait_path));
+        layers.push(layers_by_trait);
+    }
+
+    layers
+}
+
+fn trait_path_parts(layout: &LayerLayout, path: &str) -> Vec<String> {
+    let mut path_parts = layout.layer_parts(path);
+
+    for string in path_parts.iter_mut() {
+        *string = RE_PATH.replace_all(string, "").to_string();
+    }
+
+    path_parts
+}
+
+/// The `(trait_type, value)` an asset is written as in the metadata attributes.
+fn path_trait(layout: &LayerLayout, path: &str) -> Option<(String, String)> {
+    let parts = trait_path_parts(layout, path);
+    Some((parts.first()?.clone(), parts.last()?.clone()))
+}
+
+/// Value of the asset standing for an optional layer left out of a token.
+const NONE_VALUE: &str = "None";
+
+/// The asset standing for "no asset" in an optional layer. It has no file: its
+/// path only carries the layer, the `None` value and its weight, so sampling,
+/// counting, rules, caps and DNAs treat it like any other value.
+fn none_asset(layer_dir: &str, weight: u64) -> String {
+    Path::new(layer_dir)
+        .join(format!("{}#{}", NONE_VALUE, weight))
+        .display()
+        .to_string()
+}
+
+fn is_none_asset(path: &str) -> bool {
+    let path = Path::new(path);
+    path.extension().is_none()
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| RE_PATH.replace_all(name, "") == NONE_VALUE)
+}
+
+/// Whether an asset is read from a file. `None` assets and generated
+/// backgrounds only exist as paths.
+fn is_file_asset(path: &str) -> bool {
+    Path::new(path).extension().is_some()
+}
+
+/// Whether an asset is drawn from an image file, rather than being an audio
+/// stem or only a path.
+fn is_image_asset(path: &str) -> bool {
+    is_file_asset(path) && !audio::is_stem(path)
+}
+
+/// The image an asset is drawn with: none for a `None` asset or an audio stem,
+/// the rendered fill for a generated background, and otherwise its file.
+fn load_asset_image(
+    path: &str,
+    settings: &RenderSettings,
+) -> Result<Option<DynamicImage>, CustomError> {
+    if is_none_asset(path) || audio::is_stem(path) {
+        return Ok(None);
+    }
+    if let Some(recolored) = settings.recolors.render(path, |file| {
+        settings.preprocessing.load_layer_image(Path::new(file))
+    }) {
+        return recolored.map(Some);
+    }
+    let (width, height) = settings.image.canvas_size();
+    if let Some(fill) = settings
+        .background
+        .as_ref()
+        .and_then(|background| background.render(&settings.layout, path, width, height))
+    {
+        return Ok(Some(DynamicImage::ImageRgba8(fill)));
+    }
+    settings
+        .preprocessing
+        .load_layer_image(Path::new(path))
+        .map(Some)
+}
+
+/// Whether an asset is a `None` image of its layer, a blank file drawn like any
+/// other rather than the placeholder.
+fn is_none_file(layout: &LayerLayout, path: &str) -> bool {
+    !is_none_asset(path) && path_trait(layout, path).is_some_and(|(_, value)| value == NONE_VALUE)
+}
+
+fn render_settings(config: &Config) -> Result<RenderSettings, CustomError> {
+    Ok(RenderSettings {
+        output_path: config.output_path.clone(),
+        layout: layer_layout(config),
+        image: config.image.clone(),
+        rarity_only_traits: config
+            .layer_folders
+            .iter()
+            .filter(|layer_folder| layer_folder.visibility() == Visibility::RarityOnly)
+            .map(|layer_folder| layer_folder.name().to_string())
+            .collect(),
+        emit_none_traits: config
+            .layer_folders
+            .iter()
+            .filter(|layer_folder| layer_folder.emit_none())
+            .map(|layer_folder| layer_folder.name().to_string())
+            .collect(),
+        blend_modes: config
+            .layer_folders
+            .iter()
+            .filter(|layer_folder| layer_folder.blend_mode() != BlendMode::Normal)
+            .map(|layer_folder| (layer_folder.name().to_string(), layer_folder.blend_mode()))
+            .collect(),
+        offsets: config
+            .layer_folders
+            .iter()
+            .filter(|layer_folder| layer_folder.offset() != (0, 0))
+            .map(|layer_folder| (layer_folder.name().to_string(), layer_folder.offset()))
+            .collect(),
+        fits: config
+            .layer_folders
+            .iter()
+            .filter(|layer_folder| layer_folder.fit() != Fit::None)
+            .map(|layer_folder| (layer_folder.name().to_string(), layer_folder.fit()))
+            .collect(),
+        display_types: config
+            .layer_folders
+            .iter()
+            .filter_map(|layer_folder| {
+                Some((
+                    layer_folder.name().to_string(),
+                    layer_folder.display_type()?,
+                ))
+            })
+            .collect(),
+        display_names: config
+            .display_names
+            .as_ref()
+            .map(DisplayNames::load)
+            .transpose()?
+            .unwrap_or_default(),
+        transforms: Transforms::compile(&config.layer_folders, &config.trait_transforms)?,
+        stacking: Stacking::compile(&config.layer_folders, &config.trait_z_indexes)?,
+        background: config.background.clone(),
+        recolors: recolors(config)?,
+        post_processing: PostProcessing::compile(&config.post_processing, &layer_names(config))?,
+        watermark: None,
+        video: config
+            .video
+            .as_ref()
+            .map(|video| VideoSettings::compile(video, &config.image_url))
+            .transpose()?,
+        spritesheet: config
+            .spritesheet
+            .as_ref()
+            .map(Spritesheet::checked)
+            .transpose()?,
+        audio: config
+            .audio
+            .as_ref()
+            .map(|audio| AudioSettings::compile(audio, &config.image_url))
+            .transpose()?,
+        metadata_profile: config.metadata_profile.clone(),
+        trait_fields: TraitFieldTable::compile(
+            &config.trait_fields,
+            &layer_names(config),
+            &config.metadata_profile,
+            &config.metadata,
+        )?,
+        animations: Animations::compile(
+            &config.animations,
+            &layer_names(config),
+            &config.image_url,
+        )?,
+        file_size_limit: config.max_file_size_kb.map(|max_kb| FileSizeLimit {
+            max_kb,
+            policy: config.oversize_policy,
+        }),
+        png_output: PngOutput {
+            indexed: config.indexed_color,
+            optimization: config.png_optimization,
+        },
+        preprocessing: Preprocessing {
+            steps: config
+                .auto_resize
+                .map(|filter| {
+                    let (width, height) = config.image.canvas_size();
+                    PreprocessStep::Resize {
+                        width,
+                        height,
+                        filter,
+                        kept_layers: placed_layers(config).into_iter().collect(),
+                    }
+                })
+                .into_iter()
+                .collect(),
+            cache_dir: Some(PathBuf::from(
+                config.cache_dir.as_deref().unwrap_or("./.cache/"),
+            )),
+            layout: layer_layout(config),
+        },
+        file_names: FileNames::compile(config)?,
+        name_template: config
+            .name_template
+            .as_deref()
+            .map(|template| NameTemplate::compile(template, &layer_names(config)))
+            .transpose()?,
+        variants: Variants::load(
+            &layer_layout(config),
+            &layer_folder_dirs(config)?,
+            &layer_names(config),
+        )?,
+        sink: FileSink::Direct,
     })
 }
 
+/// File name of a token's image or metadata, its id zero-padded to `padding`
+/// digits.
+fn token_file_name(token_id: usize, padding: usize, extension: &str) -> String {
+    format!("{:0width$}.{}", token_id, extension, width = padding)
+}
+
+/// Draws the layers of a token, given with the trait they stand for, on top of
+/// each other in stacking order, for the image `image_file_name`.
+fn compose_token_image<'a>(
+    layers: impl Iterator<Item = (&'a DynamicImage, &'a str, &'a str)>,
+    image_file_name: &str,
+    settings: &RenderSettings,
+) -> RgbaImage {
+    let (canvas_width, canvas_height) = settings.image.canvas_size();
+    let mut combined_image = ImageBuffer::new(canvas_width, canvas_height);
+    let mut layers: Vec<_> = layers.collect();
+    let traits: Vec<(&str, &str)> = layers
+        .iter()
+        .map(|&(_, layer_name, value)| (layer_name, value))
+        .collect();
+    settings.stacking.sort(&mut layers);
+    for (layer, layer_name, value) in layers {
+        let blend_mode = settings
+            .blend_modes
+            .get(layer_name)
+            .copied()
+            .unwrap_or_default();
+        let (offset_x, offset_y) = settings
+            .offsets
+            .get(layer_name)
+            .copied()
+            .unwrap_or_default();
+        let layer = match settings.transforms.get(layer_name, value) {
+            Some(transform) => transform.apply(layer),
+            None => Cow::Borrowed(layer),
+        };
+        let fit = settings.fits.get(layer_name).copied().unwrap_or_default();
+        let (layer, x, y) = fit::fit_layer(&layer, canvas_width, canvas_height, fit);
+        blend::draw_layer(
+            &mut combined_image,
+            &layer,
+            x + offset_x,
+            y + offset_y,
+            blend_mode,
+        );
+    }
+
+    let combined_image = settings
+        .image
+        .scale_to_output(settings.image.crop_padding(combined_image));
+    let mut combined_image =
+        settings
+            .post_processing
+            .apply(combined_image, &traits, image_file_name);
+    if let Some(stamp) = &settings.watermark {
+        stamp.apply(&mut combined_image);
+    }
+    combined_image
+}
+
+/// Composes the image of a token and writes it to `image_file_name`.
+fn write_token_image<'a>(
+    layers: impl Iterator<Item = (&'a DynamicImage, &'a str, &'a str)>,
+    image_file_name: &str,
+    settings: &RenderSettings,
+) -> Result<(), CustomError> {
+    let combined_image = compose_token_image(layers, image_file_name, settings);
+    let encoded = encode::encode_png(
+        &combined_image,
+        settings.png_output,
+        settings.file_size_limit,
+        image_file_name,
+    )?;
+    settings.sink.write(
+        Path::new(&settings.output_path).join(image_file_name),
+        encoded,
+    )
+}
+
+/// Composes the video of a token, frame by frame, from the videos its layers
+/// are paired with, the other layers drawn still in every frame, and writes
+/// it to `video_file_name` with `audio` as its sound.
+fn write_token_video(
+    layers: &[(&DynamicImage, &str, &str, Option<&Path>)],
+    image_file_name: &str,
+    video_file_name: &str,
+    audio: Option<&Path>,
+    settings: &RenderSettings,
+    video: &VideoSettings,
+) -> Result<(), CustomError> {
+    let mut decoders = layers
+        .iter()
+        .map(|(image, _, _, companion)| {
+            companion
+                .map(|path| video.decode(path, image.width(), image.height()))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>, CustomError>>()?;
+    let output = Path::new(&settings.output_path).join(video_file_name);
+    let mut encoder = None;
+    for _ in 0..video.frame_count() {
+        let frames = decoders
+            .iter_mut()
+            .map(|decoder| {
+                decoder
+                    .as_mut()
+                    .map(|decoder| decoder.next_frame().map(DynamicImage::ImageRgba8))
+                    .transpose()
+            })
+            .collect::<Result<Vec<_>, CustomError>>()?;
+        let frame = compose_token_image(
+            layers
+                .iter()
+                .zip(&frames)
+                .map(|(&(image, layer_name, value, _), frame)| {
+                    (frame.as_ref().unwrap_or(image), layer_name, value)
+                }),
+            image_file_name,
+            settings,
+        );
+        let encoder = match &mut encoder {
+            Some(encoder) => encoder,
+            None => encoder.insert(video.encode(&output, frame.width(), frame.height(), audio)?),
+        };
+        encoder.write_frame(&frame)?;
+    }
+    for decoder in decoders.into_iter().flatten() {
+        decoder.finish()?;
+    }
+    encoder.map_or(Ok(()), |encoder| encoder.finish())
+}
+
+/// Composes the frames of a token, the frame sets of its layers looped over
+/// the frames of the sheet and the other layers drawn still, and writes them
+/// on one sheet to `sheet_file_name`.
+fn write_token_sheet(
+    layers: &[(&DynamicImage, &str, &str, &[PathBuf])],
+    image_file_name: &str,
+    sheet_file_name: &str,
+    settings: &RenderSettings,
+    spritesheet: &Spritesheet,
+) -> Result<(), CustomError> {
+    let frame_sets = layers
+        .iter()
+        .map(|(.., frame_set)| {
+            frame_set
+                .iter()
+                .map(|path| settings.preprocessing.load_layer_image(path))
+                .collect::<Result<Vec<_>, CustomError>>()
+        })
+        .collect::<Result<Vec<_>, CustomError>>()?;
+    let frames: Vec<RgbaImage> = (0..spritesheet.frames as usize)
+        .map(|index| {
+            compose_token_image(
+                layers.iter().zip(&frame_sets).map(
+                    |(&(image, layer_name, value, _), frame_set)| match frame_set.len() {
+                        0 => (image, layer_name, value),
+                        len => (&frame_set[index % len], layer_name, value),
+                    },
+                ),
+                image_file_name,
+                settings,
+            )
+        })
+        .collect();
+    let encoded = encode::encode_png(
+        &spritesheet.sheet(&frames),
+        settings.png_output,
+        None,
+        sheet_file_name,
+    )?;
+    settings.sink.write(
+        Path::new(&settings.output_path).join(sheet_file_name),
+        encoded,
+    )
+}
+
+fn generate_image_and_metadata(
+    metadata: HashMap<String, Value>,
+    image_paths: Vec<String>,
+    image_name: usize,
+    settings: &RenderSettings,
+    decoded: &DecodedLayers,
+) -> Result<impl FnMut() -> Result<(), CustomError>, CustomError> {
+    let traits: Vec<(String, String)> = image_paths
+        .iter()
+        .filter_map(|path| path_trait(&settings.layout, path))
+        .collect();
+    let images: Vec<(Option<Arc<DynamicImage>>, Attribute)> = image_paths
+        .par_iter()
+        .map(|path| {
+            // A variant is drawn in place of the asset, which the metadata keeps.
+            let drawn = settings.variants.resolve(path, &traits);
+            let img = match decoded.get(drawn) {
+                Some(image) => Some(Arc::clone(image)),
+                None => load_asset_image(drawn, settings)?.map(Arc::new),
+            };
+            let path_parts = trait_path_parts(&settings.layout, path);
+            let (Some(trait_type), Some(value)) = (path_parts.first(), path_parts.last()) else {
+                return Err(CustomError::InvalidTrait(format!(
+                    "{} is not in a layer folder",
+                    path
+                )));
+            };
+
+            let attribute = Attribute {
+                trait_type: trait_type.to_string(),
+                value: value.to_string(),
+                weight: asset_weight(&settings.layout, path),
+            };
+
+            Ok((img, attribute))
+        })
+        .collect::<Result<Vec<(Option<Arc<DynamicImage>>, Attribute)>, CustomError>>()?;
+    let animations: Vec<Option<PathBuf>> = images
+        .iter()
+        .map(|(_, attribute)| {
+            settings
+                .animations
+                .get(&attribute.trait_type, &attribute.value)
+                .map(Path::to_path_buf)
+        })
+        .collect();
+    // A video animation is composed like a video next to the asset.
+    let companions: Vec<Option<PathBuf>> = match &settings.video {
+        Some(_) => image_paths
+            .iter()
+            .zip(&animations)
+            .map(|(path, animation)| match animation {
+                Some(animation) if video::is_video(animation) => Some(animation.clone()),
+                _ => video::companion(settings.variants.resolve(path, &traits)),
+            })
+            .collect(),
+        None => vec![None; image_paths.len()],
+    };
+    let stems: Vec<Option<PathBuf>> = image_paths
+        .iter()
+        .map(|path| audio::is_stem(path).then(|| PathBuf::from(path)))
+        .collect();
+    let frame_sets: Vec<Vec<PathBuf>> = match &settings.spritesheet {
+        Some(_) => image_paths
+            .iter()
+            .map(|path| spritesheet::frame_set(settings.variants.resolve(path, &traits)))
+            .collect(),
+        None => vec![Vec::new(); image_paths.len()],
+    };
+    let settings = settings.clone();
+
+    let closure = move || {
+        let _span = debug_span!("render", token = image_name).entered();
+        let start = Instant::now();
+        let dna = metadata
+            .get("dna")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let image_file_name = settings.file_names.image(image_name, dna);
+        let layers: Vec<_> = images
+            .iter()
+            .zip(&companions)
+            .filter_map(|((image, attribute), companion)| {
+                Some((
+                    image.as_deref()?,
+                    attribute.trait_type.as_str(),
+                    attribute.value.as_str(),
+                    companion.as_deref(),
+                ))
+            })
+            .collect();
+        write_token_image(
+            layers
+                .iter()
+                .map(|&(image, layer_name, value, _)| (image, layer_name, value)),
+            &image_file_name,
+            &settings,
+        )?;
+        if let Some(spritesheet) = &settings.spritesheet {
+            let sheet_layers: Vec<_> = images
+                .iter()
+                .zip(&frame_sets)
+                .filter_map(|((image, attribute), frame_set)| {
+                    Some((
+                        image.as_deref()?,
+                        attribute.trait_type.as_str(),
+                        attribute.value.as_str(),
+                        frame_set.as_slice(),
+                    ))
+                })
+                .collect();
+            write_token_sheet(
+                &sheet_layers,
+                &image_file_name,
+                &settings.file_names.sheet(image_name, dna),
+                &settings,
+                spritesheet,
+            )?;
+        }
+        let mut animation_url = None;
+        let mut mix = None;
+        if let Some(audio) = &settings.audio {
+            let token_stems: Vec<&Path> = stems.iter().flatten().map(PathBuf::as_path).collect();
+            if !token_stems.is_empty() {
+                let mix_file_name =
+                    settings
+                        .file_names
+                        .animation(image_name, dna, audio.extension());
+                let mix_path = Path::new(&settings.output_path).join(mix_file_name);
+                audio.mix(&token_stems, &mix_path)?;
+                animation_url = audio.animation_url(&settings.file_names.animation_name(
+                    image_name,
+                    dna,
+                    audio.extension(),
+                ));
+                mix = Some(mix_path);
+            }
+        }
+        // Tokens without an animated layer only have their image, and their
+        // mix. A video has the mix as its sound.
+        let mut composed = false;
+        if let Some(video) = &settings.video {
+            if layers.iter().any(|layer| layer.3.is_some()) {
+                composed = true;
+                write_token_video(
+                    &layers,
+                    &image_file_name,
+                    &settings
+                        .file_names
+                        .animation(image_name, dna, video.extension()),
+                    mix.as_deref(),
+                    &settings,
+                    video,
+                )?;
+                animation_url = video.animation_url(&settings.file_names.animation_name(
+                    image_name,
+                    dna,
+                    video.extension(),
+                ));
+            }
+        }
+        // Without a video, the animation of a trait is linked as it is.
+        if !composed {
+            let mut files: Vec<&Path> = animations.iter().flatten().map(PathBuf::as_path).collect();
+            files.sort();
+            files.dedup();
+            match files.as_slice() {
+                [] => {}
+                [file] => {
+                    let extension = file
+                        .extension()
+                        .and_then(|extension| extension.to_str())
+                        .unwrap_or_default();
+                    let contents = fs::read(file).map_err(|e| {
+                        CustomError::WriteOutput(format!("{}: {}", file.display(), e))
+                    })?;
+                    settings.sink.write(
+                        Path::new(&settings.output_path)
+                            .join(settings.file_names.animation(image_name, dna, extension)),
+                        contents,
+                    )?;
+                    animation_url = settings.animations.animation_url(
+                        &settings
+                            .file_names
+                            .animation_name(image_name, dna, extension),
+                    );
+                }
+                files => {
+                    return Err(CustomError::InvalidTrait(format!(
+                        "the token has several animations, {}, and no video to compose them into",
+                        files
+                            .iter()
+                            .map(|file| file.display().to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )))
+                }
+            }
+        }
+
+        // Rendering looks the assets up by file name; the metadata shows the
+        // display names.
+        let shown = |attribute: &Attribute| -> String {
+            settings
+                .display_names
+                .rename(&attribute.trait_type, &attribute.value)
+                .unwrap_or(&attribute.value)
+                .clone()
+        };
+        let mut attributes: Vec<Value> = Vec::new();
+        let mut arrays = BTreeMap::new();
+
+        for ((image, attribute), stem) in images.iter().zip(&stems) {
+            let display_type = settings.display_types.get(&attribute.trait_type);
+            // A numeric layer has no number for None, so it is left out.
+            if settings.rarity_only_traits.contains(&attribute.trait_type)
+                || (image.is_none()
+                    && stem.is_none()
+                    && (display_type.is_some()
+                        || !settings.emit_none_traits.contains(&attribute.trait_type)))
+            {
+                continue;
+            }
+
+            let mut shown_attribute = match display_type {
+                Some(display_type) => {
+                    let value = display_type.parse(&attribute.value).ok_or_else(|| {
+                        CustomError::InvalidTrait(format!(
+                            "value {} of {} is not a {}",
+                            attribute.value,
+                            attribute.trait_type,
+                            display_type.as_str()
+                        ))
+                    })?;
+                    settings.metadata_profile.display_attribute(
+                        &attribute.trait_type,
+                        value,
+                        *display_type,
+                    )
+                }
+                None => settings
+                    .metadata_profile
+                    .attribute(&attribute.trait_type, &shown(attribute)),
+            };
+            if settings.metadata_profile.attribute_weight {
+                if let Some(weight) = attribute.weight {
+                    shown_attribute["weight"] = Value::from(weight);
+                }
+            }
+            settings.trait_fields.apply(
+                &attribute.trait_type,
+                &attribute.value,
+                &mut shown_attribute,
+                &mut arrays,
+            );
+            attributes.push(shown_attribute);
+        }
+
+        let mut combined_metadata = metadata.clone();
+        if let Some(name_template) = &settings.name_template {
+            let shown_values: Vec<(&str, String)> = images
+                .iter()
+                .map(|(_, attribute)| (attribute.trait_type.as_str(), shown(attribute)))
+                .collect();
+            let traits: Vec<(&str, &str)> = shown_values
+                .iter()
+                .map(|(trait_type, value)| (*trait_type, value.as_str()))
+                .collect();
+            combined_metadata.insert(
+                "name".to_string(),
+                Value::from(name_template.render(image_name, dna, &traits)),
+            );
+        }
+        combined_metadata.insert("attributes".to_string(), Value::Array(attributes));
+        trait_fields::insert_arrays(&mut combined_metadata, arrays);
+        if let Some(animation_url) = animation_url {
+            combined_metadata.insert("animation_url".to_string(), Value::from(animation_url));
+        }
+
+        let metadata_file_name = settings.file_names.metadata(image_name, dna);
+        let write_error = |e: &dyn fmt::Display| {
+            CustomError::WriteOutput(format!("{}: {}", metadata_file_name, e))
+        };
+        let serialized = to_string_pretty(&combined_metadata).map_err(|e| write_error(&e))?;
+        settings.sink.write(
+            Path::new(&settings.output_path).join(&metadata_file_name),
+            serialized.into_bytes(),
+        )?;
+        debug!("Token {} rendered in {:?}.", image_name, start.elapsed());
+        Ok(())
+    };
+
+    Ok(closure)
+}
+
+/// Writes `value` to `path` as pretty-printed JSON.
+fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), CustomError> {
+    let write_error =
+        |e: &dyn fmt::Display| CustomError::WriteOutput(format!("{}: {}", path.display(), e));
+    let serialized = to_string_pretty(value).map_err(|e| write_error(&e))?;
+    fs::write(path, serialized).map_err(|e| write_error(&e))
+}
+
 fn remove_ds_store_files_recursively(folder_path: String) -> std::io::Result<()> {
     for entry in WalkDir::new(folder_path) {
         let entry = entry?;
         if entry.file_name().to_string_lossy() == ".DS_Store" {
             fs::remove_file(entry.path())?;
-            println!("Removed file: {}", entry.path().display());
+            debug!("Removed file: {}", entry.path().display());
         }
     }
     Ok(())
@@ -414,9 +2259,9 @@ fn remove_ds_store_files_recursively(folder_path: String) -> std::io::Result<()>
 
 fn remove_pre_existing_output(output_path: String) -> std::io::Result<()> {
     for entry in WalkDir::new(&output_path) {
-        let entry = entry.unwrap();
+        let entry = entry?;
         if entry.path().is_file() {
-            fs::remove_file(entry.path()).unwrap();
+            fs::remove_file(entry.path())?;
         }
     }
     Ok(())
@@ -442,11 +2287,19 @@ fn should_include_file(
         .and_then(|f| f.to_str())
         .unwrap_or(base_path);
 
-    let path_parts: Vec<&str> = file_path.split('/').collect();
-
-    let file_name = path_parts.last().unwrap().split('#').next().unwrap();
+    let file_name = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or(file_path);
+    let file_name = file_name.split('#').next().unwrap_or(file_name);
 
-    let target_layer_to_find = if grandparent.eq(base_path) {
+    // `basePath` is usually written as a path, like `./images/`, while the
+    // parents are folder names.
+    let base_name = Path::new(base_path)
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or(base_path);
+    let target_layer_to_find = if grandparent.eq(base_name) {
         parent
     } else {
         grandparent
@@ -480,142 +2333,1364 @@ fn should_include_file(
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let input_path = std::env::args()
-        .nth(2)
-        .unwrap_or_else(|| "config.json".to_string());
-    let file_name = format!("./{}", input_path.as_str());
-    let json_file_path = Path::new(&file_name);
-    let file = File::open(json_file_path).expect("file not found");
-    let config: Config = serde_json::from_reader(file).expect("error while reading");
-    let base_path = config.base_path;
+/// Where the layer folders of the config are, and the weights it gives their
+/// values.
+fn layer_layout(config: &Config) -> LayerLayout {
+    let weights = config
+        .weights
+        .as_ref()
+        .and_then(|weights| weights.load().ok())
+        .map(|table| weights::annotations(&table))
+        .unwrap_or_default();
+    let layer_dirs: Vec<(&str, &str)> = config
+        .layer_folders
+        .iter()
+        .filter_map(|layer_folder| Some((layer_folder.path()?, layer_folder.name())))
+        .collect();
+    LayerLayout::new(&config.base_path)
+        .with_layer_dirs(&layer_dirs)
+        .with_weights(weights)
+}
+
+/// Loads the config at `input_path` with `overrides` set over its fields.
+fn load_config(input_path: &str, overrides: &[Override]) -> Result<Config, CustomError> {
+    let file = File::open(input_path)
+        .map_err(|e| CustomError::Config(format!("{}: {}", input_path, e)))?;
+    let mut value: Value = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| CustomError::Config(format!("{}: {}", input_path, e)))?;
+    overrides::apply(&mut value, overrides)?;
+    // The error names the field it is about, like `layerFolders[2].blendMode`.
+    let mut config: Config = serde_path_to_error::deserialize(value)
+        .map_err(|e| CustomError::Config(format!("{}: {}", input_path, e)))?;
+    config.image.check()?;
+    sidecar::merge_sidecars(&mut config)?;
+    // A weights file is read once, every path of the run reads its weights.
+    if let Some(weights @ Weights::File(_)) = &config.weights {
+        config.weights = Some(Weights::Table(weights.load()?));
+    }
+    if let Some(path) = &config.unique_combinations_file {
+        let combinations = fixed::load_unique_combinations(path)?;
+        config.unique_combinations.extend(combinations);
+    }
+    Ok(config)
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            ExitCode::from(exit_code(error.as_ref()))
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    logging::init(cli.quiet, cli.verbose);
+    let overrides = cli.overrides.overrides()?;
+    let command = cli
+        .command
+        .unwrap_or(Command::Generate(GenerateArgs::default()));
+    // None reads the config given with --config: init writes it, batch
+    // loads its own and schema describes any config.
+    match &command {
+        Command::Init(args) => return init::run(args, &cli.config),
+        Command::Batch(args) => return batch::run(args, &overrides),
+        Command::Schema => return schema::run(),
+        _ => {}
+    }
+    if matches!(command, Command::Validate) {
+        schema::report_unknown_fields(&cli.config)?;
+    }
+    let mut config = load_config(&cli.config, &overrides)?;
+    let output_root = config.output_path.clone();
+    let new_run = namespace::select_output(&mut config, &command, cli.run.as_deref())?;
+    let output_path = config.output_path.clone();
+
+    match command {
+        Command::Init(_) | Command::Batch(_) | Command::Schema => {
+            unreachable!("init, batch and schema run before the config is loaded")
+        }
+        Command::Generate(args) if !config.editions.is_empty() => {
+            editions::generate_editions(&config, &args)
+        }
+        Command::Generate(args) => generate(config, &args),
+        Command::Validate if !config.editions.is_empty() => editions::validate_editions(&config),
+        Command::Validate => validate(config),
+        Command::Estimate(args) => estimate::run(&args, &config),
+        Command::Explore(args) => explore::run(&args, &config),
+        Command::Preview(args) => preview::run(&args, &config),
+        Command::Tui => tui::run(&cli.config, &config),
+        Command::Upload { target } => upload::run(&target, &config),
+        Command::CompareGolden(args) => golden::run(&args, config),
+        Command::RenameTraits(args) => rename::run(&args, &config),
+        Command::RerenderChanged(args) => rerender::run(&args, &config),
+        Command::CheckPalette(args) => palette::run(&args, &config),
+        Command::CheckContrast(args) => contrast::run(&args, &config),
+        Command::Export { format } => export::run(&format, &config),
+        Command::LintMetadata(args) => lint::run(&args, &config),
+        Command::PackLayers(args) => layer_pack::pack_layers(&args, &config),
+        Command::ImportLayers(args) => layer_pack::import_layers(&args, &config),
+        Command::Replay(args) => replay::run(&args, &config),
+        Command::Reroll(args) => reroll::run(&args, &config),
+        Command::SetBaseUri(args) => base_uri::run(&args, &config),
+    }?;
+
+    if let Some(run) = new_run {
+        namespace::write_latest_run(&output_root, &run)?;
+        info!("Run {} written to {}.", run, output_path);
+    }
+    Ok(())
+}
+
+/// A set of layers sampled together for a share of the total supply: one per
+/// forced combination, plus the remaining layers for the rest of the supply.
+struct Pool {
+    layers: Vec<Vec<String>>,
+    supply: usize,
+}
+
+/// Every layer of `layerFolders` with its folder.
+fn layer_folder_dirs(config: &Config) -> Result<Vec<(String, String)>, CustomError> {
+    let entries = get_entries_by_path_dir(config.base_path.clone())?;
+    let dirs = layer_dirs(&entries, &config.layer_folders, &config.base_path)?;
+    Ok(config
+        .layer_folders
+        .iter()
+        .map(|layer_folder| layer_folder.name().to_string())
+        .zip(dirs)
+        .collect())
+}
+
+/// The `watermark` of the config, which `--watermark` runs need.
+fn watermark_stamp(config: &Config) -> Result<Stamp, CustomError> {
+    let watermark = config.watermark.as_ref().ok_or_else(|| {
+        CustomError::Config("--watermark needs a watermark in the config".to_string())
+    })?;
+    Stamp::compile(watermark)
+}
+
+/// The recolored assets of the config, with their files.
+fn recolors(config: &Config) -> Result<Recolors, CustomError> {
+    let Some(recolor) = &config.recolor else {
+        return Ok(Recolors::default());
+    };
+    let (names, dirs): (Vec<String>, Vec<String>) = layer_folder_dirs(config)?.into_iter().unzip();
+    Recolors::compile(
+        &layer_layout(config),
+        recolor,
+        &names,
+        &mut get_layers_by_traits(dirs),
+    )
+}
+
+fn load_layers(config: &Config) -> Result<Vec<Vec<String>>, CustomError> {
+    let base_path = &config.base_path;
+    let layout = layer_layout(config);
 
     _ = remove_ds_store_files_recursively(base_path.clone());
 
-    let traits = get_entries_by_path_dir(base_path.clone())?;
-    let traits_by_config = config
+    let folder_names: Vec<String> = config
         .layer_folders
         .iter()
-        .map(|layer_folder| format!("{}{}", base_path.clone(), layer_folder))
+        .map(|layer_folder| layer_folder.name().to_string())
+        .collect();
+    let ordered_traits: Vec<String> = layer_folder_dirs(config)?
+        .into_iter()
+        .map(|(_, dir)| dir)
+        .collect();
+    let mut layers = get_layers_by_traits(ordered_traits.clone());
+    if let Some(audio) = &config.audio {
+        audio.check_layers(&folder_names)?;
+        for ((layer, layer_dir), name) in layers.iter_mut().zip(&ordered_traits).zip(&folder_names)
+        {
+            if audio.layers.contains(name) {
+                *layer = audio::stem_paths(Path::new(layer_dir));
+            }
+        }
+    }
+    let table = match &config.weights {
+        Some(weights) => weights.load()?,
+        None => HashMap::new(),
+    };
+    let named_layers: Vec<(&str, &[String])> = folder_names
+        .iter()
+        .map(String::as_str)
+        .zip(layers.iter().map(Vec::as_slice))
+        .collect();
+    weights::check(&layout, &table, &named_layers)?;
+    if let Some(recolor) = &config.recolor {
+        Recolors::compile(&layout, recolor, &folder_names, &mut layers)?;
+    }
+
+    for ((layer, layer_dir), layer_folder) in layers
+        .iter_mut()
+        .zip(&ordered_traits)
+        .zip(&config.layer_folders)
+    {
+        check_shares(&layout, layer)?;
+        if let Some(display_type) = layer_folder.display_type() {
+            for (_, value) in layer
+                .iter()
+                .filter(|path| !is_none_asset(path))
+                .filter_map(|path| path_trait(&layout, path))
+            {
+                if display_type.parse(&value).is_none() {
+                    return Err(CustomError::InvalidTrait(format!(
+                        "value {} of {} is not a {}",
+                        value,
+                        layer_folder.name(),
+                        display_type.as_str()
+                    )));
+                }
+            }
+        }
+
+        let skip_probability = layer_folder.skip_probability();
+        if skip_probability == 0.0 {
+            continue;
+        }
+        if !(0.0..1.0).contains(&skip_probability) {
+            return Err(CustomError::InvalidTrait(format!(
+                "skipProbability of {} must be at least 0 and below 1",
+                layer_folder.name()
+            )));
+        }
+        // A blank `None` asset of the layer is merged into the placeholder,
+        // which takes its weight, so the layer has a single `None` value.
+        let resolved = resolve_weights(&layout, layer);
+        let unweighted = resolved.iter().all(|weight| *weight == 0);
+        let none_file_weight: u64 = layer
+            .iter()
+            .zip(&resolved)
+            .filter(|(path, _)| is_none_file(&layout, path))
+            .map(|(_, weight)| if unweighted { 1 } else { *weight })
+            .sum();
+        let layer_weight = if unweighted {
+            layer.len() as u64
+        } else {
+            resolved.iter().sum()
+        };
+        layer.retain(|path| !is_none_file(&layout, path));
+
+        // In quota mode the tokens left without a value of the layer get None.
+        let none_weight = if config.weight_mode == WeightMode::Quota {
+            let supply = collection_supply(config);
+            let quotas: usize = layer
+                .iter()
+                .map(|path| caps::file_name_weight(&layout, path, supply))
+                .sum();
+            supply.saturating_sub(quotas) as u64
+        } else {
+            ((layer_weight as f64 * skip_probability / (1.0 - skip_probability)).round() as u64)
+                .max(1)
+                + none_file_weight
+        };
+        if none_weight > 0 {
+            layer.push(none_asset(layer_dir, none_weight));
+        }
+    }
+
+    if let Some(background) = &config.background {
+        layers.insert(0, background.assets(base_path, &folder_names)?);
+    }
+
+    Ok(layers)
+}
+
+/// The layers a `fit` or an offset places on purpose, whatever the size of
+/// their files.
+fn placed_layers(config: &Config) -> BTreeSet<String> {
+    config
+        .layer_folders
+        .iter()
+        .filter(|layer_folder| layer_folder.fit() != Fit::None || layer_folder.offset() != (0, 0))
+        .map(|layer_folder| layer_folder.name().to_string())
+        .collect()
+}
+
+/// Checks the size of every layer file against the canvas, leaving out the
+/// placed layers, and returns how many differ. With `autoResize` they are
+/// scaled to it instead.
+fn check_dimensions(config: &Config, all_layers: &[Vec<String>]) -> Result<usize, CustomError> {
+    if config.dimension_mismatch == DimensionPolicy::Ignore && config.auto_resize.is_none() {
+        return Ok(0);
+    }
+    let exempt_layers: HashSet<String> = placed_layers(config).into_iter().collect();
+    let canvas = config.image.canvas_size();
+    let mismatches =
+        dimensions::find_mismatches(&layer_layout(config), all_layers, canvas, &exempt_layers)?;
+    if config.auto_resize.is_some() {
+        if !mismatches.is_empty() {
+            info!(
+                "{} layer files differ from the {}x{} canvas and are scaled to it.",
+                mismatches.len(),
+                canvas.0,
+                canvas.1
+            );
+        }
+    } else {
+        dimensions::report_mismatches(&mismatches, canvas, config.dimension_mismatch)?;
+    }
+    Ok(mismatches.len())
+}
+
+/// The name of every layer, in the order of `load_layers`.
+fn layer_names(config: &Config) -> Vec<String> {
+    config
+        .background
+        .iter()
+        .map(|background| background.layer.clone())
+        .chain(
+            config
+                .layer_folders
+                .iter()
+                .map(|layer_folder| layer_folder.name().to_string()),
+        )
+        .collect()
+}
+
+fn skipped_trait_regexes(config: &Config) -> Result<Vec<Regex>, CustomError> {
+    config
+        .skipped_traits
+        .iter()
+        .flatten()
+        .map(|pattern| {
+            Regex::new(pattern)
+                .map_err(|e| CustomError::InvalidTrait(format!("skippedTraits {}: {}", pattern, e)))
+        })
+        .collect()
+}
+
+/// The `traitCaps` of the config, plus the quota of every value in quota mode.
+fn compile_caps(
+    config: &Config,
+    all_layers: &[Vec<String>],
+    skipped_traits: &[Regex],
+    names: &[String],
+) -> Result<Caps, CustomError> {
+    let mut trait_caps = config.trait_caps.clone();
+    if config.weight_mode == WeightMode::Quota {
+        trait_caps.extend(caps::quota_caps(
+            &layer_layout(config),
+            &permutations::without_skipped(all_layers, skipped_traits),
+            collection_supply(config),
+        )?);
+    }
+    Caps::compile(&trait_caps, names)
+}
+
+fn plan_pools(
+    forced_combinations: &[ForcedCombinations],
+    supply: u32,
+    base_path: &str,
+    all_layers: &[Vec<String>],
+) -> Result<Vec<Pool>, CustomError> {
+    if forced_combinations.is_empty() {
+        return Ok(vec![Pool {
+            layers: all_layers.to_vec(),
+            supply: supply as usize,
+        }]);
+    }
+
+    let total_percentage: u32 = forced_combinations
+        .iter()
+        .map(|combo| u32::from(combo.percentage))
+        .sum();
+
+    if total_percentage > 100 {
+        return Err(CustomError::TotalPercentageExceeded(
+            "The sum of the percentages in the forced combinations exceeds 100%.".to_string(),
+        ));
+    }
+
+    let mut pools = Vec::new();
+    let mut remaining_layers_for_next_combinations = all_layers.to_vec();
+    let mut rest_of_items_percentage = supply;
+
+    for forced_combination_item in forced_combinations {
+        let current_forced_combination_config = &forced_combination_item.combo;
+        let current_forced_combination_percentage = forced_combination_item.percentage;
+        let mut included_layers: Vec<Vec<String>> = Vec::new();
+        let mut not_included_layers: Vec<Vec<String>> = Vec::new();
+
+        for layer_data in all_layers {
+            let mut included = Vec::new();
+            let mut not_included = Vec::new();
+
+            for file_path in layer_data {
+                if should_include_file(current_forced_combination_config, file_path, base_path) {
+                    included.push(file_path.clone());
+                } else {
+                    not_included.push(file_path.clone());
+                }
+            }
+
+            not_included_layers.push(not_included);
+            included_layers.push(included);
+        }
+
+        for (remaining_layers_for_next_combinations_el, not_included_layers_el) in
+            remaining_layers_for_next_combinations
+                .iter_mut()
+                .zip(&not_included_layers)
+        {
+            if !not_included_layers_el.is_empty() {
+                remaining_layers_for_next_combinations_el.clear();
+                remaining_layers_for_next_combinations_el
+                    .extend(not_included_layers_el.iter().cloned());
+            }
+        }
+
+        let total_items_percentage =
+            (supply * u32::from(current_forced_combination_percentage)) / 100;
+        rest_of_items_percentage = rest_of_items_percentage.saturating_sub(total_items_percentage);
+
+        pools.push(Pool {
+            layers: included_layers,
+            supply: total_items_percentage as usize,
+        });
+    }
+
+    pools.push(Pool {
+        layers: remaining_layers_for_next_combinations,
+        supply: rest_of_items_percentage as usize,
+    });
+
+    Ok(pools)
+}
+
+/// Pools of the public supply.
+fn public_pools(config: &Config, all_layers: &[Vec<String>]) -> Result<Vec<Pool>, CustomError> {
+    plan_pools(
+        &config.forced_combinations,
+        sampled_supply(config),
+        &config.base_path,
+        all_layers,
+    )
+}
+
+/// Pools of the reserved supply, empty without one.
+fn reserved_pools(config: &Config, all_layers: &[Vec<String>]) -> Result<Vec<Pool>, CustomError> {
+    match &config.reserved_supply {
+        Some(reserved) if reserved.supply > 0 => plan_pools(
+            &reserved.forced_combinations,
+            reserved.supply,
+            &config.base_path,
+            all_layers,
+        ),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// The tokens sampled from a set of pools.
+struct SampledPools {
+    /// Tokens in the order they were drawn, pool after pool.
+    permutations: Vec<(u64, Vec<String>)>,
+    /// Permutations the pools allow.
+    possible_permutations: usize,
+    /// Tokens of every pool.
+    received: Vec<usize>,
+}
+
+/// Samples the tokens of every pool. A pool never draws a token of the pools
+/// before it, whose DNAs are added to `constraints`.
+fn sample_pools<R: Rng>(
+    pools: &[Pool],
+    names: &[String],
+    skipped_traits: &[Regex],
+    constraints: &mut Constraints,
+    caps: &mut Caps,
+    rng: &mut R,
+    mut replay: Option<&mut ReplayLog>,
+) -> Result<SampledPools, CustomError> {
+    let mut permutations: Vec<(u64, Vec<String>)> = Vec::new();
+    let mut possible_permutations: usize = 0;
+    let mut received = Vec::with_capacity(pools.len());
+
+    for (index, pool) in pools.iter().enumerate() {
+        let count =
+            permutations::count_permutations(names, &pool.layers, skipped_traits, constraints);
+        if count.possible < pool.supply {
+            return Err(CustomError::InvalidTotalSupply(
+                pool.supply as u64,
+                count.possible as u64,
+            ));
+        }
+        possible_permutations = possible_permutations.saturating_add(count.possible);
+
+        let layers = permutations::without_skipped(&pool.layers, skipped_traits);
+        caps.check_reachable(&constraints.layout, &layers, pool.supply)?;
+        let drawn = generate_permutations(
+            &layers,
+            pool.supply,
+            constraints,
+            caps,
+            rng,
+            replay.as_deref_mut(),
+        )?;
+        if index + 1 < pools.len() {
+            constraints
+                .excluded_dna
+                .extend(drawn.iter().map(|(_, image_paths)| {
+                    dna::compute_dna(constraints.dna_algorithm, &constraints.layout, image_paths)
+                }));
+        }
+        received.push(drawn.len());
+        permutations.extend(drawn);
+    }
+
+    Ok(SampledPools {
+        permutations,
+        possible_permutations,
+        received,
+    })
+}
+
+/// The DNAs this run can't generate, and every DNA issued by the previous runs.
+fn previous_dna(config: &Config) -> Result<(HashSet<String>, BTreeSet<String>), CustomError> {
+    let live = dna::load_dna_files(&config.previous_dna_files, config.dna_algorithm)?;
+    let mut history =
+        manifest::load_historical_dna(&config.previous_manifest_files, config.dna_algorithm)?;
+    history.extend(live.iter().cloned());
+    let excluded = match config.dna_reissue {
+        DnaReissue::Never => history.iter().cloned().collect(),
+        DnaReissue::AllowRemoved => live,
+    };
+    Ok((excluded, history))
+}
+
+/// Tokens of the total supply generated from the layers, the 1/1 and fixed
+/// tokens left out.
+fn generated_supply(config: &Config) -> u32 {
+    let one_of_ones = config.one_of_ones.as_ref().map_or(0, OneOfOnes::count);
+    config
+        .total_supply
+        .saturating_sub((one_of_ones + config.fixed_tokens.len()) as u32)
+}
+
+/// Generated tokens drawn at random: the unique combinations are generated
+/// as they are.
+fn sampled_supply(config: &Config) -> u32 {
+    generated_supply(config).saturating_sub(config.unique_combinations.len() as u32)
+}
+
+/// The fixed tokens with the asset of every layer, by token id.
+fn fixed_tokens(
+    config: &Config,
+    all_layers: &[Vec<String>],
+) -> Result<Vec<(usize, Vec<String>)>, CustomError> {
+    fixed::resolve(
+        &layer_layout(config),
+        &config.fixed_tokens,
+        &layer_names(config),
+        all_layers,
+        &public_token_ids(config),
+    )
+}
+
+/// The asset of every layer of the unique combinations.
+fn unique_combinations(
+    config: &Config,
+    all_layers: &[Vec<String>],
+) -> Result<Vec<Vec<String>>, CustomError> {
+    fixed::resolve_unique(
+        &layer_layout(config),
+        &config.unique_combinations,
+        &fixed_tokens(config, all_layers)?,
+        &layer_names(config),
+        all_layers,
+    )
+}
+
+/// Tokens generated for the whole collection, the reserved ones included.
+fn collection_supply(config: &Config) -> usize {
+    (generated_supply(config) + reserved_supply(config)) as usize
+}
+
+fn reserved_supply(config: &Config) -> u32 {
+    config
+        .reserved_supply
+        .as_ref()
+        .map_or(0, |reserved| reserved.supply)
+}
+
+/// Token ids of the total supply, from `startIndex`. Reserved tokens follow
+/// them.
+fn public_token_ids(config: &Config) -> Range<usize> {
+    config.start_index..config.start_index + config.total_supply as usize
+}
+
+/// Token ids of every token written, the reserved ones included.
+fn collection_token_ids(config: &Config) -> Range<usize> {
+    let public = public_token_ids(config);
+    public.start..public.end + reserved_supply(config) as usize
+}
+
+fn validate(config: Config) -> Result<(), Box<dyn Error>> {
+    let (mut excluded_dna, _) = previous_dna(&config)?;
+    let all_layers = load_layers(&config)?;
+    let pools = public_pools(&config, &all_layers)?;
+    let skipped_traits = skipped_trait_regexes(&config)?;
+    let names = layer_names(&config);
+    let rules = Rules::compile(&config.dependency_rules, &config.trait_tags, &names)?;
+    if let Some(name_template) = &config.name_template {
+        NameTemplate::compile(name_template, &names)?;
+    }
+    FileNames::compile(&config)?;
+    Variants::load(&layer_layout(&config), &layer_folder_dirs(&config)?, &names)?;
+    if let Some(display_names) = &config.display_names {
+        display_names.load()?;
+    }
+    if let Some(localization) = &config.localization {
+        localization.check()?;
+    }
+    let mismatched = check_dimensions(&config, &all_layers)?;
+    let unique = unique_combinations(&config, &all_layers)?;
+    let layout = layer_layout(&config);
+    excluded_dna.extend(
+        unique
+            .iter()
+            .map(|image_paths| dna::compute_dna(config.dna_algorithm, &layout, image_paths)),
+    );
+    let constraints = Constraints {
+        excluded_dna,
+        dna_algorithm: config.dna_algorithm,
+        rules,
+        layout,
+    };
+    let caps = compile_caps(&config, &all_layers, &skipped_traits, &names)?;
+    caps.check_reachable(
+        &constraints.layout,
+        &permutations::without_skipped(&all_layers, &skipped_traits),
+        collection_supply(&config),
+    )?;
+
+    let count =
+        permutations::count_permutations(&names, &all_layers, &skipped_traits, &constraints);
+    for layer in &count.layers {
+        print!("{}: {} options", layer.layer, layer.options);
+        if layer.sublayers > 0 {
+            print!(" in {} sublayers", layer.sublayers);
+        }
+        if layer.skipped > 0 {
+            print!(", {} skipped", layer.skipped);
+        }
+        println!(".");
+    }
+    if mismatched == 0
+        && (config.dimension_mismatch != DimensionPolicy::Ignore || config.auto_resize.is_some())
+    {
+        let (width, height) = config.image.canvas_size();
+        println!("Every layer file is {}x{}.", width, height);
+    }
+
+    let mut possible_permutations: usize = 0;
+    let mut excluded: usize = 0;
+    for (index, pool) in pools.iter().enumerate() {
+        let pool_count =
+            permutations::count_permutations(&names, &pool.layers, &skipped_traits, &constraints);
+        if pools.len() > 1 {
+            let label = if index < config.forced_combinations.len() {
+                format!("Forced combination {}", index + 1)
+            } else {
+                "Remaining layers".to_string()
+            };
+            println!(
+                "{}: {} possible permutations for {} tokens.",
+                label, pool_count.possible, pool.supply
+            );
+        }
+        if pool_count.possible < pool.supply {
+            return Err(CustomError::InvalidTotalSupply(
+                pool.supply as u64,
+                pool_count.possible as u64,
+            )
+            .into());
+        }
+        caps.check_reachable(
+            &constraints.layout,
+            &permutations::without_skipped(&pool.layers, &skipped_traits),
+            pool.supply,
+        )?;
+        possible_permutations = possible_permutations.saturating_add(pool_count.possible);
+        excluded += pool_count.excluded;
+    }
+
+    println!(
+        "Possible permutations: {} ({} ruled out by previous runs, dependencyRules or uniqueCombinations). Total supply: {}.",
+        possible_permutations, excluded, config.total_supply
+    );
+
+    let fixed_ids: Vec<usize> = fixed_tokens(&config, &all_layers)?
+        .iter()
+        .map(|(token_id, _)| *token_id)
+        .collect();
+    if !fixed_ids.is_empty() {
+        println!("Fixed tokens: {:?}.", fixed_ids);
+    }
+    if let Some(one_of_ones) = &config.one_of_ones {
+        let loaded = one_of_ones.load()?;
+        one_of_ones.place(
+            &loaded,
+            public_token_ids(&config),
+            &fixed_ids,
+            &mut ChaCha8Rng::seed_from_u64(0),
+        )?;
+        println!(
+            "1/1 tokens: {}, {} tokens generated from the layers.",
+            loaded.len(),
+            generated_supply(&config)
+        );
+    }
+
+    if !unique.is_empty() {
+        println!("Unique combinations: {}.", unique.len());
+    }
+
+    if possible_permutations < sampled_supply(&config) as usize {
+        return Err(CustomError::InvalidTotalSupply(
+            sampled_supply(&config).into(),
+            possible_permutations as u64,
+        )
+        .into());
+    }
+
+    for pool in &reserved_pools(&config, &all_layers)? {
+        let pool_count =
+            permutations::count_permutations(&names, &pool.layers, &skipped_traits, &constraints);
+        println!(
+            "Reserved supply: {} possible permutations for {} tokens.",
+            pool_count.possible, pool.supply
+        );
+        if pool_count.possible < pool.supply {
+            return Err(CustomError::InvalidTotalSupply(
+                pool.supply as u64,
+                pool_count.possible as u64,
+            )
+            .into());
+        }
+        caps.check_reachable(
+            &constraints.layout,
+            &permutations::without_skipped(&pool.layers, &skipped_traits),
+            pool.supply,
+        )?;
+    }
+    if count.possible < collection_supply(&config) {
+        return Err(CustomError::InvalidTotalSupply(
+            collection_supply(&config) as u64,
+            count.possible as u64,
+        )
+        .into());
+    }
+
+    println!("The config is valid.");
+    Ok(())
+}
+
+/// The tokens of a run, picked before anything is rendered.
+struct CollectionPlan {
+    /// Asset paths of every token, in token id order; the reserved tokens
+    /// come last.
+    permutations: Vec<(u64, Vec<String>)>,
+    token_ids: Vec<usize>,
+    public_count: usize,
+    one_of_ones: Vec<OneOfOne>,
+    one_of_one_ids: Vec<usize>,
+    shuffled: Vec<shuffle::ShuffledToken>,
+    dna_history: BTreeSet<String>,
+    /// Tokens of every forced combination.
+    buckets: Vec<forced::BucketCount>,
+}
+
+/// Samples every token of the collection and gives it its token id. The
+/// asset draws are recorded into `replay` when there is one.
+fn plan_collection(
+    config: &Config,
+    all_layers: &[Vec<String>],
+    rng: &mut ChaCha8Rng,
+    mut replay: Option<&mut ReplayLog>,
+) -> Result<CollectionPlan, CustomError> {
+    let (excluded_dna, dna_history) = previous_dna(config)?;
+    let pools = public_pools(config, all_layers)?;
+    let skipped_traits = skipped_trait_regexes(config)?;
+    let names = layer_names(config);
+    let rules = Rules::compile(&config.dependency_rules, &config.trait_tags, &names)?;
+    let mut constraints = Constraints {
+        excluded_dna,
+        dna_algorithm: config.dna_algorithm,
+        rules,
+        layout: layer_layout(config),
+    };
+    let mut caps = compile_caps(config, all_layers, &skipped_traits, &names)?;
+    // Sampled tokens never repeat a fixed one or a unique combination, which
+    // count against the caps like drawn tokens.
+    let fixed = fixed_tokens(config, all_layers)?;
+    let unique = unique_combinations(config, all_layers)?;
+    constraints.excluded_dna.extend(
+        fixed
+            .iter()
+            .map(|(_, image_paths)| image_paths)
+            .chain(&unique)
+            .map(|image_paths| {
+                dna::compute_dna(config.dna_algorithm, &constraints.layout, image_paths)
+            }),
+    );
+    for image_paths in &unique {
+        let traits: Vec<(String, String)> = image_paths
+            .iter()
+            .filter_map(|path| path_trait(&constraints.layout, path))
+            .collect();
+        caps.record(&traits);
+    }
+
+    let SampledPools {
+        permutations,
+        possible_permutations,
+        received,
+    } = sample_pools(
+        &pools,
+        &names,
+        &skipped_traits,
+        &mut constraints,
+        &mut caps,
+        rng,
+        replay.as_deref_mut(),
+    )?;
+    let planned: Vec<usize> = pools.iter().map(|pool| pool.supply).collect();
+    let mut buckets = forced::bucket_counts(
+        &config.forced_combinations,
+        &planned,
+        &received,
+        sampled_supply(config) as usize,
+        false,
+    );
+
+    info!(
+        "The number of possible permutations for {} layers is: {}.",
+        all_layers.len(),
+        possible_permutations
+    );
+
+    if possible_permutations < sampled_supply(config) as usize {
+        return Err(CustomError::InvalidTotalSupply(
+            sampled_supply(config).into(),
+            possible_permutations as u64,
+        ));
+    }
+
+    let mut permutations = permutations;
+    permutations.extend(
+        unique
+            .into_iter()
+            .map(|image_paths| (permutation_key(&image_paths), image_paths)),
+    );
+    config
+        .token_order
+        .sort(&mut permutations, config.dna_algorithm, &constraints.layout);
+
+    // Fixed and 1/1 tokens take their ids out of the public range; the
+    // generated tokens fill the ids left, in order.
+    let fixed_ids: Vec<usize> = fixed.iter().map(|(token_id, _)| *token_id).collect();
+    let (one_of_ones, one_of_one_ids) = match &config.one_of_ones {
+        Some(one_of_ones) => {
+            let loaded = one_of_ones.load()?;
+            let ids = one_of_ones.place(&loaded, public_token_ids(config), &fixed_ids, rng)?;
+            (loaded, ids)
+        }
+        None => (Vec::new(), Vec::new()),
+    };
+    let generated_ids: Vec<usize> = public_token_ids(config)
+        .filter(|token_id| !one_of_one_ids.contains(token_id) && !fixed_ids.contains(token_id))
+        .collect();
+    // The generated tokens are in the configured `tokenOrder`, which anyone can
+    // recompute from the assets; shuffling with the run's rng unties the ids
+    // from it.
+    let shuffled = if config.shuffle_token_ids {
+        let order = shuffle::shuffled_order(permutations.len(), rng);
+        permutations = order
+            .iter()
+            .map(|&index| permutations[index].clone())
+            .collect();
+        order
+            .into_iter()
+            .zip(&generated_ids)
+            .map(|(generated_index, &token_id)| shuffle::ShuffledToken {
+                generated_index,
+                token_id,
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut public_tokens: Vec<(usize, (u64, Vec<String>))> = generated_ids
+        .into_iter()
+        .zip(permutations)
+        .chain(
+            fixed
+                .into_iter()
+                .map(|(token_id, image_paths)| (token_id, (0, image_paths))),
+        )
         .collect();
+    public_tokens.sort_by_key(|(token_id, _)| *token_id);
+    let (mut token_ids, mut permutations): (Vec<usize>, Vec<(u64, Vec<String>)>) =
+        public_tokens.into_iter().unzip();
+    let public_count = permutations.len();
+    if !fixed_ids.is_empty() {
+        info!("Fixed tokens at ids {:?}.", fixed_ids);
+    }
 
-    let ordered_traits = compare_and_verify_traits::<String>(traits, traits_by_config, &base_path)?;
+    let reserved_pools = reserved_pools(config, all_layers)?;
+    if !reserved_pools.is_empty() {
+        // Reserved tokens never repeat a public one.
+        constraints
+            .excluded_dna
+            .extend(permutations.iter().map(|(_, image_paths)| {
+                dna::compute_dna(config.dna_algorithm, &constraints.layout, image_paths)
+            }));
+
+        let SampledPools {
+            permutations: reserved,
+            received,
+            ..
+        } = sample_pools(
+            &reserved_pools,
+            &names,
+            &skipped_traits,
+            &mut constraints,
+            &mut caps,
+            rng,
+            replay,
+        )?;
+        if let Some(reserved_supply) = &config.reserved_supply {
+            let planned: Vec<usize> = reserved_pools.iter().map(|pool| pool.supply).collect();
+            buckets.extend(forced::bucket_counts(
+                &reserved_supply.forced_combinations,
+                &planned,
+                &received,
+                reserved_supply.supply as usize,
+                true,
+            ));
+        }
 
-    let all_layers = get_layers_by_traits(ordered_traits);
-    let mut permutations: HashMap<u64, Vec<String>> = HashMap::new();
-    let mut remaining_layers_for_next_combinations = all_layers.clone();
-    let mut possible_permutations: usize = 0;
-    let mut layer_count: usize = 0;
-    let mut rest_of_items_percentage = config.total_supply;
+        let mut reserved = reserved;
+        config
+            .token_order
+            .sort(&mut reserved, config.dna_algorithm, &constraints.layout);
+        let first_reserved = public_token_ids(config).end;
+        info!(
+            "Reserved tokens: {} to {}.",
+            first_reserved,
+            first_reserved + reserved.len() - 1
+        );
+        token_ids.extend(first_reserved..first_reserved + reserved.len());
+        permutations.extend(reserved);
+    }
 
-    if !config.forced_combinations.is_empty() {
-        let total_percentage: u32 = config
-            .forced_combinations
-            .iter()
-            .map(|combo| u32::from(combo.percentage))
-            .sum();
+    Ok(CollectionPlan {
+        permutations,
+        token_ids,
+        public_count,
+        one_of_ones,
+        one_of_one_ids,
+        shuffled,
+        dna_history,
+        buckets,
+    })
+}
 
-        if total_percentage > 100 {
-            CustomError::TotalPercentageExceeded(
-                "The sum of the percentages in the forced combinations exceeds 100%.".to_string(),
-            );
-        }
+/// The layer files none of `token_paths` was drawn with, and why.
+fn find_unused_assets(
+    config: &Config,
+    all_layers: &[Vec<String>],
+    token_paths: &[Vec<String>],
+) -> Result<Vec<unused::UnusedAsset>, CustomError> {
+    let mut pools = public_pools(config, all_layers)?;
+    pools.extend(reserved_pools(config, all_layers)?);
+    let pool_layers: Vec<&[Vec<String>]> = pools
+        .iter()
+        .filter(|pool| pool.supply > 0)
+        .map(|pool| pool.layers.as_slice())
+        .collect();
+    let names = layer_names(config);
+    let rules = Rules::compile(&config.dependency_rules, &config.trait_tags, &names)?;
+    Ok(unused::unused_assets(
+        &layer_layout(config),
+        all_layers,
+        token_paths,
+        &pool_layers,
+        &skipped_trait_regexes(config)?,
+        &rules,
+    ))
+}
 
-        let mut not_included_layers: Vec<Vec<String>> = Vec::new();
+/// The metadata of a token before its attributes: the config metadata with
+/// the DNA, the rarity when `embedRarity` is set and the image URL.
+fn token_metadata(
+    config: &Config,
+    file_names: &FileNames,
+    token_id: usize,
+    token_dna: &str,
+    token_rarity: &rarity::TokenRarity,
+) -> HashMap<String, Value> {
+    let mut metadata = config.metadata.clone();
+    metadata.insert("dna".to_string(), Value::from(token_dna));
+    if config.embed_rarity {
+        metadata.insert("rarity".to_string(), rarity::embedded_rarity(token_rarity));
+    }
+    if !config.image_url.is_empty() {
+        metadata.insert(
+            "image".to_string(),
+            Value::from(format!(
+                "{}/{}",
+                config.image_url.trim_end_matches('/'),
+                file_names.image_name(token_id, token_dna)
+            )),
+        );
+    }
+    metadata
+}
 
-        for forced_combination_item in &config.forced_combinations {
-            let current_forced_combination_config = &forced_combination_item.combo;
-            let current_forced_combination_percentage = forced_combination_item.percentage;
-            let mut included_layers: Vec<Vec<String>> = Vec::new();
-
-            for layer_data in &all_layers {
-                let mut included = Vec::new();
-                let mut not_included = Vec::new();
-
-                for file_path in layer_data {
-                    if should_include_file(
-                        current_forced_combination_config,
-                        file_path,
-                        base_path.as_str(),
-                    ) {
-                        included.push(file_path.clone());
-                    } else {
-                        not_included.push(file_path.clone());
-                    }
-                }
+/// Runs the renders of a batch of tokens, given with their ids and assets, in
+/// parallel. Every token is rendered and the first failure in token order is
+/// the one reported; a render that panics fails its token rather than the
+/// whole process.
+fn render_batch<F>(renders: Vec<(usize, &Vec<String>, F)>) -> Result<(), CustomError>
+where
+    F: FnMut() -> Result<(), CustomError> + Send,
+{
+    let results: Vec<Result<(), CustomError>> = renders
+        .into_par_iter()
+        .map(|(token_id, image_paths, mut render)| {
+            panic::catch_unwind(AssertUnwindSafe(&mut render))
+                .unwrap_or_else(|_| {
+                    Err(CustomError::WriteOutput("the render panicked".to_string()))
+                })
+                .map_err(|e| e.for_token(token_id, image_paths))
+        })
+        .collect();
+    results.into_iter().collect()
+}
 
-                not_included_layers.push(not_included);
-                included_layers.push(included);
-            }
+fn generate(config: Config, args: &GenerateArgs) -> Result<(), Box<dyn Error>> {
+    // Without a seed in the config, a random one is drawn so the replay log
+    // can still be tied to it.
+    let seed = config.seed.unwrap_or_else(rand::random);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut replay = ReplayLog::new(seed);
+
+    let verification = info_span!("verify_traits").entered();
+    let all_layers = load_layers(&config)?;
+    let skipped_traits = skipped_trait_regexes(&config)?;
+    if let Some(localization) = &config.localization {
+        localization.check()?;
+    }
+    check_dimensions(&config, &all_layers)?;
+    let watermark = args
+        .watermark
+        .then(|| watermark_stamp(&config))
+        .transpose()?
+        .map(Arc::new);
+    debug!("{} layers verified.", all_layers.len());
+    verification.exit();
+
+    let planning = info_span!("permutations", seed).entered();
+    let CollectionPlan {
+        permutations,
+        token_ids,
+        public_count,
+        one_of_ones,
+        one_of_one_ids,
+        shuffled,
+        dna_history,
+        buckets,
+    } = plan_collection(&config, &all_layers, &mut rng, Some(&mut replay))?;
+    planning.exit();
+    for bucket in &buckets {
+        info!(
+            "Forced combination {}{}: {} of {} tokens ({}% of {}).",
+            bucket.combination,
+            if bucket.reserved { " (reserved)" } else { "" },
+            bucket.received,
+            bucket.planned,
+            bucket.percentage,
+            bucket.supply
+        );
+    }
+    if let Err(error) = forced::check_buckets(&buckets, config.forced_combination_tolerance) {
+        _ = fs::create_dir_all(&config.output_path);
+        forced::write_report(Path::new(&config.output_path), &buckets)?;
+        return Err(error.into());
+    }
 
-            for (remaining_layers_for_next_combinations_el, not_included_layers_el) in
-                remaining_layers_for_next_combinations
-                    .iter_mut()
-                    .zip(&not_included_layers)
-            {
-                if !not_included_layers_el.is_empty() {
-                    remaining_layers_for_next_combinations_el.clear();
-                    remaining_layers_for_next_combinations_el
-                        .extend(not_included_layers_el.iter().cloned());
-                }
-            }
+    let token_paths: Vec<Vec<String>> = permutations
+        .into_iter()
+        .map(|(_, image_paths)| image_paths)
+        .collect();
+    let sampled_layers = permutations::without_skipped(&all_layers, &skipped_traits);
+    let layout = layer_layout(&config);
+    let mut stats = stats::compute_stats(&layout, &sampled_layers, &token_paths[..public_count]);
+    if public_count < token_paths.len() {
+        stats.reserved = Some(Box::new(stats::compute_stats(
+            &layout,
+            &sampled_layers,
+            &token_paths[public_count..],
+        )));
+    }
 
-            let total_items_percentage =
-                (config.total_supply * u32::from(current_forced_combination_percentage)) / 100;
-            rest_of_items_percentage =
-                rest_of_items_percentage.saturating_sub(total_items_percentage);
+    _ = fs::create_dir_all(config.output_path.clone());
 
-            permutations.extend(generate_permutations(
-                &included_layers,
-                total_items_percentage as usize,
-            ));
-            possible_permutations +=
-                get_permutations(&included_layers, config.skipped_traits.clone());
+    let co_occurrence = stats::compute_co_occurrence(&layout, &token_paths);
+    let unused_assets = find_unused_assets(&config, &all_layers, &token_paths)?;
+    for asset in &unused_assets {
+        info!("Unused: {} ({}).", asset.file, asset.reason);
+    }
 
-            layer_count = remaining_layers_for_next_combinations.len();
+    if args.dry_run {
+        stats::write_stats(Path::new(&config.output_path), &stats)?;
+        stats::write_co_occurrence(Path::new(&config.output_path), &co_occurrence)?;
+        unused::write_unused_assets(Path::new(&config.output_path), &unused_assets)?;
+        if !buckets.is_empty() {
+            forced::write_report(Path::new(&config.output_path), &buckets)?;
         }
-
-        permutations.extend(generate_permutations(
-            &remaining_layers_for_next_combinations,
-            rest_of_items_percentage as usize,
-        ));
-    } else {
-        permutations = generate_permutations(&all_layers, config.total_supply as usize);
-        possible_permutations = get_permutations(&all_layers, config.skipped_traits.clone());
-        layer_count = all_layers.len();
+        info!(
+            "Dry run: trait statistics for {} tokens written to {}.",
+            token_paths.len(),
+            Path::new(&config.output_path)
+                .join(stats::STATS_FILE_NAME)
+                .display()
+        );
+        return Ok(());
     }
 
-    println!(
-        "The number of possible permutations for {} layers is: {}.",
-        layer_count, possible_permutations
+    // The size of the output is extrapolated from a few tokens rendered aside,
+    // counting the files of the previous run as free, so a run that won't fit
+    // stops before anything is rendered.
+    let samples = &token_paths[..token_paths.len().min(estimate::DEFAULT_SAMPLES)];
+    let estimate = estimate::render_temporary_samples(&config, samples)?
+        .extrapolate(token_paths.len() + one_of_ones.len(), 1);
+    disk_space::ensure_space(
+        Path::new(&config.output_path),
+        estimate.bytes,
+        estimate::dir_size(Path::new(&config.output_path)),
+        args.ignore_space,
+    )?;
+
+    remove_pre_existing_output(config.output_path.clone())
+        .map_err(|e| CustomError::WriteOutput(format!("{}: {}", config.output_path, e)))?;
+
+    let mut settings = render_settings(&config)?;
+    settings.watermark = watermark;
+    for dir in [
+        settings
+            .file_names
+            .image_dir(Path::new(&config.output_path)),
+        settings
+            .file_names
+            .metadata_dir(Path::new(&config.output_path)),
+    ] {
+        fs::create_dir_all(&dir)
+            .map_err(|e| CustomError::WriteOutput(format!("{}: {}", dir.display(), e)))?;
+    }
+    let mut dna_index = Vec::with_capacity(token_paths.len());
+    let mut rarity_report = rarity::compute_rarity(&layout, &token_paths);
+    for token in &mut rarity_report.tokens {
+        token.token_id = token_ids[token.token_id];
+    }
+    let batch_size = config
+        .prefetch_batch_size
+        .unwrap_or(prefetch::DEFAULT_BATCH_SIZE)
+        .max(1);
+    let mut prefetcher = Prefetcher::spawn(
+        prefetch::unique_layer_files(&token_paths, batch_size),
+        settings.preprocessing.clone(),
     );
-
-    if possible_permutations < config.total_supply as usize {
-        CustomError::InvalidTotalSupply(config.total_supply.into(), possible_permutations as u64);
+    // Up to two batches of files wait for the writers before compositing
+    // waits for the disk.
+    let writer = OutputWriter::spawn(
+        args.io_jobs.unwrap_or(writer::DEFAULT_IO_JOBS),
+        2 * batch_size,
+    );
+    settings.sink = writer.sink();
+    let pool = args
+        .jobs
+        .map(|jobs| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(jobs.max(1))
+                .build()
+        })
+        .transpose()?;
+
+    // Tokens are rendered a batch at a time, once the layers of the batch are
+    // decoded, so only one batch of canvases and layers is held whatever the
+    // supply.
+    let render_start = Instant::now();
+    for (batch_index, batch) in token_paths.chunks(batch_size).enumerate() {
+        let decoded = prefetcher.next_batch()?;
+        let mut renders = Vec::with_capacity(batch.len());
+        for (offset, image_paths) in batch.iter().enumerate() {
+            let index = batch_index * batch_size + offset;
+            let token_id = token_ids[index];
+            let token_dna = dna::compute_dna(config.dna_algorithm, &layout, image_paths);
+            let metadata = token_metadata(
+                &config,
+                &settings.file_names,
+                token_id,
+                &token_dna,
+                &rarity_report.tokens[index],
+            );
+            dna_index.push(dna::DnaEntry {
+                token_id,
+                dna: token_dna,
+            });
+
+            let render = generate_image_and_metadata(
+                metadata,
+                image_paths.clone(),
+                token_id,
+                &settings,
+                &decoded,
+            )
+            .map_err(|e| e.for_token(token_id, image_paths))?;
+            renders.push((token_id, image_paths, render));
+        }
+        match &pool {
+            Some(pool) => pool.install(|| render_batch(renders)),
+            None => render_batch(renders),
+        }?;
     }
+    let file_names = settings.file_names.clone();
+    let watermark = settings.watermark.clone();
+    drop(settings);
+    writer.finish()?;
+    info!(
+        "{} tokens rendered in {:?}.",
+        token_ids.len(),
+        render_start.elapsed()
+    );
+    let manifest_tokens: Vec<(String, Vec<String>)> = dna_index
+        .iter()
+        .map(|entry| entry.dna.clone())
+        .zip(token_paths)
+        .collect();
 
-    let mut threads = Vec::new();
-
-    _ = fs::create_dir_all(config.output_path.clone());
-    _ = remove_pre_existing_output(config.output_path.clone());
-
-    for (index, image_paths) in permutations.into_iter().enumerate() {
-        let handle = std::thread::spawn(generate_image_and_metadata(
-            config.metadata.clone(),
-            image_paths.1,
-            config.output_path.clone(),
-            config.image,
-            index,
-        ));
-        threads.push(handle);
+    let mut one_of_one_records = Vec::new();
+    for (one_of_one, &token_id) in one_of_ones.iter().zip(&one_of_one_ids) {
+        let record = manifest::OneOfOneRecord {
+            token_id,
+            name: one_of_one.name.clone(),
+            dna: dna::one_of_one_dna(config.dna_algorithm, &one_of_one.name),
+        };
+        one_of_ones::write_one_of_one(
+            one_of_one,
+            &record,
+            &file_names,
+            &config.output_path,
+            &config.image_url,
+        )?;
+        if let Some(stamp) = &watermark {
+            watermark::stamp_file(
+                &Path::new(&config.output_path)
+                    .join(file_names.image(record.token_id, &record.dna)),
+                stamp,
+            )?;
+        }
+        one_of_one_records.push(record);
+    }
+    if !one_of_ones.is_empty() {
+        info!("1/1 tokens injected at ids {:?}.", one_of_one_ids);
     }
 
-    for handle in threads {
-        let start = Instant::now();
-        handle.join().unwrap();
-        let duration = start.elapsed();
+    let token_dnas: BTreeMap<usize, String> = dna_index
+        .iter()
+        .map(|entry| (entry.token_id, entry.dna.clone()))
+        .chain(
+            one_of_one_records
+                .iter()
+                .map(|record| (record.token_id, record.dna.clone())),
+        )
+        .collect();
+    if let Some(spritesheet) = &config.spritesheet {
+        let (width, height) = config.image.output_size();
+        let sheets = dna_index
+            .iter()
+            .map(|entry| (entry.token_id, file_names.sheet(entry.token_id, &entry.dna)))
+            .collect();
+        spritesheet::write_frames(
+            Path::new(&config.output_path),
+            &spritesheet.descriptor(width, height, sheets),
+        )?;
+    }
+    let metadata_files: Vec<String> = token_dnas
+        .iter()
+        .map(|(&token_id, token_dna)| file_names.metadata(token_id, token_dna))
+        .collect();
+    if let Some(localization) = &config.localization {
+        localization::localize_files(
+            Path::new(&config.output_path),
+            &metadata_files,
+            localization,
+            &config.metadata_profile,
+        )?;
+        info!(
+            "Metadata localized to {}.",
+            localization.locales().join(", ")
+        );
+    }
+    if let Some(limits) = &config.metadata_limits {
+        let problems = limits.check_files(
+            Path::new(&config.output_path),
+            &metadata_files,
+            &config.metadata_profile,
+        )?;
+        for (file_name, problem) in &problems {
+            warn!("{}: {}.", file_name, problem);
+        }
+        if problems.is_empty() {
+            info!("The metadata of every token is within metadataLimits.");
+        }
+    }
 
-        println!("Time elapsed in seconds: {:?}", duration);
+    dna::write_dna_index(
+        Path::new(&config.output_path),
+        &dna::DnaIndex {
+            algorithm: config.dna_algorithm,
+            tokens: dna_index,
+        },
+    )?;
+    rarity::write_rarity(Path::new(&config.output_path), &rarity_report)?;
+    if config.shuffle_token_ids {
+        shuffle::write_shuffle(Path::new(&config.output_path), &shuffled)?;
+    }
+    replay::write_replay(Path::new(&config.output_path), &replay)?;
+    stats::write_stats(Path::new(&config.output_path), &stats)?;
+    stats::write_co_occurrence(Path::new(&config.output_path), &co_occurrence)?;
+    unused::write_unused_assets(Path::new(&config.output_path), &unused_assets)?;
+    if !buckets.is_empty() {
+        forced::write_report(Path::new(&config.output_path), &buckets)?;
+    }
+    let mut token_manifest =
+        manifest::build_manifest(config.dna_algorithm, &layout, &manifest_tokens)?;
+    for token in &mut token_manifest.tokens[public_count..] {
+        token.reserved = true;
+    }
+    for token in &mut token_manifest.tokens {
+        token.token_id = token_ids[token.token_id];
+    }
+    token_manifest.one_of_ones = one_of_one_records;
+    token_manifest.historical_dna = dna_history;
+    token_manifest
+        .historical_dna
+        .extend(token_manifest.tokens.iter().map(|token| token.dna.clone()));
+    manifest::write_manifest(Path::new(&config.output_path), &token_manifest)?;
+    let provenance = provenance::write_provenance(
+        Path::new(&config.output_path),
+        &file_names.images(collection_token_ids(&config), &token_dnas)?,
+    )?;
+    info!("Provenance hash: {}", provenance.provenance_hash);
+
+    if let Some(near_duplicates) = &config.near_duplicates {
+        perceptual::check(&config, near_duplicates)?;
     }
 
     Ok(())
@@ -624,6 +3699,8 @@ fn main() -> Result<(), Box<dyn Error>> {
 mod tests {
     use super::*;
     use image::{GenericImage, Rgba};
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
     use std::{
         collections::HashSet,
         fs::{self},
@@ -671,6 +3748,13 @@ mod tests {
             file_path2,
             base_path
         ));
+
+        let file_path4 = "./images/Face/Other#25.png";
+        assert!(!should_include_file(
+            &forced_combinations,
+            file_path4,
+            "./images/"
+        ));
     }
 
     #[test]
@@ -696,11 +3780,8 @@ mod tests {
         File::create(file_path1).unwrap();
         File::create(file_path2).unwrap();
 
-        let result = get_entries_by_path_dir(format!(
-            "{}/{}",
-            dir.path().to_str().unwrap().to_string(),
-            "trait1"
-        ));
+        let result =
+            get_entries_by_path_dir(format!("{}/{}", dir.path().to_str().unwrap(), "trait1"));
         assert!(result.is_ok());
         let layers = result.unwrap();
         assert_eq!(traits.len(), 2);
@@ -717,48 +3798,137 @@ mod tests {
     }
 
     #[test]
-    fn test_compare_and_verify_traits() {
-        let base_path = "./images";
-        let vec1 = vec![
-            "back_acc".to_string(),
-            "background".to_string(),
-            "body".to_string(),
-            "face".to_string(),
-            "front_acc".to_string(),
-        ];
-        let vec2 = vec![
-            "back_acc".to_string(),
-            "background".to_string(),
-            "body".to_string(),
-            "face".to_string(),
-            "front_acc".to_string(),
-        ];
-        let vec3 = vec![
-            "back_acc".to_string(),
-            "coverall".to_string(),
-            "face".to_string(),
-            "front_acc".to_string(),
-            "body".to_string(),
-        ];
-        let vec4 = vec![
-            "back_acc".to_string(),
-            "body".to_string(),
-            "face".to_string(),
-            "front_acc".to_string(),
+    fn test_layer_dirs() {
+        let base_path = "./images/";
+        let entries = vec![
+            "./images/background".to_string(),
+            "./images/body#3".to_string(),
+            "./images/face".to_string(),
         ];
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| LayerFolder::Simple(name.to_string()))
+                .collect::<Vec<_>>()
+        };
 
         assert_eq!(
-            compare_and_verify_traits::<String>(vec1.clone(), vec2.clone(), &base_path).unwrap(),
-            vec2
+            layer_dirs(&entries, &names(&["face", "background", "body"]), base_path).unwrap(),
+            vec![
+                "./images/face".to_string(),
+                "./images/background".to_string(),
+                "./images/body#3".to_string(),
+            ]
         );
+        assert!(layer_dirs(&entries, &names(&["background", "body"]), base_path).is_err());
+        assert!(layer_dirs(
+            &entries,
+            &names(&["background", "body", "face", "coverall"]),
+            base_path
+        )
+        .is_err());
+        assert!(layer_dirs(
+            &entries,
+            &names(&["background", "body", "face", "face"]),
+            base_path
+        )
+        .is_err());
+
+        let mut ambiguous = entries.clone();
+        ambiguous.push("./images/face#2".to_string());
+        assert!(layer_dirs(
+            &ambiguous,
+            &names(&["background", "body", "face"]),
+            base_path
+        )
+        .is_err());
 
-        assert!(
-            compare_and_verify_traits::<String>(vec1.clone(), vec3.clone(), &base_path).is_err()
+        // A layer with a path uses that folder, wherever it is and whatever
+        // its name, and a folder of the base path given as a path is used.
+        let dir = tempdir().unwrap();
+        let eyes = dir.path().join("eyes v2").display().to_string();
+        fs::create_dir(&eyes).unwrap();
+        let with_path = |name: &str, path: &str| {
+            LayerFolder::Detailed(
+                serde_json::from_value(serde_json::json!({ "name": name, "path": path })).unwrap(),
+            )
+        };
+        let mut layer_folders = names(&["background", "body", "face"]);
+        layer_folders.push(with_path("eyes", &eyes));
+        assert_eq!(
+            layer_dirs(&entries, &layer_folders, base_path).unwrap()[3],
+            eyes
         );
+        let images = dir.path().join("images");
+        fs::create_dir_all(images.join("face")).unwrap();
+        let mask = format!("{}/face/", images.display());
+        let moved = [with_path("face", &eyes), with_path("mask", &mask)];
+        assert_eq!(
+            layer_dirs(
+                &[images.join("face").display().to_string()],
+                &moved,
+                &images.display().to_string()
+            )
+            .unwrap(),
+            vec![eyes.clone(), mask]
+        );
+        let missing = [
+            names(&["background", "body"]),
+            vec![with_path("face", "./nowhere")],
+        ]
+        .concat();
+        assert!(layer_dirs(&entries, &missing, base_path).is_err());
+        layer_folders.push(with_path("glasses", &eyes));
+        assert!(layer_dirs(&entries, &layer_folders, base_path).is_err());
+    }
 
-        assert!(
-            compare_and_verify_traits::<String>(vec1.clone(), vec4.clone(), &base_path).is_err()
+    #[test]
+    fn test_layer_path() {
+        let dir = tempdir().unwrap();
+        let images = dir.path().join("images");
+        let shared = dir.path().join("shared/eyes-2024");
+        fs::create_dir_all(images.join("Background")).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        fs::write(images.join("Background/Blue.png"), b"").unwrap();
+        fs::write(shared.join("Laser#10.png"), b"").unwrap();
+        fs::write(shared.join("Round#30.png"), b"").unwrap();
+        let config = |eyes: Value| -> Config {
+            serde_json::from_value(serde_json::json!({
+                "metadata": {},
+                "image": { "width": 8, "height": 8 },
+                "totalSupply": 2,
+                "basePath": format!("{}/", images.display()),
+                "outputPath": format!("{}/", dir.path().join("output").display()),
+                "imageUrl": "https://example.com",
+                "layerFolders": ["Background", eyes],
+                "skippedTraits": null,
+                "forcedCombinations": [],
+            }))
+            .unwrap()
+        };
+        assert!(load_layers(&config(Value::from("Eyes"))).is_err());
+
+        let config = config(serde_json::json!({
+            "name": "Eyes",
+            "path": shared.display().to_string(),
+        }));
+        let layers = load_layers(&config).unwrap();
+
+        let layout = layer_layout(&config);
+        let traits: Vec<(String, String)> = layers[1]
+            .iter()
+            .map(|path| path_trait(&layout, path).unwrap())
+            .collect();
+        assert_eq!(
+            traits,
+            vec![
+                ("Eyes".to_string(), "Laser".to_string()),
+                ("Eyes".to_string(), "Round".to_string()),
+            ]
         );
+        assert!(layers[1]
+            .iter()
+            .all(|path| Path::new(path).starts_with(&shared)));
     }
 
     #[test]
@@ -769,16 +3939,24 @@ mod tests {
             (vec!["x".to_string(), "y".to_string(), "z".to_string()]),
         ];
         let total_supply = 18;
-        let permutations = generate_permutations(&layers, total_supply);
+        let permutations = generate_permutations(
+            &layers,
+            total_supply,
+            &Constraints::default(),
+            &mut Caps::default(),
+            &mut rand::thread_rng(),
+            None,
+        )
+        .unwrap();
 
         assert_eq!(permutations.len(), total_supply);
 
-        for combination in permutations.values() {
+        for (_, combination) in &permutations {
             assert_eq!(combination.len(), layers.len());
         }
 
         let mut hash_set = HashSet::new();
-        for combination in permutations.values() {
+        for (_, combination) in &permutations {
             let mut hasher = DefaultHasher::new();
             combination.hash(&mut hasher);
             let hash = hasher.finish();
@@ -787,31 +3965,465 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_generate_permutations_with_seed() {
+        let layers = vec![
+            (vec!["a#1".to_string(), "b#2".to_string(), "c#3".to_string()]),
+            (vec!["1".to_string(), "2".to_string()]),
+        ];
+
+        let first = generate_permutations(
+            &layers,
+            4,
+            &Constraints::default(),
+            &mut Caps::default(),
+            &mut ChaCha8Rng::seed_from_u64(7),
+            None,
+        )
+        .unwrap();
+        let second = generate_permutations(
+            &layers,
+            4,
+            &Constraints::default(),
+            &mut Caps::default(),
+            &mut ChaCha8Rng::seed_from_u64(7),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_generate_permutations_excludes_previous_dna() {
+        let layers = vec![
+            vec![
+                "./images/Background/Blue.png".to_string(),
+                "./images/Background/Red.png".to_string(),
+            ],
+            vec![
+                "./images/Body/Robot.png".to_string(),
+                "./images/Body/Human.png".to_string(),
+            ],
+        ];
+        let minted = dna::compute_dna(
+            DnaAlgorithm::Xxh64,
+            &LayerLayout::default(),
+            &[
+                "./images/Background/Blue#20.png".to_string(),
+                "./images/Body/Robot.png".to_string(),
+            ],
+        );
+
+        let permutations = generate_permutations(
+            &layers,
+            3,
+            &Constraints {
+                excluded_dna: HashSet::from([minted.clone()]),
+                dna_algorithm: DnaAlgorithm::Xxh64,
+                ..Constraints::default()
+            },
+            &mut Caps::default(),
+            &mut rand::thread_rng(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(permutations.len(), 3);
+        assert!(permutations
+            .iter()
+            .map(|(_, permutation)| permutation)
+            .all(|permutation| {
+                dna::compute_dna(DnaAlgorithm::Xxh64, &LayerLayout::default(), permutation)
+                    != minted
+            }));
+    }
+
+    #[test]
+    fn test_generate_permutations_with_dependency_rules() {
+        let layers = vec![
+            vec![
+                "./images/Body/Human.png".to_string(),
+                "./images/Body/Robot.png".to_string(),
+            ],
+            vec![
+                "./images/Eyes/Laser.png".to_string(),
+                "./images/Eyes/Round.png".to_string(),
+                "./images/Eyes/Visor.png".to_string(),
+            ],
+        ];
+        let rules: Vec<DependencyRule> = serde_json::from_str(
+            r#"[{"if": {"layer": "Body", "value": "Robot"}, "then": {"layer": "Eyes", "values": ["Laser"]}}]"#,
+        )
+        .unwrap();
+        let constraints = Constraints {
+            rules: Rules::compile(
+                &rules,
+                &HashMap::new(),
+                &["Body".to_string(), "Eyes".to_string()],
+            )
+            .unwrap(),
+            ..Constraints::default()
+        };
+
+        let count = permutations::count_permutations(&[], &layers, &[], &constraints);
+        assert_eq!(count.possible, 4);
+
+        let permutations = generate_permutations(
+            &layers,
+            4,
+            &constraints,
+            &mut Caps::default(),
+            &mut ChaCha8Rng::seed_from_u64(1),
+            None,
+        )
+        .unwrap();
+        assert_eq!(permutations.len(), 4);
+        for (_, permutation) in &permutations {
+            if permutation[0].contains("Robot") {
+                assert_eq!(permutation[1], "./images/Eyes/Laser.png");
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_permutations_with_caps() {
+        let layers = vec![
+            vec![
+                "./images/Background/Gold#90.png".to_string(),
+                "./images/Background/Blue#10.png".to_string(),
+            ],
+            vec![
+                "./images/Eyes/Laser.png".to_string(),
+                "./images/Eyes/Round.png".to_string(),
+                "./images/Eyes/Visor.png".to_string(),
+            ],
+        ];
+        let trait_caps: Vec<TraitCap> =
+            serde_json::from_str(r#"[{"layer": "Background", "value": "Gold", "max": 1}]"#)
+                .unwrap();
+        let mut caps =
+            Caps::compile(&trait_caps, &["Background".to_string(), "Eyes".to_string()]).unwrap();
+
+        let permutations = generate_permutations(
+            &layers,
+            4,
+            &Constraints::default(),
+            &mut caps,
+            &mut ChaCha8Rng::seed_from_u64(3),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(permutations.len(), 4);
+        assert_eq!(
+            permutations
+                .iter()
+                .map(|(_, permutation)| permutation)
+                .filter(|permutation| permutation[0].contains("Gold"))
+                .count(),
+            1
+        );
+        assert!(caps.is_full(&("Background".to_string(), "Gold".to_string())));
+    }
+
+    #[test]
+    fn test_generate_permutations_with_quotas() {
+        let layers = vec![
+            vec![
+                "./images/Background/Blue#2.png".to_string(),
+                "./images/Background/Red#2.png".to_string(),
+            ],
+            vec![
+                "./images/Eyes/Laser#2.png".to_string(),
+                "./images/Eyes/Round#2.png".to_string(),
+            ],
+        ];
+        let names = ["Background".to_string(), "Eyes".to_string()];
+
+        for seed in 0..20 {
+            let mut caps = Caps::compile(
+                &caps::quota_caps(&LayerLayout::default(), &layers, 4).unwrap(),
+                &names,
+            )
+            .unwrap();
+            let permutations = generate_permutations(
+                &layers,
+                4,
+                &Constraints::default(),
+                &mut caps,
+                &mut ChaCha8Rng::seed_from_u64(seed),
+                None,
+            )
+            .unwrap();
+
+            let tokens: Vec<Vec<String>> =
+                permutations.into_iter().map(|(_, paths)| paths).collect();
+            let stats = stats::compute_stats(&LayerLayout::default(), &layers, &tokens);
+            assert_eq!(stats.layers["Background"]["Blue"].count, 2);
+            assert_eq!(stats.layers["Background"]["Red"].count, 2);
+            assert_eq!(stats.layers["Eyes"]["Laser"].count, 2);
+        }
+    }
+
+    #[test]
+    fn test_generate_permutations_with_quotas_and_rules() {
+        let layers = vec![
+            vec![
+                "./images/Background/Blue#2.png".to_string(),
+                "./images/Background/Gold#2.png".to_string(),
+                "./images/Background/Red#2.png".to_string(),
+            ],
+            vec![
+                "./images/Body/Human#3.png".to_string(),
+                "./images/Body/Robot#3.png".to_string(),
+            ],
+            vec![
+                "./images/Eyes/Laser#3.png".to_string(),
+                "./images/Eyes/Normal#3.png".to_string(),
+            ],
+        ];
+        let names = [
+            "Background".to_string(),
+            "Body".to_string(),
+            "Eyes".to_string(),
+        ];
+        let rules: Vec<DependencyRule> = serde_json::from_str(
+            r#"[{"if": {"layer": "Body", "value": "Robot"}, "then": {"layer": "Eyes", "values": ["Laser"]}}]"#,
+        )
+        .unwrap();
+        let constraints = Constraints {
+            rules: Rules::compile(&rules, &HashMap::new(), &names).unwrap(),
+            ..Constraints::default()
+        };
+
+        for seed in 0..50 {
+            let mut caps = Caps::compile(
+                &caps::quota_caps(&LayerLayout::default(), &layers, 6).unwrap(),
+                &names,
+            )
+            .unwrap();
+            let permutations = generate_permutations(
+                &layers,
+                6,
+                &constraints,
+                &mut caps,
+                &mut ChaCha8Rng::seed_from_u64(seed),
+                None,
+            )
+            .unwrap();
+
+            let tokens: Vec<Vec<String>> =
+                permutations.into_iter().map(|(_, paths)| paths).collect();
+            assert_eq!(tokens.iter().collect::<HashSet<_>>().len(), 6);
+            for token in &tokens {
+                assert!(constraints.allows(token));
+            }
+            let stats = stats::compute_stats(&LayerLayout::default(), &layers, &tokens);
+            assert_eq!(stats.layers["Background"]["Gold"].count, 2);
+            assert_eq!(stats.layers["Body"]["Robot"].count, 3);
+            assert_eq!(stats.layers["Eyes"]["Laser"].count, 3);
+            assert!(caps.is_full(&("Eyes".to_string(), "Laser".to_string())));
+        }
+    }
+
     #[test]
     fn test_get_combinations() {
         let layers = vec![
             vec!["a".to_string(), "b".to_string()],
             vec!["1".to_string(), "2".to_string(), "3".to_string()],
         ];
-        assert_eq!(get_permutations(&layers, None), 6);
+        assert_eq!(
+            permutations::count_permutations(&[], &layers, &[], &Constraints::default()).possible,
+            6
+        );
 
         let layers = vec![
             vec!["a".to_string(), "b".to_string(), "c".to_string()],
             vec!["1".to_string()],
             vec!["x".to_string(), "y".to_string(), "z".to_string()],
         ];
-        assert_eq!(get_permutations(&layers, None), 9);
+        assert_eq!(
+            permutations::count_permutations(&[], &layers, &[], &Constraints::default()).possible,
+            9
+        );
 
         let layers = vec![
             vec![],
             vec!["a".to_string(), "b".to_string(), "c".to_string()],
         ];
-        assert_eq!(get_permutations(&layers, None), 3);
+        assert_eq!(
+            permutations::count_permutations(&[], &layers, &[], &Constraints::default()).possible,
+            3
+        );
         let layers = vec![
             vec!["a".to_string(), "b".to_string(), "c".to_string()],
             vec![],
         ];
-        assert_eq!(get_permutations(&layers, None), 3);
+        assert_eq!(
+            permutations::count_permutations(&[], &layers, &[], &Constraints::default()).possible,
+            3
+        );
+    }
+
+    #[test]
+    fn test_none_file() {
+        let layer = [
+            "./images/Hat/Cap#10.png".to_string(),
+            none_asset("./images/Hat", 5),
+            "./images/Hat/None#5.png".to_string(),
+        ];
+        assert!(is_none_asset(&layer[1]));
+        let layout = LayerLayout::new("./images");
+        assert!(!is_none_file(&layout, &layer[0]));
+        assert!(!is_none_file(&layout, &layer[1]));
+        assert!(is_none_file(&layout, &layer[2]));
+
+        // With a skipProbability, the None file is merged into the placeholder.
+        let dir = tempdir().unwrap();
+        let layer_dir = dir.path().join("Hat");
+        fs::create_dir_all(&layer_dir).unwrap();
+        for file in ["Cap#10.png", "None#5.png"] {
+            RgbaImage::new(1, 1).save(layer_dir.join(file)).unwrap();
+        }
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "metadata": {},
+            "image": {"width": 1, "height": 1},
+            "totalSupply": 2,
+            "basePath": dir.path(),
+            "outputPath": dir.path().join("output"),
+            "imageUrl": "",
+            "layerFolders": [{"name": "Hat", "skipProbability": 0.5}],
+            "forcedCombinations": []
+        }))
+        .unwrap();
+        let layers = load_layers(&config).unwrap();
+        // Half of the tokens are left out, over the 15 of the layer, plus the 5
+        // of the None file.
+        assert_eq!(
+            layers,
+            vec![vec![
+                layer_dir.join("Cap#10.png").display().to_string(),
+                none_asset(&layer_dir.display().to_string(), 20),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_image_padding() {
+        let image: Image = serde_json::from_str(
+            r#"{"width": 4, "height": 2, "padding": {"top": 1, "left": 2, "right": 1}}"#,
+        )
+        .unwrap();
+        assert_eq!(image.canvas_size(), (7, 3));
+
+        let mut canvas = RgbaImage::new(7, 3);
+        canvas.put_pixel(2, 1, Rgba([255, 0, 0, 255]));
+        let cropped = image.crop_padding(canvas);
+        assert_eq!(cropped.dimensions(), (4, 2));
+        assert_eq!(cropped.get_pixel(0, 0).0, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_image_output() {
+        let image: Image = serde_json::from_str(
+            r#"{"width": 4, "height": 4, "output": {"width": 8, "height": 2, "filter": "nearest"}}"#,
+        )
+        .unwrap();
+        assert_eq!(image.canvas_size(), (4, 4));
+
+        let mut composed = RgbaImage::new(4, 4);
+        for y in 0..4 {
+            composed.put_pixel(0, y, Rgba([255, 0, 0, 255]));
+        }
+        let scaled = image.scale_to_output(composed);
+        assert_eq!(scaled.dimensions(), (8, 2));
+        assert_eq!(scaled.get_pixel(1, 0).0, [255, 0, 0, 255]);
+        assert_eq!(scaled.get_pixel(2, 0).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_pixel_art() {
+        let mut image: Image = serde_json::from_str(
+            r##"{"width": 2, "height": 2, "pixelArt": {"scale": 4, "grid": {"color": "#0000ff"}}}"##,
+        )
+        .unwrap();
+        image.check().unwrap();
+        assert_eq!(image.canvas_size(), (2, 2));
+
+        let mut composed = RgbaImage::new(2, 2);
+        composed.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        let upscaled = image.scale_to_output(composed);
+        assert_eq!(upscaled.dimensions(), (8, 8));
+        assert_eq!(upscaled.get_pixel(3, 3).0, [255, 0, 0, 255]);
+        assert_eq!(upscaled.get_pixel(5, 5).0, [0, 0, 0, 0]);
+        assert_eq!(upscaled.get_pixel(4, 1).0, [0, 0, 255, 255]);
+        assert_eq!(upscaled.get_pixel(1, 4).0, [0, 0, 255, 255]);
+
+        image
+            .pixel_art
+            .as_mut()
+            .unwrap()
+            .grid
+            .as_mut()
+            .unwrap()
+            .width = 4;
+        assert!(image.check().is_err());
+        image.pixel_art.as_mut().unwrap().grid = None;
+        image.output = Some(OutputSize {
+            width: 8,
+            height: 8,
+            filter: ResizeFilter::default(),
+        });
+        assert!(image.check().is_err());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let error: Box<dyn Error> = CustomError::InvalidTotalSupply(10, 8).into();
+        assert_eq!(exit_code(error.as_ref()), 5);
+        let error: Box<dyn Error> = CustomError::Upload("timeout".to_string()).into();
+        assert_eq!(exit_code(error.as_ref()), 7);
+        assert_eq!(
+            exit_code(&CustomError::Config("config.json".to_string())),
+            3
+        );
+        let error: Box<dyn Error> = std::io::Error::other("disk").into();
+        assert_eq!(exit_code(error.as_ref()), 1);
+
+        let error = CustomError::FileSizeExceeded("4.png".to_string(), 120, 100)
+            .for_token(4, &["./images/Eyes/Laser.png".to_string()]);
+        assert_eq!(exit_code(&error), 6);
+        assert_eq!(
+            error.to_string(),
+            "Token 4 (./images/Eyes/Laser.png) failed: 4.png is 120 KB, over the maximum file size of 100 KB."
+        );
+    }
+
+    #[test]
+    fn test_render_batch() {
+        let assets = vec!["./images/Background/Blue.png".to_string()];
+        type Render = Box<dyn FnMut() -> Result<(), CustomError> + Send>;
+        let renders: Vec<(usize, &Vec<String>, Render)> = vec![
+            (0, &assets, Box::new(|| Ok(()))),
+            (1, &assets, Box::new(|| panic!("out of memory"))),
+            (
+                2,
+                &assets,
+                Box::new(|| Err(CustomError::WriteOutput("disk full".to_string()))),
+            ),
+        ];
+        assert_eq!(
+            render_batch(renders),
+            Err(CustomError::WriteOutput("the render panicked".to_string()).for_token(1, &assets))
+        );
+    }
+
+    #[test]
+    fn test_token_file_name() {
+        assert_eq!(token_file_name(7, 0, "png"), "7.png");
+        assert_eq!(token_file_name(7, 4, "json"), "0007.json");
+        assert_eq!(token_file_name(12345, 4, "png"), "12345.png");
     }
 
     #[test]
@@ -822,7 +4434,7 @@ mod tests {
             Builder::new().suffix(".png").tempfile().unwrap(),
         ];
 
-        let mut images = vec![
+        let mut images = [
             DynamicImage::new_rgba8(800, 600),
             DynamicImage::new_rgba8(800, 600),
             DynamicImage::new_rgba8(800, 600),
@@ -846,6 +4458,9 @@ mod tests {
         let config_image = Image {
             width: 800,
             height: 600,
+            padding: Padding::default(),
+            output: None,
+            pixel_art: None,
         };
 
         let mut metadata: HashMap<String, Value> = HashMap::new();
@@ -863,14 +4478,46 @@ mod tests {
         let dir = tempdir().expect("Error to create the temp dir");
         let temp_path_str = dir.path().to_str().unwrap().to_owned();
 
+        let mut settings = RenderSettings {
+            output_path: temp_path_str.clone(),
+            layout: LayerLayout::default(),
+            image: config_image,
+            rarity_only_traits: HashSet::new(),
+            emit_none_traits: HashSet::new(),
+            blend_modes: HashMap::new(),
+            offsets: HashMap::new(),
+            fits: HashMap::new(),
+            display_types: HashMap::new(),
+            display_names: TraitRenames::default(),
+            transforms: Transforms::default(),
+            stacking: Stacking::default(),
+            background: None,
+            recolors: Recolors::default(),
+            post_processing: PostProcessing::default(),
+            watermark: None,
+            video: None,
+            spritesheet: None,
+            audio: None,
+            metadata_profile: MetadataProfile::default(),
+            trait_fields: TraitFieldTable::default(),
+            animations: Animations::default(),
+            file_size_limit: None,
+            png_output: PngOutput::default(),
+            preprocessing: Preprocessing::default(),
+            file_names: FileNames::default(),
+            name_template: None,
+            variants: Variants::default(),
+            sink: FileSink::default(),
+        };
         let mut closure = generate_image_and_metadata(
             metadata.clone(),
             temp_file_paths.clone(),
-            temp_path_str.clone(),
-            config_image,
             image_name,
-        );
-        closure();
+            &settings,
+            &DecodedLayers::new(),
+        )
+        .unwrap();
+        closure().unwrap();
 
         let file_path = format!("{}/1.png", temp_path_str.clone());
         assert!(Path::new(&file_path).exists());
@@ -896,10 +4543,117 @@ mod tests {
             "test dummy data description",
             "Description should be equal"
         );
+        assert_eq!(parsed_json["attributes"].as_array().unwrap().len(), 3);
+
+        let rarity_only_trait = Path::new(&temp_file_paths[0])
+            .file_stem()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        settings.rarity_only_traits = HashSet::from([rarity_only_trait.clone()]);
+        let mut closure = generate_image_and_metadata(
+            metadata.clone(),
+            temp_file_paths.clone(),
+            image_name,
+            &settings,
+            &DecodedLayers::new(),
+        )
+        .unwrap();
+        closure().unwrap();
+
+        let parsed_json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&json_file_path).unwrap()).unwrap();
+        let attributes = parsed_json["attributes"].as_array().unwrap();
+        assert_eq!(attributes.len(), 2);
+        assert!(attributes
+            .iter()
+            .all(|attribute| attribute["trait_type"] != rarity_only_trait.as_str()));
+
+        let mut with_none = temp_file_paths.clone();
+        with_none.push(none_asset("./images/Hat", 3));
+        for emit_none in [false, true] {
+            settings.emit_none_traits = if emit_none {
+                HashSet::from(["Hat".to_string()])
+            } else {
+                HashSet::new()
+            };
+            let mut closure = generate_image_and_metadata(
+                metadata.clone(),
+                with_none.clone(),
+                2,
+                &settings,
+                &DecodedLayers::new(),
+            )
+            .unwrap();
+            closure().unwrap();
+
+            let parsed_json: serde_json::Value = serde_json::from_str(
+                &std::fs::read_to_string(format!("{}/2.json", temp_path_str)).unwrap(),
+            )
+            .unwrap();
+            let attributes = parsed_json["attributes"].as_array().unwrap();
+            assert_eq!(
+                attributes
+                    .iter()
+                    .any(|attribute| attribute["trait_type"] == "Hat"
+                        && attribute["value"] == NONE_VALUE),
+                emit_none
+            );
+            assert!(attributes
+                .iter()
+                .all(|attribute| attribute.get("weight").is_none()));
+        }
+
+        settings.metadata_profile.attribute_weight = true;
+        let mut closure = generate_image_and_metadata(
+            metadata.clone(),
+            with_none,
+            2,
+            &settings,
+            &DecodedLayers::new(),
+        )
+        .unwrap();
+        closure().unwrap();
+        let parsed_json: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(format!("{}/2.json", temp_path_str)).unwrap(),
+        )
+        .unwrap();
+        let hat = parsed_json["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|attribute| attribute["trait_type"] == "Hat")
+            .unwrap();
+        assert_eq!(hat["weight"], 3.0);
 
         dir.close().expect("Error to delete the temp dir");
     }
 
+    #[test]
+    fn test_layer_folders_deserialization() {
+        let layer_folders: Vec<LayerFolder> = serde_json::from_str(
+            r#"["Background", {"name": "Signature", "visibility": "rarityOnly"},
+                {"name": "Body", "fit": "contain"},
+                {"name": "Hat", "skipProbability": 0.3, "emitNone": true,
+                 "blendMode": "multiply", "offsetX": 120, "offsetY": -8}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(layer_folders[0].name(), "Background");
+        assert_eq!(layer_folders[0].visibility(), Visibility::Public);
+        assert_eq!(layer_folders[1].name(), "Signature");
+        assert_eq!(layer_folders[1].visibility(), Visibility::RarityOnly);
+        assert_eq!(layer_folders[2].visibility(), Visibility::Public);
+        assert_eq!(layer_folders[2].skip_probability(), 0.0);
+        assert_eq!(layer_folders[3].skip_probability(), 0.3);
+        assert!(layer_folders[3].emit_none());
+        assert_eq!(layer_folders[3].blend_mode(), BlendMode::Multiply);
+        assert_eq!(layer_folders[0].offset(), (0, 0));
+        assert_eq!(layer_folders[3].offset(), (120, -8));
+        assert_eq!(layer_folders[2].fit(), Fit::Contain);
+        assert_eq!(layer_folders[3].fit(), Fit::None);
+    }
+
     #[test]
     fn test_calculate_weights_and_total() {
         let layer = vec![
@@ -909,10 +4663,55 @@ mod tests {
             "image.png".to_string(),
         ];
 
-        let (weights, total_weight) = calculate_weights_and_total(&layer);
+        let (weights, total_weight) = calculate_weights_and_total(&LayerLayout::default(), &layer);
 
         assert_eq!(weights, vec![100, 125, 175, 175]);
         assert_eq!(total_weight, 175);
+
+        let optional_layer = vec![
+            "./images/Hat/Cap.png".to_string(),
+            "./images/Hat/Crown.png".to_string(),
+            none_asset("./images/Hat/", 1),
+        ];
+        assert!(is_none_asset(&optional_layer[2]));
+        assert!(!is_none_asset("./images/Hat/None#1.png"));
+        assert_eq!(
+            calculate_weights_and_total(&LayerLayout::default(), &optional_layer),
+            (vec![1, 2, 3], 3)
+        );
+
+        let share_layer = vec![
+            "./images/Background/Gold#5%.png".to_string(),
+            "./images/Background/Blue#1-4.png".to_string(),
+            "./images/Background/Red#2.png".to_string(),
+            "./images/Background/Green#3.png".to_string(),
+        ];
+        assert_eq!(
+            path_trait(&LayerLayout::default(), &share_layer[1]),
+            Some(("Background".to_string(), "Blue".to_string()))
+        );
+        assert_eq!(
+            calculate_weights_and_total(&LayerLayout::default(), &share_layer),
+            (vec![50_000, 300_000, 580_000, 1_000_000], 1_000_000)
+        );
+        assert_eq!(
+            calculate_weights_and_total(&LayerLayout::default(), &share_layer[..2]),
+            (vec![166_667, 1_000_000], 1_000_000)
+        );
+        assert!(check_shares(&LayerLayout::default(), &share_layer).is_ok());
+        assert!(check_shares(
+            &LayerLayout::default(),
+            &["./images/Background/Gold#3-2.png".to_string()]
+        )
+        .is_err());
+        assert!(check_shares(
+            &LayerLayout::default(),
+            &[
+                "./images/Background/Gold#60%.png".to_string(),
+                "./images/Background/Blue#50%.png".to_string(),
+            ]
+        )
+        .is_err());
     }
 
     #[test]
@@ -924,9 +4723,14 @@ mod tests {
             "image.png".to_string(),
         ];
 
-        let (weights, total_weight) = calculate_weights_and_total(&layer);
+        let (weights, total_weight) = calculate_weights_and_total(&LayerLayout::default(), &layer);
 
-        let chosen_image = choose_image_with_precomputed_weights(&layer, &weights, total_weight);
+        let chosen_image = choose_image_with_precomputed_weights(
+            &layer,
+            &weights,
+            total_weight,
+            &mut rand::thread_rng(),
+        );
 
         assert!(layer.contains(chosen_image));
     }
@@ -0,0 +1,268 @@
+use chrono::{SecondsFormat, Utc};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::dna::DnaAlgorithm;
+use crate::paths::LayerLayout;
+use crate::{is_file_asset, is_none_asset, path_trait, write_json, CustomError};
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// The asset a token attribute was rendered from.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetRecord {
+    pub trait_type: String,
+    pub value: String,
+    pub file: String,
+    /// Empty for a generated background, which has no file.
+    pub hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenRecord {
+    pub token_id: usize,
+    pub dna: String,
+    pub assets: Vec<AssetRecord>,
+    /// Part of the reserved supply rather than the public one.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reserved: bool,
+    /// When the token was last rendered, in RFC 3339.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub rendered_at: String,
+}
+
+/// A 1/1 token, copied rather than rendered.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OneOfOneRecord {
+    pub token_id: usize,
+    pub name: String,
+    /// Hash of its name, standing for a DNA in file names.
+    pub dna: String,
+}
+
+/// Private record of how every token was rendered. Unlike the metadata it is
+/// not meant to be published.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub tokens: Vec<TokenRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub one_of_ones: Vec<OneOfOneRecord>,
+    /// Every DNA the collection has issued, in this run and the previous ones,
+    /// including tokens burned or removed since.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub historical_dna: BTreeSet<String>,
+    /// Hash the DNAs were computed with. Manifests written before it was
+    /// recorded have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dna_algorithm: Option<DnaAlgorithm>,
+    /// When `generate` wrote the collection, in RFC 3339.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub generated_at: String,
+    /// When `reroll` or `rerender-changed` last changed it, in RFC 3339.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub updated_at: String,
+}
+
+impl Manifest {
+    /// The DNA of every token by id, 1/1 tokens included.
+    pub fn token_dnas(&self) -> BTreeMap<usize, String> {
+        self.tokens
+            .iter()
+            .map(|token| (token.token_id, token.dna.clone()))
+            .chain(
+                self.one_of_ones
+                    .iter()
+                    .map(|one_of_one| (one_of_one.token_id, one_of_one.dna.clone())),
+            )
+            .collect()
+    }
+}
+
+/// The current time in UTC, to the second, as recorded in the manifest.
+pub fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn hash_file(path: &str) -> Result<String, CustomError> {
+    let contents =
+        fs::read(path).map_err(|e| CustomError::WriteOutput(format!("{}: {}", path, e)))?;
+    Ok(format!("{:x}", Sha256::digest(contents)))
+}
+
+/// Hashes every distinct asset once.
+pub fn hash_assets<'a>(
+    paths: impl Iterator<Item = &'a String>,
+) -> Result<HashMap<String, String>, CustomError> {
+    let unique: BTreeSet<&String> = paths.collect();
+    unique
+        .into_par_iter()
+        .map(|path| Ok((path.clone(), hash_file(path)?)))
+        .collect()
+}
+
+/// `tokens` holds the DNA, computed with `algorithm`, and asset paths of every
+/// token, in token id order, all rendered now.
+pub fn build_manifest(
+    algorithm: DnaAlgorithm,
+    layout: &LayerLayout,
+    tokens: &[(String, Vec<String>)],
+) -> Result<Manifest, CustomError> {
+    let now = now();
+    let hashes = hash_assets(
+        tokens
+            .iter()
+            .flat_map(|(_, paths)| paths)
+            .filter(|path| is_file_asset(path)),
+    )?;
+
+    let tokens = tokens
+        .iter()
+        .enumerate()
+        .map(|(token_id, (dna, paths))| TokenRecord {
+            token_id,
+            dna: dna.clone(),
+            assets: paths
+                .iter()
+                .filter(|path| !is_none_asset(path))
+                .map(|path| {
+                    let (trait_type, value) = path_trait(layout, path).unwrap_or_default();
+                    AssetRecord {
+                        trait_type,
+                        value,
+                        file: path.clone(),
+                        hash: hashes.get(path).cloned().unwrap_or_default(),
+                    }
+                })
+                .collect(),
+            reserved: false,
+            rendered_at: now.clone(),
+        })
+        .collect();
+
+    Ok(Manifest {
+        tokens,
+        one_of_ones: Vec::new(),
+        historical_dna: BTreeSet::new(),
+        dna_algorithm: Some(algorithm),
+        generated_at: now,
+        updated_at: String::new(),
+    })
+}
+
+pub fn write_manifest(output_path: &Path, manifest: &Manifest) -> Result<(), CustomError> {
+    let file_path = output_path.join(MANIFEST_FILE_NAME);
+    write_json(&file_path, manifest)
+}
+
+pub fn read_manifest(output_path: &Path) -> Result<Manifest, CustomError> {
+    let file_path = output_path.join(MANIFEST_FILE_NAME);
+    let contents = fs::read_to_string(&file_path)
+        .map_err(|e| CustomError::WriteOutput(format!("{}: {}", file_path.display(), e)))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| CustomError::WriteOutput(format!("{}: {}", file_path.display(), e)))
+}
+
+/// The historical DNAs of previous runs, from their `manifest.json` files: the
+/// DNAs they recorded as historical and the ones of their tokens. They must
+/// have been computed with `algorithm`, otherwise none of them could ever match.
+pub fn load_historical_dna(
+    paths: &[String],
+    algorithm: DnaAlgorithm,
+) -> Result<BTreeSet<String>, CustomError> {
+    let mut dnas = BTreeSet::new();
+    for path in paths {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| CustomError::InvalidDna(format!("{}: {}", path, e)))?;
+        let manifest: Manifest = serde_json::from_str(&contents)
+            .map_err(|e| CustomError::InvalidDna(format!("{}: {}", path, e)))?;
+        match manifest.dna_algorithm {
+            Some(recorded) if recorded != algorithm => {
+                return Err(CustomError::InvalidDna(format!(
+                    "{}: uses {} DNAs but the config uses {}",
+                    path, recorded, algorithm
+                )));
+            }
+            Some(_) => {}
+            None => {
+                return Err(CustomError::InvalidDna(format!(
+                    "{}: written by a version that didn't record its DNA algorithm, regenerate it",
+                    path
+                )));
+            }
+        }
+        dnas.extend(manifest.historical_dna);
+        dnas.extend(manifest.tokens.into_iter().map(|token| token.dna));
+    }
+    Ok(dnas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_build_manifest() {
+        let dir = tempdir().unwrap();
+        let layer_dir = dir.path().join("images").join("Background");
+        fs::create_dir_all(&layer_dir).unwrap();
+        let blue = layer_dir.join("Blue#10.png").display().to_string();
+        let red = layer_dir.join("Red#10.png").display().to_string();
+        fs::write(&blue, b"blue").unwrap();
+        fs::write(&red, b"red").unwrap();
+
+        let layout = LayerLayout::new(&dir.path().join("images").display().to_string());
+        let manifest = build_manifest(
+            DnaAlgorithm::Sha256,
+            &layout,
+            &[
+                ("aa".to_string(), vec![blue.clone()]),
+                ("bb".to_string(), vec![red.clone()]),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(manifest.tokens.len(), 2);
+        assert_eq!(manifest.dna_algorithm, Some(DnaAlgorithm::Sha256));
+        assert_eq!(manifest.tokens[1].token_id, 1);
+        assert_eq!(manifest.tokens[1].dna, "bb");
+        let asset = &manifest.tokens[0].assets[0];
+        assert_eq!(asset.file, blue);
+        assert_eq!(asset.trait_type, "Background");
+        assert_eq!(asset.value, "Blue");
+        assert_eq!(asset.hash, format!("{:x}", Sha256::digest(b"blue")));
+        assert!(chrono::DateTime::parse_from_rfc3339(&manifest.generated_at).is_ok());
+        assert_eq!(manifest.tokens[0].rendered_at, manifest.generated_at);
+        assert!(manifest.updated_at.is_empty());
+
+        let mut previous = manifest.clone();
+        previous.tokens.truncate(1);
+        previous.historical_dna = BTreeSet::from(["burned".to_string()]);
+        write_manifest(dir.path(), &previous).unwrap();
+        let paths = [dir.path().join(MANIFEST_FILE_NAME).display().to_string()];
+        assert_eq!(
+            load_historical_dna(&paths, DnaAlgorithm::Sha256).unwrap(),
+            BTreeSet::from(["aa".to_string(), "burned".to_string()])
+        );
+        assert!(load_historical_dna(&paths, DnaAlgorithm::Xxh64).is_err());
+        previous.dna_algorithm = None;
+        write_manifest(dir.path(), &previous).unwrap();
+        assert!(load_historical_dna(&paths, DnaAlgorithm::Sha256).is_err());
+
+        fs::remove_file(&red).unwrap();
+        assert!(build_manifest(
+            DnaAlgorithm::Sha256,
+            &layout,
+            &[("bb".to_string(), vec![red])]
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use chrono::NaiveDate;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// Key names of the attributes in the generated metadata. The default is the
+/// OpenSea shape, `{ "trait_type": ..., "value": ... }`; some standards and
+/// frontends expect `trait`, or `type` and `name`, instead.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MetadataProfile {
+    pub trait_type_key: String,
+    pub value_key: String,
+    /// Embed the `#N` weight of every trait as a `weight` key of its
+    /// attribute. Marketplaces don't expect it, so by default the weights are
+    /// only written to `rarity.json`.
+    pub attribute_weight: bool,
+}
+
+/// OpenSea `display_type` of a numeric layer, whose values are emitted as
+/// numbers rather than strings.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayType {
+    Number,
+    BoostPercentage,
+    BoostNumber,
+    /// A unix timestamp in seconds, or a `YYYY-MM-DD` day.
+    Date,
+}
+
+impl DisplayType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisplayType::Number => "number",
+            DisplayType::BoostPercentage => "boost_percentage",
+            DisplayType::BoostNumber => "boost_number",
+            DisplayType::Date => "date",
+        }
+    }
+
+    /// The number a trait value stands for, or `None` when it isn't one.
+    pub fn parse(&self, value: &str) -> Option<Value> {
+        if let Ok(integer) = value.parse::<i64>() {
+            return Some(Value::from(integer));
+        }
+        match self {
+            DisplayType::Date => {
+                let day = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+                Some(Value::from(day.and_hms_opt(0, 0, 0)?.and_utc().timestamp()))
+            }
+            _ => value
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite())
+                .map(Value::from),
+        }
+    }
+}
+
+impl Default for MetadataProfile {
+    fn default() -> Self {
+        MetadataProfile {
+            trait_type_key: "trait_type".to_string(),
+            value_key: "value".to_string(),
+            attribute_weight: false,
+        }
+    }
+}
+
+impl MetadataProfile {
+    pub fn attribute(&self, trait_type: &str, value: &str) -> Value {
+        let mut attribute = Map::new();
+        attribute.insert(self.trait_type_key.clone(), Value::from(trait_type));
+        attribute.insert(self.value_key.clone(), Value::from(value));
+        Value::Object(attribute)
+    }
+
+    /// An attribute with a numeric value and its `display_type`.
+    pub fn display_attribute(
+        &self,
+        trait_type: &str,
+        value: Value,
+        display_type: DisplayType,
+    ) -> Value {
+        let mut attribute = Map::new();
+        attribute.insert(
+            "display_type".to_string(),
+            Value::from(display_type.as_str()),
+        );
+        attribute.insert(self.trait_type_key.clone(), Value::from(trait_type));
+        attribute.insert(self.value_key.clone(), value);
+        Value::Object(attribute)
+    }
+
+    pub fn trait_type<'a>(&self, attribute: &'a Value) -> Option<&'a str> {
+        attribute.get(&self.trait_type_key).and_then(Value::as_str)
+    }
+
+    pub fn value<'a>(&self, attribute: &'a Value) -> Option<&'a Value> {
+        attribute.get(&self.value_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_metadata_profile() {
+        let default = MetadataProfile::default();
+        let attribute = default.attribute("Eyes", "Laser");
+        assert_eq!(attribute, json!({"trait_type": "Eyes", "value": "Laser"}));
+        assert_eq!(default.trait_type(&attribute), Some("Eyes"));
+
+        let profile: MetadataProfile =
+            serde_json::from_str(r#"{"traitTypeKey": "trait"}"#).unwrap();
+        let attribute = profile.attribute("Eyes", "Laser");
+        assert_eq!(attribute, json!({"trait": "Eyes", "value": "Laser"}));
+        assert_eq!(profile.value(&attribute), Some(&Value::from("Laser")));
+        assert_eq!(default.trait_type(&attribute), None);
+
+        assert_eq!(DisplayType::Number.parse("12"), Some(Value::from(12)));
+        assert_eq!(
+            DisplayType::BoostPercentage.parse("2.5"),
+            Some(Value::from(2.5))
+        );
+        assert_eq!(DisplayType::Number.parse("Laser"), None);
+        assert_eq!(
+            DisplayType::Date.parse("1970-01-02"),
+            Some(Value::from(86400))
+        );
+        assert_eq!(DisplayType::Date.parse("2.5"), None);
+        assert_eq!(
+            default.display_attribute("Level", Value::from(3), DisplayType::BoostNumber),
+            json!({"display_type": "boost_number", "trait_type": "Level", "value": 3})
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use crate::CustomError;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Id,
+    Dna,
+    Trait(String),
+}
+
+/// A `nameTemplate` such as `Cool Cat #{id}`, giving every token its own
+/// `name`. `{id}` is the token id, `{dna}` its DNA and `{<layer>}` the value
+/// it got from that layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+/// A piece of a template: text, or the name between `{` and `}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Splits `template` into its text and placeholders, in order.
+pub fn pieces(template: &str) -> Result<Vec<Piece<'_>>, &'static str> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            pieces.push(Piece::Text(&rest[..start]));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or("has a { that is never closed")?;
+        pieces.push(Piece::Placeholder(&rest[start + 1..start + end]));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest));
+    }
+    Ok(pieces)
+}
+
+impl NameTemplate {
+    pub fn compile(template: &str, layer_names: &[String]) -> Result<NameTemplate, CustomError> {
+        let invalid =
+            |reason: String| CustomError::Config(format!("nameTemplate {:?} {}", template, reason));
+        let parts = pieces(template)
+            .map_err(|reason| invalid(reason.to_string()))?
+            .into_iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => Ok(Part::Text(text.to_string())),
+                Piece::Placeholder("id") => Ok(Part::Id),
+                Piece::Placeholder("dna") => Ok(Part::Dna),
+                Piece::Placeholder(layer) if layer_names.iter().any(|name| name == layer) => {
+                    Ok(Part::Trait(layer.to_string()))
+                }
+                Piece::Placeholder(unknown) => Err(invalid(format!(
+                    "references {{{}}}, which is neither id, dna nor a layer",
+                    unknown
+                ))),
+            })
+            .collect::<Result<Vec<Part>, CustomError>>()?;
+        Ok(NameTemplate { parts })
+    }
+
+    /// The name of a token; `traits` holds its `(layer, value)` pairs.
+    pub fn render(&self, token_id: usize, dna: &str, traits: &[(&str, &str)]) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Id => token_id.to_string(),
+                Part::Dna => dna.to_string(),
+                Part::Trait(layer) => traits
+                    .iter()
+                    .find(|(trait_type, _)| trait_type == layer)
+                    .map(|(_, value)| value.to_string())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_template() {
+        let names = vec!["Background".to_string(), "Eyes".to_string()];
+        let template = NameTemplate::compile("{Eyes} Cat #{id}", &names).unwrap();
+        assert_eq!(
+            template.render(7, "ab12", &[("Background", "Blue"), ("Eyes", "Laser")]),
+            "Laser Cat #7"
+        );
+        let template = NameTemplate::compile("Cat {dna}", &names).unwrap();
+        assert_eq!(template.render(7, "ab12", &[]), "Cat ab12");
+
+        assert!(NameTemplate::compile("Cat #{number}", &names).is_err());
+        assert!(NameTemplate::compile("Cat #{id", &names).is_err());
+    }
+}
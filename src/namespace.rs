@@ -0,0 +1,129 @@
+use chrono::{DateTime, Local};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::cli::Command;
+use crate::{Config, CustomError};
+
+/// File in the output folder holding the name of the last namespaced run.
+pub const LATEST_RUN_FILE_NAME: &str = "latest-run";
+
+/// Name of a run, from a `strftime` pattern such as `run-%Y-%m-%d-%H%M%S`.
+pub fn namespace_name(pattern: &str, now: DateTime<Local>) -> Result<String, CustomError> {
+    let mut name = String::new();
+    write!(name, "{}", now.format(pattern)).map_err(|_| {
+        CustomError::WriteOutput(format!(
+            "outputNamespace {} is not a valid pattern",
+            pattern
+        ))
+    })?;
+    let name = name.trim_matches('/').to_string();
+    if name.is_empty() || name.split('/').any(|part| part.is_empty() || part == "..") {
+        return Err(CustomError::WriteOutput(format!(
+            "outputNamespace {} is not a folder name",
+            pattern
+        )));
+    }
+    Ok(name)
+}
+
+pub fn namespaced_output_path(output_path: &str, namespace: &str) -> String {
+    format!("{}/{}/", output_path.trim_end_matches('/'), namespace)
+}
+
+/// Creates the folder of a new run. An existing run is never written over.
+pub fn start_run(output_path: &str, namespace: &str) -> Result<String, CustomError> {
+    let run_path = namespaced_output_path(output_path, namespace);
+    if Path::new(&run_path).exists() {
+        return Err(CustomError::WriteOutput(format!(
+            "run {} already exists in {}",
+            namespace, output_path
+        )));
+    }
+    fs::create_dir_all(&run_path)
+        .map_err(|e| CustomError::WriteOutput(format!("{}: {}", run_path, e)))?;
+    Ok(run_path)
+}
+
+pub fn write_latest_run(output_path: &str, namespace: &str) -> Result<(), CustomError> {
+    let path = Path::new(output_path).join(LATEST_RUN_FILE_NAME);
+    fs::write(&path, namespace)
+        .map_err(|e| CustomError::WriteOutput(format!("{}: {}", path.display(), e)))
+}
+
+pub fn latest_run(output_path: &str) -> Option<String> {
+    let namespace = fs::read_to_string(Path::new(output_path).join(LATEST_RUN_FILE_NAME)).ok()?;
+    Some(namespace.trim().to_string()).filter(|namespace| !namespace.is_empty())
+}
+
+/// Points `config.output_path` at the folder of the run the command works on.
+/// `generate` starts a new run when a namespace is set, and its name is
+/// returned; the other commands read the run given with `--run`, or the latest.
+pub fn select_output(
+    config: &mut Config,
+    command: &Command,
+    run: Option<&str>,
+) -> Result<Option<String>, CustomError> {
+    let output_root = config.output_path.clone();
+    match command {
+        Command::Generate(args) => {
+            let Some(pattern) = args.namespace.as_ref().or(config.output_namespace.as_ref()) else {
+                return Ok(None);
+            };
+            let namespace = namespace_name(pattern, Local::now())?;
+            config.output_path = start_run(&output_root, &namespace)?;
+            Ok(Some(namespace))
+        }
+        Command::Init(_)
+        | Command::Batch(_)
+        | Command::Schema
+        | Command::Validate
+        | Command::Estimate(_)
+        | Command::Explore(_)
+        | Command::Preview(_)
+        | Command::Tui
+        | Command::CompareGolden(_)
+        | Command::PackLayers(_)
+        | Command::ImportLayers(_) => Ok(None),
+        _ => {
+            let run = match run {
+                Some(run) => Some(run.to_string()),
+                None if config.output_namespace.is_some() => latest_run(&output_root),
+                None => None,
+            };
+            if let Some(run) = run {
+                config.output_path = namespaced_output_path(&output_root, &run);
+            }
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_runs() {
+        let now = Local.with_ymd_and_hms(2024, 6, 1, 9, 30, 0).unwrap();
+        assert_eq!(
+            namespace_name("run-%Y-%m-%d/", now).unwrap(),
+            "run-2024-06-01"
+        );
+        assert!(namespace_name("../%Y", now).is_err());
+
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().display().to_string();
+        assert_eq!(latest_run(&output_path), None);
+
+        let run_path = start_run(&output_path, "run-2024-06-01").unwrap();
+        assert!(Path::new(&run_path).is_dir());
+        assert!(start_run(&output_path, "run-2024-06-01").is_err());
+
+        write_latest_run(&output_path, "run-2024-06-01").unwrap();
+        assert_eq!(latest_run(&output_path).unwrap(), "run-2024-06-01");
+    }
+}
@@ -0,0 +1,233 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::file_names::FileNames;
+use crate::manifest::OneOfOneRecord;
+use crate::{write_json, CustomError};
+
+/// A folder of pre-composed legendary tokens, `<name>.png` with its metadata
+/// in `<name>.json`, injected into the collection as they are.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OneOfOnes {
+    pub path: String,
+    /// Token id of some of them, by name. The others get random ids.
+    #[serde(default)]
+    pub token_ids: HashMap<String, usize>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct OneOfOne {
+    pub name: String,
+    pub image: PathBuf,
+    pub metadata: Map<String, Value>,
+}
+
+impl OneOfOnes {
+    fn images(&self) -> Result<Vec<PathBuf>, CustomError> {
+        let entries = fs::read_dir(&self.path)
+            .map_err(|e| CustomError::OneOfOnes(format!("{}: {}", self.path, e)))?;
+        let mut images: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+            .collect();
+        images.sort();
+        Ok(images)
+    }
+
+    /// How many 1/1 tokens the folder holds, 0 if it can't be read.
+    pub fn count(&self) -> usize {
+        self.images().map_or(0, |images| images.len())
+    }
+
+    pub fn load(&self) -> Result<Vec<OneOfOne>, CustomError> {
+        let one_of_ones = self
+            .images()?
+            .into_iter()
+            .map(|image| {
+                let metadata_path = image.with_extension("json");
+                let contents = fs::read_to_string(&metadata_path).map_err(|e| {
+                    CustomError::OneOfOnes(format!("{}: {}", metadata_path.display(), e))
+                })?;
+                let metadata = match serde_json::from_str(&contents) {
+                    Ok(Value::Object(metadata)) => metadata,
+                    _ => {
+                        return Err(CustomError::OneOfOnes(format!(
+                            "{} is not a JSON object",
+                            metadata_path.display()
+                        )))
+                    }
+                };
+                Ok(OneOfOne {
+                    name: image
+                        .file_stem()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    image,
+                    metadata,
+                })
+            })
+            .collect::<Result<Vec<OneOfOne>, CustomError>>()?;
+
+        if let Some(name) = self.token_ids.keys().find(|name| {
+            !one_of_ones
+                .iter()
+                .any(|one_of_one| &one_of_one.name == *name)
+        }) {
+            return Err(CustomError::OneOfOnes(format!(
+                "tokenIds references {}, which is not in {}",
+                name, self.path
+            )));
+        }
+        Ok(one_of_ones)
+    }
+
+    /// The token id of every 1/1, in the order of `one_of_ones`: the configured
+    /// one, or a random free id of `token_ids`. `fixed_ids` are already given
+    /// to fixed tokens.
+    pub fn place<R: Rng>(
+        &self,
+        one_of_ones: &[OneOfOne],
+        token_ids: Range<usize>,
+        fixed_ids: &[usize],
+        rng: &mut R,
+    ) -> Result<Vec<usize>, CustomError> {
+        if one_of_ones.len() + fixed_ids.len() > token_ids.len() {
+            return Err(CustomError::OneOfOnes(format!(
+                "{} 1/1 tokens don't fit in a total supply of {}",
+                one_of_ones.len(),
+                token_ids.len()
+            )));
+        }
+
+        let mut taken: HashSet<usize> = fixed_ids.iter().copied().collect();
+        for (name, &token_id) in &self.token_ids {
+            if !token_ids.contains(&token_id) || !taken.insert(token_id) {
+                return Err(CustomError::OneOfOnes(format!(
+                    "token id {} of {} is taken or not in {:?}",
+                    token_id, name, token_ids
+                )));
+            }
+        }
+        let mut free: Vec<usize> = token_ids
+            .filter(|token_id| !taken.contains(token_id))
+            .collect();
+        free.shuffle(rng);
+
+        Ok(one_of_ones
+            .iter()
+            .map(|one_of_one| match self.token_ids.get(&one_of_one.name) {
+                Some(&token_id) => token_id,
+                None => free.pop().unwrap(),
+            })
+            .collect())
+    }
+}
+
+/// Writes a 1/1 under the file names of its token, with the image URL of its
+/// image when there is one.
+pub fn write_one_of_one(
+    one_of_one: &OneOfOne,
+    record: &OneOfOneRecord,
+    file_names: &FileNames,
+    output_path: &str,
+    image_url: &str,
+) -> Result<(), CustomError> {
+    let output = Path::new(output_path);
+    let write_error = |e: &dyn std::fmt::Display| {
+        CustomError::WriteOutput(format!("{}: {}", one_of_one.image.display(), e))
+    };
+    fs::copy(
+        &one_of_one.image,
+        output.join(file_names.image(record.token_id, &record.dna)),
+    )
+    .map_err(|e| write_error(&e))?;
+
+    let mut metadata = one_of_one.metadata.clone();
+    if !image_url.is_empty() {
+        metadata.insert(
+            "image".to_string(),
+            Value::from(format!(
+                "{}/{}",
+                image_url.trim_end_matches('/'),
+                file_names.image_name(record.token_id, &record.dna)
+            )),
+        );
+    }
+    write_json(
+        &output.join(file_names.metadata(record.token_id, &record.dna)),
+        &metadata,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_one_of_ones() {
+        let dir = tempdir().unwrap();
+        for name in ["dragon", "phoenix"] {
+            fs::write(dir.path().join(format!("{}.png", name)), name).unwrap();
+            fs::write(
+                dir.path().join(format!("{}.json", name)),
+                format!(r#"{{"name": "{}"}}"#, name),
+            )
+            .unwrap();
+        }
+        let one_of_ones = OneOfOnes {
+            path: dir.path().display().to_string(),
+            token_ids: HashMap::from([("phoenix".to_string(), 3)]),
+        };
+        assert_eq!(one_of_ones.count(), 2);
+
+        let loaded = one_of_ones.load().unwrap();
+        assert_eq!(loaded[0].name, "dragon");
+        assert_eq!(loaded[1].metadata["name"], "phoenix");
+
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let token_ids = one_of_ones.place(&loaded, 0..5, &[0, 1], &mut rng).unwrap();
+        assert_eq!(token_ids[1], 3);
+        assert!(token_ids[0] < 5 && ![0, 1, 3].contains(&token_ids[0]));
+        assert!(one_of_ones.place(&loaded, 0..5, &[3], &mut rng).is_err());
+        assert!(one_of_ones.place(&loaded, 0..3, &[], &mut rng).is_err());
+        assert!(one_of_ones.place(&loaded, 4..6, &[], &mut rng).is_err());
+        assert!(one_of_ones.place(&loaded, 0..1, &[], &mut rng).is_err());
+
+        let output = tempdir().unwrap();
+        let output_path = output.path().display().to_string();
+        let record = OneOfOneRecord {
+            token_id: 3,
+            name: "phoenix".to_string(),
+            dna: "ab12".to_string(),
+        };
+        let file_names = FileNames::default();
+        write_one_of_one(
+            &loaded[1],
+            &record,
+            &file_names,
+            &output_path,
+            "ipfs://cid/",
+        )
+        .unwrap();
+        assert_eq!(fs::read(output.path().join("3.png")).unwrap(), b"phoenix");
+        let metadata: Value =
+            serde_json::from_str(&fs::read_to_string(output.path().join("3.json")).unwrap())
+                .unwrap();
+        assert_eq!(metadata["image"], "ipfs://cid/3.png");
+
+        fs::remove_file(dir.path().join("dragon.json")).unwrap();
+        assert!(one_of_ones.load().is_err());
+    }
+}
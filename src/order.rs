@@ -0,0 +1,75 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::dna::{self, DnaAlgorithm};
+use crate::paths::LayerLayout;
+
+/// The order generated tokens take their token ids in. Every order is the
+/// same on each run with the same seed.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TokenOrder {
+    /// By a hash of the assets of each token.
+    #[default]
+    Hash,
+    /// In the order the tokens were drawn, the forced combinations first.
+    Draw,
+    /// By DNA.
+    Dna,
+}
+
+impl TokenOrder {
+    /// Sorts `tokens`, given in draw order with the hash of their assets.
+    pub fn sort(
+        self,
+        tokens: &mut [(u64, Vec<String>)],
+        dna_algorithm: DnaAlgorithm,
+        layout: &LayerLayout,
+    ) {
+        match self {
+            TokenOrder::Hash => tokens.sort_by_key(|(hash, _)| *hash),
+            TokenOrder::Draw => {}
+            TokenOrder::Dna => tokens
+                .sort_by_cached_key(|(_, paths)| dna::compute_dna(dna_algorithm, layout, paths)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sort() {
+        let drawn: Vec<(u64, Vec<String>)> = ["Red", "Blue", "Gold"]
+            .iter()
+            .zip([3, 1, 2])
+            .map(|(value, hash)| (hash, vec![format!("./images/Background/{}.png", value)]))
+            .collect();
+        let layout = LayerLayout::new("./images");
+        let sorted = |order: TokenOrder| {
+            let mut tokens = drawn.clone();
+            order.sort(&mut tokens, DnaAlgorithm::default(), &layout);
+            tokens
+                .into_iter()
+                .map(|(hash, _)| hash)
+                .collect::<Vec<u64>>()
+        };
+
+        assert_eq!(sorted(TokenOrder::Hash), vec![1, 2, 3]);
+        assert_eq!(sorted(TokenOrder::Draw), vec![3, 1, 2]);
+
+        let mut dnas: Vec<(String, u64)> = drawn
+            .iter()
+            .map(|(hash, paths)| {
+                (
+                    dna::compute_dna(DnaAlgorithm::default(), &layout, paths),
+                    *hash,
+                )
+            })
+            .collect();
+        dnas.sort();
+        let by_dna: Vec<u64> = dnas.into_iter().map(|(_, hash)| hash).collect();
+        assert_eq!(sorted(TokenOrder::Dna), by_dna);
+    }
+}
@@ -0,0 +1,239 @@
+use clap::Args;
+use serde_json::{Map, Value};
+use std::env;
+use tracing::info;
+
+use crate::{schema, CustomError};
+
+/// Prefix of the environment variables overriding config fields, as in
+/// `NFTGEN_TOTAL_SUPPLY` for `totalSupply`.
+pub const ENV_PREFIX: &str = "NFTGEN_";
+
+#[derive(Debug, Args, Default, Clone)]
+pub struct OverrideArgs {
+    /// Overrides `totalSupply` of the config.
+    #[arg(long, global = true)]
+    pub total_supply: Option<u32>,
+
+    /// Overrides `outputPath` of the config.
+    #[arg(long, global = true)]
+    pub output_path: Option<String>,
+
+    /// Overrides `basePath` of the config.
+    #[arg(long, global = true)]
+    pub base_path: Option<String>,
+
+    /// Overrides any config field, as `field=value`; the value is JSON or
+    /// plain text, text only for text fields, and nested fields are joined
+    /// with dots, like `image.width=512`.
+    #[arg(long = "set", global = true, value_name = "FIELD=VALUE")]
+    pub set: Vec<String>,
+}
+
+/// A config field set from outside the config file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Override {
+    /// The field, nested ones joined with dots.
+    pub field: String,
+    pub value: Value,
+    /// The flag or environment variable it comes from.
+    pub source: String,
+}
+
+/// `text` as a value of the config field `field` of `schema`: a string when
+/// the field only takes strings, otherwise JSON, or a string when it isn't.
+fn parse_value(schema: &Value, field: &str, text: &str) -> Value {
+    if schema::expects_string(schema, field) {
+        return Value::String(text.to_string());
+    }
+    serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()))
+}
+
+/// The environment variable of a top-level config field.
+pub fn env_var(field: &str) -> String {
+    let mut name = ENV_PREFIX.to_string();
+    for character in field.chars() {
+        if character.is_uppercase() {
+            name.push('_');
+        }
+        name.push(character.to_ascii_uppercase());
+    }
+    name
+}
+
+/// The overrides of the top-level `fields` of `schema` found by `lookup` in
+/// the environment.
+pub fn env_overrides<'a>(
+    schema: &Value,
+    fields: impl Iterator<Item = &'a str>,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Vec<Override> {
+    fields
+        .filter_map(|field| {
+            let var = env_var(field);
+            Some(Override {
+                field: field.to_string(),
+                value: parse_value(schema, field, &lookup(&var)?),
+                source: var,
+            })
+        })
+        .collect()
+}
+
+impl OverrideArgs {
+    /// The overrides of the environment, then the ones of the flags, which
+    /// win when both set a field.
+    pub fn overrides(&self) -> Result<Vec<Override>, CustomError> {
+        let schema = schema::config_schema();
+        let fields: Vec<&str> = schema["properties"]
+            .as_object()
+            .map(|properties| properties.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+        let mut overrides =
+            env_overrides(&schema, fields.iter().copied(), |var| env::var(var).ok());
+
+        let flags = [
+            (
+                "totalSupply",
+                "--total-supply",
+                self.total_supply.map(Value::from),
+            ),
+            (
+                "outputPath",
+                "--output-path",
+                self.output_path.clone().map(Value::from),
+            ),
+            (
+                "basePath",
+                "--base-path",
+                self.base_path.clone().map(Value::from),
+            ),
+        ];
+        for (field, flag, value) in flags {
+            if let Some(value) = value {
+                overrides.push(Override {
+                    field: field.to_string(),
+                    value,
+                    source: flag.to_string(),
+                });
+            }
+        }
+        for assignment in &self.set {
+            let Some((field, value)) = assignment.split_once('=') else {
+                return Err(CustomError::Config(format!(
+                    "--set {}: expected FIELD=VALUE",
+                    assignment
+                )));
+            };
+            let top_level = field.split('.').next().unwrap_or_default();
+            if !fields.contains(&top_level) {
+                return Err(CustomError::Config(format!(
+                    "--set {}: {} is not a config field",
+                    assignment, top_level
+                )));
+            }
+            overrides.push(Override {
+                field: field.to_string(),
+                value: parse_value(&schema, field, value),
+                source: format!("--set {}", field),
+            });
+        }
+        Ok(overrides)
+    }
+}
+
+/// Sets every override in `config`, creating the objects a nested field is
+/// in when the file doesn't have them.
+pub fn apply(config: &mut Value, overrides: &[Override]) -> Result<(), CustomError> {
+    for config_override in overrides {
+        let mut target = &mut *config;
+        let mut parts = config_override.field.split('.').peekable();
+        while let Some(part) = parts.next() {
+            let Value::Object(fields) = target else {
+                return Err(CustomError::Config(format!(
+                    "{}: {} is not in an object",
+                    config_override.source, part
+                )));
+            };
+            if parts.peek().is_none() {
+                fields.insert(part.to_string(), config_override.value.clone());
+                break;
+            }
+            target = fields
+                .entry(part)
+                .or_insert_with(|| Value::Object(Map::new()));
+        }
+        info!(
+            "{} set to {} by {}.",
+            config_override.field, config_override.value, config_override.source
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_overrides() {
+        assert_eq!(env_var("totalSupply"), "NFTGEN_TOTAL_SUPPLY");
+        let schema = schema::config_schema();
+        let from_env = env_overrides(
+            &schema,
+            ["totalSupply", "outputPath", "imageUrl", "seed"].into_iter(),
+            |var| match var {
+                "NFTGEN_TOTAL_SUPPLY" => Some("100".to_string()),
+                "NFTGEN_OUTPUT_PATH" => Some("./staging/".to_string()),
+                "NFTGEN_IMAGE_URL" => Some("true".to_string()),
+                _ => None,
+            },
+        );
+        assert_eq!(from_env[0].value, json!(100));
+        assert_eq!(from_env[1].value, json!("./staging/"));
+        // String fields stay strings, whatever the text looks like.
+        assert_eq!(from_env[2].value, json!("true"));
+        assert_eq!(from_env.len(), 3);
+        assert_eq!(parse_value(&schema, "outputPath", "2024"), json!("2024"));
+        assert_eq!(parse_value(&schema, "video.url", "2024"), json!("2024"));
+        assert_eq!(parse_value(&schema, "image.width", "512"), json!(512));
+
+        let args = OverrideArgs {
+            total_supply: Some(5),
+            set: vec!["image.width=512".to_string(), "seed=7".to_string()],
+            ..OverrideArgs::default()
+        };
+        let mut overrides = from_env;
+        overrides.extend(
+            args.overrides()
+                .unwrap()
+                .into_iter()
+                .filter(|o| o.source.starts_with('-')),
+        );
+        let mut config = json!({"totalSupply": 10, "outputPath": "./output/"});
+        apply(&mut config, &overrides).unwrap();
+        assert_eq!(
+            config,
+            json!({
+                "totalSupply": 5,
+                "outputPath": "./staging/",
+                "imageUrl": "true",
+                "image": {"width": 512},
+                "seed": 7
+            })
+        );
+
+        let typo = OverrideArgs {
+            set: vec!["totalSuply=5".to_string()],
+            ..OverrideArgs::default()
+        };
+        assert!(typo.overrides().is_err());
+        let nested = [Override {
+            field: "totalSupply.max".to_string(),
+            value: json!(1),
+            source: "--set totalSupply.max".to_string(),
+        }];
+        assert!(apply(&mut config, &nested).is_err());
+    }
+}
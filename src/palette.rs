@@ -0,0 +1,190 @@
+use clap::Args;
+use image::RgbaImage;
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::error::Error;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::{is_image_asset, load_layers, Config, CustomError};
+
+#[derive(Debug, Args)]
+pub struct CheckPaletteArgs {
+    /// Check the layer files instead of the generated images.
+    #[arg(long)]
+    pub layers: bool,
+}
+
+/// Approved colors, as `#rrggbb`.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Palette {
+    pub colors: Vec<String>,
+    /// Maximum per-channel difference for a pixel to count as a palette color.
+    #[serde(default)]
+    pub tolerance: u8,
+    /// Share of the visible pixels of an image allowed to be off palette.
+    #[serde(default)]
+    pub max_off_palette_percent: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PaletteViolation {
+    pub file: String,
+    pub off_palette_percent: f64,
+}
+
+pub fn parse_color(color: &str) -> Result<[u8; 3], CustomError> {
+    let hex = color.trim_start_matches('#');
+    let channel = |index: usize| {
+        hex.get(index..index + 2)
+            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+    };
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(CustomError::Palette(format!(
+            "{} is not a #rrggbb color",
+            color
+        ))),
+    }
+}
+
+impl Palette {
+    fn parsed_colors(&self) -> Result<Vec<[u8; 3]>, CustomError> {
+        self.colors.iter().map(|color| parse_color(color)).collect()
+    }
+
+    /// Percentage of the visible pixels of `image` that match no palette color.
+    /// Fully transparent pixels are not counted.
+    pub fn off_palette_percent(&self, image: &RgbaImage, colors: &[[u8; 3]]) -> f64 {
+        let mut visible = 0u64;
+        let mut off_palette = 0u64;
+        for pixel in image.pixels().filter(|pixel| pixel.0[3] > 0) {
+            visible += 1;
+            let matches = colors.iter().any(|color| {
+                color
+                    .iter()
+                    .zip(&pixel.0[..3])
+                    .all(|(a, b)| a.abs_diff(*b) <= self.tolerance)
+            });
+            if !matches {
+                off_palette += 1;
+            }
+        }
+
+        if visible == 0 {
+            0.0
+        } else {
+            off_palette as f64 * 100.0 / visible as f64
+        }
+    }
+
+    pub fn check_files(&self, files: &[String]) -> Result<Vec<PaletteViolation>, CustomError> {
+        let colors = self.parsed_colors()?;
+        let mut violations = files
+            .par_iter()
+            .map(|file| {
+                let image = image::open(file)
+                    .map_err(|e| CustomError::Palette(format!("{}: {}", file, e)))?
+                    .to_rgba8();
+                let off_palette_percent = self.off_palette_percent(&image, &colors);
+                Ok(
+                    (off_palette_percent > self.max_off_palette_percent).then(|| {
+                        PaletteViolation {
+                            file: file.clone(),
+                            off_palette_percent,
+                        }
+                    }),
+                )
+            })
+            .collect::<Result<Vec<Option<PaletteViolation>>, CustomError>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<PaletteViolation>>();
+        violations.sort_by(|a, b| a.file.cmp(&b.file));
+        Ok(violations)
+    }
+}
+
+fn output_images(output_path: &Path) -> Vec<String> {
+    WalkDir::new(output_path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "png"))
+        .map(|entry| entry.path().display().to_string())
+        .collect()
+}
+
+pub fn run(args: &CheckPaletteArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let palette = config
+        .palette
+        .as_ref()
+        .ok_or_else(|| CustomError::Palette("the config has no palette".to_string()))?;
+
+    let files = if args.layers {
+        load_layers(config)?
+            .into_iter()
+            .flatten()
+            .filter(|path| is_image_asset(path))
+            .collect()
+    } else {
+        output_images(Path::new(&config.output_path))
+    };
+
+    let violations = palette.check_files(&files)?;
+    for violation in &violations {
+        println!(
+            "{}: {:.2}% of the pixels are off palette.",
+            violation.file, violation.off_palette_percent
+        );
+    }
+
+    if violations.is_empty() {
+        println!("All {} images are within the palette.", files.len());
+        Ok(())
+    } else {
+        Err(CustomError::Palette(format!(
+            "{} of {} images are off palette",
+            violations.len(),
+            files.len()
+        ))
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_palette() {
+        let palette = Palette {
+            colors: vec!["#ff0000".to_string(), "#000000".to_string()],
+            tolerance: 4,
+            max_off_palette_percent: 10.0,
+        };
+        assert_eq!(parse_color("#1a2B3c").unwrap(), [0x1a, 0x2b, 0x3c]);
+        assert!(parse_color("#12345").is_err());
+
+        let mut image = RgbaImage::from_pixel(4, 4, Rgba([252, 2, 0, 255]));
+        image.put_pixel(0, 0, Rgba([0, 0, 255, 255]));
+        image.put_pixel(1, 0, Rgba([0, 0, 255, 0]));
+        let colors = palette.parsed_colors().unwrap();
+        assert_eq!(palette.off_palette_percent(&image, &colors), 100.0 / 15.0);
+
+        let dir = tempdir().unwrap();
+        let within = dir.path().join("0.png").display().to_string();
+        let off = dir.path().join("1.png").display().to_string();
+        image.save(&within).unwrap();
+        image.put_pixel(2, 0, Rgba([0, 255, 0, 255]));
+        image.save(&off).unwrap();
+
+        let violations = palette.check_files(&[within, off.clone()]).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].file, off);
+    }
+}
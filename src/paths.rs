@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::iter;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{is_none_asset, path_trait};
+
+/// How the asset paths of a config are read: where the layer folders are, so
+/// the folders naming the layer and value of an asset are told apart from the
+/// ones the layers are in, and the weights the config gives some values over
+/// the ones of their file names.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayerLayout {
+    base_path: Option<PathBuf>,
+    /// The layers given a folder of their own, with their names.
+    layer_dirs: Vec<(PathBuf, String)>,
+    /// Weight annotations by layer and value.
+    weights: HashMap<String, HashMap<String, String>>,
+}
+
+impl LayerLayout {
+    pub fn new(base_path: &str) -> LayerLayout {
+        LayerLayout {
+            base_path: Some(PathBuf::from(base_path)),
+            layer_dirs: Vec::new(),
+            weights: HashMap::new(),
+        }
+    }
+
+    /// The layout with the folders of some layers, `(folder, layer)` pairs,
+    /// wherever they are and whatever their names.
+    pub fn with_layer_dirs(self, layer_dirs: &[(&str, &str)]) -> LayerLayout {
+        let layer_dirs = layer_dirs
+            .iter()
+            .map(|(dir, name)| (PathBuf::from(dir), name.to_string()))
+            .collect();
+        LayerLayout { layer_dirs, ..self }
+    }
+
+    /// The layout with `weights`, annotations by layer and value, over the
+    /// ones of the file names.
+    pub fn with_weights(self, weights: HashMap<String, HashMap<String, String>>) -> LayerLayout {
+        LayerLayout { weights, ..self }
+    }
+
+    /// The layer and the folder and file names of an asset path under it, the
+    /// asset last. In a folder given to a layer, the layer is the one it was
+    /// given to; under the base path, the first folder names it. A path
+    /// outside both, or any path when there is no base path, is taken to be
+    /// one folder deep, like `./images/Background/Blue.png`.
+    pub fn layer_parts(&self, path: &str) -> Vec<String> {
+        let path = Path::new(path);
+        if let Some((relative, name)) = self
+            .layer_dirs
+            .iter()
+            .find_map(|(dir, name)| Some((path.strip_prefix(dir).ok()?, name)))
+        {
+            return iter::once(name.clone()).chain(names(relative)).collect();
+        }
+        match self
+            .base_path
+            .as_ref()
+            .and_then(|base_path| path.strip_prefix(base_path).ok())
+        {
+            Some(relative) => names(relative).collect(),
+            None => names(path).skip(1).collect(),
+        }
+    }
+
+    /// The weight annotation the config gives the value of `path`, if any. The
+    /// `None` placeholder keeps the weight it was given.
+    pub fn configured_weight(&self, path: &str) -> Option<String> {
+        if self.weights.is_empty() || is_none_asset(path) {
+            return None;
+        }
+        let (layer, value) = path_trait(self, path)?;
+        self.weights.get(&layer)?.get(&value).cloned()
+    }
+}
+
+/// The folder and file names of `path`, without `.` and the root.
+fn names(path: &Path) -> impl Iterator<Item = String> + '_ {
+    path.components().filter_map(|component| match component {
+        Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+        _ => None,
+    })
+}
+
+/// Whether two paths name the same folder, however they are written, like
+/// `./images/Eyes` and `images/Eyes/`.
+pub fn same_folder(a: &str, b: &str) -> bool {
+    let components = |path| {
+        Path::new(path)
+            .components()
+            .filter(|component| *component != Component::CurDir)
+    };
+    components(a).eq(components(b))
+}
+
+/// `path` with `/` between its folders on every platform, the way
+/// `skippedTraits` patterns are written.
+pub fn portable(path: &str) -> String {
+    let mut portable = String::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Prefix(prefix) => portable.push_str(&prefix.as_os_str().to_string_lossy()),
+            Component::RootDir => portable.push('/'),
+            _ => {
+                if !portable.is_empty() && !portable.ends_with('/') {
+                    portable.push('/');
+                }
+                portable.push_str(&component.as_os_str().to_string_lossy());
+            }
+        }
+    }
+    portable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layer_parts() {
+        assert_eq!(
+            LayerLayout::default().layer_parts("./images/Background/Blue#10.png"),
+            vec!["Background", "Blue#10.png"]
+        );
+
+        let layout = LayerLayout::new("/srv/drops/art/layers/");
+        assert_eq!(
+            layout.layer_parts("/srv/drops/art/layers/Hair/Black#700/Long.png"),
+            vec!["Hair", "Black#700", "Long.png"]
+        );
+        assert_eq!(
+            LayerLayout::new("/srv/drops/").layer_parts("/srv/drops/art/layers/Hair/Long.png"),
+            vec!["art", "layers", "Hair", "Long.png"]
+        );
+
+        assert_eq!(portable("./images/Hat#2/Red.png"), "./images/Hat#2/Red.png");
+        assert_eq!(portable("/srv/drops/"), "/srv/drops");
+
+        #[cfg(windows)]
+        {
+            assert_eq!(
+                LayerLayout::new(r"C:\art\layers\")
+                    .layer_parts(r"C:\art\layers\Hair\Black#700/Long.png"),
+                vec!["Hair", "Black#700", "Long.png"]
+            );
+            assert_eq!(
+                portable(r".\images\Hat#2\Red.png"),
+                "./images/Hat#2/Red.png"
+            );
+        }
+
+        assert_eq!(
+            LayerLayout::new("assets/layers").layer_parts("assets/layers/Eyes/None#5"),
+            vec!["Eyes", "None#5"]
+        );
+
+        let layout = LayerLayout::new("./images/")
+            .with_layer_dirs(&[("./images/Eyes v2", "Eyes"), ("/srv/shared/hats", "Hat")]);
+        assert_eq!(
+            layout.layer_parts("./images/Eyes v2/Laser#10.png"),
+            vec!["Eyes", "Laser#10.png"]
+        );
+        assert_eq!(
+            layout.layer_parts("/srv/shared/hats/Cap/Red.png"),
+            vec!["Hat", "Cap", "Red.png"]
+        );
+        assert_eq!(
+            layout.layer_parts("./images/Body/Robot.png"),
+            vec!["Body", "Robot.png"]
+        );
+
+        assert!(same_folder("./images/Eyes", "images/Eyes/"));
+        assert!(!same_folder("./images/Eyes", "/images/Eyes"));
+    }
+}
@@ -0,0 +1,198 @@
+use image::imageops::{self, FilterType};
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::file_names::FileNames;
+use crate::reroll::{self, RerollArgs};
+use crate::{collection_token_ids, load_layers, manifest, write_json, Config, CustomError};
+
+pub const NEAR_DUPLICATES_FILE_NAME: &str = "near-duplicates.json";
+
+/// Times near-duplicates are re-rolled before the ones left are only reported.
+const MAX_REROLL_ROUNDS: usize = 3;
+
+/// Compares the rendered images of a run, since different combinations can
+/// look the same, e.g. when a layer hides another one entirely.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NearDuplicates {
+    /// Similarity, from 0 to 1, at which two images are flagged; 1 only flags
+    /// images with the same perceptual hash.
+    #[serde(default = "default_similarity")]
+    pub similarity: f64,
+    /// Re-roll the later token of every flagged pair.
+    #[serde(default)]
+    pub reroll: bool,
+}
+
+fn default_similarity() -> f64 {
+    0.95
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NearDuplicate {
+    pub token_id: usize,
+    pub duplicate_of: usize,
+    pub similarity: f64,
+}
+
+/// Difference hash of an image: whether each pixel of a 9x8 grayscale
+/// thumbnail is brighter than the one on its right.
+pub fn perceptual_hash(image: &image::DynamicImage) -> u64 {
+    let thumbnail = imageops::resize(&image.to_luma8(), 9, 8, FilterType::Triangle);
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = thumbnail.get_pixel(x, y).0[0] > thumbnail.get_pixel(x + 1, y).0[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    hash
+}
+
+/// Share of the bits two hashes have in common.
+pub fn similarity(a: u64, b: u64) -> f64 {
+    1.0 - (a ^ b).count_ones() as f64 / 64.0
+}
+
+/// Every token whose image is at least `min_similarity` alike an image of a
+/// lower token id, with the most similar one.
+pub fn find_near_duplicates(hashes: &[(usize, u64)], min_similarity: f64) -> Vec<NearDuplicate> {
+    hashes
+        .iter()
+        .enumerate()
+        .filter_map(|(position, &(token_id, hash))| {
+            hashes[..position]
+                .iter()
+                .map(|&(other_id, other_hash)| (other_id, similarity(hash, other_hash)))
+                .filter(|&(_, similarity)| similarity >= min_similarity)
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(duplicate_of, similarity)| NearDuplicate {
+                    token_id,
+                    duplicate_of,
+                    similarity,
+                })
+        })
+        .collect()
+}
+
+fn hash_images(
+    output_path: &Path,
+    images: &[(usize, String)],
+) -> Result<Vec<(usize, u64)>, CustomError> {
+    images
+        .par_iter()
+        .map(|(token_id, image)| {
+            let path = output_path.join(image);
+            let decoded = image::open(&path)
+                .map_err(|e| CustomError::WriteOutput(format!("{}: {}", path.display(), e)))?;
+            Ok((*token_id, perceptual_hash(&decoded)))
+        })
+        .collect()
+}
+
+fn write_report(output_path: &Path, near_duplicates: &[NearDuplicate]) -> Result<(), CustomError> {
+    let file_path = output_path.join(NEAR_DUPLICATES_FILE_NAME);
+    write_json(&file_path, near_duplicates)
+}
+
+/// Hashes the images of the collection just generated, re-rolls the
+/// near-duplicates it can when `reroll` is set, and writes the ones left to
+/// `near-duplicates.json`.
+pub fn check(config: &Config, checks: &NearDuplicates) -> Result<(), Box<dyn Error>> {
+    let output_path = Path::new(&config.output_path);
+    let file_names = FileNames::compile(config)?;
+    let all_layers = load_layers(config)?;
+    let mut round = 0;
+    loop {
+        let token_manifest = manifest::read_manifest(output_path)?;
+        let images =
+            file_names.images(collection_token_ids(config), &token_manifest.token_dnas())?;
+        let near_duplicates =
+            find_near_duplicates(&hash_images(output_path, &images)?, checks.similarity);
+        for near_duplicate in &near_duplicates {
+            warn!(
+                "Token {} looks like token {} ({:.0}% similar).",
+                near_duplicate.token_id,
+                near_duplicate.duplicate_of,
+                near_duplicate.similarity * 100.0
+            );
+        }
+
+        // 1/1 tokens, fixed tokens and unique combinations keep their image,
+        // so the other token of the pair is re-rolled, when it can be.
+        let rerollable = reroll::rerollable_ids(config, &token_manifest, &all_layers)?;
+        let mut ids = BTreeSet::new();
+        for near_duplicate in &near_duplicates {
+            let pair = [near_duplicate.token_id, near_duplicate.duplicate_of];
+            if pair.iter().any(|token_id| ids.contains(token_id)) {
+                continue;
+            }
+            if let Some(&token_id) = pair.iter().find(|token_id| rerollable.contains(token_id)) {
+                ids.insert(token_id);
+            }
+        }
+
+        if !checks.reroll || ids.is_empty() || round == MAX_REROLL_ROUNDS {
+            write_report(output_path, &near_duplicates)?;
+            info!(
+                "{} near-duplicate images, listed in {}.",
+                near_duplicates.len(),
+                NEAR_DUPLICATES_FILE_NAME
+            );
+            return Ok(());
+        }
+        round += 1;
+        info!("Re-rolling {} near-duplicate tokens.", ids.len());
+        let args = RerollArgs {
+            ids: ids.into_iter().collect(),
+            seed: config.seed.map(|seed| seed.wrapping_add(round as u64)),
+        };
+        if let Err(error) = reroll::run(&args, config) {
+            match error.downcast::<CustomError>() {
+                Ok(error) if matches!(*error, CustomError::InvalidTotalSupply(..)) => {
+                    warn!("Not enough unused combinations left to re-roll the near-duplicates.");
+                    round = MAX_REROLL_ROUNDS;
+                }
+                Ok(error) => return Err(error),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgba, RgbaImage};
+
+    #[test]
+    fn test_find_near_duplicates() {
+        let gradient = RgbaImage::from_fn(64, 64, |x, _| Rgba([(x * 4) as u8, 0, 0, 255]));
+        let mut dotted = gradient.clone();
+        dotted.put_pixel(10, 10, Rgba([255, 255, 255, 255]));
+        let flipped = imageops::flip_horizontal(&gradient);
+        let hashes: Vec<(usize, u64)> = [gradient, dotted, flipped]
+            .into_iter()
+            .enumerate()
+            .map(|(token_id, image)| (token_id, perceptual_hash(&DynamicImage::ImageRgba8(image))))
+            .collect();
+
+        assert_eq!(similarity(hashes[0].1, hashes[1].1), 1.0);
+        assert_eq!(
+            find_near_duplicates(&hashes, 0.95),
+            vec![NearDuplicate {
+                token_id: 1,
+                duplicate_of: 0,
+                similarity: 1.0
+            }]
+        );
+        assert_eq!(find_near_duplicates(&hashes, 0.0).len(), 2);
+    }
+}
@@ -0,0 +1,253 @@
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+
+use crate::dna::{self, DnaAlgorithm};
+use crate::paths::{self, LayerLayout};
+use crate::rules::Rules;
+use crate::{path_trait, trait_path_parts};
+
+/// Above this many combinations they are not checked one by one against the
+/// constraints. Every excluded DNA is assumed to be reachable instead and the
+/// dependency rules are not counted, so the count is only an estimate.
+const EXACT_COUNT_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerCount {
+    pub layer: String,
+    pub options: usize,
+    pub sublayers: usize,
+    pub skipped: usize,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PermutationCount {
+    pub layers: Vec<LayerCount>,
+    pub combinations: usize,
+    pub excluded: usize,
+    pub possible: usize,
+}
+
+/// Restrictions every sampled permutation has to satisfy.
+#[derive(Default)]
+pub struct Constraints {
+    pub excluded_dna: HashSet<String>,
+    pub dna_algorithm: DnaAlgorithm,
+    pub rules: Rules,
+    /// How the asset paths the permutations are drawn from are read.
+    pub layout: LayerLayout,
+}
+
+impl Constraints {
+    pub fn allows(&self, permutation: &[String]) -> bool {
+        let satisfies_rules = self.rules.is_empty() || {
+            let traits: Vec<(String, String)> = permutation
+                .iter()
+                .filter_map(|path| path_trait(&self.layout, path))
+                .collect();
+            self.rules.is_satisfied(&traits)
+        };
+        satisfies_rules
+            && (self.excluded_dna.is_empty()
+                || !self.excluded_dna.contains(&dna::compute_dna(
+                    self.dna_algorithm,
+                    &self.layout,
+                    permutation,
+                )))
+    }
+}
+
+/// Whether a `skippedTraits` pattern matches `path`, written with `/` on
+/// every platform.
+pub fn is_skipped(path: &str, skipped_traits: &[Regex]) -> bool {
+    let path = paths::portable(path);
+    skipped_traits.iter().any(|regex| regex.is_match(&path))
+}
+
+/// Removes the assets matching `skippedTraits` from the layers sampled from.
+pub fn without_skipped(layers: &[Vec<String>], skipped_traits: &[Regex]) -> Vec<Vec<String>> {
+    layers
+        .iter()
+        .map(|layer| {
+            layer
+                .iter()
+                .filter(|path| !is_skipped(path, skipped_traits))
+                .cloned()
+                .collect()
+        })
+        .collect()
+}
+
+/// Counts the distinct combinations the sampler can draw from `layers`. Every
+/// asset of a layer, sublayers included, is one option; skipped assets are
+/// not, an empty layer is left out of the image, and combinations ruled out by
+/// a dependency rule or whose DNA was used by a previous run are subtracted.
+pub fn count_permutations(
+    names: &[String],
+    layers: &[Vec<String>],
+    skipped_traits: &[Regex],
+    constraints: &Constraints,
+) -> PermutationCount {
+    let pools: Vec<Vec<&String>> = layers
+        .iter()
+        .map(|layer| {
+            layer
+                .iter()
+                .filter(|path| !is_skipped(path, skipped_traits))
+                .collect()
+        })
+        .collect();
+
+    let layer_counts: Vec<LayerCount> = layers
+        .iter()
+        .zip(&pools)
+        .enumerate()
+        .map(|(index, (layer, pool))| {
+            let sublayers: BTreeSet<String> = pool
+                .iter()
+                .filter_map(|path| {
+                    let parts = trait_path_parts(&constraints.layout, path);
+                    (parts.len() > 2).then(|| parts[1].clone())
+                })
+                .collect();
+            LayerCount {
+                layer: names.get(index).cloned().unwrap_or_default(),
+                options: pool.len(),
+                sublayers: sublayers.len(),
+                skipped: layer.len() - pool.len(),
+            }
+        })
+        .collect();
+
+    let combinations = pools
+        .iter()
+        .fold(1usize, |acc, pool| acc.saturating_mul(pool.len().max(1)));
+
+    let excluded = if constraints.excluded_dna.is_empty() && constraints.rules.is_empty() {
+        0
+    } else if combinations <= EXACT_COUNT_LIMIT {
+        let pools: Vec<&Vec<&String>> = pools.iter().filter(|pool| !pool.is_empty()).collect();
+        let mut excluded = 0;
+        let mut indices = vec![0; pools.len()];
+        loop {
+            let permutation: Vec<String> = indices
+                .iter()
+                .zip(&pools)
+                .map(|(&index, pool)| pool[index].clone())
+                .collect();
+            if !constraints.allows(&permutation) {
+                excluded += 1;
+            }
+
+            let Some(position) = (0..pools.len())
+                .rev()
+                .find(|&position| indices[position] + 1 < pools[position].len())
+            else {
+                break;
+            };
+            indices[position] += 1;
+            indices[position + 1..].fill(0);
+        }
+        excluded
+    } else {
+        constraints.excluded_dna.len().min(combinations)
+    };
+
+    PermutationCount {
+        layers: layer_counts,
+        combinations,
+        excluded,
+        possible: combinations - excluded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<String> {
+        vec!["Background".to_string(), "Hair".to_string()]
+    }
+
+    fn layers() -> Vec<Vec<String>> {
+        vec![
+            vec![
+                "./images/Background/Blue#10.png".to_string(),
+                "./images/Background/Red#5.png".to_string(),
+            ],
+            vec![
+                "./images/Hair/Black#700/Long#25.png".to_string(),
+                "./images/Hair/Black#700/Short#25.png".to_string(),
+                "./images/Hair/Red#300/Long#25.png".to_string(),
+            ],
+        ]
+    }
+
+    #[test]
+    fn test_count_permutations() {
+        let count = count_permutations(&names(), &layers(), &[], &Constraints::default());
+        assert_eq!(count.combinations, 6);
+        assert_eq!(count.possible, 6);
+        assert_eq!(
+            count.layers[1],
+            LayerCount {
+                layer: "Hair".to_string(),
+                options: 3,
+                sublayers: 2,
+                skipped: 0,
+            }
+        );
+
+        let skipped = [Regex::new("Red#300").unwrap()];
+        let count = count_permutations(&names(), &layers(), &skipped, &Constraints::default());
+        assert_eq!(count.possible, 4);
+        assert_eq!(count.layers[1].sublayers, 1);
+        assert_eq!(count.layers[1].skipped, 1);
+
+        let everything = [Regex::new("Background").unwrap()];
+        let count = count_permutations(&names(), &layers(), &everything, &Constraints::default());
+        assert_eq!(count.possible, 3);
+    }
+
+    #[test]
+    fn test_without_skipped() {
+        let skipped = [Regex::new("Red").unwrap()];
+        let layers = without_skipped(&layers(), &skipped);
+
+        assert_eq!(
+            layers[0],
+            vec!["./images/Background/Blue#10.png".to_string()]
+        );
+        assert_eq!(layers[1].len(), 2);
+        assert!(layers.iter().flatten().all(|path| !path.contains("Red")));
+        assert_eq!(without_skipped(&layers, &[]), layers);
+    }
+
+    #[test]
+    fn test_count_permutations_with_excluded_dna() {
+        let layers = layers();
+        let excluded_dna = HashSet::from([
+            dna::compute_dna(
+                DnaAlgorithm::Sha256,
+                &LayerLayout::default(),
+                &[layers[0][1].clone(), layers[1][2].clone()],
+            ),
+            "not-in-this-collection".to_string(),
+        ]);
+
+        let count = count_permutations(
+            &names(),
+            &layers,
+            &[],
+            &Constraints {
+                excluded_dna,
+                ..Constraints::default()
+            },
+        );
+        assert_eq!(count.combinations, 6);
+        assert_eq!(count.excluded, 1);
+        assert_eq!(count.possible, 5);
+    }
+}
@@ -0,0 +1,239 @@
+use image::{imageops, RgbaImage};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::rules::TraitRef;
+use crate::CustomError;
+
+/// An image filter run on a token once its layers are composed.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, JsonSchema)]
+#[serde(rename_all = "camelCase", tag = "effect")]
+pub enum Effect {
+    /// Monochrome noise of up to `amount`, from 0 to 1, of the full range.
+    Grain { amount: f32 },
+    /// Darkens the edges, the corners by `strength`, from 0 to 1.
+    Vignette { strength: f32 },
+    /// Rotates the hue of every pixel by `degrees`.
+    HueShift { degrees: f32 },
+    /// Gaussian blur, `sigma` in output pixels.
+    Blur { sigma: f32 },
+}
+
+impl Effect {
+    fn validate(&self) -> Result<(), String> {
+        match *self {
+            Effect::Grain { amount } if !(0.0..=1.0).contains(&amount) => {
+                Err(format!("grain amount {} is not between 0 and 1", amount))
+            }
+            Effect::Vignette { strength } if !(0.0..=1.0).contains(&strength) => Err(format!(
+                "vignette strength {} is not between 0 and 1",
+                strength
+            )),
+            Effect::HueShift { degrees } if !degrees.is_finite() => {
+                Err(format!("hue shift of {} degrees", degrees))
+            }
+            Effect::Blur { sigma } if !(sigma > 0.0 && sigma.is_finite()) => {
+                Err(format!("blur sigma {} is not above 0", sigma))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Filters `image`; the grain is drawn from `rng`.
+    pub fn apply(&self, image: RgbaImage, rng: &mut impl Rng) -> RgbaImage {
+        match *self {
+            Effect::Grain { amount } => grain(image, amount, rng),
+            Effect::Vignette { strength } => vignette(image, strength),
+            Effect::HueShift { degrees } => hue_shift(image, degrees),
+            Effect::Blur { sigma } => imageops::blur(&image, sigma),
+        }
+    }
+}
+
+/// A `postProcessing` step: an effect, for every token or only the ones with
+/// a trait.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PostEffect {
+    #[serde(flatten)]
+    pub effect: Effect,
+    pub when: Option<TraitRef>,
+}
+
+/// The post-processing chain of a run, its steps in the order they are
+/// applied.
+#[derive(Debug, Default, Clone)]
+pub struct PostProcessing {
+    steps: Vec<PostEffect>,
+}
+
+impl PostProcessing {
+    /// Checks the effects and that every `when` is for one of the layers of
+    /// `names`.
+    pub fn compile(steps: &[PostEffect], names: &[String]) -> Result<PostProcessing, CustomError> {
+        for step in steps {
+            step.effect
+                .validate()
+                .map_err(|reason| CustomError::Config(format!("postProcessing: {}", reason)))?;
+            if let Some(when) = &step.when {
+                if !names.contains(&when.layer) {
+                    return Err(CustomError::Config(format!(
+                        "postProcessing references unknown layer {}",
+                        when.layer
+                    )));
+                }
+            }
+        }
+        Ok(PostProcessing {
+            steps: steps.to_vec(),
+        })
+    }
+
+    /// Runs the steps of the chain for a token with `traits` on its image,
+    /// written to `image_file_name`. The grain is seeded by the file name, so
+    /// re-rendering a token gives the same image.
+    pub fn apply(
+        &self,
+        mut image: RgbaImage,
+        traits: &[(&str, &str)],
+        image_file_name: &str,
+    ) -> RgbaImage {
+        if self.steps.is_empty() {
+            return image;
+        }
+        let mut rng = ChaCha8Rng::seed_from_u64(xxh64(image_file_name.as_bytes(), 0));
+        for step in &self.steps {
+            let applies = step.when.as_ref().is_none_or(|when| {
+                traits
+                    .iter()
+                    .any(|&(layer, value)| layer == when.layer && value == when.value)
+            });
+            if applies {
+                image = step.effect.apply(image, &mut rng);
+            }
+        }
+        image
+    }
+}
+
+fn grain(mut image: RgbaImage, amount: f32, rng: &mut impl Rng) -> RgbaImage {
+    if amount == 0.0 {
+        return image;
+    }
+    let range = amount * 255.0;
+    for pixel in image.pixels_mut() {
+        let noise = rng.gen_range(-range..=range);
+        for channel in &mut pixel.0[..3] {
+            *channel = (*channel as f32 + noise).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    image
+}
+
+fn vignette(mut image: RgbaImage, strength: f32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let (center_x, center_y) = (width as f32 / 2.0, height as f32 / 2.0);
+    let corner = (center_x * center_x + center_y * center_y).max(f32::EPSILON);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 + 0.5 - center_x, y as f32 + 0.5 - center_y);
+        let factor = 1.0 - strength * (dx * dx + dy * dy) / corner;
+        for channel in &mut pixel.0[..3] {
+            *channel = (*channel as f32 * factor).round() as u8;
+        }
+    }
+    image
+}
+
+/// The hue rotation of CSS `hue-rotate()`, which keeps the luminance.
+fn hue_shift(mut image: RgbaImage, degrees: f32) -> RgbaImage {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let matrix = [
+        [
+            0.213 + cos * 0.787 - sin * 0.213,
+            0.715 - cos * 0.715 - sin * 0.715,
+            0.072 - cos * 0.072 + sin * 0.928,
+        ],
+        [
+            0.213 - cos * 0.213 + sin * 0.143,
+            0.715 + cos * 0.285 + sin * 0.140,
+            0.072 - cos * 0.072 - sin * 0.283,
+        ],
+        [
+            0.213 - cos * 0.213 - sin * 0.787,
+            0.715 - cos * 0.715 + sin * 0.715,
+            0.072 + cos * 0.928 + sin * 0.072,
+        ],
+    ];
+    for pixel in image.pixels_mut() {
+        let rgb = [pixel.0[0] as f32, pixel.0[1] as f32, pixel.0[2] as f32];
+        for (channel, row) in matrix.iter().enumerate() {
+            let value = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+            pixel.0[channel] = value.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_post_processing() {
+        let steps: Vec<PostEffect> = serde_json::from_str(
+            r#"[
+                {"effect": "hueShift", "degrees": 120},
+                {"effect": "vignette", "strength": 1, "when": {"layer": "Background", "value": "Gold"}}
+            ]"#,
+        )
+        .unwrap();
+        let names = vec!["Background".to_string()];
+        let post_processing = PostProcessing::compile(&steps, &names).unwrap();
+
+        let red = RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 200]));
+        let shifted = post_processing.apply(red.clone(), &[("Background", "Blue")], "1.png");
+        let [r, g, b, a] = shifted.get_pixel(2, 2).0;
+        assert!(g > r && g > b, "{:?}", (r, g, b));
+        assert_eq!(a, 200);
+        assert_eq!(shifted.get_pixel(0, 0), shifted.get_pixel(2, 2));
+        let gold = post_processing.apply(red.clone(), &[("Background", "Gold")], "1.png");
+        assert!(gold.get_pixel(0, 0).0[1] < gold.get_pixel(2, 2).0[1]);
+
+        let gray = RgbaImage::from_pixel(8, 8, Rgba([128, 128, 128, 255]));
+        let grain = PostProcessing::compile(
+            &[PostEffect {
+                effect: Effect::Grain { amount: 0.2 },
+                when: None,
+            }],
+            &names,
+        )
+        .unwrap();
+        let grainy = grain.apply(gray.clone(), &[], "1.png");
+        assert_ne!(grainy, gray);
+        assert_eq!(grain.apply(gray.clone(), &[], "1.png"), grainy);
+        assert_ne!(grain.apply(gray.clone(), &[], "2.png"), grainy);
+        let blurred = Effect::Blur { sigma: 2.0 }.apply(gray.clone(), &mut rand::thread_rng());
+        assert_eq!(blurred.dimensions(), (8, 8));
+        assert_eq!(
+            PostProcessing::default().apply(red.clone(), &[], "1.png"),
+            red
+        );
+
+        for invalid in [
+            r#"[{"effect": "grain", "amount": 2}]"#,
+            r#"[{"effect": "blur", "sigma": 0}]"#,
+            r#"[{"effect": "vignette", "strength": 0.5, "when": {"layer": "Hat", "value": "Cap"}}]"#,
+        ] {
+            let steps: Vec<PostEffect> = serde_json::from_str(invalid).unwrap();
+            assert!(
+                PostProcessing::compile(&steps, &names).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
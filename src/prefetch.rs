@@ -0,0 +1,120 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+use crate::preprocess::Preprocessing;
+use crate::{is_image_asset, CustomError};
+
+/// Number of tokens whose layer files are decoded together when
+/// `prefetchBatchSize` isn't set.
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// Decoded layer files by path, shared by the tokens that use them.
+pub type DecodedLayers = HashMap<String, Arc<DynamicImage>>;
+
+/// Decodes the layer files of the upcoming batches of tokens on a background
+/// thread, so reading and decoding the next batch overlaps with compositing the
+/// current one. Each file is decoded once per batch, however many tokens use it.
+pub struct Prefetcher {
+    receiver: Receiver<Result<DecodedLayers, CustomError>>,
+}
+
+/// The distinct layer files of every batch of `batch_size` tokens, in order.
+/// Assets without a file, `None` and generated backgrounds, are left out.
+pub fn unique_layer_files(tokens: &[Vec<String>], batch_size: usize) -> Vec<Vec<String>> {
+    tokens
+        .chunks(batch_size.max(1))
+        .map(|batch| {
+            let mut files: Vec<String> = batch
+                .iter()
+                .flatten()
+                .filter(|path| is_image_asset(path))
+                .cloned()
+                .collect();
+            files.sort();
+            files.dedup();
+            files
+        })
+        .collect()
+}
+
+impl Prefetcher {
+    pub fn spawn(batches: Vec<Vec<String>>, preprocessing: Preprocessing) -> Prefetcher {
+        // A rendezvous channel keeps the decoder a single batch ahead of the
+        // consumer instead of decoding the whole collection up front.
+        let (sender, receiver) = mpsc::sync_channel(0);
+        thread::spawn(move || {
+            for files in batches {
+                let decoded = files
+                    .par_iter()
+                    .map(|path| {
+                        let image = preprocessing.load_layer_image(Path::new(path))?;
+                        Ok((path.clone(), Arc::new(image)))
+                    })
+                    .collect::<Result<DecodedLayers, CustomError>>();
+                let failed = decoded.is_err();
+                // Stops once the batches aren't wanted anymore, or on an error.
+                if sender.send(decoded).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Prefetcher { receiver }
+    }
+
+    /// Waits for the layer files of the next batch.
+    pub fn next_batch(&mut self) -> Result<DecodedLayers, CustomError> {
+        self.receiver.recv().map_err(|_| {
+            CustomError::Preprocess("the layer prefetcher stopped early".to_string())
+        })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_prefetcher() {
+        let dir = tempdir().unwrap();
+        let layer = |name: &str| {
+            let path = dir.path().join(name);
+            RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]))
+                .save(&path)
+                .unwrap();
+            path.display().to_string()
+        };
+        let red = layer("Red#10.png");
+        let blue = layer("Blue#10.png");
+        let none = dir.path().join("None#10").display().to_string();
+        let tokens = vec![
+            vec![red.clone(), none.clone()],
+            vec![red.clone(), blue.clone()],
+            vec![blue.clone(), none],
+        ];
+
+        let batches = unique_layer_files(&tokens, 2);
+        assert_eq!(
+            batches,
+            vec![vec![blue.clone(), red.clone()], vec![blue.clone()]]
+        );
+
+        let mut prefetcher = Prefetcher::spawn(batches, Preprocessing::default());
+        let first = prefetcher.next_batch().unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[&red].width(), 2);
+        assert!(prefetcher.next_batch().unwrap().contains_key(&blue));
+        assert!(prefetcher.next_batch().is_err());
+
+        let missing = vec![vec![dir.path().join("Gone.png").display().to_string()]];
+        let mut prefetcher = Prefetcher::spawn(missing, Preprocessing::default());
+        assert!(prefetcher.next_batch().is_err());
+    }
+}
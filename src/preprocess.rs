@@ -0,0 +1,250 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths::LayerLayout;
+use crate::{path_trait, CustomError};
+
+/// The filter layer files or composed images are scaled with.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ResizeFilter {
+    /// Keeps hard pixel edges, for pixel art.
+    Nearest,
+    /// Smooth, for illustrations.
+    #[default]
+    Lanczos,
+}
+
+impl ResizeFilter {
+    pub fn filter_type(self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Lanczos => FilterType::Lanczos3,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ResizeFilter::Nearest => "nearest",
+            ResizeFilter::Lanczos => "lanczos",
+        }
+    }
+}
+
+/// A transformation applied to a layer image before it is composed. Steps are
+/// added by the features that need them, and each one describes itself in the
+/// cache key so changing its settings invalidates the cached variants.
+#[derive(Debug, PartialEq, Clone)]
+pub enum PreprocessStep {
+    /// Scales the images of a size other than `width` x `height` to it,
+    /// except the ones of `kept_layers`.
+    Resize {
+        width: u32,
+        height: u32,
+        filter: ResizeFilter,
+        kept_layers: Vec<String>,
+    },
+}
+
+impl PreprocessStep {
+    fn cache_key(&self) -> String {
+        match self {
+            PreprocessStep::Resize {
+                width,
+                height,
+                filter,
+                kept_layers,
+            } => format!(
+                "resize:{}x{}:{}:{}",
+                width,
+                height,
+                filter.as_str(),
+                kept_layers.join(",")
+            ),
+        }
+    }
+
+    fn apply(&self, layout: &LayerLayout, path: &Path, image: DynamicImage) -> DynamicImage {
+        match self {
+            PreprocessStep::Resize {
+                width,
+                height,
+                filter,
+                kept_layers,
+            } => {
+                let kept = path
+                    .to_str()
+                    .and_then(|path| path_trait(layout, path))
+                    .is_some_and(|(layer, _)| kept_layers.contains(&layer));
+                if kept || (image.width(), image.height()) == (*width, *height) {
+                    return image;
+                }
+                image.resize_exact(*width, *height, filter.filter_type())
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Preprocessing {
+    pub steps: Vec<PreprocessStep>,
+    pub cache_dir: Option<PathBuf>,
+    /// Where the layer folders of the files are, which steps keeping some
+    /// layers as they are tell them apart by.
+    pub layout: LayerLayout,
+}
+
+impl Preprocessing {
+    fn cache_key(&self) -> String {
+        self.steps
+            .iter()
+            .map(PreprocessStep::cache_key)
+            .collect::<Vec<String>>()
+            .join("|")
+    }
+
+    /// Opens a layer image and runs the preprocessing steps on it. With a cache
+    /// folder, the processed image is stored there keyed by the hash of the
+    /// source file and the steps, so later runs skip the work.
+    pub fn load_layer_image(&self, path: &Path) -> Result<DynamicImage, CustomError> {
+        if self.steps.is_empty() {
+            return open_image(path);
+        }
+
+        let process = |image: DynamicImage| {
+            self.steps
+                .iter()
+                .fold(image, |image, step| step.apply(&self.layout, path, image))
+        };
+        match &self.cache_dir {
+            Some(cache_dir) => load_cached(path, cache_dir, &self.cache_key(), process),
+            None => open_image(path).map(process),
+        }
+    }
+}
+
+fn open_image(path: &Path) -> Result<DynamicImage, CustomError> {
+    image::open(path).map_err(|e| CustomError::Preprocess(format!("{}: {}", path.display(), e)))
+}
+
+fn load_cached(
+    path: &Path,
+    cache_dir: &Path,
+    steps_key: &str,
+    process: impl FnOnce(DynamicImage) -> DynamicImage,
+) -> Result<DynamicImage, CustomError> {
+    let source = fs::read(path)
+        .map_err(|e| CustomError::Preprocess(format!("{}: {}", path.display(), e)))?;
+    let mut hasher = Sha256::new();
+    hasher.update(&source);
+    hasher.update([0]);
+    hasher.update(steps_key.as_bytes());
+    let cached_path = cache_dir.join(format!("{:x}.png", hasher.finalize()));
+
+    if let Ok(cached) = image::open(&cached_path) {
+        return Ok(cached);
+    }
+
+    let image = image::load_from_memory(&source)
+        .map_err(|e| CustomError::Preprocess(format!("{}: {}", path.display(), e)))?;
+    let processed = process(image);
+
+    // Write to a unique temporary name first so parallel runs never read a
+    // half-written cache entry.
+    let cache_error = |e: &dyn std::fmt::Display| {
+        CustomError::Preprocess(format!("{}: {}", cache_dir.display(), e))
+    };
+    fs::create_dir_all(cache_dir).map_err(|e| cache_error(&e))?;
+    let temp_path = cached_path.with_extension(format!("{}.tmp", std::process::id()));
+    processed
+        .save_with_format(&temp_path, image::ImageFormat::Png)
+        .map_err(|e| cache_error(&e))?;
+    fs::rename(&temp_path, &cached_path).map_err(|e| cache_error(&e))?;
+
+    Ok(processed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use std::cell::Cell;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_cached() {
+        let dir = tempdir().unwrap();
+        let layer_path = dir.path().join("Blue#10.png");
+        let cache_dir = dir.path().join("cache");
+        RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255]))
+            .save(&layer_path)
+            .unwrap();
+
+        let calls = Cell::new(0);
+        let halve = |image: DynamicImage| {
+            calls.set(calls.get() + 1);
+            image.resize_exact(2, 2, image::imageops::FilterType::Nearest)
+        };
+
+        let first = load_cached(&layer_path, &cache_dir, "resize:2x2", halve).unwrap();
+        let second = load_cached(&layer_path, &cache_dir, "resize:2x2", halve).unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(first.to_rgba8(), second.to_rgba8());
+        assert_eq!(second.width(), 2);
+
+        load_cached(&layer_path, &cache_dir, "resize:3x3", halve).unwrap();
+        assert_eq!(calls.get(), 2);
+        assert_eq!(fs::read_dir(&cache_dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_load_layer_image_without_steps() {
+        let dir = tempdir().unwrap();
+        let layer_path = dir.path().join("Blue.png");
+        RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255]))
+            .save(&layer_path)
+            .unwrap();
+
+        let preprocessing = Preprocessing {
+            steps: vec![],
+            cache_dir: Some(dir.path().join("cache")),
+            layout: LayerLayout::default(),
+        };
+        assert_eq!(
+            preprocessing.load_layer_image(&layer_path).unwrap().width(),
+            4
+        );
+        assert!(!dir.path().join("cache").exists());
+        assert!(preprocessing
+            .load_layer_image(&dir.path().join("missing.png"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_resize_step() {
+        let resize = |kept_layers: Vec<String>| PreprocessStep::Resize {
+            width: 4,
+            height: 4,
+            filter: ResizeFilter::Nearest,
+            kept_layers,
+        };
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([9, 9, 9, 255])));
+        let path = Path::new("./images/Eyes/Laser.png");
+        let layout = LayerLayout::new("./images");
+
+        let resized = resize(vec![]).apply(&layout, path, image.clone());
+        assert_eq!((resized.width(), resized.height()), (4, 4));
+        assert_eq!(resized.to_rgba8().get_pixel(3, 3), &Rgba([9, 9, 9, 255]));
+        let kept = resize(vec!["Eyes".to_string()]).apply(&layout, path, image);
+        assert_eq!(kept.width(), 2);
+        assert_ne!(
+            resize(vec![]).cache_key(),
+            resize(vec!["Eyes".to_string()]).cache_key()
+        );
+    }
+}
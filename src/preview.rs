@@ -0,0 +1,209 @@
+use clap::Args;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::caps::Caps;
+use crate::file_names;
+use crate::permutations::{self, Constraints};
+use crate::prefetch::DecodedLayers;
+use crate::rules::Rules;
+use crate::watermark::Stamp;
+use crate::{
+    generate_image_and_metadata, generate_permutations, layer_layout, layer_names, load_layers,
+    render_settings, skipped_trait_regexes, watermark_stamp, Config, CustomError,
+    ALLOWED_EXTENSION,
+};
+
+pub const PREVIEW_DIR_NAME: &str = "preview";
+
+/// How long the layer folders have to be quiet before a change is rendered,
+/// so saving several files, or one in several writes, renders once.
+const WATCH_SETTLE_TIME: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Args, Clone)]
+pub struct PreviewArgs {
+    /// Number of tokens rendered.
+    #[arg(long, default_value_t = 20)]
+    pub count: usize,
+    /// Seed of the draws; a random one by default, so every preview differs.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Draw the `watermark` of the config over every preview image, or this
+    /// PNG over their center.
+    #[arg(long, value_name = "PNG", num_args = 0..=1)]
+    pub watermark: Option<Option<String>>,
+    /// Keep running and render the preview again whenever a layer file changes.
+    #[arg(long)]
+    pub watch: bool,
+}
+
+/// Draws `count` tokens one by one, following the dependency rules but not
+/// the supply, caps or previous runs, so tokens may repeat.
+pub fn sample_tokens(
+    config: &Config,
+    count: usize,
+    rng: &mut ChaCha8Rng,
+) -> Result<Vec<Vec<String>>, CustomError> {
+    let all_layers = load_layers(config)?;
+    let layers = permutations::without_skipped(&all_layers, &skipped_trait_regexes(config)?);
+    let constraints = Constraints {
+        rules: Rules::compile(
+            &config.dependency_rules,
+            &config.trait_tags,
+            &layer_names(config),
+        )?,
+        layout: layer_layout(config),
+        ..Constraints::default()
+    };
+
+    let mut tokens = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (_, token) =
+            generate_permutations(&layers, 1, &constraints, &mut Caps::default(), rng, None)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    CustomError::InvalidTrait("no token satisfies the dependency rules".to_string())
+                })?;
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Renders a preview set into `preview/` of the output folder, replacing the
+/// previous one, and returns the folder and the seed it was drawn with.
+pub fn render_preview(config: &Config, args: &PreviewArgs) -> Result<(PathBuf, u64), CustomError> {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let tokens = sample_tokens(config, args.count, &mut rng)?;
+    let watermark = match &args.watermark {
+        None => None,
+        Some(None) => Some(watermark_stamp(config)?),
+        Some(Some(path)) => {
+            Some(Stamp::centered(image::open(path).map_err(|e| {
+                CustomError::Config(format!("{}: {}", path, e))
+            })?))
+        }
+    };
+
+    let preview_dir = Path::new(&config.output_path).join(PREVIEW_DIR_NAME);
+    let write_error = |e: &dyn std::fmt::Display| {
+        CustomError::WriteOutput(format!("{}: {}", preview_dir.display(), e))
+    };
+    if preview_dir.exists() {
+        fs::remove_dir_all(&preview_dir).map_err(|e| write_error(&e))?;
+    }
+    fs::create_dir_all(&preview_dir).map_err(|e| write_error(&e))?;
+    let mut preview_config = config.clone();
+    preview_config.output_path = preview_dir.display().to_string();
+    file_names::use_plain_names(&mut preview_config);
+    let mut settings = render_settings(&preview_config)?;
+    settings.watermark = watermark.map(Arc::new);
+
+    for (index, image_paths) in tokens.into_iter().enumerate() {
+        let mut render = generate_image_and_metadata(
+            config.metadata.clone(),
+            image_paths.clone(),
+            index,
+            &settings,
+            &DecodedLayers::new(),
+        )
+        .map_err(|e| e.for_token(index, &image_paths))?;
+        render().map_err(|e| e.for_token(index, &image_paths))?;
+    }
+    Ok((preview_dir, seed))
+}
+
+/// Whether a file system event adds, changes or removes a layer image.
+fn is_layer_change(event: &Event) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    ) && event.paths.iter().any(|path| {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ALLOWED_EXTENSION.eq_ignore_ascii_case(ext))
+    })
+}
+
+/// Renders the preview again, with the same seed, every time a layer image
+/// in `basePath` changes, until the process is stopped.
+fn watch(args: &PreviewArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(Path::new(&config.base_path), RecursiveMode::Recursive)?;
+    info!("Watching {} for layer changes.", config.base_path);
+
+    while let Ok(event) = receiver.recv() {
+        if !event.as_ref().is_ok_and(is_layer_change) {
+            continue;
+        }
+        while receiver.recv_timeout(WATCH_SETTLE_TIME).is_ok() {}
+
+        // A file caught halfway through a save fails to load; the next event
+        // renders it again, so the error is only reported.
+        match render_preview(config, args) {
+            Ok((preview_dir, _)) => {
+                info!(
+                    "Layers changed, preview written to {}.",
+                    preview_dir.display()
+                )
+            }
+            Err(error) => warn!("Layers changed, preview failed: {}", error),
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: &PreviewArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let (preview_dir, seed) = render_preview(config, args)?;
+    info!(
+        "{} preview tokens drawn with seed {} written to {}.",
+        args.count,
+        seed,
+        preview_dir.display()
+    );
+    if args.watch {
+        let args = PreviewArgs {
+            seed: Some(seed),
+            ..args.clone()
+        };
+        watch(&args, config)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_layer_change() {
+        use notify::event::{AccessKind, CreateKind, ModifyKind};
+
+        let event = |kind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+        assert!(is_layer_change(&event(
+            EventKind::Modify(ModifyKind::Any),
+            "./images/Eyes/Laser#10.png"
+        )));
+        assert!(is_layer_change(&event(
+            EventKind::Create(CreateKind::File),
+            "./images/Eyes/Visor.PNG"
+        )));
+        assert!(!is_layer_change(&event(
+            EventKind::Access(AccessKind::Any),
+            "./images/Eyes/Laser#10.png"
+        )));
+        assert!(!is_layer_change(&event(
+            EventKind::Modify(ModifyKind::Any),
+            "./images/Eyes/notes.txt"
+        )));
+    }
+}
@@ -0,0 +1,99 @@
+use rayon::prelude::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use crate::{write_json, CustomError};
+
+pub const PROVENANCE_FILE_NAME: &str = "provenance.json";
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenHash {
+    pub token_id: usize,
+    pub hash: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    pub algorithm: String,
+    pub provenance_hash: String,
+    pub concatenated_hashes: String,
+    pub tokens: Vec<TokenHash>,
+}
+
+/// Hashes the images of the tokens, given in token id order with their image
+/// file.
+pub fn compute_provenance(
+    output_path: &Path,
+    images: &[(usize, String)],
+) -> Result<Provenance, CustomError> {
+    let tokens = images
+        .par_iter()
+        .map(|(token_id, image)| {
+            let token_id = *token_id;
+            let image_path = output_path.join(image);
+            let contents = fs::read(&image_path).map_err(|e| {
+                CustomError::WriteOutput(format!("{}: {}", image_path.display(), e))
+            })?;
+            Ok(TokenHash {
+                token_id,
+                hash: format!("{:x}", Sha256::digest(contents)),
+            })
+        })
+        .collect::<Result<Vec<TokenHash>, CustomError>>()?;
+
+    let concatenated_hashes: String = tokens.iter().map(|token| token.hash.as_str()).collect();
+    let provenance_hash = format!("{:x}", Sha256::digest(concatenated_hashes.as_bytes()));
+
+    Ok(Provenance {
+        algorithm: "sha256".to_string(),
+        provenance_hash,
+        concatenated_hashes,
+        tokens,
+    })
+}
+
+pub fn write_provenance(
+    output_path: &Path,
+    images: &[(usize, String)],
+) -> Result<Provenance, CustomError> {
+    let provenance = compute_provenance(output_path, images)?;
+    let file_path = output_path.join(PROVENANCE_FILE_NAME);
+    write_json(&file_path, &provenance)?;
+    Ok(provenance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compute_provenance() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("0.png"), b"first").unwrap();
+        fs::write(dir.path().join("1.png"), b"second").unwrap();
+        let mut images = vec![(0, "0.png".to_string()), (1, "1.png".to_string())];
+
+        let provenance = compute_provenance(dir.path(), &images).unwrap();
+
+        let first = format!("{:x}", Sha256::digest(b"first"));
+        let second = format!("{:x}", Sha256::digest(b"second"));
+        assert_eq!(provenance.tokens[0].hash, first);
+        assert_eq!(provenance.tokens[1].token_id, 1);
+        assert_eq!(
+            provenance.concatenated_hashes,
+            format!("{}{}", first, second)
+        );
+        assert_eq!(
+            provenance.provenance_hash,
+            format!("{:x}", Sha256::digest(format!("{}{}", first, second)))
+        );
+
+        images.push((2, "2.png".to_string()));
+        assert!(compute_provenance(dir.path(), &images).is_err());
+    }
+}
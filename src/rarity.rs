@@ -0,0 +1,192 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::paths::LayerLayout;
+use crate::{asset_weight, path_trait, write_json, CustomError};
+
+pub const RARITY_FILE_NAME: &str = "rarity.json";
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TraitRarity {
+    pub count: usize,
+    pub frequency: f64,
+    pub score: f64,
+    /// The `#N` weight the value is drawn with. Kept here rather than in the
+    /// attributes, where marketplaces don't expect it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<f64>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenRarity {
+    pub token_id: usize,
+    pub rank: usize,
+    pub score: f64,
+    pub normalized_score: f64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RarityReport {
+    pub total_supply: usize,
+    pub traits: BTreeMap<String, BTreeMap<String, TraitRarity>>,
+    pub tokens: Vec<TokenRarity>,
+}
+
+/// Scores every token as the sum of `1 / frequency` of its traits. The
+/// normalized score divides each trait's contribution by the number of values
+/// its layer has, so layers with many values don't dominate the ranking.
+/// Rank 1 is the rarest token; tokens with the same score share a rank.
+pub fn compute_rarity(layout: &LayerLayout, tokens: &[Vec<String>]) -> RarityReport {
+    let total_supply = tokens.len();
+    let token_traits: Vec<Vec<(String, String)>> = tokens
+        .iter()
+        .map(|paths| {
+            paths
+                .iter()
+                .filter_map(|path| path_trait(layout, path))
+                .collect()
+        })
+        .collect();
+
+    let mut counts: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for (trait_type, value) in token_traits.iter().flatten() {
+        *counts
+            .entry(trait_type.clone())
+            .or_default()
+            .entry(value.clone())
+            .or_default() += 1;
+    }
+    let weights: BTreeMap<(String, String), f64> = tokens
+        .iter()
+        .flatten()
+        .filter_map(|path| Some((path_trait(layout, path)?, asset_weight(layout, path)?)))
+        .collect();
+
+    let traits: BTreeMap<String, BTreeMap<String, TraitRarity>> = counts
+        .into_iter()
+        .map(|(trait_type, values)| {
+            let values = values
+                .into_iter()
+                .map(|(value, count)| {
+                    let frequency = count as f64 / total_supply as f64;
+                    let rarity = TraitRarity {
+                        count,
+                        frequency,
+                        score: 1.0 / frequency,
+                        weight: weights.get(&(trait_type.clone(), value.clone())).copied(),
+                    };
+                    (value, rarity)
+                })
+                .collect();
+            (trait_type, values)
+        })
+        .collect();
+
+    let mut scored: Vec<TokenRarity> = token_traits
+        .iter()
+        .enumerate()
+        .map(|(token_id, pairs)| {
+            let (score, normalized_score) =
+                pairs
+                    .iter()
+                    .fold((0.0, 0.0), |(score, normalized), (trait_type, value)| {
+                        let values = &traits[trait_type];
+                        let trait_score = values[value].score;
+                        (
+                            score + trait_score,
+                            normalized + trait_score / values.len() as f64,
+                        )
+                    });
+            TokenRarity {
+                token_id,
+                rank: 0,
+                score,
+                normalized_score,
+            }
+        })
+        .collect();
+
+    scored.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(a.token_id.cmp(&b.token_id))
+    });
+    let mut previous: Option<(f64, usize)> = None;
+    for (position, token) in scored.iter_mut().enumerate() {
+        token.rank = match previous {
+            Some((score, rank)) if score == token.score => rank,
+            _ => position + 1,
+        };
+        previous = Some((token.score, token.rank));
+    }
+    scored.sort_by_key(|token| token.token_id);
+
+    RarityReport {
+        total_supply,
+        traits,
+        tokens: scored,
+    }
+}
+
+/// The `rarity` field of a token's metadata, with `embedRarity`.
+pub fn embedded_rarity(token: &TokenRarity) -> Value {
+    json!({
+        "rank": token.rank,
+        "score": token.score,
+    })
+}
+
+pub fn write_rarity(output_path: &Path, report: &RarityReport) -> Result<(), CustomError> {
+    let file_path = output_path.join(RARITY_FILE_NAME);
+    write_json(&file_path, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_rarity() {
+        let token = |background: &str, eyes: &str| {
+            vec![
+                format!("./images/Background/{}#10.png", background),
+                format!("./images/Eyes/{}.png", eyes),
+            ]
+        };
+        let tokens = vec![
+            token("Blue", "Laser"),
+            token("Blue", "Round"),
+            token("Blue", "Round"),
+            token("Red", "Round"),
+        ];
+
+        let report = compute_rarity(&LayerLayout::default(), &tokens);
+
+        assert_eq!(report.total_supply, 4);
+        assert_eq!(report.traits["Background"]["Blue"].count, 3);
+        assert_eq!(report.traits["Background"]["Red"].frequency, 0.25);
+        assert_eq!(report.traits["Eyes"]["Laser"].score, 4.0);
+        assert_eq!(report.traits["Background"]["Red"].weight, Some(10.0));
+        assert_eq!(report.traits["Eyes"]["Laser"].weight, None);
+
+        let scores: Vec<f64> = report.tokens.iter().map(|token| token.score).collect();
+        assert_eq!(
+            scores,
+            vec![
+                4.0 / 3.0 + 4.0,
+                4.0 / 3.0 + 4.0 / 3.0,
+                4.0 / 3.0 + 4.0 / 3.0,
+                4.0 + 4.0 / 3.0
+            ]
+        );
+        assert_eq!(report.tokens[0].normalized_score, (4.0 / 3.0 + 4.0) / 2.0);
+
+        let ranks: Vec<usize> = report.tokens.iter().map(|token| token.rank).collect();
+        assert_eq!(ranks, vec![1, 3, 3, 1]);
+    }
+}
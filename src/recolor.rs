@@ -0,0 +1,220 @@
+use image::{DynamicImage, RgbaImage};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::palette::parse_color;
+use crate::paths::LayerLayout;
+use crate::{is_image_asset, path_trait, CustomError};
+
+/// Assets drawn once in grayscale and recolored with palettes at render time,
+/// every palette giving a trait value of its own.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Recolor {
+    /// Palettes by name, each a list of `#rrggbb` colors from the one the
+    /// darkest pixels get to the one the lightest get.
+    pub palettes: BTreeMap<String, Vec<String>>,
+    pub assets: Vec<RecoloredAsset>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoloredAsset {
+    pub layer: String,
+    pub value: String,
+    /// The palettes the asset is recolored with, and the weight of each.
+    pub palettes: BTreeMap<String, u64>,
+}
+
+/// The recolored assets of a run: the file and the colors of every one.
+#[derive(Debug, Default, Clone)]
+pub struct Recolors {
+    recolored: HashMap<String, (String, Vec<[u8; 3]>)>,
+}
+
+impl Recolors {
+    /// Replaces every recolored asset of `layers`, given in the order of
+    /// `names`, with one asset per palette, named `<palette> <value>`. Like
+    /// `None` assets they have no file, only a path carrying the layer, the
+    /// value and its weight.
+    pub fn compile(
+        layout: &LayerLayout,
+        recolor: &Recolor,
+        names: &[String],
+        layers: &mut [Vec<String>],
+    ) -> Result<Recolors, CustomError> {
+        let mut palettes = HashMap::new();
+        for (name, colors) in &recolor.palettes {
+            if name.is_empty() || name.contains(['.', '/', '#']) {
+                return Err(CustomError::InvalidTrait(format!(
+                    "recolor palette name {:?} can't be empty or contain '.', '/' or '#'",
+                    name
+                )));
+            }
+            if colors.is_empty() {
+                return Err(CustomError::InvalidTrait(format!(
+                    "recolor palette {} has no colors",
+                    name
+                )));
+            }
+            let colors = colors
+                .iter()
+                .map(|color| parse_color(color))
+                .collect::<Result<Vec<[u8; 3]>, CustomError>>()
+                .map_err(|_| {
+                    CustomError::InvalidTrait(format!(
+                        "recolor palette {} has a color that is not #rrggbb",
+                        name
+                    ))
+                })?;
+            palettes.insert(name.as_str(), colors);
+        }
+
+        let mut recolored = HashMap::new();
+        for asset in &recolor.assets {
+            let Some(layer) = names
+                .iter()
+                .position(|name| *name == asset.layer)
+                .map(|index| &mut layers[index])
+            else {
+                return Err(CustomError::InvalidTrait(format!(
+                    "recolor references unknown layer {}",
+                    asset.layer
+                )));
+            };
+            let Some(index) = layer.iter().position(|path| {
+                is_image_asset(path)
+                    && path_trait(layout, path).is_some_and(|(_, value)| value == asset.value)
+            }) else {
+                return Err(CustomError::InvalidTrait(format!(
+                    "recolor references {}/{}, which has no file",
+                    asset.layer, asset.value
+                )));
+            };
+            let file = layer.remove(index);
+            let folder = Path::new(&file).parent().unwrap_or(Path::new(""));
+            for (palette, weight) in &asset.palettes {
+                let Some(colors) = palettes.get(palette.as_str()) else {
+                    return Err(CustomError::InvalidTrait(format!(
+                        "recolor of {}/{} references unknown palette {}",
+                        asset.layer, asset.value, palette
+                    )));
+                };
+                let path = folder
+                    .join(format!("{} {}#{}", palette, asset.value, weight))
+                    .display()
+                    .to_string();
+                layer.push(path.clone());
+                recolored.insert(path, (file.clone(), colors.clone()));
+            }
+        }
+        Ok(Recolors { recolored })
+    }
+
+    /// Renders the recolored asset `path` from its file, read with `load`, or
+    /// returns `None` when `path` isn't one.
+    pub fn render(
+        &self,
+        path: &str,
+        load: impl FnOnce(&str) -> Result<DynamicImage, CustomError>,
+    ) -> Option<Result<DynamicImage, CustomError>> {
+        let (file, colors) = self.recolored.get(path)?;
+        Some(load(file).map(|image| DynamicImage::ImageRgba8(recolor_image(&image, colors))))
+    }
+}
+
+/// Maps the luminance of every pixel of `image` onto the gradient through
+/// `colors`, keeping its alpha.
+pub fn recolor_image(image: &DynamicImage, colors: &[[u8; 3]]) -> RgbaImage {
+    let mut recolored = image.to_rgba8();
+    let stops = (colors.len() - 1) as f32;
+    for pixel in recolored.pixels_mut() {
+        let [r, g, b, _] = pixel.0;
+        let luminance = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) / 255.0;
+        let position = luminance * stops;
+        let index = (position.floor() as usize).min(colors.len().saturating_sub(2));
+        let (from, to) = (colors[index], colors[(index + 1).min(colors.len() - 1)]);
+        let t = position - index as f32;
+        for channel in 0..3 {
+            pixel.0[channel] = (from[channel] as f32
+                + (to[channel] as f32 - from[channel] as f32) * t)
+                .round() as u8;
+        }
+    }
+    recolored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_recolor() {
+        let recolor: Recolor = serde_json::from_str(
+            r##"{
+                "palettes": {"Fire": ["#400000", "#ff8000"], "Ice": ["#0000ff"]},
+                "assets": [{"layer": "Hair", "value": "Mohawk", "palettes": {"Fire": 3, "Ice": 1}}]
+            }"##,
+        )
+        .unwrap();
+        let layout = LayerLayout::new("./images");
+        let names = vec!["Hair".to_string()];
+        let mut layers = vec![vec![
+            "./images/Hair/Mohawk#10.png".to_string(),
+            "./images/Hair/Bald#5.png".to_string(),
+        ]];
+        let recolors = Recolors::compile(&layout, &recolor, &names, &mut layers).unwrap();
+        assert_eq!(
+            layers[0],
+            vec![
+                "./images/Hair/Bald#5.png".to_string(),
+                Path::new("./images/Hair")
+                    .join("Fire Mohawk#3")
+                    .display()
+                    .to_string(),
+                Path::new("./images/Hair")
+                    .join("Ice Mohawk#1")
+                    .display()
+                    .to_string(),
+            ]
+        );
+        assert_eq!(
+            path_trait(&layout, &layers[0][1]),
+            Some(("Hair".to_string(), "Fire Mohawk".to_string()))
+        );
+
+        let base = RgbaImage::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 128])
+            }
+        });
+        let fire = recolors
+            .render(&layers[0][1], |file| {
+                assert_eq!(file, "./images/Hair/Mohawk#10.png");
+                Ok(DynamicImage::ImageRgba8(base.clone()))
+            })
+            .unwrap()
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(fire.get_pixel(0, 0), &Rgba([0x40, 0, 0, 255]));
+        assert_eq!(fire.get_pixel(1, 0), &Rgba([0xff, 0x80, 0, 128]));
+        let ice = recolor_image(&DynamicImage::ImageRgba8(base), &[[0, 0, 255]]);
+        assert_eq!(ice.get_pixel(1, 0), &Rgba([0, 0, 255, 128]));
+        assert!(recolors.render(&layers[0][0], |_| unreachable!()).is_none());
+
+        for invalid in [
+            r##"{"palettes": {}, "assets": [{"layer": "Hair", "value": "Mohawk", "palettes": {"Fire": 1}}]}"##,
+            r##"{"palettes": {"Fire": ["red"]}, "assets": []}"##,
+            r##"{"palettes": {"Fire": ["#ff0000"]}, "assets": [{"layer": "Hat", "value": "Cap", "palettes": {"Fire": 1}}]}"##,
+        ] {
+            let invalid: Recolor = serde_json::from_str(invalid).unwrap();
+            let mut layers = vec![vec!["./images/Hair/Mohawk#10.png".to_string()]];
+            assert!(Recolors::compile(&layout, &invalid, &names, &mut layers).is_err());
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use clap::Args;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use crate::manifest::{self, MANIFEST_FILE_NAME};
+use crate::metadata::MetadataProfile;
+use crate::{write_json, Config, CustomError};
+
+#[derive(Debug, Args)]
+pub struct RenameTraitsArgs {
+    /// JSON file mapping old trait values to new ones.
+    pub mapping: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum RenameRule {
+    Value(String),
+    Scoped(HashMap<String, String>),
+}
+
+/// `displayNames` of the config: the values shown in the metadata instead of
+/// the file names, as a mapping like the one of `rename-traits` or the path of
+/// a JSON file holding it.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum DisplayNames {
+    File(String),
+    Table(HashMap<String, RenameRule>),
+}
+
+impl DisplayNames {
+    /// The mapping, read from its file when it's one.
+    pub fn rules(&self) -> Result<HashMap<String, RenameRule>, CustomError> {
+        match self {
+            DisplayNames::File(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| CustomError::Config(format!("displayNames {}: {}", path, e)))?;
+                serde_json::from_str(&contents)
+                    .map_err(|e| CustomError::Config(format!("displayNames {}: {}", path, e)))
+            }
+            DisplayNames::Table(rules) => Ok(rules.clone()),
+        }
+    }
+
+    pub fn load(&self) -> Result<TraitRenames, CustomError> {
+        Ok(TraitRenames::from_rules(self.rules()?))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct TraitRenames {
+    global: HashMap<String, String>,
+    scoped: HashMap<String, HashMap<String, String>>,
+}
+
+impl TraitRenames {
+    pub fn from_json(contents: &str) -> Result<TraitRenames, serde_json::Error> {
+        Ok(TraitRenames::from_rules(serde_json::from_str(contents)?))
+    }
+
+    pub fn from_rules(rules: HashMap<String, RenameRule>) -> TraitRenames {
+        let mut renames = TraitRenames::default();
+        for (key, rule) in rules {
+            match rule {
+                RenameRule::Value(new_value) => {
+                    renames.global.insert(key, new_value);
+                }
+                RenameRule::Scoped(values) => {
+                    renames.scoped.insert(key, values);
+                }
+            }
+        }
+        renames
+    }
+
+    /// The new value of `value`, a value of `trait_type`, when a rule matches.
+    pub fn rename(&self, trait_type: &str, value: &str) -> Option<&String> {
+        self.scoped
+            .get(trait_type)
+            .and_then(|values| values.get(value))
+            .or_else(|| self.global.get(value))
+    }
+
+    /// The rule renaming `value` of `trait_type`, as `value` or
+    /// `trait_type/value`, whether or not there is one.
+    fn rule(&self, trait_type: &str, value: &str) -> String {
+        if self
+            .scoped
+            .get(trait_type)
+            .is_some_and(|values| values.contains_key(value))
+        {
+            format!("{}/{}", trait_type, value)
+        } else {
+            value.to_string()
+        }
+    }
+
+    fn rules(&self) -> BTreeSet<String> {
+        let scoped = self.scoped.iter().flat_map(|(trait_type, values)| {
+            values
+                .keys()
+                .map(move |value| format!("{}/{}", trait_type, value))
+        });
+        self.global.keys().cloned().chain(scoped).collect()
+    }
+}
+
+/// Renames the attribute values of a metadata document in place and returns the
+/// rules that matched, as `value` or `trait_type/value`.
+pub fn rename_attributes(
+    metadata: &mut Value,
+    renames: &TraitRenames,
+    profile: &MetadataProfile,
+) -> BTreeSet<String> {
+    let mut applied = BTreeSet::new();
+    let Some(attributes) = metadata.get_mut("attributes").and_then(Value::as_array_mut) else {
+        return applied;
+    };
+
+    for attribute in attributes {
+        let trait_type = profile
+            .trait_type(attribute)
+            .unwrap_or_default()
+            .to_string();
+        let Some(value) = profile.value(attribute).and_then(Value::as_str) else {
+            continue;
+        };
+
+        let rule = renames.rule(&trait_type, value);
+        if let Some(new_value) = renames.rename(&trait_type, value) {
+            attribute[&profile.value_key] = Value::from(new_value.clone());
+            applied.insert(rule);
+        }
+    }
+
+    applied
+}
+
+/// Renames the trait values the tokens of `manifest.json` in `output_path`
+/// were recorded with, so the manifest agrees with the metadata, and returns
+/// the rules that matched. An output without a manifest has none to rename.
+fn rename_manifest_values(
+    output_path: &Path,
+    renames: &TraitRenames,
+) -> Result<BTreeSet<String>, CustomError> {
+    let mut applied = BTreeSet::new();
+    if !output_path.join(MANIFEST_FILE_NAME).exists() {
+        return Ok(applied);
+    }
+    let mut token_manifest = manifest::read_manifest(output_path)?;
+    for asset in token_manifest
+        .tokens
+        .iter_mut()
+        .flat_map(|token| &mut token.assets)
+    {
+        if let Some(new_value) = renames.rename(&asset.trait_type, &asset.value) {
+            applied.insert(renames.rule(&asset.trait_type, &asset.value));
+            asset.value = new_value.clone();
+        }
+    }
+    if !applied.is_empty() {
+        manifest::write_manifest(output_path, &token_manifest)?;
+    }
+    Ok(applied)
+}
+
+/// Renames the trait values of every metadata file in `output_path` and of
+/// its manifest, and returns how many files were rewritten and the rules
+/// that matched.
+pub fn rename_traits_in_dir(
+    output_path: &Path,
+    renames: &TraitRenames,
+    profile: &MetadataProfile,
+) -> Result<(usize, BTreeSet<String>), CustomError> {
+    let mut rewritten = 0;
+    let mut applied = BTreeSet::new();
+
+    for entry in WalkDir::new(output_path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+    {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "json")
+            || path == output_path.join(MANIFEST_FILE_NAME)
+        {
+            continue;
+        }
+
+        let contents = fs::read_to_string(path)
+            .map_err(|e| CustomError::WriteOutput(format!("{}: {}", path.display(), e)))?;
+        let Ok(mut metadata) = serde_json::from_str::<Value>(&contents) else {
+            continue;
+        };
+
+        let applied_here = rename_attributes(&mut metadata, renames, profile);
+        if applied_here.is_empty() {
+            continue;
+        }
+
+        write_json(path, &metadata)?;
+        rewritten += 1;
+        applied.extend(applied_here);
+    }
+
+    let applied_in_manifest = rename_manifest_values(output_path, renames)?;
+    if !applied_in_manifest.is_empty() {
+        rewritten += 1;
+        applied.extend(applied_in_manifest);
+    }
+
+    Ok((rewritten, applied))
+}
+
+pub fn run(args: &RenameTraitsArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let contents = fs::read_to_string(&args.mapping)
+        .map_err(|e| CustomError::InvalidTrait(format!("{}: {}", args.mapping, e)))?;
+    let renames = TraitRenames::from_json(&contents)
+        .map_err(|e| CustomError::InvalidTrait(format!("{}: {}", args.mapping, e)))?;
+
+    let (rewritten, applied) = rename_traits_in_dir(
+        Path::new(&config.output_path),
+        &renames,
+        &config.metadata_profile,
+    )?;
+    info!(
+        "Renamed trait values in {} metadata and manifest files.",
+        rewritten
+    );
+
+    for unused in renames.rules().difference(&applied) {
+        warn!("rename rule {} matched no attribute.", unused);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dna::DnaAlgorithm;
+    use crate::paths::LayerLayout;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rename_attributes() {
+        let renames = TraitRenames::from_json(
+            r#"{"blu_bg_v2_final": "Ocean Blue", "Eyes": {"Laser": "Laser Eyes"}}"#,
+        )
+        .unwrap();
+        let mut metadata = json!({
+            "name": "Test",
+            "attributes": [
+                {"trait_type": "Background", "value": "blu_bg_v2_final"},
+                {"trait_type": "Eyes", "value": "Laser"},
+                {"trait_type": "Mouth", "value": "Laser"}
+            ]
+        });
+
+        let applied = rename_attributes(&mut metadata, &renames, &MetadataProfile::default());
+
+        assert_eq!(metadata["attributes"][0]["value"], "Ocean Blue");
+        assert_eq!(metadata["attributes"][1]["value"], "Laser Eyes");
+        assert_eq!(metadata["attributes"][2]["value"], "Laser");
+        assert_eq!(
+            applied,
+            BTreeSet::from(["blu_bg_v2_final".to_string(), "Eyes/Laser".to_string()])
+        );
+
+        let display_names: DisplayNames =
+            serde_json::from_str(r#"{"Eyes": {"Laser": "Laser Eyes"}}"#).unwrap();
+        let display_names = display_names.load().unwrap();
+        assert_eq!(
+            display_names.rename("Eyes", "Laser"),
+            Some(&"Laser Eyes".to_string())
+        );
+        assert_eq!(display_names.rename("Mouth", "Laser"), None);
+        let missing = DisplayNames::File("./missing.json".to_string());
+        assert!(missing.load().is_err());
+    }
+
+    #[test]
+    fn test_rename_traits_in_dir() {
+        let dir = tempdir().unwrap();
+        fs::write(
+            dir.path().join("0.json"),
+            r#"{"attributes": [{"trait_type": "Background", "value": "Blue"}]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("dna.json"),
+            r#"[{"tokenId": 0, "dna": "aa"}]"#,
+        )
+        .unwrap();
+
+        let renames = TraitRenames::from_json(r#"{"Blue": "Ocean", "Red": "Lava"}"#).unwrap();
+        let (rewritten, applied) =
+            rename_traits_in_dir(dir.path(), &renames, &MetadataProfile::default()).unwrap();
+
+        assert_eq!(rewritten, 1);
+        assert_eq!(applied, BTreeSet::from(["Blue".to_string()]));
+        assert_eq!(
+            renames.rules().difference(&applied).collect::<Vec<_>>(),
+            vec!["Red"]
+        );
+
+        let metadata: Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("0.json")).unwrap()).unwrap();
+        assert_eq!(metadata["attributes"][0]["value"], "Ocean");
+
+        let layer_dir = dir.path().join("images").join("Background");
+        fs::create_dir_all(&layer_dir).unwrap();
+        let red = layer_dir.join("Red.png").display().to_string();
+        fs::write(&red, b"red").unwrap();
+        let token_manifest = manifest::build_manifest(
+            DnaAlgorithm::Sha256,
+            &LayerLayout::default(),
+            &[("bb".to_string(), vec![red])],
+        )
+        .unwrap();
+        manifest::write_manifest(dir.path(), &token_manifest).unwrap();
+
+        let (rewritten, applied) =
+            rename_traits_in_dir(dir.path(), &renames, &MetadataProfile::default()).unwrap();
+        assert_eq!(rewritten, 1);
+        assert_eq!(applied, BTreeSet::from(["Red".to_string()]));
+        let renamed = manifest::read_manifest(dir.path()).unwrap();
+        assert_eq!(renamed.tokens[0].assets[0].value, "Lava");
+        assert_eq!(
+            renamed.tokens[0].assets[0].file,
+            token_manifest.tokens[0].assets[0].file
+        );
+    }
+}
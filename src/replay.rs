@@ -0,0 +1,218 @@
+use clap::Args;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::Path;
+use tracing::info;
+use xxhash_rust::xxh64::xxh64;
+
+use crate::dna::{self, DnaIndex};
+use crate::paths::LayerLayout;
+use crate::{layer_layout, load_layers, path_trait, plan_collection, Config, CustomError};
+
+pub const REPLAY_FILE_NAME: &str = "replay.json.gz";
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// Replay log to verify, instead of the one in the output folder.
+    #[arg(long)]
+    pub log: Option<String>,
+}
+
+/// One pick of an asset while sampling a token.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Draw {
+    pub layer: String,
+    /// xxh64 of the `layer=value` of the candidates, in order.
+    pub pool: String,
+    pub candidates: usize,
+    /// Index of the picked asset among the candidates.
+    pub index: usize,
+}
+
+/// Every draw of a run, in order, with the seed that made them.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayLog {
+    pub seed: u64,
+    pub draws: Vec<Draw>,
+}
+
+impl ReplayLog {
+    pub fn new(seed: u64) -> Self {
+        ReplayLog {
+            seed,
+            draws: Vec::new(),
+        }
+    }
+
+    /// Records the pick of `chosen` among `candidates`.
+    pub fn record(&mut self, layout: &LayerLayout, candidates: &[String], chosen: &str) {
+        // The pool is hashed by trait, not by path, so the log can be replayed
+        // from another base path.
+        let pool: Vec<String> = dna::trait_pairs(layout, candidates)
+            .into_iter()
+            .map(|(layer, value)| format!("{}={}", layer, value))
+            .collect();
+        self.draws.push(Draw {
+            layer: path_trait(layout, chosen)
+                .map(|(layer, _)| layer)
+                .unwrap_or_default(),
+            pool: format!("{:016x}", xxh64(pool.join("\n").as_bytes(), 0)),
+            candidates: candidates.len(),
+            index: candidates
+                .iter()
+                .position(|candidate| candidate == chosen)
+                .unwrap_or_default(),
+        });
+    }
+
+    /// Index of the first draw that isn't the same in both logs.
+    pub fn first_difference(&self, other: &ReplayLog) -> Option<usize> {
+        self.draws
+            .iter()
+            .zip(&other.draws)
+            .position(|(a, b)| a != b)
+            .or_else(|| {
+                (self.draws.len() != other.draws.len())
+                    .then(|| self.draws.len().min(other.draws.len()))
+            })
+    }
+}
+
+pub fn write_replay(output_path: &Path, log: &ReplayLog) -> Result<(), CustomError> {
+    let file_path = output_path.join(REPLAY_FILE_NAME);
+    let write_error = |e: &dyn std::fmt::Display| {
+        CustomError::WriteOutput(format!("{}: {}", file_path.display(), e))
+    };
+    let file = File::create(&file_path).map_err(|e| write_error(&e))?;
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    serde_json::to_writer(&mut encoder, log).map_err(|e| write_error(&e))?;
+    encoder.finish().map_err(|e| write_error(&e))?;
+    Ok(())
+}
+
+pub fn read_replay(path: &Path) -> Result<ReplayLog, CustomError> {
+    let file =
+        File::open(path).map_err(|e| CustomError::Replay(format!("{}: {}", path.display(), e)))?;
+    serde_json::from_reader(GzDecoder::new(file))
+        .map_err(|e| CustomError::Replay(format!("{}: {}", path.display(), e)))
+}
+
+/// Samples the collection again with the seed of the log, and checks that
+/// every draw and the DNA of every token in `dna.json` are the same.
+pub fn run(args: &ReplayArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let output_path = Path::new(&config.output_path);
+    let log_path = match &args.log {
+        Some(log) => Path::new(log).to_path_buf(),
+        None => output_path.join(REPLAY_FILE_NAME),
+    };
+    let logged = read_replay(&log_path)?;
+
+    let mut replay_config = config.clone();
+    replay_config.seed = Some(logged.seed);
+    let mut rng = ChaCha8Rng::seed_from_u64(logged.seed);
+    let mut replayed = ReplayLog::new(logged.seed);
+    let all_layers = load_layers(&replay_config)?;
+    let plan = plan_collection(&replay_config, &all_layers, &mut rng, Some(&mut replayed))?;
+
+    if let Some(index) = logged.first_difference(&replayed) {
+        let describe = |draw: Option<&Draw>| match draw {
+            Some(draw) => format!(
+                "{} #{} of {} (pool {})",
+                draw.layer, draw.index, draw.candidates, draw.pool
+            ),
+            None => "nothing".to_string(),
+        };
+        return Err(CustomError::Replay(format!(
+            "draw {} differs: the log has {}, seed {} gives {}",
+            index,
+            describe(logged.draws.get(index)),
+            logged.seed,
+            describe(replayed.draws.get(index))
+        ))
+        .into());
+    }
+
+    let layout = layer_layout(config);
+    let dna_path = output_path.join(dna::DNA_FILE_NAME);
+    let contents = fs::read_to_string(&dna_path)
+        .map_err(|e| CustomError::Replay(format!("{}: {}", dna_path.display(), e)))?;
+    let written: DnaIndex = serde_json::from_str(&contents)
+        .map_err(|e| CustomError::Replay(format!("{}: {}", dna_path.display(), e)))?;
+    let written: HashMap<usize, String> = written
+        .tokens
+        .into_iter()
+        .map(|entry| (entry.token_id, entry.dna))
+        .collect();
+    for (token_id, (_, image_paths)) in plan.token_ids.iter().zip(&plan.permutations) {
+        let replayed_dna = dna::compute_dna(config.dna_algorithm, &layout, image_paths);
+        if written.get(token_id) != Some(&replayed_dna) {
+            return Err(CustomError::Replay(format!(
+                "token {} in {} doesn't have the DNA seed {} gives",
+                token_id,
+                dna_path.display(),
+                logged.seed
+            ))
+            .into());
+        }
+    }
+    if written.len() != plan.token_ids.len() {
+        return Err(CustomError::Replay(format!(
+            "{} has {} tokens, seed {} gives {}",
+            dna_path.display(),
+            written.len(),
+            logged.seed,
+            plan.token_ids.len()
+        ))
+        .into());
+    }
+
+    info!(
+        "Replay verified: {} draws and {} token DNAs match seed {}.",
+        logged.draws.len(),
+        written.len(),
+        logged.seed
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_replay_log() {
+        let layer = vec![
+            "./images/Background/Blue#50.png".to_string(),
+            "./images/Background/Red#50.png".to_string(),
+        ];
+        let layout = LayerLayout::new("./images");
+        let mut log = ReplayLog::new(42);
+        log.record(&layout, &layer, &layer[1]);
+        assert_eq!(log.draws[0].layer, "Background");
+        assert_eq!(log.draws[0].index, 1);
+        assert_eq!(log.draws[0].candidates, 2);
+
+        let dir = tempdir().unwrap();
+        write_replay(dir.path(), &log).unwrap();
+        let read = read_replay(&dir.path().join(REPLAY_FILE_NAME)).unwrap();
+        assert_eq!(read, log);
+        assert_eq!(read.first_difference(&log), None);
+
+        let mut other = ReplayLog::new(42);
+        other.record(&layout, &layer, &layer[0]);
+        assert_eq!(log.first_difference(&other), Some(0));
+        other.draws[0] = log.draws[0].clone();
+        other.record(&layout, &layer, &layer[0]);
+        assert_eq!(log.first_difference(&other), Some(1));
+    }
+}
@@ -0,0 +1,195 @@
+use clap::Args;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+use tracing::info;
+
+use crate::manifest::{self, Manifest, TokenRecord};
+use crate::{
+    collection_token_ids, is_file_asset, load_asset_image, path_trait, provenance, render_settings,
+    write_token_image, Config, CustomError, RenderSettings,
+};
+
+#[derive(Debug, Args)]
+pub struct RerenderChangedArgs {
+    /// List the changed assets and the tokens using them without rendering anything.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Assets whose contents no longer match the hash recorded in the manifest,
+/// with their current hash.
+pub fn changed_assets(manifest: &Manifest) -> Result<BTreeMap<String, String>, CustomError> {
+    let recorded: HashMap<&String, &String> = manifest
+        .tokens
+        .iter()
+        .flat_map(|token| &token.assets)
+        .filter(|asset| is_file_asset(&asset.file))
+        .map(|asset| (&asset.file, &asset.hash))
+        .collect();
+
+    if let Some(missing) = recorded.keys().find(|file| !Path::new(file).is_file()) {
+        return Err(CustomError::Rerender(format!(
+            "{} no longer exists, run generate instead",
+            missing
+        )));
+    }
+
+    let current = manifest::hash_assets(recorded.keys().copied())?;
+    Ok(current
+        .into_iter()
+        .filter(|(file, hash)| recorded[file] != hash)
+        .collect())
+}
+
+pub fn affected_tokens<'a>(
+    manifest: &'a Manifest,
+    changed: &BTreeMap<String, String>,
+) -> Vec<&'a TokenRecord> {
+    manifest
+        .tokens
+        .iter()
+        .filter(|token| {
+            token
+                .assets
+                .iter()
+                .any(|asset| changed.contains_key(&asset.file))
+        })
+        .collect()
+}
+
+fn rerender_token(token: &TokenRecord, settings: &RenderSettings) -> Result<(), CustomError> {
+    let layers = token
+        .assets
+        .iter()
+        .map(|asset| load_asset_image(&asset.file, settings))
+        .collect::<Result<Vec<_>, CustomError>>()?;
+    // The traits are the ones of the files, which the settings are keyed by,
+    // whatever `rename-traits` made of the recorded values since.
+    let traits: Vec<(String, String)> = token
+        .assets
+        .iter()
+        .map(|asset| {
+            path_trait(&settings.layout, &asset.file)
+                .unwrap_or_else(|| (asset.trait_type.clone(), asset.value.clone()))
+        })
+        .collect();
+    write_token_image(
+        layers
+            .iter()
+            .zip(&traits)
+            .filter_map(|(layer, (trait_type, value))| {
+                Some((layer.as_ref()?, trait_type.as_str(), value.as_str()))
+            }),
+        &settings.file_names.image(token.token_id, &token.dna),
+        settings,
+    )
+}
+
+pub fn run(args: &RerenderChangedArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let output_path = Path::new(&config.output_path);
+    let mut manifest = manifest::read_manifest(output_path)?;
+
+    let changed = changed_assets(&manifest)?;
+    if changed.is_empty() {
+        info!("No asset changed since the last run.");
+        return Ok(());
+    }
+    for file in changed.keys() {
+        info!("Changed: {}", file);
+    }
+
+    let affected = affected_tokens(&manifest, &changed);
+    let token_ids: Vec<String> = affected
+        .iter()
+        .map(|token| token.token_id.to_string())
+        .collect();
+    info!(
+        "{} tokens use a changed asset: {}.",
+        affected.len(),
+        token_ids.join(", ")
+    );
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let settings = render_settings(config)?;
+    affected
+        .par_iter()
+        .try_for_each(|token| rerender_token(token, &settings))?;
+    let rendered: HashSet<usize> = affected.iter().map(|token| token.token_id).collect();
+    let now = manifest::now();
+    for token in &mut manifest.tokens {
+        if rendered.contains(&token.token_id) {
+            token.rendered_at = now.clone();
+        }
+    }
+    manifest.updated_at = now;
+
+    for asset in manifest
+        .tokens
+        .iter_mut()
+        .flat_map(|token| &mut token.assets)
+    {
+        if let Some(hash) = changed.get(&asset.file) {
+            asset.hash = hash.clone();
+        }
+    }
+    manifest::write_manifest(output_path, &manifest)?;
+
+    let provenance = provenance::write_provenance(
+        output_path,
+        &settings
+            .file_names
+            .images(collection_token_ids(config), &manifest.token_dnas())?,
+    )?;
+    info!("Provenance hash: {}", provenance.provenance_hash);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dna::DnaAlgorithm;
+    use crate::paths::LayerLayout;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_changed_assets() {
+        let dir = tempdir().unwrap();
+        let layer_dir = dir.path().join("images").join("Background");
+        fs::create_dir_all(&layer_dir).unwrap();
+        let blue = layer_dir.join("Blue.png").display().to_string();
+        let red = layer_dir.join("Red.png").display().to_string();
+        fs::write(&blue, b"blue").unwrap();
+        fs::write(&red, b"red").unwrap();
+
+        let manifest = manifest::build_manifest(
+            DnaAlgorithm::Sha256,
+            &LayerLayout::new(&dir.path().join("images").display().to_string()),
+            &[
+                ("aa".to_string(), vec![blue.clone()]),
+                ("bb".to_string(), vec![red.clone()]),
+                ("cc".to_string(), vec![blue.clone()]),
+            ],
+        )
+        .unwrap();
+        assert!(changed_assets(&manifest).unwrap().is_empty());
+
+        fs::write(&blue, b"darker blue").unwrap();
+        let changed = changed_assets(&manifest).unwrap();
+        assert_eq!(changed.keys().collect::<Vec<_>>(), vec![&blue]);
+
+        let token_ids: Vec<usize> = affected_tokens(&manifest, &changed)
+            .iter()
+            .map(|token| token.token_id)
+            .collect();
+        assert_eq!(token_ids, vec![0, 2]);
+
+        fs::remove_file(&red).unwrap();
+        assert!(changed_assets(&manifest).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use clap::Args;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use crate::dna::{self, DnaAlgorithm};
+use crate::manifest::{self, Manifest, TokenRecord};
+use crate::paths::LayerLayout;
+use crate::permutations::{self, Constraints};
+use crate::rules::Rules;
+use crate::{
+    collection_token_ids, compile_caps, find_unused_assets, generate_image_and_metadata,
+    generate_permutations, is_none_asset, layer_layout, layer_names, load_layers, localization,
+    path_trait, previous_dna, provenance, rarity, render_settings, skipped_trait_regexes, stats,
+    token_metadata, unique_combinations, unused, write_json, Config, CustomError, DecodedLayers,
+};
+
+#[derive(Debug, Args)]
+pub struct RerollArgs {
+    /// Token ids to re-roll, separated by commas.
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub ids: Vec<usize>,
+    /// Seed of the new draws, instead of a random one.
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+/// Asset paths of a manifest token, one per non-empty layer: layers it has no
+/// asset of took their `None` asset. The paths must give the DNA the token
+/// was generated with, or the layers changed since.
+pub fn token_paths(
+    token: &TokenRecord,
+    all_layers: &[Vec<String>],
+    dna_algorithm: DnaAlgorithm,
+    layout: &LayerLayout,
+) -> Result<Vec<String>, CustomError> {
+    let paths: Vec<String> = all_layers
+        .iter()
+        .filter_map(|layer| {
+            layer
+                .iter()
+                .find(|path| token.assets.iter().any(|asset| &asset.file == *path))
+                .or_else(|| layer.iter().find(|path| is_none_asset(path)))
+                .cloned()
+        })
+        .collect();
+    if dna::compute_dna(dna_algorithm, layout, &paths) != token.dna {
+        return Err(CustomError::Reroll(format!(
+            "the layers of token {} changed since it was generated, run generate instead",
+            token.token_id
+        )));
+    }
+    Ok(paths)
+}
+
+/// Ids of the tokens of `manifest` that can be re-rolled: the generated ones,
+/// but for the fixed tokens and the unique combinations, which must stay.
+pub fn rerollable_ids(
+    config: &Config,
+    manifest: &Manifest,
+    all_layers: &[Vec<String>],
+) -> Result<BTreeSet<usize>, CustomError> {
+    let layout = layer_layout(config);
+    let unique_dna: BTreeSet<String> = unique_combinations(config, all_layers)?
+        .iter()
+        .map(|paths| dna::compute_dna(config.dna_algorithm, &layout, paths))
+        .collect();
+    Ok(manifest
+        .tokens
+        .iter()
+        .filter(|token| {
+            !unique_dna.contains(&token.dna)
+                && !config
+                    .fixed_tokens
+                    .iter()
+                    .any(|fixed| fixed.token_id == token.token_id)
+        })
+        .map(|token| token.token_id)
+        .collect())
+}
+
+/// Writes the new rarity into the metadata file of a token that kept its
+/// combination.
+fn update_embedded_rarity(
+    output_path: &Path,
+    file_name: &str,
+    token: &rarity::TokenRarity,
+) -> Result<(), CustomError> {
+    let file_path = output_path.join(file_name);
+    let write_error = |e: &dyn std::fmt::Display| {
+        CustomError::WriteOutput(format!("{}: {}", file_path.display(), e))
+    };
+    let contents = fs::read_to_string(&file_path).map_err(|e| write_error(&e))?;
+    let mut metadata: Value = serde_json::from_str(&contents).map_err(|e| write_error(&e))?;
+    metadata["rarity"] = rarity::embedded_rarity(token);
+    write_json(&file_path, &metadata)
+}
+
+/// Replaces the combination of every token in `args.ids` with a new one no
+/// token of the collection, or of the previous runs, has had. The rules and
+/// caps still hold for the whole collection, counting the tokens kept.
+pub fn run(args: &RerollArgs, config: &Config) -> Result<(), Box<dyn Error>> {
+    let output_path = Path::new(&config.output_path);
+    let mut manifest = manifest::read_manifest(output_path)?;
+    let ids: BTreeSet<usize> = args.ids.iter().copied().collect();
+    for token_id in &ids {
+        if config
+            .fixed_tokens
+            .iter()
+            .any(|fixed| fixed.token_id == *token_id)
+        {
+            return Err(CustomError::Reroll(format!("token {} is a fixed token", token_id)).into());
+        }
+        if !manifest
+            .tokens
+            .iter()
+            .any(|token| token.token_id == *token_id)
+        {
+            return Err(CustomError::Reroll(format!(
+                "token {} is not a generated token of {}",
+                token_id,
+                output_path.join(manifest::MANIFEST_FILE_NAME).display()
+            ))
+            .into());
+        }
+    }
+
+    let all_layers = load_layers(config)?;
+    let layout = layer_layout(config);
+    let rerollable = rerollable_ids(config, &manifest, &all_layers)?;
+    if let Some(token_id) = ids.iter().find(|token_id| !rerollable.contains(token_id)) {
+        return Err(
+            CustomError::Reroll(format!("token {} is a unique combination", token_id)).into(),
+        );
+    }
+    let mut token_paths = manifest
+        .tokens
+        .iter()
+        .map(|token| token_paths(token, &all_layers, config.dna_algorithm, &layout))
+        .collect::<Result<Vec<Vec<String>>, CustomError>>()?;
+
+    let skipped_traits = skipped_trait_regexes(config)?;
+    let names = layer_names(config);
+    // The discarded combinations are excluded too, so every re-rolled token
+    // gets one it never had.
+    let (mut excluded_dna, _) = previous_dna(config)?;
+    excluded_dna.extend(manifest.historical_dna.iter().cloned());
+    excluded_dna.extend(manifest.tokens.iter().map(|token| token.dna.clone()));
+    let constraints = Constraints {
+        excluded_dna,
+        dna_algorithm: config.dna_algorithm,
+        rules: Rules::compile(&config.dependency_rules, &config.trait_tags, &names)?,
+        layout: layout.clone(),
+    };
+    let mut caps = compile_caps(config, &all_layers, &skipped_traits, &names)?;
+    for (token, paths) in manifest.tokens.iter().zip(&token_paths) {
+        if !ids.contains(&token.token_id) {
+            let traits: Vec<(String, String)> = paths
+                .iter()
+                .filter_map(|path| path_trait(&layout, path))
+                .collect();
+            caps.record(&traits);
+        }
+    }
+
+    let count =
+        permutations::count_permutations(&names, &all_layers, &skipped_traits, &constraints);
+    if count.possible < ids.len() {
+        return Err(
+            CustomError::InvalidTotalSupply(ids.len() as u64, count.possible as u64).into(),
+        );
+    }
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let layers = permutations::without_skipped(&all_layers, &skipped_traits);
+    caps.check_reachable(&layout, &layers, ids.len())?;
+    let mut rerolled =
+        generate_permutations(&layers, ids.len(), &constraints, &mut caps, &mut rng, None)?;
+    config
+        .token_order
+        .sort(&mut rerolled, config.dna_algorithm, &layout);
+
+    let positions: Vec<usize> = manifest
+        .tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| ids.contains(&token.token_id))
+        .map(|(position, _)| position)
+        .collect();
+    let rerolled_tokens: Vec<(String, Vec<String>)> = rerolled
+        .into_iter()
+        .map(|(_, paths)| {
+            (
+                dna::compute_dna(config.dna_algorithm, &layout, &paths),
+                paths,
+            )
+        })
+        .collect();
+    let settings = render_settings(config)?;
+    let records = manifest::build_manifest(config.dna_algorithm, &layout, &rerolled_tokens)?.tokens;
+    // Files named after the DNA change name; the old ones are removed once the
+    // new ones are written.
+    let mut stale_files = Vec::new();
+    for ((&position, (_, paths)), record) in positions.iter().zip(&rerolled_tokens).zip(records) {
+        let token = &mut manifest.tokens[position];
+        let file_names = |dna: &str| {
+            [
+                settings.file_names.image(token.token_id, dna),
+                settings.file_names.metadata(token.token_id, dna),
+            ]
+        };
+        for (old, new) in file_names(&token.dna)
+            .into_iter()
+            .zip(file_names(&record.dna))
+        {
+            if old != new {
+                stale_files.push(output_path.join(old));
+            }
+        }
+        token.dna = record.dna;
+        token.assets = record.assets;
+        token.rendered_at = record.rendered_at;
+        token_paths[position] = paths.clone();
+    }
+
+    let mut rarity_report = rarity::compute_rarity(&layout, &token_paths);
+    for token in &mut rarity_report.tokens {
+        token.token_id = manifest.tokens[token.token_id].token_id;
+    }
+    let decoded = DecodedLayers::new();
+    for (position, token) in manifest.tokens.iter().enumerate() {
+        let token_rarity = &rarity_report.tokens[position];
+        if positions.contains(&position) {
+            let metadata = token_metadata(
+                config,
+                &settings.file_names,
+                token.token_id,
+                &token.dna,
+                token_rarity,
+            );
+            let image_paths = &token_paths[position];
+            generate_image_and_metadata(
+                metadata,
+                image_paths.clone(),
+                token.token_id,
+                &settings,
+                &decoded,
+            )
+            .and_then(|mut render| render())
+            .map_err(|e| e.for_token(token.token_id, image_paths))?;
+            info!("Token {} re-rolled, DNA {}.", token.token_id, token.dna);
+        } else if config.embed_rarity {
+            update_embedded_rarity(
+                output_path,
+                &settings.file_names.metadata(token.token_id, &token.dna),
+                token_rarity,
+            )?;
+        }
+    }
+    for stale_file in stale_files {
+        fs::remove_file(&stale_file)
+            .map_err(|e| CustomError::WriteOutput(format!("{}: {}", stale_file.display(), e)))?;
+    }
+
+    let (reserved, public): (Vec<_>, Vec<_>) = manifest
+        .tokens
+        .iter()
+        .zip(&token_paths)
+        .partition(|(token, _)| token.reserved);
+    let sampled_layers = permutations::without_skipped(&all_layers, &skipped_traits);
+    let paths_of = |tokens: Vec<(&TokenRecord, &Vec<String>)>| -> Vec<Vec<String>> {
+        tokens.into_iter().map(|(_, paths)| paths.clone()).collect()
+    };
+    let mut token_stats = stats::compute_stats(&layout, &sampled_layers, &paths_of(public));
+    if !reserved.is_empty() {
+        token_stats.reserved = Some(Box::new(stats::compute_stats(
+            &layout,
+            &sampled_layers,
+            &paths_of(reserved),
+        )));
+    }
+
+    manifest
+        .historical_dna
+        .extend(manifest.tokens.iter().map(|token| token.dna.clone()));
+    manifest.dna_algorithm = Some(config.dna_algorithm);
+    manifest.updated_at = manifest::now();
+    manifest::write_manifest(output_path, &manifest)?;
+    dna::write_dna_index(
+        output_path,
+        &dna::DnaIndex {
+            algorithm: config.dna_algorithm,
+            tokens: manifest
+                .tokens
+                .iter()
+                .map(|token| dna::DnaEntry {
+                    token_id: token.token_id,
+                    dna: token.dna.clone(),
+                })
+                .collect(),
+        },
+    )?;
+    if let Some(localization) = &config.localization {
+        let file_names: Vec<String> = manifest
+            .tokens
+            .iter()
+            .map(|token| settings.file_names.metadata(token.token_id, &token.dna))
+            .collect();
+        localization::localize_files(
+            output_path,
+            &file_names,
+            localization,
+            &config.metadata_profile,
+        )?;
+    }
+    rarity::write_rarity(output_path, &rarity_report)?;
+    stats::write_stats(output_path, &token_stats)?;
+    stats::write_co_occurrence(
+        output_path,
+        &stats::compute_co_occurrence(&layout, &token_paths),
+    )?;
+    unused::write_unused_assets(
+        output_path,
+        &find_unused_assets(config, &all_layers, &token_paths)?,
+    )?;
+    let provenance = provenance::write_provenance(
+        output_path,
+        &settings
+            .file_names
+            .images(collection_token_ids(config), &manifest.token_dnas())?,
+    )?;
+    info!("Provenance hash: {}", provenance.provenance_hash);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::none_asset;
+
+    #[test]
+    fn test_token_paths() {
+        let layers = vec![
+            vec![
+                "./images/Background/Blue#10.png".to_string(),
+                "./images/Background/Red#10.png".to_string(),
+            ],
+            vec![
+                "./images/Hat/Cap#10.png".to_string(),
+                none_asset("./images/Hat", 5),
+            ],
+        ];
+        let paths = vec![layers[0][1].clone(), layers[1][1].clone()];
+        let layout = LayerLayout::new("./images");
+        let mut token = TokenRecord {
+            token_id: 7,
+            dna: dna::compute_dna(DnaAlgorithm::default(), &layout, &paths),
+            assets: vec![manifest::AssetRecord {
+                trait_type: "Background".to_string(),
+                value: "Red".to_string(),
+                file: layers[0][1].clone(),
+                hash: String::new(),
+            }],
+            reserved: false,
+            rendered_at: String::new(),
+        };
+        assert_eq!(
+            token_paths(&token, &layers, DnaAlgorithm::default(), &layout).unwrap(),
+            paths
+        );
+
+        token.assets[0].file = "./images/Background/Green#10.png".to_string();
+        assert!(token_paths(&token, &layers, DnaAlgorithm::default(), &layout).is_err());
+    }
+}
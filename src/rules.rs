@@ -0,0 +1,186 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::CustomError;
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+pub struct TraitRef {
+    pub layer: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+pub struct Requirement {
+    pub layer: String,
+    #[serde(default)]
+    pub values: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// "If a token has trait `if`, its `then.layer` value must be one of
+/// `then.values` or carry one of `then.tags`."
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+pub struct DependencyRule {
+    #[serde(rename = "if")]
+    pub condition: TraitRef,
+    pub then: Requirement,
+}
+
+struct CompiledRule {
+    condition: (String, String),
+    layer: String,
+    allowed: HashSet<String>,
+}
+
+#[derive(Default)]
+pub struct Rules {
+    rules: Vec<CompiledRule>,
+}
+
+impl Rules {
+    /// Resolves the tags of every rule into the values they stand for and
+    /// checks that every layer a rule mentions exists.
+    pub fn compile(
+        rules: &[DependencyRule],
+        trait_tags: &HashMap<String, Vec<TraitRef>>,
+        layer_names: &[String],
+    ) -> Result<Rules, CustomError> {
+        let check_layer = |layer: &str| {
+            if layer_names.iter().any(|name| name == layer) {
+                Ok(())
+            } else {
+                Err(CustomError::InvalidTrait(format!(
+                    "dependency rule references unknown layer {}",
+                    layer
+                )))
+            }
+        };
+
+        let mut compiled = Vec::with_capacity(rules.len());
+        for rule in rules {
+            check_layer(&rule.condition.layer)?;
+            check_layer(&rule.then.layer)?;
+
+            let mut allowed: HashSet<String> = rule.then.values.iter().cloned().collect();
+            for tag in &rule.then.tags {
+                let tagged = trait_tags.get(tag).ok_or_else(|| {
+                    CustomError::InvalidTrait(format!(
+                        "dependency rule references unknown tag {}",
+                        tag
+                    ))
+                })?;
+                allowed.extend(
+                    tagged
+                        .iter()
+                        .filter(|trait_ref| trait_ref.layer == rule.then.layer)
+                        .map(|trait_ref| trait_ref.value.clone()),
+                );
+            }
+
+            compiled.push(CompiledRule {
+                condition: (rule.condition.layer.clone(), rule.condition.value.clone()),
+                layer: rule.then.layer.clone(),
+                allowed,
+            });
+        }
+
+        Ok(Rules { rules: compiled })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Values `layer` may take given the traits chosen so far, or `None` when
+    /// no rule restricts it.
+    pub fn allowed_values(
+        &self,
+        chosen: &[(String, String)],
+        layer: &str,
+    ) -> Option<HashSet<&String>> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.layer == layer && chosen.contains(&rule.condition))
+            .map(|rule| rule.allowed.iter().collect::<HashSet<&String>>())
+            .reduce(|acc, allowed| acc.intersection(&allowed).copied().collect())
+    }
+
+    /// Whether `traits`, the values of some of the layers of a token, break no
+    /// rule whose condition and layer are both among them.
+    pub fn is_consistent(&self, traits: &[(String, String)]) -> bool {
+        self.rules.iter().all(|rule| {
+            !traits.contains(&rule.condition)
+                || traits
+                    .iter()
+                    .filter(|(layer, _)| *layer == rule.layer)
+                    .all(|(_, value)| rule.allowed.contains(value))
+        })
+    }
+
+    /// Whether some rule keeps `value` out of `layer` when its condition holds.
+    pub fn restricts(&self, layer: &str, value: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.layer == layer && !rule.allowed.contains(value))
+    }
+
+    pub fn is_satisfied(&self, traits: &[(String, String)]) -> bool {
+        self.rules.iter().all(|rule| {
+            !traits.contains(&rule.condition)
+                || traits
+                    .iter()
+                    .any(|(layer, value)| *layer == rule.layer && rule.allowed.contains(value))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(layer: &str, value: &str) -> (String, String) {
+        (layer.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules: Vec<DependencyRule> = serde_json::from_str(
+            r#"[{"if": {"layer": "Body", "value": "Robot"},
+                 "then": {"layer": "Eyes", "values": ["Visor"], "tags": ["robot-compatible"]}}]"#,
+        )
+        .unwrap();
+        let tags: HashMap<String, Vec<TraitRef>> = serde_json::from_str(
+            r#"{"robot-compatible": [{"layer": "Eyes", "value": "Laser"},
+                                     {"layer": "Mouth", "value": "Grill"}]}"#,
+        )
+        .unwrap();
+        let layers = vec!["Body".to_string(), "Eyes".to_string()];
+
+        let compiled = Rules::compile(&rules, &tags, &layers).unwrap();
+
+        let allowed = compiled
+            .allowed_values(&[pair("Body", "Robot")], "Eyes")
+            .unwrap();
+        assert_eq!(allowed.len(), 2);
+        assert!(allowed.contains(&"Laser".to_string()));
+        assert_eq!(
+            compiled.allowed_values(&[pair("Body", "Human")], "Eyes"),
+            None
+        );
+
+        assert!(compiled.is_satisfied(&[pair("Body", "Robot"), pair("Eyes", "Visor")]));
+        assert!(!compiled.is_satisfied(&[pair("Body", "Robot"), pair("Eyes", "Round")]));
+        assert!(compiled.is_satisfied(&[pair("Body", "Human"), pair("Eyes", "Round")]));
+        assert!(compiled.is_consistent(&[pair("Body", "Robot")]));
+        assert!(compiled.is_consistent(&[pair("Eyes", "Round")]));
+        assert!(!compiled.is_consistent(&[pair("Eyes", "Round"), pair("Body", "Robot")]));
+        assert!(compiled.restricts("Eyes", "Round"));
+        assert!(!compiled.restricts("Eyes", "Laser"));
+        assert!(!compiled.restricts("Body", "Human"));
+
+        assert!(Rules::compile(&rules, &HashMap::new(), &layers).is_err());
+        assert!(Rules::compile(&rules, &tags, &layers[..1]).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use schemars::schema_for;
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fs;
+use tracing::warn;
+
+use crate::{Config, CustomError};
+
+/// The JSON Schema of the config file.
+pub fn config_schema() -> Value {
+    serde_json::to_value(schema_for!(Config)).unwrap_or_default()
+}
+
+/// `schema` itself, or the definition it refers to.
+fn resolve<'a>(schema: &'a Value, root: &'a Value) -> &'a Value {
+    match schema
+        .get("$ref")
+        .and_then(Value::as_str)
+        .and_then(|reference| reference.strip_prefix("#/$defs/"))
+    {
+        Some(name) => root["$defs"].get(name).unwrap_or(schema),
+        None => schema,
+    }
+}
+
+/// Whether `value` has a JSON type `schema` allows.
+fn allows_type(schema: &Value, value: &Value) -> bool {
+    let name = match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    };
+    let allowed = |kind: &Value| kind == name || (name == "integer" && kind == "number");
+    match schema.get("type") {
+        Some(Value::Array(kinds)) => kinds.iter().any(allowed),
+        Some(kind) => allowed(kind),
+        None => true,
+    }
+}
+
+fn walk(schema: &Value, root: &Value, value: &Value, path: &str, unknown: &mut Vec<String>) {
+    let schema = resolve(schema, root);
+    if let Some(alternatives) = schema.get("anyOf").and_then(Value::as_array) {
+        // An option or an untagged enum: the alternative of the value's type,
+        // when only one has it.
+        let matching: Vec<&Value> = alternatives
+            .iter()
+            .map(|alternative| resolve(alternative, root))
+            .filter(|alternative| allows_type(alternative, value))
+            .collect();
+        if let [alternative] = matching[..] {
+            walk(alternative, root, value, path, unknown);
+        }
+        return;
+    }
+
+    match value {
+        Value::Object(fields) => walk_fields(schema, root, fields, path, unknown),
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    let item_path = format!("{}[{}]", path, index);
+                    walk(item_schema, root, item, &item_path, unknown);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The fields of an object `schema`, with those of the enums flattened into
+/// it, or `None` when it doesn't list its fields.
+fn properties(schema: &Value, root: &Value) -> Option<Map<String, Value>> {
+    let mut properties = schema.get("properties").and_then(Value::as_object).cloned();
+    let parts = ["oneOf", "allOf"]
+        .iter()
+        .filter_map(|key| schema.get(key).and_then(Value::as_array))
+        .flatten();
+    for part in parts {
+        if let Some(part_properties) = self::properties(resolve(part, root), root) {
+            properties
+                .get_or_insert_with(Map::new)
+                .extend(part_properties);
+        }
+    }
+    properties
+}
+
+fn walk_fields(
+    schema: &Value,
+    root: &Value,
+    fields: &Map<String, Value>,
+    path: &str,
+    unknown: &mut Vec<String>,
+) {
+    let properties = properties(schema, root);
+    let additional = schema
+        .get("additionalProperties")
+        .filter(|additional| additional.is_object());
+    for (key, value) in fields {
+        let field_path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        match (
+            properties
+                .as_ref()
+                .and_then(|properties| properties.get(key)),
+            additional,
+        ) {
+            (Some(property), _) | (None, Some(property)) => {
+                walk(property, root, value, &field_path, unknown)
+            }
+            // Structs list their fields; anything else is ignored by serde.
+            (None, None) if properties.is_some() => unknown.push(field_path),
+            (None, None) => {}
+        }
+    }
+}
+
+/// The schema of the field of `schema` at `field`, nested fields joined with
+/// dots, or `None` when there is no such field.
+fn field_schema(schema: &Value, field: &str) -> Option<Value> {
+    let mut current = schema.clone();
+    for part in field.split('.') {
+        let resolved = resolve(&current, schema).clone();
+        // An optional struct: its alternative listing fields.
+        let alternatives = resolved
+            .get("anyOf")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+        current = std::iter::once(&resolved)
+            .chain(&alternatives)
+            .find_map(|candidate| properties(resolve(candidate, schema), schema)?.remove(part))?;
+    }
+    Some(resolve(&current, schema).clone())
+}
+
+/// Whether every value `schema` allows, but `null`, is a string.
+fn is_string(schema: &Value, root: &Value) -> bool {
+    let schema = resolve(schema, root);
+    let alternatives = ["anyOf", "oneOf"]
+        .iter()
+        .find_map(|key| schema.get(key).and_then(Value::as_array));
+    if let Some(alternatives) = alternatives {
+        let values: Vec<&Value> = alternatives
+            .iter()
+            .map(|alternative| resolve(alternative, root))
+            .filter(|alternative| alternative.get("type").is_none_or(|kind| kind != "null"))
+            .collect();
+        return !values.is_empty() && values.iter().all(|value| is_string(value, root));
+    }
+    match schema.get("type") {
+        Some(Value::Array(kinds)) => {
+            kinds.iter().any(|kind| kind == "string")
+                && kinds.iter().all(|kind| kind == "string" || kind == "null")
+        }
+        Some(kind) => kind == "string",
+        None => schema.get("const").is_some_and(Value::is_string),
+    }
+}
+
+/// Whether the config field at `field` only takes strings, so that a value
+/// given for it as text is never read as a number or a boolean.
+pub fn expects_string(schema: &Value, field: &str) -> bool {
+    field_schema(schema, field).is_some_and(|field_schema| is_string(&field_schema, schema))
+}
+
+/// The fields of `config` the config file doesn't have, like a misspelled
+/// one, which would be ignored. Each is given by its path, such as
+/// `layerFolders[2].blendmode`.
+pub fn unknown_fields(schema: &Value, config: &Value) -> Vec<String> {
+    let mut unknown = Vec::new();
+    walk(schema, schema, config, "", &mut unknown);
+    unknown
+}
+
+/// Warns about the fields of the config at `config_path` that aren't config
+/// fields.
+pub fn report_unknown_fields(config_path: &str) -> Result<(), CustomError> {
+    let config: Value = fs::read_to_string(config_path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+        .map_err(|e| CustomError::Config(format!("{}: {}", config_path, e)))?;
+    for field in unknown_fields(&config_schema(), &config) {
+        warn!("Unknown field {}, ignored.", field);
+    }
+    Ok(())
+}
+
+pub fn run() -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(&config_schema())?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unknown_fields() {
+        let schema = config_schema();
+        assert_eq!(schema["properties"]["totalSupply"]["type"], "integer");
+
+        let config = json!({
+            "metadata": {"name": "Drop", "anyKey": 1},
+            "totalSuply": 10,
+            "layerFolders": ["Background", {"name": "Hat", "blendmode": "multiply", "offsetX": 2}],
+            "background": {"fills": [{"weight": 1, "color": "#ff0000", "colour": "red"}]},
+            "weights": {"Hat": {"Red": 5}}
+        });
+        assert_eq!(
+            unknown_fields(&schema, &config),
+            vec![
+                "background.fills[0].colour",
+                "layerFolders[1].blendmode",
+                "totalSuply"
+            ]
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::{write_json, CustomError};
+
+pub const SHUFFLE_FILE_NAME: &str = "shuffle.json";
+
+/// Where a generated token went: its index in the order the tokens were
+/// generated in, and the token id it got.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ShuffledToken {
+    pub generated_index: usize,
+    pub token_id: usize,
+}
+
+/// A random order of `count` generated tokens: the token at `order[k]` takes
+/// the `k`-th token id.
+pub fn shuffled_order<R: Rng>(count: usize, rng: &mut R) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..count).collect();
+    order.shuffle(rng);
+    order
+}
+
+pub fn write_shuffle(output_path: &Path, tokens: &[ShuffledToken]) -> Result<(), CustomError> {
+    let file_path = output_path.join(SHUFFLE_FILE_NAME);
+    write_json(&file_path, tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_shuffled_order() {
+        let order = shuffled_order(10, &mut ChaCha8Rng::seed_from_u64(7));
+        assert_eq!(order, shuffled_order(10, &mut ChaCha8Rng::seed_from_u64(7)));
+        assert_ne!(order, (0..10).collect::<Vec<usize>>());
+
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..10).collect::<Vec<usize>>());
+    }
+}
@@ -0,0 +1,236 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use walkdir::WalkDir;
+
+use crate::animation::TraitAnimation;
+use crate::paths::LayerLayout;
+use crate::rename::{DisplayNames, RenameRule};
+use crate::rules::TraitRef;
+use crate::stacking::TraitZIndex;
+use crate::trait_fields::TraitFields;
+use crate::weights::{WeightValue, Weights};
+use crate::{layer_layout, path_trait, Config, CustomError, ALLOWED_EXTENSION};
+
+/// What a `<asset>.json` next to an asset says about its value, e.g.
+/// `Gold#10.png.json` for `Gold#10.png`. Each field is the per-asset way of
+/// writing an entry of the config.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Sidecar {
+    /// See `displayNames`.
+    pub display_name: Option<String>,
+    /// See `weights`.
+    pub weight: Option<WeightValue>,
+    /// The `traitTags` groups the value belongs to, for dependency rules.
+    #[serde(alias = "ruleTags")]
+    pub tags: Vec<String>,
+    /// See `traitZIndexes`.
+    pub z_index: Option<f32>,
+    /// See `traitFields`.
+    pub attribute_fields: BTreeMap<String, Value>,
+    /// See `traitFields`.
+    pub metadata_arrays: BTreeMap<String, Vec<Value>>,
+    /// See `animations`, the file given from the folder of the asset.
+    pub animation: Option<String>,
+}
+
+/// The sidecar of every asset in `folder`, with the layer and value it
+/// describes, in file name order.
+pub fn load_sidecars(
+    layout: &LayerLayout,
+    folder: &str,
+) -> Result<Vec<(String, String, Sidecar)>, CustomError> {
+    let sidecar_suffix = format!(".{}.json", *ALLOWED_EXTENSION);
+    let mut sidecars = Vec::new();
+    for entry in WalkDir::new(folder)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+    {
+        let path = entry.path();
+        let Some(asset) = path
+            .to_str()
+            .filter(|path| path.ends_with(&sidecar_suffix))
+            .and_then(|path| path.strip_suffix(".json"))
+        else {
+            continue;
+        };
+        let Some((layer, value)) = path_trait(layout, asset) else {
+            continue;
+        };
+        let contents = fs::read_to_string(path)
+            .map_err(|e| CustomError::Config(format!("{}: {}", path.display(), e)))?;
+        let mut sidecar: Sidecar = serde_json::from_str(&contents)
+            .map_err(|e| CustomError::Config(format!("{}: {}", path.display(), e)))?;
+        if let (Some(animation), Some(folder)) = (&sidecar.animation, path.parent()) {
+            sidecar.animation = Some(folder.join(animation).display().to_string());
+        }
+        sidecars.push((layer, value, sidecar));
+    }
+    Ok(sidecars)
+}
+
+/// Adds the sidecars of the assets to the config. Where both give something
+/// for the same value, the config wins.
+pub fn merge_sidecars(config: &mut Config) -> Result<(), CustomError> {
+    let layout = layer_layout(config);
+    let mut sidecars = load_sidecars(&layout, &config.base_path)?;
+    // Layer folders outside `basePath` have sidecars too.
+    let base_path = fs::canonicalize(&config.base_path).ok();
+    for path in config
+        .layer_folders
+        .iter()
+        .filter_map(|layer_folder| layer_folder.path())
+    {
+        let inside_base_path = fs::canonicalize(path).is_ok_and(|path| {
+            base_path
+                .as_ref()
+                .is_some_and(|base| path.starts_with(base))
+        });
+        if !inside_base_path {
+            sidecars.extend(load_sidecars(&layout, path)?);
+        }
+    }
+    if sidecars.is_empty() {
+        return Ok(());
+    }
+
+    let mut display_names = match &config.display_names {
+        Some(display_names) => display_names.rules()?,
+        None => HashMap::new(),
+    };
+    let mut weights = match &config.weights {
+        Some(weights) => weights.load()?,
+        None => HashMap::new(),
+    };
+    for (layer, value, sidecar) in sidecars {
+        if let Some(display_name) = sidecar.display_name {
+            if let RenameRule::Scoped(values) = display_names
+                .entry(layer.clone())
+                .or_insert_with(|| RenameRule::Scoped(HashMap::new()))
+            {
+                values.entry(value.clone()).or_insert(display_name);
+            }
+        }
+        if let Some(weight) = sidecar.weight {
+            weights
+                .entry(layer.clone())
+                .or_default()
+                .entry(value.clone())
+                .or_insert(weight);
+        }
+        let trait_ref = TraitRef {
+            layer: layer.clone(),
+            value: value.clone(),
+        };
+        for tag in sidecar.tags {
+            let tagged = config.trait_tags.entry(tag).or_default();
+            if !tagged.contains(&trait_ref) {
+                tagged.push(trait_ref.clone());
+            }
+        }
+        if !sidecar.attribute_fields.is_empty() || !sidecar.metadata_arrays.is_empty() {
+            let position = config
+                .trait_fields
+                .iter()
+                .position(|fields| fields.layer == layer && fields.value == value)
+                .unwrap_or_else(|| {
+                    config.trait_fields.push(TraitFields {
+                        layer: layer.clone(),
+                        value: value.clone(),
+                        attribute_fields: BTreeMap::new(),
+                        metadata_arrays: BTreeMap::new(),
+                    });
+                    config.trait_fields.len() - 1
+                });
+            let fields = &mut config.trait_fields[position];
+            for (key, field) in sidecar.attribute_fields {
+                fields.attribute_fields.entry(key).or_insert(field);
+            }
+            for (key, items) in sidecar.metadata_arrays {
+                fields.metadata_arrays.entry(key).or_insert(items);
+            }
+        }
+        if let Some(file) = sidecar.animation {
+            if !config
+                .animations
+                .iter()
+                .any(|animation| animation.layer == layer && animation.value == value)
+            {
+                config.animations.push(TraitAnimation {
+                    layer: layer.clone(),
+                    value: value.clone(),
+                    file,
+                });
+            }
+        }
+        if let Some(z_index) = sidecar.z_index {
+            if !config
+                .trait_z_indexes
+                .iter()
+                .any(|trait_z_index| trait_z_index.layer == layer && trait_z_index.value == value)
+            {
+                config.trait_z_indexes.push(TraitZIndex {
+                    layer,
+                    value,
+                    z_index,
+                });
+            }
+        }
+    }
+
+    if !display_names.is_empty() {
+        config.display_names = Some(DisplayNames::Table(display_names));
+    }
+    if !weights.is_empty() {
+        config.weights = Some(Weights::Table(weights));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_sidecars() {
+        let dir = tempdir().unwrap();
+        let eyes = dir.path().join("Eyes#2");
+        fs::create_dir_all(&eyes).unwrap();
+        fs::write(eyes.join("Laser#10.png"), b"laser").unwrap();
+        fs::write(
+            eyes.join("Laser#10.png.json"),
+            r#"{"displayName": "Laser Eyes", "weight": 5, "tags": ["glowing"], "zIndex": 2.5,
+                "attributeFields": {"power": 9}, "metadataArrays": {"effects": ["glow"]},
+                "animation": "Laser.gif"}"#,
+        )
+        .unwrap();
+        fs::write(eyes.join("notes.json"), "not a sidecar").unwrap();
+
+        let base_path = dir.path().display().to_string();
+        let layout = LayerLayout::new(&base_path);
+        let sidecars = load_sidecars(&layout, &base_path).unwrap();
+        assert_eq!(sidecars.len(), 1);
+        let (layer, value, sidecar) = &sidecars[0];
+        assert_eq!((layer.as_str(), value.as_str()), ("Eyes", "Laser"));
+        assert_eq!(sidecar.display_name.as_deref(), Some("Laser Eyes"));
+        assert_eq!(sidecar.weight, Some(WeightValue::Percentage(5.0)));
+        assert_eq!(sidecar.tags, vec!["glowing".to_string()]);
+        assert_eq!(sidecar.z_index, Some(2.5));
+        assert_eq!(sidecar.attribute_fields["power"], Value::from(9));
+        assert_eq!(
+            sidecar.metadata_arrays["effects"],
+            vec![Value::from("glow")]
+        );
+        assert_eq!(
+            sidecar.animation,
+            Some(eyes.join("Laser.gif").display().to_string())
+        );
+
+        fs::write(eyes.join("Laser#10.png.json"), r#"{"colour": "red"}"#).unwrap();
+        assert!(load_sidecars(&layout, &base_path).is_err());
+    }
+}
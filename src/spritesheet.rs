@@ -0,0 +1,214 @@
+use image::{imageops, RgbaImage};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{write_json, CustomError, ALLOWED_EXTENSION};
+
+pub const FRAMES_FILE_NAME: &str = "frames.json";
+
+/// The extension of the folder holding the frames of an asset.
+const FRAME_DIR_EXTENSION: &str = "frames";
+
+/// Renders every token as a sheet of frames too, for games that play sheets
+/// rather than GIFs.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Spritesheet {
+    /// The number of frames of every sheet; shorter frame sets loop.
+    pub frames: u32,
+    /// Frames per row of the sheet; all of them in one row by default.
+    pub columns: Option<u32>,
+    /// How long every frame shows, in milliseconds.
+    #[serde(default = "default_frame_duration")]
+    pub frame_duration: u32,
+}
+
+fn default_frame_duration() -> u32 {
+    100
+}
+
+/// Where a frame is on every sheet.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Frame {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// The layout shared by the sheets of a run, and the sheet of every token.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FramesDescriptor {
+    pub frame_width: u32,
+    pub frame_height: u32,
+    pub frame_duration: u32,
+    pub columns: u32,
+    pub rows: u32,
+    pub frames: Vec<Frame>,
+    /// The sheets by token id, as paths under the output folder.
+    pub sheets: BTreeMap<usize, String>,
+}
+
+impl Spritesheet {
+    /// This spritesheet, once checked to have a frame and a column.
+    pub fn checked(&self) -> Result<Spritesheet, CustomError> {
+        if self.frames == 0 {
+            return Err(CustomError::Config(
+                "spritesheet.frames must be at least 1".to_string(),
+            ));
+        }
+        if self.columns == Some(0) {
+            return Err(CustomError::Config(
+                "spritesheet.columns must be at least 1".to_string(),
+            ));
+        }
+        Ok(self.clone())
+    }
+
+    fn columns(&self) -> u32 {
+        self.columns.unwrap_or(self.frames).min(self.frames)
+    }
+
+    fn rows(&self) -> u32 {
+        self.frames.div_ceil(self.columns())
+    }
+
+    /// Where every frame of `width` by `height` goes, row by row.
+    fn layout(&self, width: u32, height: u32) -> Vec<Frame> {
+        (0..self.frames)
+            .map(|index| Frame {
+                x: index % self.columns() * width,
+                y: index / self.columns() * height,
+                w: width,
+                h: height,
+            })
+            .collect()
+    }
+
+    /// Draws `frames`, all the same size, on one sheet.
+    pub fn sheet(&self, frames: &[RgbaImage]) -> RgbaImage {
+        let (width, height) = frames.first().map_or((0, 0), |frame| frame.dimensions());
+        let mut sheet = RgbaImage::new(width * self.columns(), height * self.rows());
+        for (frame, position) in frames.iter().zip(self.layout(width, height)) {
+            imageops::replace(&mut sheet, frame, position.x as i64, position.y as i64);
+        }
+        sheet
+    }
+
+    pub fn descriptor(
+        &self,
+        width: u32,
+        height: u32,
+        sheets: BTreeMap<usize, String>,
+    ) -> FramesDescriptor {
+        FramesDescriptor {
+            frame_width: width,
+            frame_height: height,
+            frame_duration: self.frame_duration,
+            columns: self.columns(),
+            rows: self.rows(),
+            frames: self.layout(width, height),
+            sheets,
+        }
+    }
+}
+
+/// Whether a folder holds the frames of an asset rather than values of its
+/// layer.
+pub fn is_frame_dir(folder_name: &OsStr) -> bool {
+    Path::new(folder_name)
+        .extension()
+        .is_some_and(|extension| extension == FRAME_DIR_EXTENSION)
+}
+
+/// The frames an asset is animated with: the PNGs, in name order, of the
+/// folder next to it with its name, e.g. `Eyes/Laser#10.frames/` for
+/// `Eyes/Laser#10.png`.
+pub fn frame_set(path: &str) -> Vec<PathBuf> {
+    let path = Path::new(path);
+    // `None` assets, recolored assets and generated backgrounds have no file.
+    if path.extension().is_none() {
+        return Vec::new();
+    }
+    let Ok(entries) = fs::read_dir(path.with_extension(FRAME_DIR_EXTENSION)) else {
+        return Vec::new();
+    };
+    let mut frames: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|frame| {
+            frame.is_file()
+                && frame
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| ALLOWED_EXTENSION.eq_ignore_ascii_case(extension))
+        })
+        .collect();
+    frames.sort();
+    frames
+}
+
+pub fn write_frames(output_path: &Path, descriptor: &FramesDescriptor) -> Result<(), CustomError> {
+    let file_path = output_path.join(FRAMES_FILE_NAME);
+    write_json(&file_path, descriptor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_spritesheet() {
+        let dir = tempdir().unwrap();
+        let still = dir.path().join("Laser#10.png");
+        assert!(frame_set(&still.display().to_string()).is_empty());
+        let frame_dir = dir.path().join("Laser#10.frames");
+        fs::create_dir(&frame_dir).unwrap();
+        for name in ["1.png", "0.png", "notes.txt"] {
+            fs::write(frame_dir.join(name), b"").unwrap();
+        }
+        assert_eq!(
+            frame_set(&still.display().to_string()),
+            vec![frame_dir.join("0.png"), frame_dir.join("1.png")]
+        );
+        assert!(is_frame_dir(frame_dir.file_name().unwrap()));
+        assert!(!is_frame_dir(OsStr::new("{Body=Robot}")));
+
+        let spritesheet: Spritesheet =
+            serde_json::from_str(r#"{"frames": 3, "columns": 2}"#).unwrap();
+        let frames: Vec<RgbaImage> = (0..3)
+            .map(|index| RgbaImage::from_pixel(2, 2, Rgba([index * 100, 0, 0, 255])))
+            .collect();
+        let sheet = spritesheet.checked().unwrap().sheet(&frames);
+        assert_eq!(sheet.dimensions(), (4, 4));
+        assert_eq!(sheet.get_pixel(3, 1), &Rgba([100, 0, 0, 255]));
+        assert_eq!(sheet.get_pixel(1, 3), &Rgba([200, 0, 0, 255]));
+        assert_eq!(sheet.get_pixel(3, 3), &Rgba([0, 0, 0, 0]));
+
+        let descriptor =
+            spritesheet.descriptor(2, 2, BTreeMap::from([(1, "1.sheet.png".to_string())]));
+        assert_eq!((descriptor.columns, descriptor.rows), (2, 2));
+        assert_eq!(descriptor.frame_duration, 100);
+        assert_eq!(
+            descriptor.frames[2],
+            Frame {
+                x: 0,
+                y: 2,
+                w: 2,
+                h: 2
+            }
+        );
+
+        for invalid in [r#"{"frames": 0}"#, r#"{"frames": 2, "columns": 0}"#] {
+            let invalid: Spritesheet = serde_json::from_str(invalid).unwrap();
+            assert!(invalid.checked().is_err());
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::{CustomError, LayerFolder};
+
+/// Moves the assets of one trait value to another place in the stack, e.g. a
+/// long hair drawn over the coat layer.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TraitZIndex {
+    pub layer: String,
+    pub value: String,
+    /// Layers have the z-index of their position in `layerFolders`, 0 for the
+    /// first one, so 2.5 is drawn between the third and the fourth layer.
+    pub z_index: f32,
+}
+
+/// The stacking order of a run: the layer order, with the trait values that
+/// override it.
+#[derive(Debug, Default, Clone)]
+pub struct Stacking {
+    layers: HashMap<String, f32>,
+    values: HashMap<(String, String), f32>,
+}
+
+impl Stacking {
+    pub fn compile(
+        layer_folders: &[LayerFolder],
+        trait_z_indexes: &[TraitZIndex],
+    ) -> Result<Stacking, CustomError> {
+        let layers: HashMap<String, f32> = layer_folders
+            .iter()
+            .enumerate()
+            .map(|(index, layer_folder)| (layer_folder.name().to_string(), index as f32))
+            .collect();
+
+        let mut values = HashMap::new();
+        for trait_z_index in trait_z_indexes {
+            if !layers.contains_key(&trait_z_index.layer) {
+                return Err(CustomError::InvalidTrait(format!(
+                    "trait z-index references unknown layer {}",
+                    trait_z_index.layer
+                )));
+            }
+            if !trait_z_index.z_index.is_finite() {
+                return Err(CustomError::InvalidTrait(format!(
+                    "z-index of {}/{} is not a number",
+                    trait_z_index.layer, trait_z_index.value
+                )));
+            }
+            values.insert(
+                (trait_z_index.layer.clone(), trait_z_index.value.clone()),
+                trait_z_index.z_index,
+            );
+        }
+
+        Ok(Stacking { layers, values })
+    }
+
+    fn z_index(&self, layer: &str, value: &str) -> f32 {
+        self.values
+            .get(&(layer.to_string(), value.to_string()))
+            .or_else(|| self.layers.get(layer))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sorts the `(asset, layer, value)` items of a token, given in layer
+    /// order, into drawing order. Equal z-indexes keep the layer order.
+    pub fn sort<T>(&self, layers: &mut [(T, &str, &str)]) {
+        if self.values.is_empty() {
+            return;
+        }
+        layers.sort_by(|(_, layer_a, value_a), (_, layer_b, value_b)| {
+            self.z_index(layer_a, value_a)
+                .total_cmp(&self.z_index(layer_b, value_b))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stacking() {
+        let layer_folders: Vec<LayerFolder> =
+            serde_json::from_str(r#"["Background", "Hair", "Coat", "Hat"]"#).unwrap();
+        let trait_z_indexes: Vec<TraitZIndex> =
+            serde_json::from_str(r#"[{"layer": "Hair", "value": "LongOverCoat", "zIndex": 2.5}]"#)
+                .unwrap();
+        let stacking = Stacking::compile(&layer_folders, &trait_z_indexes).unwrap();
+
+        let order = |hair: &'static str| {
+            let mut layers = vec![
+                (0, "Background", "Blue"),
+                (1, "Hair", hair),
+                (2, "Coat", "Red"),
+                (3, "Hat", "Cap"),
+            ];
+            stacking.sort(&mut layers);
+            layers
+                .iter()
+                .map(|(index, _, _)| *index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(order("Short"), vec![0, 1, 2, 3]);
+        assert_eq!(order("LongOverCoat"), vec![0, 2, 1, 3]);
+
+        assert!(Stacking::compile(&[], &trait_z_indexes).is_err());
+    }
+}
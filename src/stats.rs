@@ -0,0 +1,257 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use crate::export::csv_field;
+use crate::paths::LayerLayout;
+use crate::{calculate_weights_and_total, path_trait, write_json, CustomError};
+
+pub const STATS_FILE_NAME: &str = "stats.json";
+pub const CO_OCCURRENCE_FILE_NAME: &str = "co-occurrence.json";
+pub const CO_OCCURRENCE_CSV_FILE_NAME: &str = "co-occurrence.csv";
+
+#[derive(Debug, Serialize, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ValueStats {
+    pub count: usize,
+    pub percentage: f64,
+    pub weight: u64,
+    pub expected_percentage: f64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Stats {
+    pub total_supply: usize,
+    pub layers: BTreeMap<String, BTreeMap<String, ValueStats>>,
+    /// Statistics of the reserved supply, kept apart from the public ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reserved: Option<Box<Stats>>,
+}
+
+/// Compares how often every value was picked with the share its filename
+/// weight gives it within its layer. Assets sharing a value, like the same
+/// file name in two sublayers, are added together.
+pub fn compute_stats(
+    layout: &LayerLayout,
+    layers: &[Vec<String>],
+    tokens: &[Vec<String>],
+) -> Stats {
+    let total_supply = tokens.len();
+    let mut stats: BTreeMap<String, BTreeMap<String, ValueStats>> = BTreeMap::new();
+
+    for layer in layers {
+        let (cumulative_weights, total_weight) = calculate_weights_and_total(layout, layer);
+        let mut previous_weight = 0;
+        for (path, cumulative_weight) in layer.iter().zip(cumulative_weights) {
+            let weight = cumulative_weight - previous_weight;
+            previous_weight = cumulative_weight;
+
+            let Some((trait_type, value)) = path_trait(layout, path) else {
+                continue;
+            };
+            let expected_percentage = if total_weight == 0 {
+                100.0 / layer.len() as f64
+            } else {
+                weight as f64 * 100.0 / total_weight as f64
+            };
+            let value_stats = stats
+                .entry(trait_type)
+                .or_default()
+                .entry(value)
+                .or_default();
+            value_stats.weight += weight;
+            value_stats.expected_percentage += expected_percentage;
+        }
+    }
+
+    for (trait_type, value) in tokens
+        .iter()
+        .flatten()
+        .filter_map(|path| path_trait(layout, path))
+    {
+        stats
+            .entry(trait_type)
+            .or_default()
+            .entry(value)
+            .or_default()
+            .count += 1;
+    }
+
+    for value_stats in stats.values_mut().flat_map(|values| values.values_mut()) {
+        if total_supply > 0 {
+            value_stats.percentage = value_stats.count as f64 * 100.0 / total_supply as f64;
+        }
+    }
+
+    Stats {
+        total_supply,
+        layers: stats,
+        reserved: None,
+    }
+}
+
+pub fn write_stats(output_path: &Path, stats: &Stats) -> Result<(), CustomError> {
+    let file_path = output_path.join(STATS_FILE_NAME);
+    write_json(&file_path, stats)
+}
+
+/// How often every two trait values end up on the same token, as a square
+/// matrix ready to be drawn as a heatmap.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CoOccurrence {
+    pub total_supply: usize,
+    /// Labels of the rows and columns, as `layer=value`.
+    pub values: Vec<String>,
+    /// Tokens having both values; the diagonal holds the count of each value.
+    pub counts: Vec<Vec<usize>>,
+    /// `counts` over the count expected if the two values were drawn
+    /// independently. Well above or below 1, the values are correlated, e.g.
+    /// by a dependency rule or a forced combination.
+    pub lift: Vec<Vec<f64>>,
+}
+
+impl CoOccurrence {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        let header: Vec<String> = self.values.iter().map(|value| csv_field(value)).collect();
+        csv.push_str(&format!(",{}\n", header.join(",")));
+        for (value, row) in self.values.iter().zip(&self.counts) {
+            let row: Vec<String> = row.iter().map(usize::to_string).collect();
+            csv.push_str(&format!("{},{}\n", csv_field(value), row.join(",")));
+        }
+        csv
+    }
+}
+
+/// Counts every pair of values drawn together. Values no token got are left
+/// out.
+pub fn compute_co_occurrence(layout: &LayerLayout, tokens: &[Vec<String>]) -> CoOccurrence {
+    let total_supply = tokens.len();
+    let token_traits: Vec<BTreeSet<(String, String)>> = tokens
+        .iter()
+        .map(|paths| {
+            paths
+                .iter()
+                .filter_map(|path| path_trait(layout, path))
+                .collect()
+        })
+        .collect();
+    let values: Vec<&(String, String)> = token_traits
+        .iter()
+        .flatten()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let index: BTreeMap<&(String, String), usize> = values
+        .iter()
+        .enumerate()
+        .map(|(index, value)| (*value, index))
+        .collect();
+
+    let mut counts = vec![vec![0; values.len()]; values.len()];
+    for traits in &token_traits {
+        for a in traits {
+            for b in traits {
+                counts[index[a]][index[b]] += 1;
+            }
+        }
+    }
+    let lift = counts
+        .iter()
+        .enumerate()
+        .map(|(a, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(b, &count)| {
+                    let expected = counts[a][a] as f64 * counts[b][b] as f64 / total_supply as f64;
+                    count as f64 / expected
+                })
+                .collect()
+        })
+        .collect();
+
+    CoOccurrence {
+        total_supply,
+        values: values
+            .into_iter()
+            .map(|(layer, value)| format!("{}={}", layer, value))
+            .collect(),
+        counts,
+        lift,
+    }
+}
+
+/// Writes the matrix as JSON and as CSV, the counts only.
+pub fn write_co_occurrence(
+    output_path: &Path,
+    co_occurrence: &CoOccurrence,
+) -> Result<(), CustomError> {
+    let json_path = output_path.join(CO_OCCURRENCE_FILE_NAME);
+    write_json(&json_path, co_occurrence)?;
+    let csv_path = output_path.join(CO_OCCURRENCE_CSV_FILE_NAME);
+    fs::write(&csv_path, co_occurrence.to_csv())
+        .map_err(|e| CustomError::WriteOutput(format!("{}: {}", csv_path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_stats() {
+        let layers = vec![
+            vec![
+                "./images/Background/Blue#30.png".to_string(),
+                "./images/Background/Red#10.png".to_string(),
+            ],
+            vec![
+                "./images/Eyes/Laser.png".to_string(),
+                "./images/Eyes/Round.png".to_string(),
+            ],
+        ];
+        let tokens = vec![
+            vec![layers[0][0].clone(), layers[1][0].clone()],
+            vec![layers[0][0].clone(), layers[1][1].clone()],
+            vec![layers[0][0].clone(), layers[1][1].clone()],
+            vec![layers[0][1].clone(), layers[1][1].clone()],
+        ];
+
+        let layout = LayerLayout::new("./images");
+        let stats = compute_stats(&layout, &layers, &tokens);
+
+        assert_eq!(stats.total_supply, 4);
+        assert_eq!(
+            stats.layers["Background"]["Blue"],
+            ValueStats {
+                count: 3,
+                percentage: 75.0,
+                weight: 30,
+                expected_percentage: 75.0,
+            }
+        );
+        assert_eq!(stats.layers["Background"]["Red"].weight, 10);
+        assert_eq!(stats.layers["Eyes"]["Laser"].percentage, 25.0);
+        assert_eq!(stats.layers["Eyes"]["Laser"].expected_percentage, 50.0);
+
+        let co_occurrence = compute_co_occurrence(&layout, &tokens);
+        assert_eq!(
+            co_occurrence.values,
+            vec![
+                "Background=Blue",
+                "Background=Red",
+                "Eyes=Laser",
+                "Eyes=Round"
+            ]
+        );
+        assert_eq!(co_occurrence.counts[0], vec![3, 0, 1, 2]);
+        assert_eq!(co_occurrence.counts[3][1], 1);
+        assert_eq!(co_occurrence.lift[1][3], 4.0 / 3.0);
+        assert_eq!(
+            co_occurrence.to_csv().lines().nth(2),
+            Some("Background=Red,0,1,0,1")
+        );
+    }
+}
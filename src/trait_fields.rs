@@ -0,0 +1,181 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::metadata::MetadataProfile;
+use crate::CustomError;
+
+/// Token metadata fields a generated token always has, which arrays can't
+/// replace.
+const RESERVED_FIELDS: [&str; 6] = [
+    "name",
+    "image",
+    "animation_url",
+    "attributes",
+    "dna",
+    "rarity",
+];
+
+/// Structured data a trait value carries into the metadata of the tokens that
+/// show it, beyond its `trait_type` and `value`.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TraitFields {
+    pub layer: String,
+    pub value: String,
+    /// Keys added to the attribute of the value, e.g. `max_value`.
+    #[serde(default)]
+    pub attribute_fields: BTreeMap<String, Value>,
+    /// Items added to arrays at the top level of the token metadata, e.g.
+    /// `"tags": ["glowing"]`.
+    #[serde(default)]
+    pub metadata_arrays: BTreeMap<String, Vec<Value>>,
+}
+
+/// The `traitFields` of a run, by layer and value.
+#[derive(Debug, Default, Clone)]
+pub struct TraitFieldTable {
+    fields: HashMap<(String, String), TraitFields>,
+}
+
+impl TraitFieldTable {
+    /// Checks that every entry is for one of the layers of `names`, that no
+    /// attribute field replaces a key of `profile` and that no array replaces
+    /// a field of `metadata` that isn't an array.
+    pub fn compile(
+        entries: &[TraitFields],
+        names: &[String],
+        profile: &MetadataProfile,
+        metadata: &HashMap<String, Value>,
+    ) -> Result<TraitFieldTable, CustomError> {
+        let mut fields = HashMap::new();
+        for entry in entries {
+            let label = format!("traitFields of {}/{}", entry.layer, entry.value);
+            if !names.contains(&entry.layer) {
+                return Err(CustomError::Config(format!(
+                    "{} references an unknown layer",
+                    label
+                )));
+            }
+            if let Some(key) = entry.attribute_fields.keys().find(|key| {
+                [
+                    profile.trait_type_key.as_str(),
+                    profile.value_key.as_str(),
+                    "display_type",
+                ]
+                .contains(&key.as_str())
+            }) {
+                return Err(CustomError::Config(format!(
+                    "{} can't set the attribute key {}",
+                    label, key
+                )));
+            }
+            if let Some(key) = entry.metadata_arrays.keys().find(|key| {
+                RESERVED_FIELDS.contains(&key.as_str())
+                    || metadata.get(*key).is_some_and(|value| !value.is_array())
+            }) {
+                return Err(CustomError::Config(format!(
+                    "{} can't add an array to the metadata field {}",
+                    label, key
+                )));
+            }
+            fields.insert((entry.layer.clone(), entry.value.clone()), entry.clone());
+        }
+        Ok(TraitFieldTable { fields })
+    }
+
+    /// Adds the attribute fields of `layer`'s `value` to its `attribute`, and
+    /// its array items to `arrays`, each item once.
+    pub fn apply(
+        &self,
+        layer: &str,
+        value: &str,
+        attribute: &mut Value,
+        arrays: &mut BTreeMap<String, Vec<Value>>,
+    ) {
+        let Some(fields) = self.fields.get(&(layer.to_string(), value.to_string())) else {
+            return;
+        };
+        for (key, field) in &fields.attribute_fields {
+            attribute[key] = field.clone();
+        }
+        for (key, items) in &fields.metadata_arrays {
+            let array = arrays.entry(key.clone()).or_default();
+            for item in items {
+                if !array.contains(item) {
+                    array.push(item.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Sets the `arrays` of a token on its `metadata`, after the items the
+/// config metadata already has.
+pub fn insert_arrays(metadata: &mut HashMap<String, Value>, arrays: BTreeMap<String, Vec<Value>>) {
+    for (key, items) in arrays {
+        let mut array = match metadata.get(&key) {
+            Some(Value::Array(array)) => array.clone(),
+            _ => Vec::new(),
+        };
+        for item in items {
+            if !array.contains(&item) {
+                array.push(item);
+            }
+        }
+        metadata.insert(key, Value::Array(array));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_trait_fields() {
+        let entries: Vec<TraitFields> = serde_json::from_str(
+            r#"[
+                {"layer": "Level", "value": "3", "attributeFields": {"max_value": 10}},
+                {"layer": "Eyes", "value": "Laser", "metadataArrays": {"tags": ["glowing", "rare"]}},
+                {"layer": "Hat", "value": "Crown", "metadataArrays": {"tags": ["rare", "royal"]}}
+            ]"#,
+        )
+        .unwrap();
+        let names: Vec<String> = ["Level", "Eyes", "Hat"].map(String::from).to_vec();
+        let profile = MetadataProfile::default();
+        let metadata = HashMap::from([("tags".to_string(), json!(["genesis"]))]);
+        let table = TraitFieldTable::compile(&entries, &names, &profile, &metadata).unwrap();
+
+        let mut arrays = BTreeMap::new();
+        let mut level = profile.attribute("Level", "3");
+        table.apply("Level", "3", &mut level, &mut arrays);
+        assert_eq!(
+            level,
+            json!({"trait_type": "Level", "value": "3", "max_value": 10})
+        );
+        for (layer, value) in [("Eyes", "Laser"), ("Hat", "Crown"), ("Eyes", "Normal")] {
+            let mut attribute = profile.attribute(layer, value);
+            table.apply(layer, value, &mut attribute, &mut arrays);
+            assert_eq!(attribute, profile.attribute(layer, value));
+        }
+        let mut token = metadata.clone();
+        insert_arrays(&mut token, arrays);
+        assert_eq!(
+            token["tags"],
+            json!(["genesis", "glowing", "rare", "royal"])
+        );
+
+        for invalid in [
+            r#"[{"layer": "Body", "value": "Robot"}]"#,
+            r#"[{"layer": "Level", "value": "3", "attributeFields": {"value": 4}}]"#,
+            r#"[{"layer": "Eyes", "value": "Laser", "metadataArrays": {"attributes": []}}]"#,
+        ] {
+            let entries: Vec<TraitFields> = serde_json::from_str(invalid).unwrap();
+            assert!(TraitFieldTable::compile(&entries, &names, &profile, &metadata).is_err());
+        }
+        let metadata = HashMap::from([("tags".to_string(), json!("genesis"))]);
+        assert!(TraitFieldTable::compile(&entries, &names, &profile, &metadata).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::{CustomError, LayerFolder};
+
+/// Flips, then rotates, an asset before it is drawn.
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Transform {
+    /// Clockwise rotation in degrees. Multiples of 90 are exact; other angles
+    /// rotate around the center and keep the size of the asset.
+    #[serde(default)]
+    pub rotate: f32,
+    #[serde(default)]
+    pub flip_horizontal: bool,
+    #[serde(default)]
+    pub flip_vertical: bool,
+}
+
+/// A transform for the assets of one trait value, instead of the one of its layer.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+pub struct TraitTransform {
+    pub layer: String,
+    pub value: String,
+    #[serde(flatten)]
+    pub transform: Transform,
+}
+
+/// The transforms of a run, by layer and by trait value.
+#[derive(Debug, Default, Clone)]
+pub struct Transforms {
+    layers: HashMap<String, Transform>,
+    values: HashMap<(String, String), Transform>,
+}
+
+impl Transform {
+    fn is_identity(&self) -> bool {
+        self.rotate.rem_euclid(360.0) == 0.0 && !self.flip_horizontal && !self.flip_vertical
+    }
+
+    pub fn apply<'a>(&self, layer: &'a DynamicImage) -> Cow<'a, DynamicImage> {
+        if self.is_identity() {
+            return Cow::Borrowed(layer);
+        }
+
+        let mut image = layer.clone();
+        if self.flip_horizontal {
+            image = image.fliph();
+        }
+        if self.flip_vertical {
+            image = image.flipv();
+        }
+        let degrees = self.rotate.rem_euclid(360.0);
+        image = match degrees {
+            0.0 => image,
+            90.0 => image.rotate90(),
+            180.0 => image.rotate180(),
+            270.0 => image.rotate270(),
+            degrees => DynamicImage::ImageRgba8(rotate_about_center(&image.to_rgba8(), degrees)),
+        };
+        Cow::Owned(image)
+    }
+}
+
+/// Rotates clockwise by `degrees` around the center, with bilinear sampling.
+/// The corners that leave the image are cut off and the uncovered ones are
+/// transparent.
+fn rotate_about_center(image: &RgbaImage, degrees: f32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let center_x = (width as f32 - 1.0) / 2.0;
+    let center_y = (height as f32 - 1.0) / 2.0;
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let dx = x as f32 - center_x;
+        let dy = y as f32 - center_y;
+        let source_x = cos * dx + sin * dy + center_x;
+        let source_y = -sin * dx + cos * dy + center_y;
+        image::imageops::interpolate_bilinear(image, source_x, source_y)
+            .unwrap_or(Rgba([0, 0, 0, 0]))
+    })
+}
+
+impl Transforms {
+    pub fn compile(
+        layer_folders: &[LayerFolder],
+        trait_transforms: &[TraitTransform],
+    ) -> Result<Transforms, CustomError> {
+        let layers = layer_folders
+            .iter()
+            .filter(|layer_folder| !layer_folder.transform().is_identity())
+            .map(|layer_folder| (layer_folder.name().to_string(), layer_folder.transform()))
+            .collect();
+
+        let mut values = HashMap::new();
+        for trait_transform in trait_transforms {
+            if !layer_folders
+                .iter()
+                .any(|layer_folder| layer_folder.name() == trait_transform.layer)
+            {
+                return Err(CustomError::InvalidTrait(format!(
+                    "trait transform references unknown layer {}",
+                    trait_transform.layer
+                )));
+            }
+            values.insert(
+                (trait_transform.layer.clone(), trait_transform.value.clone()),
+                trait_transform.transform,
+            );
+        }
+
+        Ok(Transforms { layers, values })
+    }
+
+    /// The transform of a trait value, falling back to the one of its layer.
+    pub fn get(&self, layer: &str, value: &str) -> Option<&Transform> {
+        self.values
+            .get(&(layer.to_string(), value.to_string()))
+            .or_else(|| self.layers.get(layer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transforms() {
+        let mut arrow = RgbaImage::new(3, 2);
+        arrow.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+        let arrow = DynamicImage::ImageRgba8(arrow);
+        let red_at = |transform: Transform| {
+            let image = transform.apply(&arrow).to_rgba8();
+            let red = image
+                .enumerate_pixels()
+                .find(|(_, _, pixel)| pixel.0 == [255, 0, 0, 255])
+                .map(|(x, y, _)| (x, y));
+            (image.dimensions(), red)
+        };
+
+        assert_eq!(red_at(Transform::default()), ((3, 2), Some((0, 0))));
+        let flipped = Transform {
+            flip_horizontal: true,
+            ..Default::default()
+        };
+        assert_eq!(red_at(flipped), ((3, 2), Some((2, 0))));
+        let rotated = Transform {
+            rotate: -270.0,
+            ..Default::default()
+        };
+        assert_eq!(red_at(rotated), ((2, 3), Some((1, 0))));
+
+        let square = DynamicImage::ImageRgba8(RgbaImage::from_pixel(9, 9, Rgba([0, 0, 255, 255])));
+        let tilted = Transform {
+            rotate: 45.0,
+            ..Default::default()
+        }
+        .apply(&square)
+        .to_rgba8();
+        assert_eq!(tilted.dimensions(), (9, 9));
+        assert_eq!(tilted.get_pixel(4, 4).0, [0, 0, 255, 255]);
+        assert_eq!(tilted.get_pixel(0, 0).0[3], 0);
+
+        let layer_folders: Vec<LayerFolder> =
+            serde_json::from_str(r#"["Background", {"name": "Hand", "flipHorizontal": true}]"#)
+                .unwrap();
+        let trait_transforms: Vec<TraitTransform> =
+            serde_json::from_str(r#"[{"layer": "Hand", "value": "Sword", "rotate": 90}]"#).unwrap();
+        let transforms = Transforms::compile(&layer_folders, &trait_transforms).unwrap();
+        assert_eq!(transforms.get("Background", "Blue"), None);
+        assert!(transforms.get("Hand", "Shield").unwrap().flip_horizontal);
+        assert_eq!(transforms.get("Hand", "Sword").unwrap().rotate, 90.0);
+        assert!(Transforms::compile(&[], &trait_transforms).is_err());
+    }
+}
@@ -0,0 +1,523 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use walkdir::WalkDir;
+
+use crate::{
+    is_none_asset, layer_layout, load_layers, path_trait, resolve_weights, Config, CustomError,
+};
+
+/// How often the screen is redrawn while nothing is pressed.
+const TICK: Duration = Duration::from_millis(200);
+/// Lines of the run output kept on screen.
+const LOG_LINES: usize = 200;
+/// Weight units a layer is split into when the TUI starts; one key press moves
+/// a value by a tenth of a percent of that.
+const WEIGHT_UNITS: f64 = 1000.0;
+const WEIGHT_STEP: u64 = 10;
+
+#[derive(Debug, PartialEq)]
+struct ValueRow {
+    value: String,
+    weight: u64,
+    /// The `None` of a `skipProbability` has no file to weight.
+    tweakable: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct LayerRow {
+    name: String,
+    values: Vec<ValueRow>,
+    tweaked: bool,
+}
+
+impl LayerRow {
+    fn total_weight(&self) -> u64 {
+        self.values.iter().map(|row| row.weight).sum()
+    }
+
+    fn share(&self, row: &ValueRow) -> f64 {
+        match self.total_weight() {
+            0 => 0.0,
+            total => row.weight as f64 * 100.0 / total as f64,
+        }
+    }
+}
+
+/// The layers of the config with the weight of every value, scaled to
+/// `WEIGHT_UNITS` per layer. Only the layers of `layerFolders` can be tweaked.
+fn layer_rows(config: &Config, all_layers: &[Vec<String>]) -> Vec<LayerRow> {
+    let folder_names: Vec<&str> = config
+        .layer_folders
+        .iter()
+        .map(|layer_folder| layer_folder.name())
+        .collect();
+    let layout = layer_layout(config);
+    all_layers
+        .iter()
+        .filter_map(|layer| {
+            let name = layer.iter().find_map(|path| path_trait(&layout, path))?.0;
+            if !folder_names.contains(&name.as_str()) {
+                return None;
+            }
+            let weights = resolve_weights(&layout, layer);
+            let total: u64 = weights.iter().sum();
+            let mut values: BTreeMap<String, ValueRow> = BTreeMap::new();
+            for (path, weight) in layer.iter().zip(weights) {
+                let Some((_, value)) = path_trait(&layout, path) else {
+                    continue;
+                };
+                let units = match total {
+                    0 => 0,
+                    total => (weight as f64 * WEIGHT_UNITS / total as f64).round() as u64,
+                };
+                values
+                    .entry(value.clone())
+                    .or_insert(ValueRow {
+                        value,
+                        weight: 0,
+                        tweakable: !is_none_asset(path),
+                    })
+                    .weight += units;
+            }
+            Some(LayerRow {
+                name,
+                values: values.into_values().collect(),
+                tweaked: false,
+            })
+        })
+        .collect()
+}
+
+/// A `generate` running in a child process.
+struct RunState {
+    child: Child,
+    started: Instant,
+    since: SystemTime,
+    output: Receiver<(bool, String)>,
+    config_path: PathBuf,
+    status: Option<ExitStatus>,
+}
+
+struct App {
+    layers: Vec<LayerRow>,
+    layer: usize,
+    value: usize,
+    supply: u32,
+    output_root: PathBuf,
+    run: Option<RunState>,
+    /// Output of the last run, `true` for the lines it wrote to stderr.
+    log: Vec<(bool, String)>,
+    rendered: usize,
+}
+
+impl App {
+    fn selected_layer(&mut self) -> Option<&mut LayerRow> {
+        self.layers.get_mut(self.layer)
+    }
+
+    fn move_layer(&mut self, step: isize) {
+        if self.layers.is_empty() {
+            return;
+        }
+        self.layer = (self.layer as isize + step).rem_euclid(self.layers.len() as isize) as usize;
+        self.value = 0;
+    }
+
+    fn move_value(&mut self, step: isize) {
+        let value = self.value;
+        let Some(layer) = self.selected_layer() else {
+            return;
+        };
+        if layer.values.is_empty() {
+            return;
+        }
+        let count = layer.values.len() as isize;
+        self.value = (value as isize + step).rem_euclid(count) as usize;
+    }
+
+    fn adjust_weight(&mut self, increase: bool) {
+        let value = self.value;
+        let Some(layer) = self.selected_layer() else {
+            return;
+        };
+        let Some(row) = layer.values.get_mut(value).filter(|row| row.tweakable) else {
+            return;
+        };
+        row.weight = if increase {
+            row.weight + WEIGHT_STEP
+        } else {
+            row.weight.saturating_sub(WEIGHT_STEP)
+        };
+        layer.tweaked = true;
+    }
+
+    fn adjust_supply(&mut self, step: i64) {
+        self.supply = (self.supply as i64 + step).max(1) as u32;
+    }
+
+    fn is_running(&self) -> bool {
+        self.run.as_ref().is_some_and(|run| run.status.is_none())
+    }
+}
+
+/// The config as written in `raw`, with the supply and the tweaked layers of
+/// the TUI. Tweaked layers get a plain weight for every value in `weights`.
+fn patched_config(raw: &Value, layers: &[LayerRow], supply: u32) -> Result<Value, CustomError> {
+    let mut patched = raw.clone();
+    patched["totalSupply"] = json!(supply);
+
+    if layers.iter().any(|layer| layer.tweaked) {
+        let mut weights = match raw.get("weights") {
+            Some(Value::String(path)) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| CustomError::Config(format!("weights {}: {}", path, e)))?;
+                serde_json::from_str(&contents)
+                    .map_err(|e| CustomError::Config(format!("weights {}: {}", path, e)))?
+            }
+            Some(weights) => weights.clone(),
+            None => json!({}),
+        };
+        for layer in layers.iter().filter(|layer| layer.tweaked) {
+            weights[&layer.name] = layer
+                .values
+                .iter()
+                .filter(|row| row.tweakable)
+                .map(|row| (row.value.clone(), json!(format!("#{}", row.weight))))
+                .collect::<serde_json::Map<String, Value>>()
+                .into();
+        }
+        patched["weights"] = weights;
+    }
+    Ok(patched)
+}
+
+fn forward_lines(
+    stream: impl Read + Send + 'static,
+    is_error: bool,
+    sender: Sender<(bool, String)>,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if sender.send((is_error, line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Starts `generate` with the patched config written next to the original,
+/// since the paths in a config are read from the working directory.
+fn start_run(config_path: &str, patched: &Value) -> Result<RunState, Box<dyn Error>> {
+    let config_path = Path::new(config_path);
+    let run_config_path = config_path.with_file_name(format!(
+        "{}.tui.json",
+        config_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("config")
+    ));
+    fs::write(&run_config_path, serde_json::to_string_pretty(patched)?)?;
+
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg("--config")
+        .arg(&run_config_path)
+        .arg("generate")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(stdout, false, sender.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_lines(stderr, true, sender);
+    }
+    Ok(RunState {
+        child,
+        started: Instant::now(),
+        since: SystemTime::now(),
+        output: receiver,
+        config_path: run_config_path,
+        status: None,
+    })
+}
+
+/// Token images written below `output_root` since `since`, run namespaces
+/// included.
+fn count_rendered(output_root: &Path, since: SystemTime) -> usize {
+    WalkDir::new(output_root)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "png"))
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .filter(|modified| *modified >= since)
+        .count()
+}
+
+fn poll_run(app: &mut App) -> Result<(), Box<dyn Error>> {
+    let Some(run) = app.run.as_mut() else {
+        return Ok(());
+    };
+    while let Ok(line) = run.output.try_recv() {
+        app.log.push(line);
+    }
+    if app.log.len() > LOG_LINES {
+        app.log.drain(..app.log.len() - LOG_LINES);
+    }
+    if run.status.is_none() {
+        app.rendered = count_rendered(&app.output_root, run.since);
+        if let Some(status) = run.child.try_wait()? {
+            run.status = Some(status);
+            _ = fs::remove_file(&run.config_path);
+        }
+    }
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, app: &App) {
+    let [header, body, run_area] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(8),
+            Constraint::Length(12),
+        ])
+        .areas(frame.area());
+    let [layers_area, values_area] = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
+        .areas(body);
+
+    let keys = "←/→ layer  ↑/↓ value  +/- weight  [/] supply  {/} supply x10  g generate  q quit";
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(format!("Supply: {}", app.supply)),
+            Line::from(keys),
+        ])
+        .block(Block::default().borders(Borders::BOTTOM)),
+        header,
+    );
+
+    let highlight = Style::default().add_modifier(Modifier::REVERSED);
+    let layer_items: Vec<ListItem> = app
+        .layers
+        .iter()
+        .map(|layer| {
+            let marker = if layer.tweaked { " *" } else { "" };
+            ListItem::new(format!("{}{}", layer.name, marker))
+        })
+        .collect();
+    let mut layer_state = ListState::default().with_selected(Some(app.layer));
+    frame.render_stateful_widget(
+        List::new(layer_items)
+            .block(Block::default().borders(Borders::ALL).title("Layers"))
+            .highlight_style(highlight),
+        layers_area,
+        &mut layer_state,
+    );
+
+    if let Some(layer) = app.layers.get(app.layer) {
+        let value_items: Vec<ListItem> = layer
+            .values
+            .iter()
+            .map(|row| {
+                let share = layer.share(row);
+                let bar = "█".repeat((share / 5.0).round() as usize);
+                let style = if row.tweakable {
+                    Style::default()
+                } else {
+                    Style::default().fg(Color::DarkGray)
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{:<24} {:>6.1}% ", row.value, share), style),
+                    Span::styled(bar, Style::default().fg(Color::Cyan)),
+                ]))
+            })
+            .collect();
+        let mut value_state = ListState::default().with_selected(Some(app.value));
+        frame.render_stateful_widget(
+            List::new(value_items)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(layer.name.as_str()),
+                )
+                .highlight_style(highlight),
+            values_area,
+            &mut value_state,
+        );
+    }
+
+    let [gauge_area, log_area] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(1)])
+        .areas(run_area);
+    let (title, ratio) = match &app.run {
+        None => ("Not started".to_string(), 0.0),
+        Some(run) => {
+            let elapsed = run.started.elapsed().as_secs();
+            let state = match run.status {
+                None => "Generating".to_string(),
+                Some(status) if status.success() => "Done".to_string(),
+                Some(status) => format!("Failed ({})", status),
+            };
+            (
+                format!(
+                    "{}, {} of {} images, {}s",
+                    state, app.rendered, app.supply, elapsed
+                ),
+                (app.rendered as f64 / app.supply as f64).min(1.0),
+            )
+        }
+    };
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(ratio),
+        gauge_area,
+    );
+    let visible = log_area.height.saturating_sub(2) as usize;
+    let log_lines: Vec<Line> = app.log[app.log.len().saturating_sub(visible)..]
+        .iter()
+        .map(|(is_error, line)| {
+            let style = if *is_error {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            Line::styled(line.as_str(), style)
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(log_lines).block(Block::default().borders(Borders::ALL).title("Output")),
+        log_area,
+    );
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    raw: &Value,
+    config_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        poll_run(app)?;
+        terminal.draw(|frame| draw(frame, app))?;
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            KeyCode::Left => app.move_layer(-1),
+            KeyCode::Right | KeyCode::Tab => app.move_layer(1),
+            KeyCode::Up => app.move_value(-1),
+            KeyCode::Down => app.move_value(1),
+            KeyCode::Char('+') | KeyCode::Char('=') => app.adjust_weight(true),
+            KeyCode::Char('-') => app.adjust_weight(false),
+            KeyCode::Char(']') => app.adjust_supply(1),
+            KeyCode::Char('[') => app.adjust_supply(-1),
+            KeyCode::Char('}') => app.adjust_supply(10),
+            KeyCode::Char('{') => app.adjust_supply(-10),
+            KeyCode::Char('g') if !app.is_running() => {
+                let patched = patched_config(raw, &app.layers, app.supply)?;
+                app.log.clear();
+                app.rendered = 0;
+                app.run = Some(start_run(config_path, &patched)?);
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(run) = app.run.as_mut().filter(|run| run.status.is_none()) {
+        _ = run.child.kill();
+        _ = run.child.wait();
+        _ = fs::remove_file(&run.config_path);
+    }
+    Ok(())
+}
+
+pub fn run(config_path: &str, config: &Config) -> Result<(), Box<dyn Error>> {
+    let contents = fs::read_to_string(config_path)
+        .map_err(|e| CustomError::Config(format!("{}: {}", config_path, e)))?;
+    let raw: Value = serde_json::from_str(&contents)
+        .map_err(|e| CustomError::Config(format!("{}: {}", config_path, e)))?;
+    let all_layers = load_layers(config)?;
+    let mut app = App {
+        layers: layer_rows(config, &all_layers),
+        layer: 0,
+        value: 0,
+        supply: config.total_supply,
+        output_root: PathBuf::from(&config.output_path),
+        run: None,
+        log: Vec::new(),
+        rendered: 0,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, &mut app, &raw, config_path);
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patched_config() {
+        let raw = json!({"totalSupply": 10, "weights": {"Body": {"Robot": 5}}});
+        let mut layers = vec![LayerRow {
+            name: "Eyes".to_string(),
+            values: vec![
+                ValueRow {
+                    value: "Laser".to_string(),
+                    weight: 100,
+                    tweakable: true,
+                },
+                ValueRow {
+                    value: "None".to_string(),
+                    weight: 900,
+                    tweakable: false,
+                },
+            ],
+            tweaked: false,
+        }];
+        assert_eq!(layers[0].share(&layers[0].values[0]), 10.0);
+
+        let patched = patched_config(&raw, &layers, 20).unwrap();
+        assert_eq!(
+            patched,
+            json!({"totalSupply": 20, "weights": {"Body": {"Robot": 5}}})
+        );
+
+        layers[0].values[0].weight += WEIGHT_STEP;
+        layers[0].tweaked = true;
+        let patched = patched_config(&raw, &layers, 20).unwrap();
+        assert_eq!(
+            patched["weights"],
+            json!({"Body": {"Robot": 5}, "Eyes": {"Laser": "#110"}})
+        );
+    }
+}
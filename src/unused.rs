@@ -0,0 +1,181 @@
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use crate::paths::LayerLayout;
+use crate::permutations::is_skipped;
+use crate::rules::Rules;
+use crate::{calculate_weights_and_total, is_file_asset, path_trait, write_json, CustomError};
+
+pub const UNUSED_ASSETS_FILE_NAME: &str = "unused-assets.json";
+
+/// The likely reason an asset never made it into the collection, the first
+/// one that applies.
+#[derive(Debug, Serialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum UnusedReason {
+    /// Matched by `skippedTraits`.
+    Skipped,
+    ZeroWeight,
+    /// Not in any pool the forced combinations gave a share of the supply.
+    ForcedOut,
+    /// Not allowed by a dependency rule when its condition holds.
+    RestrictedByRules,
+    /// Could be drawn but wasn't; its weight may be too low for the supply.
+    NotDrawn,
+}
+
+impl fmt::Display for UnusedReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnusedReason::Skipped => write!(f, "matched by skippedTraits"),
+            UnusedReason::ZeroWeight => write!(f, "weighted 0"),
+            UnusedReason::ForcedOut => write!(f, "left out by the forced combinations"),
+            UnusedReason::RestrictedByRules => write!(f, "restricted by dependency rules"),
+            UnusedReason::NotDrawn => write!(f, "never drawn"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UnusedAsset {
+    pub file: String,
+    pub trait_type: String,
+    pub value: String,
+    pub reason: UnusedReason,
+    /// The share its weight gives it within its layer.
+    pub expected_percentage: f64,
+}
+
+/// Layer files no token was drawn with. `pools` holds the layers of every
+/// pool given some supply.
+pub fn unused_assets(
+    layout: &LayerLayout,
+    all_layers: &[Vec<String>],
+    tokens: &[Vec<String>],
+    pools: &[&[Vec<String>]],
+    skipped_traits: &[Regex],
+    rules: &Rules,
+) -> Vec<UnusedAsset> {
+    let used: HashSet<&String> = tokens.iter().flatten().collect();
+    let mut unused = Vec::new();
+
+    for (index, layer) in all_layers.iter().enumerate() {
+        let (cumulative_weights, total_weight) = calculate_weights_and_total(layout, layer);
+        let mut previous_weight = 0;
+        for (path, cumulative_weight) in layer.iter().zip(cumulative_weights) {
+            let weight = cumulative_weight - previous_weight;
+            previous_weight = cumulative_weight;
+            if !is_file_asset(path) || used.contains(path) {
+                continue;
+            }
+            let Some((trait_type, value)) = path_trait(layout, path) else {
+                continue;
+            };
+
+            let reason = if is_skipped(path, skipped_traits) {
+                UnusedReason::Skipped
+            } else if weight == 0 && total_weight > 0 {
+                UnusedReason::ZeroWeight
+            } else if !pools
+                .iter()
+                .any(|pool| pool.get(index).is_some_and(|layer| layer.contains(path)))
+            {
+                UnusedReason::ForcedOut
+            } else if rules.restricts(&trait_type, &value) {
+                UnusedReason::RestrictedByRules
+            } else {
+                UnusedReason::NotDrawn
+            };
+            let expected_percentage = if total_weight == 0 {
+                100.0 / layer.len() as f64
+            } else {
+                weight as f64 * 100.0 / total_weight as f64
+            };
+            unused.push(UnusedAsset {
+                file: path.clone(),
+                trait_type,
+                value,
+                reason,
+                expected_percentage,
+            });
+        }
+    }
+
+    unused
+}
+
+pub fn write_unused_assets(output_path: &Path, unused: &[UnusedAsset]) -> Result<(), CustomError> {
+    let file_path = output_path.join(UNUSED_ASSETS_FILE_NAME);
+    write_json(&file_path, unused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::none_asset;
+    use crate::rules::DependencyRule;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_unused_assets() {
+        let layers = vec![
+            vec![
+                "./images/Background/Blue#10.png".to_string(),
+                "./images/Background/Grey#0.png".to_string(),
+                "./images/Background/Red#10.png".to_string(),
+                "./images/Background/Old#10.png".to_string(),
+            ],
+            vec![
+                "./images/Eyes/Laser#10.png".to_string(),
+                "./images/Eyes/Round#10.png".to_string(),
+                "./images/Eyes/Visor#10.png".to_string(),
+                none_asset("./images/Eyes", 10),
+            ],
+        ];
+        let tokens = vec![vec![layers[0][0].clone(), layers[1][0].clone()]];
+        // A forced combination keeps Red to its own pool, which got no supply.
+        let pool = vec![
+            vec![
+                layers[0][0].clone(),
+                layers[0][1].clone(),
+                layers[0][3].clone(),
+            ],
+            layers[1].clone(),
+        ];
+        let rules: Vec<DependencyRule> = serde_json::from_str(
+            r#"[{"if": {"layer": "Background", "value": "Blue"},
+                 "then": {"layer": "Eyes", "values": ["Laser", "Round"]}}]"#,
+        )
+        .unwrap();
+        let names = vec!["Background".to_string(), "Eyes".to_string()];
+        let rules = Rules::compile(&rules, &HashMap::new(), &names).unwrap();
+
+        let unused = unused_assets(
+            &LayerLayout::new("./images"),
+            &layers,
+            &tokens,
+            &[&pool],
+            &[Regex::new("Old").unwrap()],
+            &rules,
+        );
+        let reasons: Vec<(&str, UnusedReason)> = unused
+            .iter()
+            .map(|asset| (asset.value.as_str(), asset.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                ("Grey", UnusedReason::ZeroWeight),
+                ("Red", UnusedReason::ForcedOut),
+                ("Old", UnusedReason::Skipped),
+                ("Round", UnusedReason::NotDrawn),
+                ("Visor", UnusedReason::RestrictedByRules),
+            ]
+        );
+        assert_eq!(unused[3].expected_percentage, 25.0);
+    }
+}
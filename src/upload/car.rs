@@ -0,0 +1,356 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use walkdir::WalkDir;
+
+const CID_VERSION: u64 = 1;
+const RAW_CODEC: u64 = 0x55;
+const DAG_PB_CODEC: u64 = 0x70;
+const SHA2_256_CODE: u64 = 0x12;
+const CHUNK_SIZE: usize = 256 * 1024;
+const MAX_LINKS_PER_NODE: usize = 174;
+const UNIXFS_DIRECTORY: u64 = 1;
+const UNIXFS_FILE: u64 = 2;
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cid(Vec<u8>);
+
+impl Cid {
+    fn new(codec: u64, data: &[u8]) -> Cid {
+        let digest = Sha256::digest(data);
+        let mut bytes = Vec::with_capacity(4 + digest.len());
+        write_varint(&mut bytes, CID_VERSION);
+        write_varint(&mut bytes, codec);
+        write_varint(&mut bytes, SHA2_256_CODE);
+        write_varint(&mut bytes, digest.len() as u64);
+        bytes.extend_from_slice(&digest);
+        Cid(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "b{}", base32_lower(&self.0))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub cid: Cid,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct CarArchive {
+    pub root: Cid,
+    pub blocks: Vec<Block>,
+}
+
+struct DagNode {
+    cid: Cid,
+    tsize: u64,
+    filesize: u64,
+}
+
+impl CarArchive {
+    /// Serializes the archive into CARv1 payloads of at most `max_bytes` each.
+    /// Every payload carries the same root so the remote side can reassemble the
+    /// DAG from any subset of them. A block bigger than `max_bytes` gets a
+    /// payload of its own.
+    pub fn to_chunks(&self, max_bytes: usize) -> Vec<Vec<u8>> {
+        let header = car_header(&self.root);
+        let mut chunks = Vec::new();
+        let mut current = header.clone();
+
+        for block in &self.blocks {
+            let section = car_section(block);
+            if current.len() > header.len() && current.len() + section.len() > max_bytes {
+                chunks.push(std::mem::replace(&mut current, header.clone()));
+            }
+            current.extend_from_slice(&section);
+        }
+
+        chunks.push(current);
+        chunks
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_chunks(usize::MAX).remove(0)
+    }
+}
+
+/// Packs `dir` as a UnixFS directory of the files `include` keeps, leaving
+/// out the folders none of them are in.
+pub fn pack_directory(dir: &Path, include: &dyn Fn(&Path) -> bool) -> io::Result<CarArchive> {
+    let mut blocks = Vec::new();
+    let root = pack_dir_node(dir, include, &mut blocks)?;
+    Ok(CarArchive {
+        root: root.cid,
+        blocks,
+    })
+}
+
+fn pack_dir_node(
+    dir: &Path,
+    include: &dyn Fn(&Path) -> bool,
+    blocks: &mut Vec<Block>,
+) -> io::Result<DagNode> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut links = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let path = entry.path();
+        let node = if path.is_dir() {
+            if !WalkDir::new(&path)
+                .into_iter()
+                .filter_map(Result::ok)
+                .any(|entry| entry.file_type().is_file() && include(entry.path()))
+            {
+                continue;
+            }
+            pack_dir_node(&path, include, blocks)?
+        } else if include(&path) {
+            pack_file(&fs::read(&path)?, blocks)
+        } else {
+            continue;
+        };
+        links.push((entry.file_name().to_string_lossy().into_owned(), node));
+    }
+
+    let mut unixfs = Vec::new();
+    write_varint_field(&mut unixfs, 1, UNIXFS_DIRECTORY);
+
+    let mut pb_node = Vec::new();
+    for (name, node) in &links {
+        write_bytes_field(&mut pb_node, 2, &pb_link(node, Some(name)));
+    }
+    write_bytes_field(&mut pb_node, 1, &unixfs);
+
+    Ok(push_dag_pb_block(
+        pb_node,
+        &links.iter().map(|(_, n)| n).collect::<Vec<_>>(),
+        0,
+        blocks,
+    ))
+}
+
+fn pack_file(data: &[u8], blocks: &mut Vec<Block>) -> DagNode {
+    let mut nodes: Vec<DagNode> = if data.is_empty() {
+        vec![push_raw_block(Vec::new(), blocks)]
+    } else {
+        data.chunks(CHUNK_SIZE)
+            .map(|chunk| push_raw_block(chunk.to_vec(), blocks))
+            .collect()
+    };
+
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(MAX_LINKS_PER_NODE)
+            .map(|children| file_parent_node(children, blocks))
+            .collect();
+    }
+
+    nodes.remove(0)
+}
+
+fn file_parent_node(children: &[DagNode], blocks: &mut Vec<Block>) -> DagNode {
+    let filesize: u64 = children.iter().map(|child| child.filesize).sum();
+
+    let mut unixfs = Vec::new();
+    write_varint_field(&mut unixfs, 1, UNIXFS_FILE);
+    write_varint_field(&mut unixfs, 3, filesize);
+    for child in children {
+        write_varint_field(&mut unixfs, 4, child.filesize);
+    }
+
+    let mut pb_node = Vec::new();
+    for child in children {
+        write_bytes_field(&mut pb_node, 2, &pb_link(child, None));
+    }
+    write_bytes_field(&mut pb_node, 1, &unixfs);
+
+    push_dag_pb_block(
+        pb_node,
+        &children.iter().collect::<Vec<_>>(),
+        filesize,
+        blocks,
+    )
+}
+
+fn push_raw_block(data: Vec<u8>, blocks: &mut Vec<Block>) -> DagNode {
+    let cid = Cid::new(RAW_CODEC, &data);
+    let size = data.len() as u64;
+    blocks.push(Block {
+        cid: cid.clone(),
+        data,
+    });
+    DagNode {
+        cid,
+        tsize: size,
+        filesize: size,
+    }
+}
+
+fn push_dag_pb_block(
+    data: Vec<u8>,
+    children: &[&DagNode],
+    filesize: u64,
+    blocks: &mut Vec<Block>,
+) -> DagNode {
+    let cid = Cid::new(DAG_PB_CODEC, &data);
+    let tsize = data.len() as u64 + children.iter().map(|child| child.tsize).sum::<u64>();
+    blocks.push(Block {
+        cid: cid.clone(),
+        data,
+    });
+    DagNode {
+        cid,
+        tsize,
+        filesize,
+    }
+}
+
+fn pb_link(node: &DagNode, name: Option<&str>) -> Vec<u8> {
+    let mut link = Vec::new();
+    write_bytes_field(&mut link, 1, node.cid.as_bytes());
+    if let Some(name) = name {
+        write_bytes_field(&mut link, 2, name.as_bytes());
+    }
+    write_varint_field(&mut link, 3, node.tsize);
+    link
+}
+
+fn car_header(root: &Cid) -> Vec<u8> {
+    // DAG-CBOR encoding of {"roots": [root], "version": 1}.
+    let mut cbor = vec![0xa2, 0x65];
+    cbor.extend_from_slice(b"roots");
+    cbor.extend_from_slice(&[0x81, 0xd8, 0x2a]);
+    let cid_len = root.as_bytes().len() + 1;
+    if cid_len < 24 {
+        cbor.push(0x40 | cid_len as u8);
+    } else {
+        cbor.extend_from_slice(&[0x58, cid_len as u8]);
+    }
+    cbor.push(0x00);
+    cbor.extend_from_slice(root.as_bytes());
+    cbor.push(0x67);
+    cbor.extend_from_slice(b"version");
+    cbor.push(0x01);
+
+    let mut header = Vec::with_capacity(cbor.len() + 2);
+    write_varint(&mut header, cbor.len() as u64);
+    header.extend_from_slice(&cbor);
+    header
+}
+
+fn car_section(block: &Block) -> Vec<u8> {
+    let cid = block.cid.as_bytes();
+    let mut section = Vec::with_capacity(cid.len() + block.data.len() + 4);
+    write_varint(&mut section, (cid.len() + block.data.len()) as u64);
+    section.extend_from_slice(cid);
+    section.extend_from_slice(&block.data);
+    section
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn write_varint_field(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buffer, field << 3);
+    write_varint(buffer, value);
+}
+
+fn write_bytes_field(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(buffer, (field << 3) | 2);
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn base32_lower(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_varint() {
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, 1);
+        write_varint(&mut buffer, 300);
+        assert_eq!(buffer, vec![0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_cid_display() {
+        assert_eq!(
+            Cid::new(RAW_CODEC, b"hello world").to_string(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+    }
+
+    #[test]
+    fn test_pack_empty_directory() {
+        let dir = tempdir().unwrap();
+        let archive = pack_directory(dir.path(), &|_| true).unwrap();
+
+        assert_eq!(
+            archive.root.to_string(),
+            "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354"
+        );
+        assert_eq!(archive.blocks.len(), 1);
+    }
+
+    #[test]
+    fn test_pack_directory_chunks() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("1.json"), b"{}").unwrap();
+        fs::write(dir.path().join("0.png"), vec![7u8; CHUNK_SIZE + 10]).unwrap();
+
+        let archive = pack_directory(dir.path(), &|_| true).unwrap();
+        // Two leaves and a parent for the png, one leaf for the json, plus the root.
+        assert_eq!(archive.blocks.len(), 5);
+        assert_eq!(archive.blocks.last().unwrap().cid, archive.root);
+
+        let header = car_header(&archive.root);
+        let chunks = archive.to_chunks(CHUNK_SIZE);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.starts_with(&header));
+        }
+
+        let payload: usize = chunks.iter().map(|chunk| chunk.len() - header.len()).sum();
+        assert_eq!(payload + header.len(), archive.to_bytes().len());
+    }
+}
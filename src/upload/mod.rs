@@ -0,0 +1,209 @@
+pub mod car;
+mod nft_storage;
+mod s3;
+
+use clap::Subcommand;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use crate::file_names::FileNames;
+use crate::localization::LOCALIZED_DIR_NAME;
+use crate::{Config, CustomError};
+
+/// Extensions of the images and animations of the tokens.
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "svg", "mp4", "webm", "mp3", "wav",
+];
+
+/// The files of `output_path` that are uploaded: the images and animations at
+/// the top of the image folder, the metadata of the tokens and its localized
+/// copies. The manifest and the reports written next to them stay local.
+pub fn public_files(
+    output_path: &Path,
+    file_names: &FileNames,
+) -> Result<BTreeSet<PathBuf>, CustomError> {
+    let media = WalkDir::new(file_names.image_dir(output_path))
+        .max_depth(1)
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| MEDIA_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        });
+    let localized = WalkDir::new(output_path.join(LOCALIZED_DIR_NAME))
+        .min_depth(2)
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"));
+    let metadata = file_names
+        .token_files(output_path)?
+        .into_iter()
+        .map(|(_, path)| path)
+        .filter(|path| path.is_file());
+    Ok(media.chain(localized).chain(metadata).collect())
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UploadTarget {
+    /// Pack the output folder into a CAR file and upload it to NFT.Storage.
+    NftStorage(nft_storage::NftStorageArgs),
+    /// Upload images and metadata to an S3-compatible bucket (AWS S3, R2, MinIO).
+    S3(s3::S3Args),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RetryError {
+    Transient(String),
+    Permanent(String),
+}
+
+pub fn run(target: &UploadTarget, config: &Config) -> Result<(), Box<dyn Error>> {
+    let output_path = Path::new(&config.output_path);
+    let file_names = FileNames::compile(config)?;
+    let files = public_files(output_path, &file_names)?;
+    match target {
+        UploadTarget::NftStorage(args) => {
+            let root = nft_storage::upload(args, output_path, &files)?;
+            info!("Collection available at ipfs://{}", root);
+        }
+        UploadTarget::S3(args) => {
+            let image_dir = file_names.image_dir(output_path);
+            let public_url = s3::upload(args, output_path, &image_dir, &files)?;
+            info!("Collection available at {}", public_url);
+        }
+    }
+    Ok(())
+}
+
+pub fn classify_http_error(error: ureq::Error) -> RetryError {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let msg = format!(
+                "status {}: {}",
+                code,
+                response.into_string().unwrap_or_default()
+            );
+            if code == 429 || code >= 500 {
+                RetryError::Transient(msg)
+            } else {
+                RetryError::Permanent(msg)
+            }
+        }
+        ureq::Error::Transport(transport) => RetryError::Transient(transport.to_string()),
+    }
+}
+
+pub fn retry_with_backoff<T>(
+    max_retries: u32,
+    base_delay: Duration,
+    mut operation: impl FnMut() -> Result<T, RetryError>,
+) -> Result<T, CustomError> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Ok(value) => return Ok(value),
+            Err(RetryError::Transient(msg)) if attempt < max_retries => {
+                let delay = base_delay * 2u32.pow(attempt);
+                warn!(
+                    "Upload attempt {} failed: {}. Retrying in {:?}.",
+                    attempt + 1,
+                    msg,
+                    delay
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(RetryError::Transient(msg)) | Err(RetryError::Permanent(msg)) => {
+                return Err(CustomError::Upload(msg))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::GenerateArgs;
+    use crate::init::{scaffold, Answers};
+    use crate::{generate, load_config};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_retry_with_backoff() {
+        let mut calls = 0;
+        let result = retry_with_backoff(3, Duration::ZERO, || {
+            calls += 1;
+            if calls < 3 {
+                Err(RetryError::Transient("timeout".to_string()))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result, Ok(3));
+
+        let mut calls = 0;
+        let result: Result<(), CustomError> = retry_with_backoff(3, Duration::ZERO, || {
+            calls += 1;
+            Err(RetryError::Permanent("unauthorized".to_string()))
+        });
+        assert_eq!(result, Err(CustomError::Upload("unauthorized".to_string())));
+        assert_eq!(calls, 1);
+
+        let mut calls = 0;
+        let result: Result<(), CustomError> = retry_with_backoff(2, Duration::ZERO, || {
+            calls += 1;
+            Err(RetryError::Transient("timeout".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_public_files() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        let base_path = dir.path().join("images").display().to_string();
+        let answers = Answers {
+            name: "Upload".to_string(),
+            supply: 4,
+            width: 8,
+            height: 8,
+        };
+        scaffold(&config_path, &base_path, &answers, false).unwrap();
+        let mut config = load_config(&config_path.display().to_string(), &[]).unwrap();
+        config.output_path = dir.path().join("output").display().to_string();
+        generate(config.clone(), &GenerateArgs::default()).unwrap();
+
+        let output_path = Path::new(&config.output_path);
+        let reports: Vec<PathBuf> = WalkDir::new(output_path)
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|entry| entry.into_path())
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(|stem| stem.parse::<usize>().is_err())
+            })
+            .collect();
+        assert!(reports.contains(&output_path.join("dna.json")));
+        assert!(reports.contains(&output_path.join("rarity.json")));
+
+        let files = public_files(output_path, &FileNames::compile(&config).unwrap()).unwrap();
+        assert_eq!(files.len(), 8);
+        for id in 0..4 {
+            assert!(files.contains(&output_path.join(format!("{}.png", id))));
+            assert!(files.contains(&output_path.join(format!("{}.json", id))));
+        }
+        assert!(reports.iter().all(|report| !files.contains(report)));
+    }
+}
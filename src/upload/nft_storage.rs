@@ -0,0 +1,192 @@
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+use super::car::pack_directory;
+use super::{classify_http_error, retry_with_backoff, RetryError};
+use crate::{disk_space, CustomError};
+
+const DEFAULT_ENDPOINT: &str = "https://api.nft.storage/upload";
+const STATE_FILE_NAME: &str = "upload-state.json";
+
+#[derive(Debug, Args)]
+pub struct NftStorageArgs {
+    /// NFT.Storage API token.
+    #[arg(long, env = "NFT_STORAGE_TOKEN", hide_env_values = true)]
+    token: String,
+    /// Folder where the packed CAR file and the resumable upload state are written.
+    #[arg(long, default_value = "./car/")]
+    car_path: String,
+    /// Maximum size of each uploaded CAR chunk, in megabytes.
+    #[arg(long, default_value_t = 50)]
+    chunk_size_mb: usize,
+    /// Number of retries per CAR chunk before giving up.
+    #[arg(long, default_value_t = 5)]
+    max_retries: u32,
+    /// NFT.Storage upload endpoint.
+    #[arg(long, default_value = DEFAULT_ENDPOINT)]
+    endpoint: String,
+    /// Write the CAR file even when it doesn't fit in the free disk space.
+    #[arg(long)]
+    ignore_space: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct UploadState {
+    root: String,
+    total_chunks: usize,
+    uploaded_chunks: Vec<usize>,
+}
+
+impl UploadState {
+    fn read(path: &Path) -> Option<UploadState> {
+        let contents = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    fn load(path: &Path, root: &str, total_chunks: usize) -> UploadState {
+        UploadState::read(path)
+            .filter(|state| state.root == root && state.total_chunks == total_chunks)
+            .unwrap_or_else(|| UploadState {
+                root: root.to_string(),
+                total_chunks,
+                uploaded_chunks: Vec::new(),
+            })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), CustomError> {
+        let serialized =
+            serde_json::to_string_pretty(self).map_err(|e| CustomError::Upload(e.to_string()))?;
+        fs::write(path, serialized).map_err(|e| CustomError::Upload(e.to_string()))
+    }
+}
+
+pub fn upload(
+    args: &NftStorageArgs,
+    output_path: &Path,
+    files: &BTreeSet<PathBuf>,
+) -> Result<String, CustomError> {
+    // The CAR holds the files and a few bytes per block; the one of the last
+    // upload is replaced, so its space is counted as free.
+    let car_path = PathBuf::from(&args.car_path);
+    let state_path = car_path.join(STATE_FILE_NAME);
+    let previous_car =
+        UploadState::read(&state_path).map(|state| car_path.join(format!("{}.car", state.root)));
+    let needed = files
+        .iter()
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let reclaimed = previous_car
+        .as_ref()
+        .and_then(|path| fs::metadata(path).ok())
+        .map_or(0, |metadata| metadata.len());
+    disk_space::ensure_space(&car_path, needed, reclaimed, args.ignore_space)?;
+
+    let archive = pack_directory(output_path, &|path| files.contains(path))
+        .map_err(|e| CustomError::Upload(format!("{}: {}", output_path.display(), e)))?;
+    let root = archive.root.to_string();
+    let chunks = archive.to_chunks(args.chunk_size_mb.max(1) * 1024 * 1024);
+
+    fs::create_dir_all(&car_path).map_err(|e| CustomError::Upload(e.to_string()))?;
+    let car_file = car_path.join(format!("{}.car", root));
+    if let Some(previous_car) = previous_car.filter(|path| *path != car_file) {
+        fs::remove_file(previous_car).ok();
+    }
+    fs::write(car_file, archive.to_bytes()).map_err(|e| CustomError::Upload(e.to_string()))?;
+
+    let mut state = UploadState::load(&state_path, &root, chunks.len());
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        if state.uploaded_chunks.contains(&index) {
+            info!(
+                "Skipping CAR {}/{}: already uploaded.",
+                index + 1,
+                chunks.len()
+            );
+            continue;
+        }
+
+        retry_with_backoff(args.max_retries, Duration::from_secs(1), || {
+            post_car(&args.endpoint, &args.token, chunk)
+        })?;
+
+        state.uploaded_chunks.push(index);
+        state.save(&state_path)?;
+        info!("Uploaded CAR {}/{}.", index + 1, chunks.len());
+    }
+
+    Ok(root)
+}
+
+fn post_car(endpoint: &str, token: &str, car: &[u8]) -> Result<String, RetryError> {
+    let response = ureq::post(endpoint)
+        .set("Authorization", &format!("Bearer {}", token))
+        .set("Content-Type", "application/car")
+        .send_bytes(car)
+        .map_err(classify_http_error)?;
+
+    let body: Value = response
+        .into_json()
+        .map_err(|e| RetryError::Transient(e.to_string()))?;
+    body.pointer("/value/cid")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| RetryError::Permanent(format!("unexpected response: {}", body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_upload_state_resume() {
+        let dir = tempdir().unwrap();
+        let state_path = dir.path().join(STATE_FILE_NAME);
+
+        let mut state = UploadState::load(&state_path, "bafyroot", 3);
+        assert!(state.uploaded_chunks.is_empty());
+
+        state.uploaded_chunks.push(0);
+        state.save(&state_path).unwrap();
+
+        let resumed = UploadState::load(&state_path, "bafyroot", 3);
+        assert_eq!(resumed, state);
+
+        let other_root = UploadState::load(&state_path, "bafyother", 3);
+        assert!(other_root.uploaded_chunks.is_empty());
+    }
+
+    #[test]
+    fn test_upload_out_of_space() {
+        let dir = tempdir().unwrap();
+        let output_path = dir.path().join("output");
+        fs::create_dir_all(&output_path).unwrap();
+        fs::write(output_path.join("0.png"), vec![0u8; 64]).unwrap();
+        let args = NftStorageArgs {
+            token: String::new(),
+            car_path: dir.path().join("car").display().to_string(),
+            chunk_size_mb: 50,
+            max_retries: 0,
+            endpoint: "http://127.0.0.1:9".to_string(),
+            ignore_space: false,
+        };
+
+        disk_space::FREE_SPACE.with(|free_space| free_space.set(Some(1)));
+        let result = upload(
+            &args,
+            &output_path,
+            &BTreeSet::from([output_path.join("0.png")]),
+        );
+        disk_space::FREE_SPACE.with(|free_space| free_space.set(None));
+        assert!(matches!(result, Err(CustomError::DiskSpace(_))));
+        assert!(!dir.path().join("car").exists());
+    }
+}
@@ -0,0 +1,161 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use crate::paths::LayerLayout;
+use crate::{get_image_paths_recursive, trait_path_parts, CustomError};
+
+lazy_static! {
+    /// A variant folder, `{Body=Robot}`: its assets replace the ones of the
+    /// same name in tokens with that trait.
+    static ref RE_CONDITION: Regex = Regex::new(r"^\{([^=]+)=([^}]+)\}$").unwrap();
+}
+
+/// The trait a variant folder is for, `(layer, value)`.
+fn condition(folder_name: &OsStr) -> Option<(String, String)> {
+    let captures = RE_CONDITION.captures(folder_name.to_str()?)?;
+    Some((captures[1].to_string(), captures[2].to_string()))
+}
+
+/// Whether a folder holds variants rather than values of its layer.
+pub fn is_variant_dir(folder_name: &OsStr) -> bool {
+    condition(folder_name).is_some()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Variant {
+    layer: String,
+    value: String,
+    path: String,
+}
+
+/// The variants of the layer assets, by the asset they stand for: its layer,
+/// sublayers and value, weights left out.
+#[derive(Debug, Default, Clone)]
+pub struct Variants {
+    variants: HashMap<Vec<String>, Vec<Variant>>,
+    layout: LayerLayout,
+}
+
+impl Variants {
+    /// Finds the variant folders of every layer of `layers`, given with its
+    /// folder. A variant must be for a trait of another layer of `names` and
+    /// stand for an asset of its own layer.
+    pub fn load(
+        layout: &LayerLayout,
+        layers: &[(String, String)],
+        names: &[String],
+    ) -> Result<Variants, CustomError> {
+        let mut variants: HashMap<Vec<String>, Vec<Variant>> = HashMap::new();
+        for (name, layer_dir) in layers {
+            let Ok(entries) = fs::read_dir(layer_dir) else {
+                continue;
+            };
+            let mut folders: Vec<_> = entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .filter_map(|path| Some((condition(path.file_name()?)?, path)))
+                .collect();
+            if folders.is_empty() {
+                continue;
+            }
+            folders.sort();
+
+            let assets: Vec<Vec<String>> = get_image_paths_recursive(Path::new(layer_dir))
+                .iter()
+                .map(|path| trait_path_parts(layout, path))
+                .collect();
+            for ((layer, value), folder) in folders {
+                if &layer == name || !names.contains(&layer) {
+                    return Err(CustomError::InvalidTrait(format!(
+                        "variant folder {} is for {}, which is not another layer",
+                        folder.display(),
+                        layer
+                    )));
+                }
+                for path in get_image_paths_recursive(&folder) {
+                    let mut key = trait_path_parts(layout, &path);
+                    key.retain(|part| !RE_CONDITION.is_match(part));
+                    if !assets.contains(&key) {
+                        return Err(CustomError::InvalidTrait(format!(
+                            "variant {} stands for no asset of {}",
+                            path, name
+                        )));
+                    }
+                    variants.entry(key).or_default().push(Variant {
+                        layer: layer.clone(),
+                        value: value.clone(),
+                        path,
+                    });
+                }
+            }
+        }
+        Ok(Variants {
+            variants,
+            layout: layout.clone(),
+        })
+    }
+
+    /// The file to draw for the asset `path` of a token with `traits`: the
+    /// first variant for one of them, or the asset itself.
+    pub fn resolve<'a>(&'a self, path: &'a str, traits: &[(String, String)]) -> &'a str {
+        if self.variants.is_empty() {
+            return path;
+        }
+        self.variants
+            .get(&trait_path_parts(&self.layout, path))
+            .and_then(|variants| {
+                variants.iter().find(|variant| {
+                    traits
+                        .iter()
+                        .any(|(layer, value)| *layer == variant.layer && *value == variant.value)
+                })
+            })
+            .map_or(path, |variant| variant.path.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_variants() {
+        let dir = tempdir().unwrap();
+        let eyes = dir.path().join("Eyes");
+        let robot = eyes.join("{Body=Robot}");
+        fs::create_dir_all(&robot).unwrap();
+        for path in [
+            eyes.join("Laser#10.png"),
+            eyes.join("Normal#90.png"),
+            robot.join("Laser.png"),
+        ] {
+            fs::write(path, b"").unwrap();
+        }
+        let layers = vec![("Eyes".to_string(), eyes.display().to_string())];
+        let names = vec!["Body".to_string(), "Eyes".to_string()];
+        let layout = LayerLayout::new(&dir.path().display().to_string());
+
+        assert_eq!(get_image_paths_recursive(&eyes).len(), 2);
+        let variants = Variants::load(&layout, &layers, &names).unwrap();
+        let laser = eyes.join("Laser#10.png").display().to_string();
+        let normal = eyes.join("Normal#90.png").display().to_string();
+        let robot_traits = vec![("Body".to_string(), "Robot".to_string())];
+        assert_eq!(
+            variants.resolve(&laser, &robot_traits),
+            robot.join("Laser.png").display().to_string()
+        );
+        assert_eq!(variants.resolve(&normal, &robot_traits), normal);
+        let human_traits = vec![("Body".to_string(), "Human".to_string())];
+        assert_eq!(variants.resolve(&laser, &human_traits), laser);
+
+        assert!(Variants::load(&layout, &layers, &names[1..]).is_err());
+        fs::write(robot.join("Sleepy.png"), b"").unwrap();
+        assert!(Variants::load(&layout, &layers, &names).is_err());
+    }
+}
@@ -0,0 +1,418 @@
+use image::RgbaImage;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use crate::file_names;
+use crate::CustomError;
+
+/// Videos composed with ffmpeg for the tokens with an animated layer, linked
+/// as their `animation_url` while the still image stays their `image`.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Video {
+    #[serde(default)]
+    pub format: VideoFormat,
+    /// Length of every video in seconds; shorter layer videos loop.
+    pub duration: f64,
+    #[serde(default = "default_frame_rate")]
+    pub frame_rate: u32,
+    /// The ffmpeg executable, looked up in `PATH` unless it is a path.
+    #[serde(default = "default_ffmpeg")]
+    pub ffmpeg: String,
+    /// The base URI of the videos; `imageUrl` by default.
+    pub url: Option<String>,
+}
+
+fn default_frame_rate() -> u32 {
+    30
+}
+
+pub fn default_ffmpeg() -> String {
+    "ffmpeg".to_string()
+}
+
+/// Checks that `ffmpeg`, given in the config `field`, can be run.
+pub fn check_ffmpeg(field: &str, ffmpeg: &str) -> Result<(), CustomError> {
+    Command::new(ffmpeg)
+        .arg("-version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(drop)
+        .map_err(|e| CustomError::Config(format!("{}: {} can't be run: {}", field, ffmpeg, e)))
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum VideoFormat {
+    /// H.264, which every marketplace plays, without transparency.
+    #[default]
+    Mp4,
+    /// VP9, keeping the transparency.
+    Webm,
+}
+
+impl VideoFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Mp4 => "mp4",
+            VideoFormat::Webm => "webm",
+        }
+    }
+
+    fn audio_codec(self) -> &'static str {
+        match self {
+            VideoFormat::Mp4 => "aac",
+            VideoFormat::Webm => "libopus",
+        }
+    }
+
+    fn codec_args(self) -> &'static [&'static str] {
+        match self {
+            // yuv420p needs an even width and height.
+            VideoFormat::Mp4 => &[
+                "-c:v",
+                "libx264",
+                "-pix_fmt",
+                "yuv420p",
+                "-vf",
+                "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+                "-movflags",
+                "+faststart",
+            ],
+            VideoFormat::Webm => &[
+                "-c:v",
+                "libvpx-vp9",
+                "-pix_fmt",
+                "yuva420p",
+                "-b:v",
+                "0",
+                "-crf",
+                "30",
+            ],
+        }
+    }
+}
+
+/// The extensions of the videos an asset can be paired with.
+const VIDEO_EXTENSIONS: [&str; 3] = ["webm", "mov", "mp4"];
+
+/// Whether `path` is a video ffmpeg can compose.
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            VIDEO_EXTENSIONS
+                .iter()
+                .any(|video| video.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// The video an asset is animated with: the file next to it with its name and
+/// a video extension, e.g. `Eyes/Laser#10.webm` for `Eyes/Laser#10.png`.
+pub fn companion(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    // `None` assets, recolored assets and generated backgrounds have no file.
+    path.extension()?;
+    VIDEO_EXTENSIONS
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|video| video.is_file())
+}
+
+/// The `video` of a run, checked.
+#[derive(Debug, Clone)]
+pub struct VideoSettings {
+    video: Video,
+    url: String,
+}
+
+impl VideoSettings {
+    /// Checks the duration and the frame rate, and that ffmpeg runs.
+    pub fn compile(video: &Video, image_url: &str) -> Result<VideoSettings, CustomError> {
+        let invalid = |reason: String| CustomError::Config(format!("video: {}", reason));
+        if !(video.duration > 0.0 && video.duration.is_finite()) {
+            return Err(invalid(format!(
+                "duration {} is not above 0",
+                video.duration
+            )));
+        }
+        if video.frame_rate == 0 {
+            return Err(invalid("frameRate must be at least 1".to_string()));
+        }
+        check_ffmpeg("video", &video.ffmpeg)?;
+        Ok(VideoSettings {
+            video: video.clone(),
+            url: video.url.as_deref().unwrap_or(image_url).to_string(),
+        })
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.video.format.extension()
+    }
+
+    /// The number of frames of every video.
+    pub fn frame_count(&self) -> usize {
+        (self.video.duration * self.video.frame_rate as f64)
+            .round()
+            .max(1.0) as usize
+    }
+
+    /// The `animation_url` of the video `file_name`.
+    pub fn animation_url(&self, file_name: &str) -> Option<String> {
+        file_names::file_url(&self.url, file_name)
+    }
+
+    fn decode_args(&self, video: &Path, width: u32, height: u32) -> Vec<OsString> {
+        let mut args: Vec<OsString> = ["-v", "error", "-nostdin", "-stream_loop", "-1"]
+            .map(OsString::from)
+            .to_vec();
+        // ffmpeg's own VP9 decoder drops the alpha channel.
+        if video
+            .extension()
+            .is_some_and(|extension| extension == "webm")
+        {
+            args.extend(["-c:v", "libvpx-vp9"].map(OsString::from));
+        }
+        args.push("-i".into());
+        args.push(video.into());
+        args.extend(
+            [
+                "-vf".to_string(),
+                format!("fps={},scale={}:{}", self.video.frame_rate, width, height),
+                "-frames:v".to_string(),
+                self.frame_count().to_string(),
+                "-f".to_string(),
+                "rawvideo".to_string(),
+                "-pix_fmt".to_string(),
+                "rgba".to_string(),
+                "-".to_string(),
+            ]
+            .map(OsString::from),
+        );
+        args
+    }
+
+    fn encode_args(
+        &self,
+        output: &Path,
+        width: u32,
+        height: u32,
+        audio: Option<&Path>,
+    ) -> Vec<OsString> {
+        let mut args: Vec<OsString> = [
+            "-v".to_string(),
+            "error".to_string(),
+            "-y".to_string(),
+            "-f".to_string(),
+            "rawvideo".to_string(),
+            "-pix_fmt".to_string(),
+            "rgba".to_string(),
+            "-s".to_string(),
+            format!("{}x{}", width, height),
+            "-r".to_string(),
+            self.video.frame_rate.to_string(),
+            "-i".to_string(),
+            "-".to_string(),
+        ]
+        .map(OsString::from)
+        .to_vec();
+        // The audio is cut to the length of the video.
+        if let Some(audio) = audio {
+            args.push("-i".into());
+            args.push(audio.into());
+            args.extend(
+                [
+                    "-map",
+                    "0:v",
+                    "-map",
+                    "1:a",
+                    "-c:a",
+                    self.video.format.audio_codec(),
+                    "-shortest",
+                ]
+                .map(OsString::from),
+            );
+        }
+        args.extend(self.video.format.codec_args().iter().map(OsString::from));
+        args.push(output.into());
+        args
+    }
+
+    /// Starts decoding `video`, looped and scaled to `width` by `height`, into
+    /// frames.
+    pub fn decode(&self, video: &Path, width: u32, height: u32) -> Result<Decoder, CustomError> {
+        let mut child = Command::new(&self.video.ffmpeg)
+            .args(self.decode_args(video, width, height))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| CustomError::Video(format!("{}: {}", video.display(), e)))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Decoder {
+            child,
+            stdout,
+            width,
+            height,
+            label: video.display().to_string(),
+        })
+    }
+
+    /// Starts encoding frames of `width` by `height` into `output`, with
+    /// `audio` as its sound.
+    pub fn encode(
+        &self,
+        output: &Path,
+        width: u32,
+        height: u32,
+        audio: Option<&Path>,
+    ) -> Result<Encoder, CustomError> {
+        let mut child = Command::new(&self.video.ffmpeg)
+            .args(self.encode_args(output, width, height, audio))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| CustomError::Video(format!("{}: {}", output.display(), e)))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        Ok(Encoder {
+            child,
+            stdin,
+            label: output.display().to_string(),
+        })
+    }
+}
+
+/// Waits for an ffmpeg process, failing with what it printed when it failed.
+fn finish(child: Child, label: &str) -> Result<(), CustomError> {
+    let output = child
+        .wait_with_output()
+        .map_err(|e| CustomError::Video(format!("{}: {}", label, e)))?;
+    if !output.status.success() {
+        return Err(CustomError::Video(format!(
+            "{}: ffmpeg {}: {}",
+            label,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// The frames of a layer video, read as ffmpeg decodes them.
+pub struct Decoder {
+    child: Child,
+    stdout: ChildStdout,
+    width: u32,
+    height: u32,
+    label: String,
+}
+
+impl Decoder {
+    pub fn next_frame(&mut self) -> Result<RgbaImage, CustomError> {
+        let mut buffer = vec![0; self.width as usize * self.height as usize * 4];
+        if let Err(e) = self.stdout.read_exact(&mut buffer) {
+            let Decoder { child, label, .. } = self;
+            _ = child.kill();
+            return Err(CustomError::Video(format!(
+                "{}: no frame left to read: {}",
+                label, e
+            )));
+        }
+        Ok(
+            RgbaImage::from_raw(self.width, self.height, buffer)
+                .expect("the buffer fits the frame"),
+        )
+    }
+
+    pub fn finish(self) -> Result<(), CustomError> {
+        drop(self.stdout);
+        finish(self.child, &self.label)
+    }
+}
+
+/// A token video, written by ffmpeg as frames are handed to it.
+pub struct Encoder {
+    child: Child,
+    stdin: ChildStdin,
+    label: String,
+}
+
+impl Encoder {
+    pub fn write_frame(&mut self, frame: &RgbaImage) -> Result<(), CustomError> {
+        self.stdin
+            .write_all(frame.as_raw())
+            .map_err(|e| CustomError::Video(format!("{}: {}", self.label, e)))
+    }
+
+    pub fn finish(self) -> Result<(), CustomError> {
+        drop(self.stdin);
+        finish(self.child, &self.label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_video() {
+        let dir = tempdir().unwrap();
+        let still = dir.path().join("Laser#10.png");
+        std::fs::write(&still, b"").unwrap();
+        assert_eq!(companion(&still.display().to_string()), None);
+        std::fs::write(dir.path().join("Laser#10.webm"), b"").unwrap();
+        assert_eq!(
+            companion(&still.display().to_string()),
+            Some(dir.path().join("Laser#10.webm"))
+        );
+        assert_eq!(
+            companion(&dir.path().join("None#5").display().to_string()),
+            None
+        );
+
+        let video: Video =
+            serde_json::from_str(r#"{"format": "webm", "duration": 1.5, "frameRate": 24}"#)
+                .unwrap();
+        let settings = VideoSettings {
+            video: video.clone(),
+            url: "ipfs://bafy/".to_string(),
+        };
+        assert_eq!(settings.frame_count(), 36);
+        assert_eq!(
+            settings.animation_url("1.webm"),
+            Some("ipfs://bafy/1.webm".to_string())
+        );
+        let decode = settings.decode_args(Path::new("Laser#10.webm"), 64, 32);
+        assert!(decode.windows(2).any(|pair| pair == ["-c:v", "libvpx-vp9"]));
+        assert!(decode
+            .windows(2)
+            .any(|pair| pair == ["-vf", "fps=24,scale=64:32"]));
+        assert!(decode.windows(2).any(|pair| pair == ["-frames:v", "36"]));
+        let encode = settings.encode_args(Path::new("out/1.webm"), 64, 32, None);
+        assert!(encode.windows(2).any(|pair| pair == ["-s", "64x32"]));
+        assert!(!encode.iter().any(|arg| arg == "-shortest"));
+        assert_eq!(encode.last().unwrap(), "out/1.webm");
+        let encode = settings.encode_args(
+            Path::new("out/1.webm"),
+            64,
+            32,
+            Some(Path::new("out/1.mp3")),
+        );
+        assert!(encode.windows(2).any(|pair| pair == ["-i", "out/1.mp3"]));
+        assert!(encode.windows(2).any(|pair| pair == ["-c:a", "libopus"]));
+
+        let mut invalid = video.clone();
+        invalid.duration = 0.0;
+        assert!(VideoSettings::compile(&invalid, "").is_err());
+        let mut missing = video;
+        missing.ffmpeg = dir.path().join("no-ffmpeg").display().to_string();
+        assert!(VideoSettings::compile(&missing, "").is_err());
+    }
+}
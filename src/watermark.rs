@@ -0,0 +1,260 @@
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use image::{DynamicImage, Rgba, RgbaImage};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::blend::{self, BlendMode};
+use crate::palette::parse_color;
+use crate::CustomError;
+
+/// A mark drawn over the images of runs made with `--watermark`, so previews
+/// can be shared before the art is released.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Watermark {
+    /// A PNG drawn as the watermark.
+    pub image: Option<String>,
+    /// Text drawn as the watermark, in `font`.
+    pub text: Option<String>,
+    /// The TrueType or OpenType font of `text`.
+    pub font: Option<String>,
+    #[serde(default = "default_font_size")]
+    pub font_size: f32,
+    /// The `#rrggbb` color of `text`.
+    #[serde(default = "default_color")]
+    pub color: String,
+    #[serde(default)]
+    pub position: WatermarkPosition,
+    /// From 0, invisible, to 1, opaque.
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    /// Room in pixels between the watermark and the edges, or between the
+    /// tiles.
+    #[serde(default = "default_margin")]
+    pub margin: u32,
+}
+
+fn default_font_size() -> f32 {
+    32.0
+}
+
+fn default_color() -> String {
+    "#ffffff".to_string()
+}
+
+fn default_opacity() -> f32 {
+    0.5
+}
+
+fn default_margin() -> u32 {
+    16
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum WatermarkPosition {
+    #[default]
+    Center,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    /// Repeated over the whole image.
+    Tile,
+}
+
+/// A watermark ready to be drawn: its image, opacity applied, and where it
+/// goes.
+#[derive(Debug, Clone)]
+pub struct Stamp {
+    image: DynamicImage,
+    position: WatermarkPosition,
+    margin: u32,
+}
+
+impl Stamp {
+    /// Reads the image, or renders the text, of `watermark`.
+    pub fn compile(watermark: &Watermark) -> Result<Stamp, CustomError> {
+        let invalid = |reason: String| CustomError::Config(format!("watermark: {}", reason));
+        if !(0.0..=1.0).contains(&watermark.opacity) {
+            return Err(invalid(format!(
+                "opacity {} is not between 0 and 1",
+                watermark.opacity
+            )));
+        }
+        let mut image = match (&watermark.image, &watermark.text) {
+            (Some(path), None) => image::open(path)
+                .map_err(|e| invalid(format!("{}: {}", path, e)))?
+                .to_rgba8(),
+            (None, Some(text)) => {
+                let Some(font) = &watermark.font else {
+                    return Err(invalid("text needs a font".to_string()));
+                };
+                let color = parse_color(&watermark.color).map_err(|e| invalid(e.to_string()))?;
+                render_text(text, Path::new(font), watermark.font_size, color)?
+            }
+            _ => return Err(invalid("needs either an image or a text".to_string())),
+        };
+        for pixel in image.pixels_mut() {
+            pixel.0[3] = (pixel.0[3] as f32 * watermark.opacity).round() as u8;
+        }
+        Ok(Stamp {
+            image: DynamicImage::ImageRgba8(image),
+            position: watermark.position,
+            margin: watermark.margin,
+        })
+    }
+
+    /// `image` drawn as it is over the center.
+    pub fn centered(image: DynamicImage) -> Stamp {
+        Stamp {
+            image,
+            position: WatermarkPosition::Center,
+            margin: 0,
+        }
+    }
+
+    pub fn apply(&self, image: &mut RgbaImage) {
+        let (width, height) = (image.width() as i64, image.height() as i64);
+        let (stamp_width, stamp_height) = (self.image.width() as i64, self.image.height() as i64);
+        let margin = self.margin as i64;
+        let (x, y) = match self.position {
+            WatermarkPosition::Center => ((width - stamp_width) / 2, (height - stamp_height) / 2),
+            WatermarkPosition::TopLeft => (margin, margin),
+            WatermarkPosition::TopRight => (width - stamp_width - margin, margin),
+            WatermarkPosition::BottomLeft => (margin, height - stamp_height - margin),
+            WatermarkPosition::BottomRight => {
+                (width - stamp_width - margin, height - stamp_height - margin)
+            }
+            WatermarkPosition::Tile => {
+                let (step_x, step_y) = (stamp_width + margin, stamp_height + margin);
+                for y in (0..height).step_by(step_y.max(1) as usize) {
+                    for x in (0..width).step_by(step_x.max(1) as usize) {
+                        blend::draw_layer(image, &self.image, x, y, BlendMode::Normal);
+                    }
+                }
+                return;
+            }
+        };
+        blend::draw_layer(image, &self.image, x, y, BlendMode::Normal);
+    }
+}
+
+/// Renders `text` on one line, in `color` over a transparent background.
+fn render_text(
+    text: &str,
+    font_path: &Path,
+    font_size: f32,
+    color: [u8; 3],
+) -> Result<RgbaImage, CustomError> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        CustomError::Config(format!("watermark font {}: {}", font_path.display(), e))
+    };
+    let font = FontVec::try_from_vec(fs::read(font_path).map_err(|e| invalid(&e))?)
+        .map_err(|e| invalid(&e))?;
+    let scale = PxScale::from(font_size);
+    let scaled = font.as_scaled(scale);
+
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    let mut previous = None;
+    for character in text.chars() {
+        let id = scaled.glyph_id(character);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(scale, point(caret, scaled.ascent())));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+    }
+
+    let width = caret.ceil().max(1.0) as u32;
+    let height = (scaled.ascent() - scaled.descent()).ceil().max(1.0) as u32;
+    let mut image = RgbaImage::new(width, height);
+    let [r, g, b] = color;
+    for glyph in glyphs {
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|x, y, coverage| {
+            let (x, y) = (
+                bounds.min.x as i64 + x as i64,
+                bounds.min.y as i64 + y as i64,
+            );
+            if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                return;
+            }
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            let alpha = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+            *pixel = Rgba([r, g, b, pixel.0[3].max(alpha)]);
+        });
+    }
+    Ok(image)
+}
+
+/// Draws `stamp` over the image at `image_path`.
+pub fn stamp_file(image_path: &Path, stamp: &Stamp) -> Result<(), CustomError> {
+    let write_error = |e: &dyn std::fmt::Display| {
+        CustomError::WriteOutput(format!("{}: {}", image_path.display(), e))
+    };
+    let mut image = image::open(image_path)
+        .map_err(|e| write_error(&e))?
+        .to_rgba8();
+    stamp.apply(&mut image);
+    image.save(image_path).map_err(|e| write_error(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_watermark() {
+        let dir = tempdir().unwrap();
+        let image_path = dir.path().join("0.png");
+        RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255]))
+            .save(&image_path)
+            .unwrap();
+        let white = RgbaImage::from_pixel(2, 2, Rgba([255; 4]));
+
+        stamp_file(
+            &image_path,
+            &Stamp::centered(DynamicImage::ImageRgba8(white.clone())),
+        )
+        .unwrap();
+        let watermarked = image::open(&image_path).unwrap().to_rgba8();
+        assert_eq!(watermarked.get_pixel(1, 1), &Rgba([255; 4]));
+        assert_eq!(watermarked.get_pixel(2, 2), &Rgba([255; 4]));
+        assert_eq!(watermarked.get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(watermarked.get_pixel(3, 3), &Rgba([0, 0, 255, 255]));
+
+        let watermark_path = dir.path().join("watermark.png");
+        white.save(&watermark_path).unwrap();
+        let mut watermark: Watermark = serde_json::from_str(&format!(
+            r#"{{"image": {:?}, "position": "bottomRight", "opacity": 0.5, "margin": 1}}"#,
+            watermark_path.display().to_string()
+        ))
+        .unwrap();
+        let stamp = Stamp::compile(&watermark).unwrap();
+        let mut image = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        stamp.apply(&mut image);
+        assert_eq!(image.get_pixel(2, 2).0[..3], [128, 128, 128]);
+        assert_eq!(image.get_pixel(3, 3), &Rgba([0, 0, 0, 255]));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+
+        watermark.position = WatermarkPosition::Tile;
+        watermark.margin = 0;
+        let mut image = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+        Stamp::compile(&watermark).unwrap().apply(&mut image);
+        assert!(image.pixels().all(|pixel| pixel.0[0] == 128));
+
+        watermark.text = Some("DRAFT".to_string());
+        assert!(Stamp::compile(&watermark).is_err());
+        watermark.image = None;
+        assert!(Stamp::compile(&watermark).is_err());
+    }
+}
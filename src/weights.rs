@@ -0,0 +1,152 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+use crate::paths::LayerLayout;
+use crate::{path_trait, CustomError};
+
+lazy_static! {
+    static ref RE_ANNOTATION: Regex = Regex::new(r"^(\d+(?:\.\d+)?%|\d+-\d+|\d+)$").unwrap();
+}
+
+/// A weight set in the config: a number is a percentage of the layer, a
+/// string is written like the `#N` of a file name (`10`, `12.5%` or `1-4`).
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum WeightValue {
+    Percentage(f64),
+    Annotation(String),
+}
+
+impl WeightValue {
+    /// The file name annotation it stands for.
+    fn annotation(&self) -> Option<String> {
+        let annotation = match self {
+            WeightValue::Percentage(percentage) if *percentage >= 0.0 => {
+                format!("{}%", percentage)
+            }
+            WeightValue::Percentage(_) => return None,
+            WeightValue::Annotation(annotation) => annotation.trim_start_matches('#').to_string(),
+        };
+        RE_ANNOTATION.is_match(&annotation).then_some(annotation)
+    }
+}
+
+/// `weights` of the config: the weights of the values of each layer, inline
+/// or in a JSON file of the same shape.
+#[derive(Debug, Deserialize, PartialEq, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum Weights {
+    File(String),
+    Table(HashMap<String, HashMap<String, WeightValue>>),
+}
+
+impl Weights {
+    pub fn load(&self) -> Result<HashMap<String, HashMap<String, WeightValue>>, CustomError> {
+        match self {
+            Weights::File(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| CustomError::Config(format!("weights {}: {}", path, e)))?;
+                serde_json::from_str(&contents)
+                    .map_err(|e| CustomError::Config(format!("weights {}: {}", path, e)))
+            }
+            Weights::Table(table) => Ok(table.clone()),
+        }
+    }
+}
+
+/// The weight annotation of every value of `table`, by layer. Weights that
+/// are neither are left out; `check` reports them.
+pub fn annotations(
+    table: &HashMap<String, HashMap<String, WeightValue>>,
+) -> HashMap<String, HashMap<String, String>> {
+    table
+        .iter()
+        .map(|(layer_name, values)| {
+            let annotations = values
+                .iter()
+                .filter_map(|(value, weight)| Some((value.clone(), weight.annotation()?)))
+                .collect();
+            (layer_name.clone(), annotations)
+        })
+        .collect()
+}
+
+/// Checks the weights against the layers, `(name, assets)` pairs: every
+/// weight has to be valid and name a value of a layer.
+pub fn check(
+    layout: &LayerLayout,
+    table: &HashMap<String, HashMap<String, WeightValue>>,
+    layers: &[(&str, &[String])],
+) -> Result<(), CustomError> {
+    for (layer_name, values) in table {
+        let Some((_, assets)) = layers.iter().find(|(name, _)| name == layer_name) else {
+            return Err(CustomError::Config(format!(
+                "weights: {} is not a layer",
+                layer_name
+            )));
+        };
+        for (value, weight) in values {
+            if weight.annotation().is_none() {
+                return Err(CustomError::Config(format!(
+                    "weights: the weight of {} {} is neither a percentage nor a #N",
+                    layer_name, value
+                )));
+            }
+            if !assets.iter().any(|path| {
+                path_trait(layout, path).is_some_and(|(_, asset_value)| &asset_value == value)
+            }) {
+                return Err(CustomError::Config(format!(
+                    "weights: {} has no value {}",
+                    layer_name, value
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calculate_weights_and_total, path_share};
+
+    #[test]
+    fn test_configured_weights() {
+        let weights: Weights = serde_json::from_str(
+            r##"{"Eyes": {"Laser": 25, "Round": "#3", "Visor": "1-4", "Sleepy": "x"}}"##,
+        )
+        .unwrap();
+        let mut table = weights.load().unwrap();
+        let layer = vec![
+            "./weights-test/Eyes/Laser#1.png".to_string(),
+            "./weights-test/Eyes/Round#1.png".to_string(),
+            "./weights-test/Eyes/Visor.png".to_string(),
+        ];
+        let layers = [("Eyes", layer.as_slice())];
+        let layout = LayerLayout::new("./weights-test").with_weights(annotations(&table));
+        assert!(check(&layout, &table, &layers).is_err());
+
+        table.get_mut("Eyes").unwrap().remove("Sleepy");
+        check(&layout, &table, &layers).unwrap();
+        assert_eq!(layout.configured_weight(&layer[0]), Some("25%".to_string()));
+        assert_eq!(path_share(&layout, &layer[2]), Some(25.0));
+        // Laser and Visor take a quarter each, Round the half that is left.
+        assert_eq!(
+            calculate_weights_and_total(&layout, &layer),
+            (vec![250_000, 750_000, 1_000_000], 1_000_000)
+        );
+        // The layout of another config reads the file names alone.
+        assert_eq!(
+            calculate_weights_and_total(&LayerLayout::new("./weights-test"), &layer),
+            (vec![1, 2, 2], 2)
+        );
+
+        let unknown: Weights = serde_json::from_str(r#"{"Mouth": {"Smile": 5}}"#).unwrap();
+        assert!(check(&layout, &unknown.load().unwrap(), &layers).is_err());
+        assert!(Weights::File("./missing.json".to_string()).load().is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::CustomError;
+
+/// Threads writing the output when `--io-jobs` isn't given.
+pub const DEFAULT_IO_JOBS: usize = 2;
+
+/// An output file, encoded and waiting to be written.
+#[derive(Debug)]
+pub struct PendingFile {
+    pub path: PathBuf,
+    pub contents: Vec<u8>,
+}
+
+/// Where the files of a token go: written right away, or handed over to the
+/// writers of an `OutputWriter`.
+#[derive(Debug, Clone, Default)]
+pub enum FileSink {
+    #[default]
+    Direct,
+    Queued(SyncSender<PendingFile>),
+}
+
+fn write_file(file: &PendingFile) -> Result<(), CustomError> {
+    fs::write(&file.path, &file.contents)
+        .map_err(|e| CustomError::WriteOutput(format!("{}: {}", file.path.display(), e)))
+}
+
+impl FileSink {
+    /// Writes `contents` to `path`, or queues it, waiting while the queue is
+    /// full.
+    pub fn write(&self, path: PathBuf, contents: Vec<u8>) -> Result<(), CustomError> {
+        let file = PendingFile { path, contents };
+        match self {
+            FileSink::Direct => write_file(&file),
+            FileSink::Queued(sender) => sender.send(file).map_err(|error| {
+                CustomError::WriteOutput(format!(
+                    "{}: the output writers stopped",
+                    error.0.path.display()
+                ))
+            }),
+        }
+    }
+}
+
+/// Threads writing the files compositing hands over on a bounded queue, so a
+/// slow disk holds back at most `capacity` files instead of the renderers.
+pub struct OutputWriter {
+    sender: SyncSender<PendingFile>,
+    writers: Vec<JoinHandle<Result<(), CustomError>>>,
+}
+
+impl OutputWriter {
+    pub fn spawn(io_jobs: usize, capacity: usize) -> OutputWriter {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let receiver: Arc<Mutex<Receiver<PendingFile>>> = Arc::new(Mutex::new(receiver));
+        let writers = (0..io_jobs.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    // The lock is only held to take the next file.
+                    let next = receiver.lock().map(|receiver| receiver.recv());
+                    match next {
+                        Ok(Ok(file)) => write_file(&file)?,
+                        _ => return Ok(()),
+                    }
+                })
+            })
+            .collect();
+        OutputWriter { sender, writers }
+    }
+
+    pub fn sink(&self) -> FileSink {
+        FileSink::Queued(self.sender.clone())
+    }
+
+    /// Waits for every queued file to be written, once every sink is dropped,
+    /// and gives the first error of a writer.
+    pub fn finish(self) -> Result<(), CustomError> {
+        drop(self.sender);
+        let mut result = Ok(());
+        for writer in self.writers {
+            let written = writer.join().unwrap_or_else(|_| {
+                Err(CustomError::WriteOutput(
+                    "an output writer panicked".to_string(),
+                ))
+            });
+            result = result.and(written);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_output_writer() {
+        let dir = tempdir().unwrap();
+        let writer = OutputWriter::spawn(2, 1);
+        let sink = writer.sink();
+        for index in 0..10 {
+            let path = dir.path().join(format!("{}.json", index));
+            sink.write(path, index.to_string().into_bytes()).unwrap();
+        }
+        drop(sink);
+        writer.finish().unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("7.json")).unwrap(), "7");
+
+        let writer = OutputWriter::spawn(1, 1);
+        let sink = writer.sink();
+        let missing = dir.path().join("missing/0.json");
+        sink.write(missing, Vec::new()).unwrap();
+        drop(sink);
+        assert!(matches!(writer.finish(), Err(CustomError::WriteOutput(_))));
+    }
+}